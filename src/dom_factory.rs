@@ -1,9 +1,10 @@
 use crate::{rc_rcell, editor::fps};
+use js_sys::{Array, Uint8Array};
 use maud::{html, Markup};
 use wasm_bindgen::{closure::Closure, JsCast, JsValue};
 use web_sys::{
-    Document, Element, Event, EventTarget, FileList, FileReader, HtmlCanvasElement, HtmlCollection,
-    HtmlElement, HtmlInputElement, Node, NodeList, ProgressEvent, Window,
+    Blob, Document, Element, Event, EventTarget, FileList, FileReader, HtmlCanvasElement,
+    HtmlCollection, HtmlElement, HtmlInputElement, Node, NodeList, ProgressEvent, Url, Window,
 };
 
 pub fn window() -> Window {
@@ -171,6 +172,19 @@ fn button(id: &str, label: Option<&str>, hint: &str, icon_name: &str, hotkey: &s
     }
 }
 
+/// Hands the bytes to the browser as a file download.
+pub fn download_bytes(file_name: &str, bytes: &[u8]) -> Result<(), JsValue> {
+    let parts = Array::new();
+    parts.push(&Uint8Array::from(bytes));
+    let blob = Blob::new_with_u8_array_sequence(&parts)?;
+    let url = Url::create_object_url_with_blob(&blob)?;
+    let link = create_el("a").dyn_into::<HtmlElement>()?;
+    link.set_attribute("href", &url)?;
+    link.set_attribute("download", file_name)?;
+    link.click();
+    Url::revoke_object_url(&url)
+}
+
 pub fn push_history(title: &str) -> Result<(), JsValue> {
     window().history().unwrap().push_state_with_url(
         &JsValue::from_str(title),
@@ -258,6 +272,15 @@ pub fn get_target_file_result(e: &Event) -> String {
         .expect("Can't parse reader result as string!")
 }
 
+pub fn get_target_file_bytes(e: &Event) -> Vec<u8> {
+    let result = get_target(e)
+        .dyn_into::<FileReader>()
+        .expect("Can't cast as File Reader!")
+        .result()
+        .expect("File reader has no result content!");
+    Uint8Array::new(&result).to_vec()
+}
+
 pub fn get_progress(e: Event) -> ProgressEvent {
     e.dyn_into::<ProgressEvent>()
         .expect("Can't cast event as ProgrssEvent")
@@ -44,8 +44,6 @@ impl Gizmo {
                 .isometry
                 .into(),
         );
-        self.node
-            .apply_parent_transform(self.node.parent_transform() * self.node.transform());
     }
     pub fn collision_constraint(&self) -> CollisionConstraint {
         self.collision_constraint
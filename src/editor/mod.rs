@@ -4,9 +4,9 @@ mod gizmo;
 mod scene_tree;
 mod toolbar;
 use crate::{
-    dom_factory::{add_event, get_el, window},
+    dom_factory::{add_event, download_bytes, get_el, query_html_el, window},
     mesh::{Geometry, Material},
-    node, rc_rcell,
+    log, node, rc_rcell,
     scene::{
         primitives::{create_origin, create_transform_gizmo, ArrowTip},
         Node, Scene,
@@ -53,6 +53,12 @@ impl Editor {
         grid.set_rotation(UnitQuaternion::from_euler_angles(PI / 2., 0., 0.));
         let gizmo = create_transform_gizmo(&scene, ArrowTip::Cone);
         let spawn_origin = rc_rcell({ create_origin(&scene) });
+        {
+            let origin = spawn_origin.borrow();
+            let mut info = origin.info();
+            info.persist = false;
+            origin.set_info(info);
+        }
         scene.add(spawn_origin.clone());
         scene.show(&gizmo);
         let gizmo = Gizmo::new(gizmo);
@@ -120,7 +126,7 @@ impl Editor {
                 .borrow_mut()
                 .handle_mousedown(&ray, &view.borrow())
             {
-                if let Some((node, _)) = editor.scene.root().borrow().collides_w_children(&ray) {
+                if let Some(node) = editor.pick_node(&me) {
                     editor.set_active_node(node);
                 }
             }
@@ -192,6 +198,10 @@ impl Editor {
                 editor.scale_gizmos();
             } else if keycode == "KeyA" {
                 get_el("mesh-list").class_list().toggle("shown").unwrap();
+            } else if keycode == "Delete" {
+                editor.delete_active_node();
+            } else if keycode == "KeyE" {
+                editor.export_scene();
             }
         });
         let view = self.scene.view();
@@ -203,6 +213,51 @@ impl Editor {
             }
         });
     }
+    /// Downloads the scene as a binary glTF file.
+    pub fn export_scene(&self) {
+        if let Err(e) = download_bytes("scene.glb", &self.scene.to_glb()) {
+            log!("Couldn't export the scene:" e);
+        }
+    }
+    /// Removes the selected node and its children from the scene and refreshes the scene tree.
+    pub fn delete_active_node(&self) {
+        let node = self.active_node.borrow_mut().take();
+        if let Some(node) = node {
+            self.scene.delete(&node.borrow());
+            query_html_el("#scene-tree > ul").remove();
+            scene_tree::build_node(
+                self,
+                &get_el("scene-tree"),
+                NodeRef::Mutable(self.scene.root()),
+            );
+        }
+    }
+    /// The node drawn under the cursor. Clicking a part that a node owns, like the arrows of a
+    /// light, picks the node itself.
+    fn pick_node(&self, me: &MouseEvent) -> Option<RcRcell<Node>> {
+        let pick = {
+            let renderer = self.scene.renderer();
+            let renderer = renderer.borrow();
+            // the canvas holds more pixels than css ones with a pixel ratio above 1
+            let canvas = renderer.canvas();
+            let x = me.offset_x() as f32 * canvas.width() as f32 / canvas.offset_width() as f32;
+            let y = me.offset_y() as f32 * canvas.height() as f32 / canvas.offset_height() as f32;
+            renderer.pick(&self.scene, &self.scene.view().borrow(), x, y)?
+        };
+        let storage = self.scene.storage();
+        let handle = {
+            let storage = storage.borrow();
+            let mut index = storage.index_of(pick.node);
+            while let Some(parent) = storage.parent(index) {
+                if !storage.owned_children(parent).contains(&index) {
+                    break;
+                }
+                index = parent;
+            }
+            storage.handle_at(index)
+        };
+        Some(rc_rcell(Node::new(handle, storage)))
+    }
     fn scene(&self) -> Rc<Scene> {
         self.scene.clone()
     }
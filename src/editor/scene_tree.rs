@@ -74,7 +74,7 @@ pub fn build_node(editor: &Editor, parent: &Element, node: NodeRef) -> Element {
         NodeRef::Mutable(n) => {
             let node = n.borrow();
             let (children, owned_children) = (node.children(), node.owned_children());
-            add_collapse_icon(children, owned_children);
+            add_collapse_icon(&children, &owned_children);
             if parent.id().as_str() != "scene-tree" {
                 let eyei = create_el_w_class_n_inner("i", "material-icons eye", "visibility");
                 insert_el(&li, &eyei);
@@ -85,15 +85,15 @@ pub fn build_node(editor: &Editor, parent: &Element, node: NodeRef) -> Element {
             add_drag_events(&p, editor);
             let name = node.info().name;
             add_class(&ul, "shown");
-            recurse_children(children, owned_children);
+            recurse_children(&children, &owned_children);
             p.set_attribute("draggable", "true").unwrap();
             name
         }
         NodeRef::Owned(n) => {
             let (children, owned_children) = (n.children(), n.owned_children());
-            add_collapse_icon(children, owned_children);
+            add_collapse_icon(&children, &owned_children);
             add_class(&ul, "disabled");
-            recurse_children(children, owned_children);
+            recurse_children(&children, &owned_children);
             n.info().name
         }
     };
@@ -204,8 +204,8 @@ fn add_drag_events(el: &Element, editor: &Editor) {
             let dragged_node = scene.find_node_w_name(&dragged_el_name).unwrap();
             let parent_node = scene.find_node_w_name(&dragged_parent_name).unwrap();
             let target_node = scene.find_node_w_name(&drop_target_name).unwrap();
-            parent_node.borrow_mut().remove(&dragged_el_name);
-            target_node.borrow_mut().add(dragged_node.clone());
+            parent_node.borrow().remove(&dragged_el_name);
+            target_node.borrow().add(dragged_node.clone());
             if dragged_parent_name.as_str() != "Scene" {
                 let li = create_el("li");
                 let g_p_el = get_parent(&dragged_el, 6).unwrap();
@@ -2,20 +2,24 @@ use super::scene_tree::build_node;
 use super::NodeRef;
 use crate::{
     dom_factory::{
-        add_event, body, document, get_el, get_progress, get_target_file_result, get_target_files,
+        add_event, body, document, get_el, get_progress, get_target_file_bytes, get_target_file_result,
+        get_target_files,
         get_target_innerh, icon_btn_w_id, query_els, query_html_el, set_timeout,
     },
     log, rc_rcell,
     scene::primitives::create_primitive_node,
-    Editor, LightType, Primitive, RcRcell, Viewport,
+    Editor, LightType, Node, Primitive, RcRcell, Viewport,
 };
 use maud::html;
 use std::collections::HashMap;
+use std::fmt::Display;
 use std::rc::Rc;
 use std::str::FromStr;
 use strum::IntoEnumIterator;
 use wasm_bindgen::JsCast;
 use web_sys::{EventTarget, File, FileReader, Url};
+const OBJ_PROGRESS: &str = "#obj-file + label .progress";
+const GLTF_PROGRESS: &str = "#gltf-file + label .progress";
 pub fn build(editor: &Editor) {
     body()
         .insert_adjacent_html("beforeend", markup().as_str())
@@ -32,6 +36,7 @@ fn markup() -> String {
             (icon_btn_w_id("focus", "Focus view to selected object", "center_focus_weak", "F"))
             (icon_btn_w_id("toggle-perspective", "Switch Perspective", "crop_5_4", "P"))
             (icon_btn_w_id("zoom-in-out", "Zoom in/out view", "zoom_in", "Z"))
+            (icon_btn_w_id("export-glb", "Export scene as glTF", "save_alt", "E"))
         }
         section #mesh-list.panel {
             h3 {"Add Objects" hr{} "Mesh"}
@@ -43,6 +48,7 @@ fn markup() -> String {
             h3 {"File"}
             ul#file {
                 li {input multiple="" type="file" id="obj-file" {} label for="obj-file" {"Wavefront OBJ" span.progress{}}}
+                li {input multiple="" type="file" id="gltf-file" {} label for="gltf-file" {"glTF 2.0" span.progress{}}}
             }
             h3 {"Light"}
             ul#light {
@@ -64,6 +70,43 @@ pub fn handle_persp_toggle(a_view: RcRcell<Viewport>) {
     let mut view = a_view.borrow_mut();
     view.switch_projection();
 }
+fn update_progress(reader: &FileReader, progress: RcRcell<f64>, total: usize, selector: &str) {
+    let progress_el = query_html_el(selector);
+    //progress_el.class_list().remove_1("loaded");
+    add_event(reader, "progress", move |e| {
+        let mut progress = progress.borrow_mut();
+        let pe = get_progress(e);
+        *progress += (pe.loaded() * 100.) / (pe.total() * total as f64);
+        log!("Progress" progress.to_string());
+        progress_el
+            .style()
+            .set_property("width", &format!("{}%", *progress))
+            .unwrap();
+        if *progress == 100. {
+            let p = progress_el.clone();
+            set_timeout(
+                move || {
+                    //p.class_list().add_1("loaded");
+                    p.style().set_property("width", "0");
+                },
+                1000,
+            );
+        }
+    });
+}
+/// Places a node loaded from a file at the spawn origin and shows it in the scene tree.
+fn add_imported_node<E: Display>(editor: &Editor, result: Result<Node, E>) {
+    let scene = editor.scene();
+    match result {
+        Ok(node) => {
+            node.copy_location(&editor.spawn_origin.borrow());
+            scene.add(rc_rcell(node));
+            query_html_el("#scene-tree > ul").remove();
+            build_node(editor, &get_el("scene-tree"), NodeRef::Mutable(scene.root()));
+        }
+        Err(e) => log!("Couldn't import the file:" e.to_string()),
+    }
+}
 fn add_events(editor: &Editor) {
     add_event(
         &document().get_element_by_id("add-mesh").unwrap(),
@@ -93,6 +136,14 @@ fn add_events(editor: &Editor) {
             }
         },
     );
+    let a_editor = editor.clone();
+    add_event(
+        &document().get_element_by_id("export-glb").unwrap(),
+        "click",
+        move |_| {
+            a_editor.export_scene();
+        },
+    );
     let a_view = view.clone();
     add_event(
         &document().get_element_by_id("zoom-in-out").unwrap(),
@@ -155,30 +206,6 @@ fn add_events(editor: &Editor) {
                 total += 1;
             }
             total += tex.len();
-            let update_progress = |reader: Rc<FileReader>, progress: RcRcell<f64>, total| {
-                let progress_el = query_html_el("#obj-file + label .progress");
-                //progress_el.class_list().remove_1("loaded");
-                add_event(&reader, "progress", move |e| {
-                    let mut progress = progress.borrow_mut();
-                    let pe = get_progress(e);
-                    *progress += (pe.loaded() * 100.) / (pe.total() * total as f64);
-                    log!("Progress" progress.to_string());
-                    progress_el
-                        .style()
-                        .set_property("width", &format!("{}%", *progress))
-                        .unwrap();
-                    if *progress == 100. {
-                        let p = progress_el.clone();
-                        set_timeout(
-                            move || {
-                                //p.class_list().add_1("loaded");
-                                p.style().set_property("width", "0");
-                            },
-                            1000,
-                        );
-                    }
-                });
-            };
             let obj_reader = Rc::new(FileReader::new().unwrap());
             let tex = Rc::new(tex);
             let editor = a_editor.clone();
@@ -197,10 +224,9 @@ fn add_events(editor: &Editor) {
                         if tex.len() == 0 {
                             log!("No texture file uploaded. Will not load textures.");
                             let scene = editor.scene();
-                            let node =
+                            let result =
                                 scene.object_from_obj("", &obj_src, Some(&mtl_src), None, true);
-                            node.copy_location(&editor.spawn_origin.borrow());
-                            scene.add(rc_rcell(node));
+                            add_imported_node(&editor, result);
                         } else {
                             let h_m: HashMap<String, String> = HashMap::new();
                             let mut loaded_urls = rc_rcell(h_m);
@@ -219,38 +245,109 @@ fn add_events(editor: &Editor) {
                                     loaded_urls.insert(n.clone(), url);
                                     if loaded_urls.len() == len {
                                         let scene = editor.scene();
-                                        let node = scene.object_from_obj(
+                                        let result = scene.object_from_obj(
                                             "",
                                             &a_o_src,
                                             Some(&m_src),
                                             Some(&loaded_urls),
                                             false,
                                         );
-                                        node.copy_location(&editor.spawn_origin.borrow());
-                                        scene.add(rc_rcell(node));
+                                        add_imported_node(&editor, result);
                                     }
                                 });
-                                update_progress(tex_reader.clone(), p.clone(), total);
+                                update_progress(&tex_reader, p.clone(), total, OBJ_PROGRESS);
                                 tex_reader.read_as_data_url(file.as_ref());
                             }
                         }
                     });
-                    update_progress(mat_reader.clone(), a_p.clone(), total);
+                    update_progress(&mat_reader, a_p.clone(), total, OBJ_PROGRESS);
                     mat_reader.read_as_text(file.as_ref());
                 } else {
                     log!("No material file uploaded. Will load default material instead.");
                     let scene = editor.scene();
-                    let node = scene.object_from_obj("", &obj_src, None, None, false);
-                    node.copy_location(&editor.spawn_origin.borrow());
-                    scene.add(rc_rcell(node));
+                    let result = scene.object_from_obj("", &obj_src, None, None, false);
+                    add_imported_node(&editor, result);
                 }
             });
-            update_progress(obj_reader.clone(), progress.clone(), total);
+            update_progress(&obj_reader, progress.clone(), total, OBJ_PROGRESS);
             obj_reader.read_as_text(&file);
         } else {
             log!("You didn't provide obj file! Can't upload anything.");
         }
     });
+    let a_editor = editor.clone();
+    add_event(&get_el("gltf-file"), "input", move |e| {
+        let files = get_target_files(&e);
+        let mut gltf = None;
+        let mut buffers = Vec::new();
+        let mut img_urls = HashMap::new();
+        for i in 0..files.length() {
+            let file = files.item(i).unwrap();
+            let file_name = file.name();
+            let ext = file_name.rsplit('.').next().unwrap_or("").to_lowercase();
+            if ext == "gltf" || ext == "glb" {
+                gltf = Some(file);
+            } else if file.type_().starts_with("image") {
+                img_urls.insert(file_name, Url::create_object_url_with_blob(&file).unwrap());
+            } else {
+                buffers.push(file);
+            }
+        }
+        let file = if let Some(file) = gltf {
+            file
+        } else {
+            log!("You didn't provide a gltf or glb file! Can't upload anything.");
+            return;
+        };
+        let is_glb = file.name().to_lowercase().ends_with(".glb");
+        let total = buffers.len() + 1;
+        let progress = rc_rcell(0.);
+        let src: RcRcell<Option<Vec<u8>>> = rc_rcell(None);
+        let loaded: RcRcell<HashMap<String, Vec<u8>>> = rc_rcell(HashMap::new());
+        // every reader stores its result and the last one to finish loads the scene
+        let finish = {
+            let editor = a_editor.clone();
+            let src = src.clone();
+            let loaded = loaded.clone();
+            Rc::new(move || {
+                let src = src.borrow();
+                let loaded = loaded.borrow();
+                let src = match src.as_ref() {
+                    Some(src) if loaded.len() == total - 1 => src,
+                    _ => return,
+                };
+                let scene = editor.scene();
+                let result = if is_glb {
+                    scene.object_from_glb("", src, &loaded, Some(&img_urls))
+                } else {
+                    let src = String::from_utf8_lossy(src);
+                    scene.object_from_gltf("", &src, &loaded, Some(&img_urls))
+                };
+                add_imported_node(&editor, result);
+            })
+        };
+        for file in buffers {
+            let reader = FileReader::new().unwrap();
+            let loaded = loaded.clone();
+            let finish = finish.clone();
+            let file_name = file.name();
+            add_event(&reader, "load", move |e| {
+                loaded
+                    .borrow_mut()
+                    .insert(file_name.clone(), get_target_file_bytes(&e));
+                finish();
+            });
+            update_progress(&reader, progress.clone(), total, GLTF_PROGRESS);
+            reader.read_as_array_buffer(&file).unwrap();
+        }
+        let reader = FileReader::new().unwrap();
+        add_event(&reader, "load", move |e| {
+            *src.borrow_mut() = Some(get_target_file_bytes(&e));
+            finish();
+        });
+        update_progress(&reader, progress, total, GLTF_PROGRESS);
+        reader.read_as_array_buffer(&file).unwrap();
+    });
     let list = &query_els("#mesh-list #light li");
     for i in 0..list.length() {
         let each = list.get(i).unwrap();
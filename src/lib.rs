@@ -11,7 +11,8 @@ static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 #[macro_use]
 extern crate serde_derive;
 
-mod log_macro;
+#[doc(hidden)]
+pub mod log_macro;
 mod node_macro;
 
 use std::cell::RefCell;
@@ -26,19 +27,26 @@ pub fn rc_rcell<T>(inner: T) -> RcRcell<T> {
 }
 
 pub mod controller;
+#[cfg(feature = "web")]
 pub mod dom_factory;
+#[cfg(feature = "web")]
 pub mod editor;
 pub mod mesh;
+pub mod physics;
 pub mod renderer;
 pub mod scene;
 
 #[doc(inline)]
 pub use crate::{
     controller::{MouseButton, ProjectionType, Viewport},
-    editor::Editor,
-    mesh::{Geometry, Material, Mesh, TextureType, Transform, Color},
+    mesh::{Geometry, Material, Mesh, TextureMap, TextureType, Transform, Color},
     renderer::Renderer,
-    scene::{Light, LightType, Node, ObjectInfo, Primitive, Scene, Storage},
+    scene::{Handle, Light, LightType, Node, ObjectInfo, Primitive, Scene, Storage},
 };
 
+#[doc(inline)]
+#[cfg(feature = "web")]
+pub use crate::editor::Editor;
+
+#[cfg(feature = "web")]
 mod start;
@@ -7,8 +7,6 @@
 macro_rules! log {
     ($($x:expr) *) => {
         {
-            let document = crate::dom_factory::document();
-            let console_el = document.get_element_by_id("console");
             let mut msg = String::new();
             use std::any::Any;
             $(
@@ -22,24 +20,37 @@ macro_rules! log {
                     }
                 }
             )*
-            match console_el {
-                Some(_) => {
-                    let log_el = document
-                        .get_element_by_id("logs")
-                        .unwrap();
-                    web_sys::console::log_1(&wasm_bindgen::JsValue::from_str(&msg));
-                    log_el
-                        .insert_adjacent_html(
-                            "afterbegin",
-                            &format!("<div><i class='material-icons-outlined'>info</i><pre>{}</pre></div>", msg),
-                        )
-                        .unwrap();
-                },
-                None => {
-                    let msg = format!("dev console only: {:?}", msg);
-                    web_sys::console::log_1(&wasm_bindgen::JsValue::from_str(&msg));
-                }
-            }
+            $crate::log_macro::write_log(msg);
         }
     };
 }
+
+/// Writes a message built by log! to the console panel of the editor, or only to the developer
+/// console when there is no editor.
+#[cfg(all(feature = "web", target_arch = "wasm32"))]
+pub fn write_log(msg: String) {
+    let document = crate::dom_factory::document();
+    let console_el = document.get_element_by_id("console");
+    match console_el {
+        Some(_) => {
+            let log_el = document.get_element_by_id("logs").unwrap();
+            web_sys::console::log_1(&wasm_bindgen::JsValue::from_str(&msg));
+            log_el
+                .insert_adjacent_html(
+                    "afterbegin",
+                    &format!("<div><i class='material-icons-outlined'>info</i><pre>{}</pre></div>", msg),
+                )
+                .unwrap();
+        },
+        None => {
+            let msg = format!("dev console only: {:?}", msg);
+            web_sys::console::log_1(&wasm_bindgen::JsValue::from_str(&msg));
+        }
+    }
+}
+
+/// There is no browser outside of wasm, so log! prints to stdout instead.
+#[cfg(not(all(feature = "web", target_arch = "wasm32")))]
+pub fn write_log(msg: String) {
+    println!("{}", msg);
+}
@@ -1,18 +1,55 @@
-use crate::renderer::ShaderType;
+use crate::renderer::{RenderTargetId, ShaderType};
 use genmesh::{
     generators::{IndexedPolygon, SharedVertex},
     EmitTriangles, Triangulate, Vertex,
 };
-use nalgebra::{one, Isometry3, Matrix4, Point3, Translation3, Vector3};
-use wasm_bindgen::JsValue;
+use nalgebra::{one, Isometry3, Matrix4, Point3, Quaternion, Translation3, UnitQuaternion, Vector3};
+use ncollide3d::bounding_volume::{BoundingSphere, BoundingVolume, AABB};
 
 /// A 3D transform that can handle translation, rotation, and non-uniform scaling.
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(from = "TransformData", into = "TransformData")]
 pub struct Transform {
     pub isometry: Isometry3<f32>,
     pub scale: Vector3<f32>,
 }
 
+/// Flat representation of a Transform that is written to scene documents. Rotation is stored as
+/// an (x, y, z, w) quaternion.
+#[derive(Serialize, Deserialize)]
+struct TransformData {
+    translation: [f32; 3],
+    rotation: [f32; 4],
+    scale: [f32; 3],
+}
+
+impl From<Transform> for TransformData {
+    fn from(transform: Transform) -> Self {
+        let t = transform.isometry.translation.vector;
+        let q = transform.isometry.rotation.into_inner();
+        let s = transform.scale;
+        Self {
+            translation: [t.x, t.y, t.z],
+            rotation: [q.i, q.j, q.k, q.w],
+            scale: [s.x, s.y, s.z],
+        }
+    }
+}
+
+impl From<TransformData> for Transform {
+    fn from(data: TransformData) -> Self {
+        let [x, y, z] = data.translation;
+        let [i, j, k, w] = data.rotation;
+        Self {
+            isometry: Isometry3::from_parts(
+                Translation3::new(x, y, z),
+                UnitQuaternion::from_quaternion(Quaternion::new(w, i, j, k)),
+            ),
+            scale: data.scale.into(),
+        }
+    }
+}
+
 impl Transform {
     pub fn to_homogeneous(&self) -> Matrix4<f32> {
         self.isometry.to_homogeneous() * Matrix4::new_nonuniform_scaling(&self.scale)
@@ -92,7 +129,7 @@ impl Default for Transform {
 }
 
 /// A 3D mesh containing geometry and material.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Mesh {
     pub geometry: Geometry,
     pub material: Material,
@@ -133,11 +170,21 @@ impl Mesh {
     }
 }
 
+/// Width of the indices uploaded to the GPU for a Geometry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexType {
+    U16,
+    U32,
+}
+
 /// Geometry of a 3D object containing vertices, indices, and face normals.
-#[derive(Debug, Clone, PartialEq)]
+///
+/// Indices are always held as u32 and are narrowed to u16 on upload whenever the vertex count
+/// allows it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Geometry {
     pub vertices: Vec<f32>,
-    pub indices: Vec<u16>,
+    pub indices: Vec<u32>,
     pub normals: Vec<f32>,
 }
 
@@ -151,7 +198,118 @@ impl Default for Geometry {
     }
 }
 
+/// The box and the sphere around the vertices of a Geometry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bounds {
+    pub aabb: AABB<f32>,
+    pub sphere: BoundingSphere<f32>,
+}
+
+impl Bounds {
+    /// The bounds once transformed, e.g. into world space. The box is the one around the
+    /// transformed corners, so under a rotation it's larger than the box of the transformed
+    /// vertices would be.
+    pub fn transformed(&self, matrix: &Matrix4<f32>) -> Self {
+        let (mins, maxs) = (self.aabb.mins(), self.aabb.maxs());
+        let corners: Vec<Point3<f32>> = (0..8)
+            .map(|c| {
+                let pick = |bit: usize, axis: usize| {
+                    if c & bit == 0 {
+                        mins[axis]
+                    } else {
+                        maxs[axis]
+                    }
+                };
+                matrix.transform_point(&Point3::new(pick(1, 0), pick(2, 1), pick(4, 2)))
+            })
+            .collect();
+        let aabb = aabb_around(&corners).expect("A box has corners");
+        let center = matrix.transform_point(self.sphere.center());
+        // both hold every transformed vertex: the corners of the box are the farthest points
+        // of it from any center, and no direction is stretched more than the norm of the matrix
+        let around_corners = corners
+            .iter()
+            .map(|p| nalgebra::distance(&center, p))
+            .fold(0., f32::max);
+        let linear = matrix.fixed_slice::<nalgebra::U3, nalgebra::U3>(0, 0);
+        let scaled = self.sphere.radius() * linear.norm();
+        Self {
+            aabb,
+            sphere: BoundingSphere::new(center, around_corners.min(scaled)),
+        }
+    }
+    /// The bounds that hold both.
+    pub fn merged(&self, other: &Self) -> Self {
+        Self {
+            aabb: self.aabb.merged(&other.aabb),
+            sphere: self.sphere.merged(&other.sphere),
+        }
+    }
+}
+
+/// The smallest box around the points, None without points.
+fn aabb_around(points: &[Point3<f32>]) -> Option<AABB<f32>> {
+    let first = points.first()?.coords;
+    let (mins, maxs) = points.iter().fold((first, first), |(mins, maxs), p| {
+        (
+            mins.zip_map(&p.coords, f32::min),
+            maxs.zip_map(&p.coords, f32::max),
+        )
+    });
+    Some(AABB::new(Point3::from(mins), Point3::from(maxs)))
+}
+
 impl Geometry {
+    /// Picks the smallest index width that can address all of the vertices.
+    pub fn index_type(&self) -> IndexType {
+        if self.vertices.len() / 3 > u16::MAX as usize + 1 {
+            IndexType::U32
+        } else {
+            IndexType::U16
+        }
+    }
+    /// The box around the vertices, and the sphere around them centered on the box. None for a
+    /// geometry without vertices.
+    pub fn bounds(&self) -> Option<Bounds> {
+        let points: Vec<Point3<f32>> = self
+            .vertices
+            .chunks_exact(3)
+            .map(|v| Point3::new(v[0], v[1], v[2]))
+            .collect();
+        let aabb = aabb_around(&points)?;
+        let center = aabb.center();
+        let radius = points
+            .iter()
+            .map(|p| nalgebra::distance(&center, p))
+            .fold(0., f32::max);
+        Some(Bounds {
+            aabb,
+            sphere: BoundingSphere::new(center, radius),
+        })
+    }
+    /// Replaces the normals with smooth vertex normals, averaged over the faces that share each
+    /// vertex and weighted by their area.
+    pub fn compute_normals(&mut self) {
+        let mut normals = vec![Vector3::new(0., 0., 0.); self.vertices.len() / 3];
+        let vertex = |i: u32| {
+            let i = i as usize * 3;
+            Vector3::new(self.vertices[i], self.vertices[i + 1], self.vertices[i + 2])
+        };
+        for face in self.indices.chunks(3).filter(|f| f.len() == 3) {
+            let (a, b, c) = (vertex(face[0]), vertex(face[1]), vertex(face[2]));
+            let normal = (b - a).cross(&(c - a));
+            for i in face {
+                normals[*i as usize] += normal;
+            }
+        }
+        self.normals = normals
+            .iter()
+            .flat_map(|n| {
+                let n = n.try_normalize(f32::EPSILON).unwrap_or_else(Vector3::y);
+                vec![n.x, n.y, n.z]
+            })
+            .collect();
+    }
     pub fn from_genmesh<T, P>(primitive: &T) -> Self
     where
         P: EmitTriangles<Vertex = usize>,
@@ -185,9 +343,9 @@ impl Geometry {
             }
         }
         for t in primitive.indexed_polygon_iter().triangulate() {
-            indices.push(t.x as u16);
-            indices.push(t.y as u16);
-            indices.push(t.z as u16);
+            indices.push(t.x as u32);
+            indices.push(t.y as u32);
+            indices.push(t.z as u32);
         }
         Self {
             vertices,
@@ -197,14 +355,14 @@ impl Geometry {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum TextureType {
     Tex2d,
     CubeMap,
     None,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Color {
     pub r: f32,
     pub g: f32,
@@ -238,8 +396,35 @@ impl From<[f32;4]> for Color {
     }
 }
 
+/// A texture of a Pbr material besides its albedo, sampled with the material's texture
+/// coordinates.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TextureMap {
+    pub url: String,
+    /// The scale of a normal map or the strength of an occlusion map, as in glTF. Metallic
+    /// roughness maps ignore it.
+    pub strength: f32,
+    /// Texture bound in Storage; rebuilt from the url when a scene is loaded.
+    #[serde(skip)]
+    pub texture_index: Option<usize>,
+}
+
+impl TextureMap {
+    pub fn new(url: &str, strength: f32) -> Self {
+        Self {
+            url: String::from(url),
+            strength,
+            texture_index: None,
+        }
+    }
+}
+
 /// Material for a 3D object; can contain either color, vertex colors, or texture.
-#[derive(Debug, Clone, PartialEq)]
+///
+/// The Pbr shader follows the metallic/roughness model of glTF: the color is the linear base
+/// color, and metallic and roughness default to 1 like their glTF factors.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Material {
     pub shader_type: ShaderType,
     pub flat_shade: bool,
@@ -250,7 +435,20 @@ pub struct Material {
     pub tex_type: TextureType,
     pub tex_coords: Option<Vec<f32>>,
     pub texture_urls: Vec<String>,
+    /// Textures bound in Storage; these are rebuilt from the urls when a scene is loaded.
+    #[serde(skip)]
     pub texture_indices: Vec<usize>,
+    /// A render target to draw with instead of the first texture, e.g. for a mirror.
+    #[serde(skip)]
+    pub render_target: Option<RenderTargetId>,
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Light emitted by the surface in linear RGB.
+    pub emissive: [f32; 3],
+    pub normal_map: Option<TextureMap>,
+    pub occlusion_map: Option<TextureMap>,
+    /// Roughness in the green channel and metalness in the blue channel, as in glTF.
+    pub metallic_roughness_map: Option<TextureMap>,
 }
 
 impl Default for Material {
@@ -266,6 +464,13 @@ impl Default for Material {
             tex_coords: None,
             texture_urls: Vec::new(),
             texture_indices: Vec::new(),
+            render_target: None,
+            metallic: 1.,
+            roughness: 1.,
+            emissive: [0., 0., 0.],
+            normal_map: None,
+            occlusion_map: None,
+            metallic_roughness_map: None,
         }
     }
 }
@@ -285,6 +490,12 @@ impl Material {
             .wire_overlay_colored(Color::rgba(r, g, b, a))
             .shader_type(ShaderType::Wireframe)
     }
+    /// A physically based material with glTF's default metallic and roughness of 1.
+    pub fn new_pbr(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Self::default()
+            .color(r, g, b, a)
+            .shader_type(ShaderType::Pbr)
+    }
     pub fn new_texture(url: &str, tex_coords: Vec<f32>) -> Self {
         Self::new_color(1., 1., 1., 1.)
             .tex_type(TextureType::Tex2d)
@@ -340,6 +551,57 @@ impl Material {
         self.texture_urls.push(String::from(url));
         self
     }
+    pub fn render_target(mut self, target: RenderTargetId) -> Self {
+        self.render_target = Some(target);
+        self
+    }
+    pub fn metallic(mut self, metallic: f32) -> Self {
+        self.metallic = metallic;
+        self
+    }
+    pub fn roughness(mut self, roughness: f32) -> Self {
+        self.roughness = roughness;
+        self
+    }
+    pub fn emissive(mut self, r: f32, g: f32, b: f32) -> Self {
+        self.emissive = [r, g, b];
+        self
+    }
+    pub fn normal_map(mut self, url: &str, scale: f32) -> Self {
+        self.normal_map = Some(TextureMap::new(url, scale));
+        self
+    }
+    pub fn occlusion_map(mut self, url: &str, strength: f32) -> Self {
+        self.occlusion_map = Some(TextureMap::new(url, strength));
+        self
+    }
+    pub fn metallic_roughness_map(mut self, url: &str) -> Self {
+        self.metallic_roughness_map = Some(TextureMap::new(url, 1.));
+        self
+    }
+    /// The normal, occlusion and metallic roughness maps that are set.
+    pub fn texture_maps_mut(&mut self) -> impl Iterator<Item = &mut TextureMap> {
+        vec![
+            self.normal_map.as_mut(),
+            self.occlusion_map.as_mut(),
+            self.metallic_roughness_map.as_mut(),
+        ]
+        .into_iter()
+        .flatten()
+    }
+    /// Every texture of the material that is bound in Storage, including those of its maps.
+    pub fn textures(&self) -> impl Iterator<Item = usize> + '_ {
+        let maps = vec![
+            &self.normal_map,
+            &self.occlusion_map,
+            &self.metallic_roughness_map,
+        ];
+        self.texture_indices.iter().copied().chain(
+            maps.into_iter()
+                .flatten()
+                .filter_map(|map| map.texture_index),
+        )
+    }
     pub fn vertex_colors(vertex_color: Vec<f32>) -> Self {
         let mut mat = Self::default();
         mat.vertex_colors = Some(vertex_color);
@@ -8,6 +8,7 @@ macro_rules! node_from_obj_wired {
             None,
             true,
         )
+        .expect("Couldn't load the bundled obj file!")
     }};
 }
 #[macro_export]
@@ -20,6 +21,7 @@ macro_rules! node_from_obj {
             None,
             false,
         )
+        .expect("Couldn't load the bundled obj file!")
     }};
 }
 #[macro_export]
@@ -0,0 +1,199 @@
+use crate::Geometry;
+use nalgebra::{Point3, Vector3};
+use ncollide3d::{
+    bounding_volume::AABB,
+    shape::{Ball, Capsule, ConvexHull, Cuboid, ShapeHandle, TriMesh},
+};
+use std::fmt;
+
+/// How a body moves.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BodyType {
+    /// Falls, collides and is pushed around by other bodies.
+    Dynamic,
+    /// Never moves, like the ground.
+    Static,
+    /// Moves at its own velocity and pushes dynamic bodies out of its way, but is never pushed
+    /// back, like a moving platform.
+    Kinematic,
+}
+
+/// The shape a body collides with, in the space of its node. The node's scale isn't applied to
+/// it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColliderShape {
+    Ball {
+        radius: f32,
+    },
+    Cuboid {
+        half_extents: Vector3<f32>,
+    },
+    /// Upright along the y axis, with the center of the caps `half_height` away from the center.
+    Capsule {
+        half_height: f32,
+        radius: f32,
+    },
+    ConvexHull(Vec<Point3<f32>>),
+    /// Best suited to static bodies, since it has no inside that bodies can be pushed out of.
+    TriMesh(Geometry),
+}
+
+/// A collider shape along with the ncollide shape it's tested with.
+#[derive(Clone)]
+pub struct Collider {
+    shape: ColliderShape,
+    handle: ShapeHandle<f32>,
+}
+
+impl fmt::Debug for Collider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.shape {
+            ColliderShape::ConvexHull(points) => {
+                f.debug_tuple("ConvexHull").field(&points.len()).finish()
+            }
+            ColliderShape::TriMesh(geometry) => f
+                .debug_tuple("TriMesh")
+                .field(&(geometry.indices.len() / 3))
+                .finish(),
+            shape => shape.fmt(f),
+        }
+    }
+}
+
+impl PartialEq for Collider {
+    fn eq(&self, other: &Self) -> bool {
+        self.shape == other.shape
+    }
+}
+
+impl Collider {
+    pub fn ball(radius: f32) -> Self {
+        Self {
+            shape: ColliderShape::Ball { radius },
+            handle: ShapeHandle::new(Ball::new(radius)),
+        }
+    }
+    pub fn cuboid(half_extents: Vector3<f32>) -> Self {
+        Self {
+            shape: ColliderShape::Cuboid { half_extents },
+            handle: ShapeHandle::new(Cuboid::new(half_extents)),
+        }
+    }
+    pub fn capsule(half_height: f32, radius: f32) -> Self {
+        Self {
+            shape: ColliderShape::Capsule {
+                half_height,
+                radius,
+            },
+            handle: ShapeHandle::new(Capsule::new(half_height, radius)),
+        }
+    }
+    /// The convex hull of the vertices of a geometry, None if they're all on a plane.
+    pub fn convex_hull(geometry: &Geometry) -> Option<Self> {
+        let points: Vec<Point3<f32>> = geometry
+            .vertices
+            .chunks_exact(3)
+            .map(|v| Point3::new(v[0], v[1], v[2]))
+            .collect();
+        let hull = ConvexHull::try_from_points(&points)?;
+        Some(Self {
+            shape: ColliderShape::ConvexHull(points),
+            handle: ShapeHandle::new(hull),
+        })
+    }
+    /// The triangles of a geometry, None without any.
+    pub fn trimesh(geometry: &Geometry) -> Option<Self> {
+        let vertices = geometry.vertices.len() / 3;
+        let indices: Vec<Point3<usize>> = geometry
+            .indices
+            .chunks_exact(3)
+            .map(|t| Point3::new(t[0] as usize, t[1] as usize, t[2] as usize))
+            .collect();
+        if indices.is_empty() || indices.iter().any(|t| t.iter().any(|v| *v >= vertices)) {
+            return None;
+        }
+        let points = geometry
+            .vertices
+            .chunks_exact(3)
+            .map(|v| Point3::new(v[0], v[1], v[2]))
+            .collect();
+        Some(Self {
+            shape: ColliderShape::TriMesh(geometry.clone()),
+            handle: ShapeHandle::new(TriMesh::new(points, indices, None)),
+        })
+    }
+    pub fn shape(&self) -> &ColliderShape {
+        &self.shape
+    }
+    pub(super) fn handle(&self) -> &ShapeHandle<f32> {
+        &self.handle
+    }
+    /// The diagonal of the inertia tensor of a body of mass 1. Capsules, hulls and meshes are
+    /// taken to be the box around them.
+    fn unit_inertia(&self) -> Vector3<f32> {
+        let half_extents = match &self.shape {
+            ColliderShape::Ball { radius } => return Vector3::repeat(0.4 * radius * radius),
+            ColliderShape::Cuboid { half_extents } => *half_extents,
+            ColliderShape::Capsule {
+                half_height,
+                radius,
+            } => Vector3::new(*radius, half_height + radius, *radius),
+            _ => {
+                let aabb: AABB<f32> = self.handle.aabb(&nalgebra::Isometry3::identity());
+                aabb.half_extents()
+            }
+        };
+        let squared = half_extents.component_mul(&half_extents);
+        Vector3::new(
+            squared.y + squared.z,
+            squared.x + squared.z,
+            squared.x + squared.y,
+        ) / 3.
+    }
+}
+
+/// The physical props of a node, which the PhysicsWorld moves it by.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RigidBody {
+    pub body_type: BodyType,
+    pub collider: Collider,
+    /// In kilograms, only used by dynamic bodies.
+    pub mass: f32,
+    /// How much of its speed a body keeps when it bounces, from 0 to 1. The bouncier of two
+    /// bodies in contact sets how they bounce off each other.
+    pub restitution: f32,
+    /// The friction coefficient. Two bodies in contact use the geometric mean of theirs.
+    pub friction: f32,
+    /// In world space, meters per second.
+    pub linear_velocity: Vector3<f32>,
+    /// In world space, radians per second about each axis.
+    pub angular_velocity: Vector3<f32>,
+}
+
+impl RigidBody {
+    /// A body of 1 kilogram that doesn't bounce, at rest.
+    pub fn new(body_type: BodyType, collider: Collider) -> Self {
+        Self {
+            body_type,
+            collider,
+            mass: 1.,
+            restitution: 0.,
+            friction: 0.5,
+            linear_velocity: Vector3::zeros(),
+            angular_velocity: Vector3::zeros(),
+        }
+    }
+    pub(super) fn inverse_mass(&self) -> f32 {
+        match self.body_type {
+            BodyType::Dynamic if self.mass > 0. => 1. / self.mass,
+            _ => 0.,
+        }
+    }
+    /// The diagonal of the inverse inertia tensor, in the space of the body.
+    pub(super) fn inverse_inertia(&self) -> Vector3<f32> {
+        let inverse_mass = self.inverse_mass();
+        self.collider
+            .unit_inertia()
+            .map(|i| if i > 0. { inverse_mass / i } else { 0. })
+    }
+}
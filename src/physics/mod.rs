@@ -0,0 +1,496 @@
+//! Rigid body physics on top of ncollide. The bodies live on nodes in Storage; on every step the
+//! world finds their contacts with ncollide, solves them with sequential impulses, and writes the
+//! new positions back to the transforms of the nodes. Contacts push bodies apart and make them
+//! spin, but there are no joints.
+
+mod body;
+
+pub use body::*;
+
+use crate::{mesh::divide, scene::Handle, Scene, Storage, Transform};
+use nalgebra::{Isometry3, Matrix3, Point3, Translation3, UnitQuaternion, Vector3};
+use ncollide3d::{
+    bounding_volume::{BoundingVolume, AABB},
+    pipeline::narrow_phase::{ContactAlgorithm, ContactDispatcher, DefaultContactDispatcher},
+    query::{ContactManifold, ContactPrediction},
+    shape::ShapeHandle,
+};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+/// How far apart bodies at rest can be for their contacts to be found ahead of time. Moving bodies
+/// look further ahead, by as far as they move in a step, so that they're slowed before they sink
+/// in.
+const PREDICTION: f32 = 0.02;
+/// How deep bodies rest in each other. Resting contacts stay a little deep so that they don't
+/// come and go every step.
+const SLOP: f32 = 0.005;
+/// How much of the overlap beyond the slop is pushed out each step.
+const BAUMGARTE: f32 = 0.2;
+/// Bodies that meet slower than this, in meters per second, don't bounce.
+const BOUNCE_THRESHOLD: f32 = 0.5;
+
+/// Two bodies started or stopped touching. The body of the slot with the lower index comes first.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ContactEvent {
+    Started(Handle, Handle),
+    Stopped(Handle, Handle),
+}
+
+/// How the world steps.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PhysicsConfig {
+    pub gravity: Vector3<f32>,
+    /// The length of a step, in seconds.
+    pub timestep: f32,
+    /// The most steps taken by a call to `step`, so that a slow frame doesn't make the next one
+    /// slower still. Time left over beyond them is dropped.
+    pub max_steps: usize,
+    /// How many times the contacts are solved on each step. More makes stacks steadier.
+    pub iterations: usize,
+}
+
+impl Default for PhysicsConfig {
+    fn default() -> Self {
+        Self {
+            gravity: Vector3::new(0., -9.81, 0.),
+            timestep: 1. / 60.,
+            max_steps: 8,
+            iterations: 10,
+        }
+    }
+}
+
+/// The contacts of two bodies whose boxes overlap, along with the ncollide algorithm that
+/// finds them, kept from step to step.
+struct Pair {
+    algorithm: ContactAlgorithm<f32>,
+    manifold: ContactManifold<f32>,
+    touching: bool,
+}
+
+/// Steps the rigid bodies of a scene with a fixed timestep.
+pub struct PhysicsWorld {
+    config: PhysicsConfig,
+    /// The time that hasn't been stepped through yet.
+    accumulator: f32,
+    dispatcher: DefaultContactDispatcher,
+    pairs: HashMap<(Handle, Handle), Pair>,
+    events: Vec<ContactEvent>,
+}
+
+impl fmt::Debug for PhysicsWorld {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PhysicsWorld")
+            .field("config", &self.config)
+            .field("accumulator", &self.accumulator)
+            .field("pairs", &self.pairs.len())
+            .field("events", &self.events)
+            .finish()
+    }
+}
+
+impl Default for PhysicsWorld {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+/// A body's state while a step is solved.
+struct BodyState {
+    slot: usize,
+    handle: Handle,
+    body_type: BodyType,
+    shape: ShapeHandle<f32>,
+    position: Isometry3<f32>,
+    linear: Vector3<f32>,
+    angular: Vector3<f32>,
+    inverse_mass: f32,
+    /// In world space.
+    inverse_inertia: Matrix3<f32>,
+    restitution: f32,
+    friction: f32,
+}
+
+impl BodyState {
+    fn velocity_at(&self, r: &Vector3<f32>) -> Vector3<f32> {
+        self.linear + self.angular.cross(r)
+    }
+    fn apply_impulse(&mut self, impulse: &Vector3<f32>, r: &Vector3<f32>) {
+        self.linear += impulse * self.inverse_mass;
+        self.angular += self.inverse_inertia * r.cross(impulse);
+    }
+    /// How much an impulse along the direction at `r` changes the velocity there, inverted.
+    fn inverse_effective_mass(&self, r: &Vector3<f32>, direction: &Vector3<f32>) -> f32 {
+        let angular = (self.inverse_inertia * r.cross(direction)).cross(r);
+        self.inverse_mass + angular.dot(direction)
+    }
+}
+
+/// A contact point between bodies `a` and `b`, with the impulses solved for it so far.
+struct Constraint {
+    pair: (Handle, Handle),
+    a: usize,
+    b: usize,
+    /// From the contact point to the center of each body.
+    r_a: Vector3<f32>,
+    r_b: Vector3<f32>,
+    /// Points from `a` to `b`.
+    normal: Vector3<f32>,
+    tangents: [Vector3<f32>; 2],
+    normal_mass: f32,
+    tangent_masses: [f32; 2],
+    /// The speed the depth asks the bodies to part at along the normal.
+    bias: f32,
+    restitution: f32,
+    /// The speed the bodies should part at along the normal, bounces included.
+    target: f32,
+    friction: f32,
+    /// Whether the bodies overlap at the contact.
+    overlapping: bool,
+    normal_impulse: f32,
+    tangent_impulses: [f32; 2],
+}
+
+impl PhysicsWorld {
+    pub fn new(config: PhysicsConfig) -> Self {
+        Self {
+            config,
+            accumulator: 0.,
+            dispatcher: DefaultContactDispatcher::new(),
+            pairs: HashMap::new(),
+            events: Vec::new(),
+        }
+    }
+    pub fn config(&self) -> PhysicsConfig {
+        self.config
+    }
+    pub fn set_config(&mut self, config: PhysicsConfig) {
+        self.config = config;
+    }
+    /// Advances the bodies of the scene by the time elapsed since the last call, in seconds, in
+    /// as many whole steps as fit. Returns how many steps were taken.
+    pub fn step(&mut self, scene: &Scene, elapsed: f32) -> usize {
+        self.events.clear();
+        self.accumulator += elapsed;
+        let storage = scene.storage();
+        let mut storage = storage.borrow_mut();
+        let mut steps = 0;
+        while self.accumulator >= self.config.timestep {
+            if steps == self.config.max_steps {
+                self.accumulator %= self.config.timestep;
+                break;
+            }
+            self.step_once(&mut storage);
+            self.accumulator -= self.config.timestep;
+            steps += 1;
+        }
+        steps
+    }
+    /// The bodies that started or stopped touching during the last call to `step`.
+    pub fn events(&self) -> &[ContactEvent] {
+        &self.events
+    }
+    fn step_once(&mut self, storage: &mut Storage) {
+        let dt = self.config.timestep;
+        storage.update_world_transforms();
+        let mut bodies: Vec<BodyState> = (0..storage.meshes().len())
+            .filter(|i| storage.is_alive(*i))
+            .filter_map(|i| {
+                let body = storage.body(i)?;
+                let position = storage.world_transform(i).isometry;
+                let rotation = position.rotation.to_rotation_matrix();
+                let inverse_inertia = rotation.matrix()
+                    * Matrix3::from_diagonal(&body.inverse_inertia())
+                    * rotation.matrix().transpose();
+                Some(BodyState {
+                    slot: i,
+                    handle: storage.handle_at(i),
+                    body_type: body.body_type,
+                    shape: body.collider.handle().clone(),
+                    position,
+                    linear: body.linear_velocity,
+                    angular: body.angular_velocity,
+                    inverse_mass: body.inverse_mass(),
+                    inverse_inertia,
+                    restitution: body.restitution,
+                    friction: body.friction,
+                })
+            })
+            .collect();
+        for body in bodies.iter_mut() {
+            if body.body_type == BodyType::Dynamic {
+                body.linear += self.config.gravity * dt;
+            }
+        }
+
+        let mut constraints = self.find_contacts(&bodies);
+        for c in constraints.iter_mut() {
+            let (a, b) = (&bodies[c.a], &bodies[c.b]);
+            let relative = b.velocity_at(&c.r_b) - a.velocity_at(&c.r_a);
+            let approach = relative.dot(&c.normal);
+            // bodies bounce when they'd meet within the step, and not while still apart
+            let meets = approach < c.bias.min(-BOUNCE_THRESHOLD);
+            c.target = if meets && c.restitution > 0. {
+                c.bias.max(-c.restitution * approach)
+            } else {
+                c.bias
+            };
+            let (r_a, r_b) = (c.r_a, c.r_b);
+            // no impulse moves two bodies of zero inverse mass, so they're left a mass of zero
+            let mass = |direction: &Vector3<f32>| {
+                let inverse = a.inverse_effective_mass(&r_a, direction)
+                    + b.inverse_effective_mass(&r_b, direction);
+                if inverse > 0. && inverse.is_finite() {
+                    1. / inverse
+                } else {
+                    0.
+                }
+            };
+            c.normal_mass = mass(&c.normal);
+            c.tangent_masses = [mass(&c.tangents[0]), mass(&c.tangents[1])];
+        }
+        for _ in 0..self.config.iterations {
+            // contacts that neither body can be moved by are skipped
+            for c in constraints.iter_mut().filter(|c| c.normal_mass > 0.) {
+                Self::solve(&mut bodies, c);
+            }
+        }
+        self.update_touching(&constraints);
+
+        for body in bodies.iter_mut() {
+            if body.body_type == BodyType::Static {
+                continue;
+            }
+            let translation = body.position.translation.vector + body.linear * dt;
+            let rotation = UnitQuaternion::new(body.angular * dt) * body.position.rotation;
+            body.position = Isometry3::from_parts(Translation3::from(translation), rotation);
+            let parent = storage.parent_tranform(body.slot);
+            storage.mut_transform(body.slot).isometry = local_isometry(&parent, &body.position);
+            if let Some(rigid_body) = storage.mut_body(body.slot) {
+                rigid_body.linear_velocity = body.linear;
+                rigid_body.angular_velocity = body.angular;
+            }
+        }
+    }
+    /// Updates the contacts of the bodies whose boxes overlap, sends the events of pairs whose
+    /// boxes parted while touching, and returns the contacts to solve.
+    fn find_contacts(&mut self, bodies: &[BodyState]) -> Vec<Constraint> {
+        let dt = self.config.timestep;
+        let margins: Vec<f32> = bodies
+            .iter()
+            .map(|body| PREDICTION + body.linear.norm() * dt)
+            .collect();
+        let boxes: Vec<AABB<f32>> = bodies
+            .iter()
+            .zip(&margins)
+            .map(|(body, margin)| body.shape.aabb(&body.position).loosened(*margin))
+            .collect();
+        // sweeps along x over the boxes sorted by where they start
+        let mut order: Vec<usize> = (0..bodies.len()).collect();
+        order.sort_by(|a, b| {
+            let (a, b) = (boxes[*a].mins().x, boxes[*b].mins().x);
+            a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+        });
+        let mut overlapping = Vec::new();
+        for (n, a) in order.iter().enumerate() {
+            for b in order[n + 1..].iter() {
+                if boxes[*b].mins().x > boxes[*a].maxs().x {
+                    break;
+                }
+                let either_dynamic = bodies[*a].body_type == BodyType::Dynamic
+                    || bodies[*b].body_type == BodyType::Dynamic;
+                if either_dynamic && boxes[*a].intersects(&boxes[*b]) {
+                    let (a, b) = if bodies[*a].slot < bodies[*b].slot {
+                        (*a, *b)
+                    } else {
+                        (*b, *a)
+                    };
+                    overlapping.push((a, b));
+                }
+            }
+        }
+        overlapping.sort_unstable_by_key(|(a, b)| (bodies[*a].slot, bodies[*b].slot));
+
+        let mut pairs = HashMap::new();
+        let mut constraints = Vec::new();
+        for (a, b) in overlapping {
+            let (body_a, body_b) = (&bodies[a], &bodies[b]);
+            let prediction = ContactPrediction::new(margins[a] + margins[b], 0., 0.);
+            let key = (body_a.handle, body_b.handle);
+            let pair = match self.pairs.remove(&key) {
+                Some(pair) => pair,
+                None => {
+                    let algorithm = match self
+                        .dispatcher
+                        .get_contact_algorithm(&*body_a.shape, &*body_b.shape)
+                    {
+                        Some(algorithm) => algorithm,
+                        None => continue,
+                    };
+                    let manifold = algorithm.init_manifold();
+                    Pair {
+                        algorithm,
+                        manifold,
+                        touching: false,
+                    }
+                }
+            };
+            let Pair {
+                mut algorithm,
+                mut manifold,
+                touching,
+            } = pair;
+            manifold.save_cache_and_clear();
+            algorithm.generate_contacts(
+                &self.dispatcher,
+                &body_a.position,
+                &*body_a.shape,
+                None,
+                &body_b.position,
+                &*body_b.shape,
+                None,
+                &prediction,
+                &mut manifold,
+            );
+            let restitution = body_a.restitution.max(body_b.restitution);
+            let friction = (body_a.friction * body_b.friction).sqrt();
+            for tracked in manifold.contacts() {
+                let contact = &tracked.contact;
+                let point = nalgebra::center(&contact.world1, &contact.world2);
+                let normal = contact.normal.into_inner();
+                constraints.push(Constraint {
+                    pair: key,
+                    a,
+                    b,
+                    r_a: point - Point3::from(body_a.position.translation.vector),
+                    r_b: point - Point3::from(body_b.position.translation.vector),
+                    normal,
+                    tangents: tangents(&normal),
+                    normal_mass: 0.,
+                    tangent_masses: [0.; 2],
+                    bias: bias(contact.depth, dt),
+                    restitution,
+                    target: 0.,
+                    friction,
+                    overlapping: contact.depth >= 0.,
+                    normal_impulse: 0.,
+                    tangent_impulses: [0.; 2],
+                });
+            }
+            pairs.insert(
+                key,
+                Pair {
+                    algorithm,
+                    manifold,
+                    touching,
+                },
+            );
+        }
+        // the pairs left were touching until their boxes parted or one of them was removed
+        let mut parted: Vec<(Handle, Handle)> = self
+            .pairs
+            .drain()
+            .filter(|(_, pair)| pair.touching)
+            .map(|(key, _)| key)
+            .collect();
+        parted.sort_unstable_by_key(|(a, b)| (a.index(), b.index()));
+        self.events
+            .extend(parted.into_iter().map(|(a, b)| ContactEvent::Stopped(a, b)));
+        self.pairs = pairs;
+        constraints
+    }
+    /// Sends the events of the pairs that started or stopped touching. Bodies touch when they
+    /// overlap or push each other apart, so that a bounce that's solved before they meet counts.
+    fn update_touching(&mut self, constraints: &[Constraint]) {
+        let touching: HashSet<(Handle, Handle)> = constraints
+            .iter()
+            .filter(|c| c.overlapping || c.normal_impulse > 0.)
+            .map(|c| c.pair)
+            .collect();
+        let mut changed: Vec<ContactEvent> = self
+            .pairs
+            .iter_mut()
+            .filter_map(|(key, pair)| {
+                let now_touching = touching.contains(key);
+                let event = match (pair.touching, now_touching) {
+                    (false, true) => ContactEvent::Started(key.0, key.1),
+                    (true, false) => ContactEvent::Stopped(key.0, key.1),
+                    _ => return None,
+                };
+                pair.touching = now_touching;
+                Some(event)
+            })
+            .collect();
+        changed.sort_unstable_by_key(|event| match event {
+            ContactEvent::Started(a, b) | ContactEvent::Stopped(a, b) => (a.index(), b.index()),
+        });
+        self.events.extend(changed);
+    }
+    /// Applies the impulses that bring a contact closer to its target speed along the normal,
+    /// and to rest along the surface as far as friction allows.
+    fn solve(bodies: &mut [BodyState], c: &mut Constraint) {
+        let (a, b, r_a, r_b) = (c.a, c.b, c.r_a, c.r_b);
+        let relative =
+            |bodies: &[BodyState]| bodies[b].velocity_at(&r_b) - bodies[a].velocity_at(&r_a);
+        let apply = |bodies: &mut [BodyState], impulse: Vector3<f32>| {
+            bodies[a].apply_impulse(&-impulse, &r_a);
+            bodies[b].apply_impulse(&impulse, &r_b);
+        };
+
+        // the bodies can only be pushed apart
+        let speed = relative(bodies).dot(&c.normal);
+        let total = (c.normal_impulse + (c.target - speed) * c.normal_mass).max(0.);
+        let impulse = total - c.normal_impulse;
+        c.normal_impulse = total;
+        apply(bodies, c.normal * impulse);
+
+        let limit = c.friction * c.normal_impulse;
+        for t in 0..2 {
+            let speed = relative(bodies).dot(&c.tangents[t]);
+            let total = (c.tangent_impulses[t] - speed * c.tangent_masses[t])
+                .max(-limit)
+                .min(limit);
+            let impulse = total - c.tangent_impulses[t];
+            c.tangent_impulses[t] = total;
+            apply(bodies, c.tangents[t] * impulse);
+        }
+    }
+}
+
+/// The speed along the normal that a contact of the given depth is solved to: bodies are let sink
+/// in as deep as the slop but no more, and pushed out bit by bit beyond it.
+fn bias(depth: f32, dt: f32) -> f32 {
+    if depth < SLOP {
+        (depth - SLOP) / dt
+    } else {
+        BAUMGARTE * (depth - SLOP) / dt
+    }
+}
+
+/// Two directions along the surface of a contact.
+fn tangents(normal: &Vector3<f32>) -> [Vector3<f32>; 2] {
+    let other = if normal.x.abs() < 0.6 {
+        Vector3::x()
+    } else {
+        Vector3::y()
+    };
+    let first = normal.cross(&other).normalize();
+    [first, normal.cross(&first)]
+}
+
+/// The local isometry that puts a node at the world isometry under its parent.
+fn local_isometry(parent: &Transform, world: &Isometry3<f32>) -> Isometry3<f32> {
+    let rotation = parent.isometry.rotation.inverse();
+    let shift = divide(
+        world.translation.vector - parent.isometry.translation.vector,
+        parent.scale,
+    );
+    Isometry3::from_parts(
+        Translation3::from(rotation * shift),
+        rotation * world.rotation,
+    )
+}
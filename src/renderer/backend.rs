@@ -0,0 +1,177 @@
+use crate::TextureType;
+use std::fmt::Debug;
+
+/// An opaque reference to a buffer created by a Backend.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BufferId(pub u32);
+
+/// An opaque reference to a vertex array object created by a Backend.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct VaoId(pub u32);
+
+/// An opaque reference to a texture created by a Backend.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TextureId(pub u32);
+
+/// An opaque reference to a framebuffer created by a Backend.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FramebufferId(pub u32);
+
+/// An opaque reference to a renderbuffer created by a Backend.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RenderbufferId(pub u32);
+
+/// An opaque reference to a linked shader program created by a Backend.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ProgramId(pub u32);
+
+/// A value written to a shader uniform.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Uniform {
+    Bool(bool),
+    U32(u32),
+    I32(i32),
+    F32(f32),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
+    /// A column major 4x4 matrix.
+    Mat4([f32; 16]),
+}
+
+/// Everything the renderer needs from a graphics API. The calls mirror WebGL 2 and take the
+/// enum values in `renderer::gl`, but GPU objects are handed out as opaque ids so that a backend
+/// doesn't have to be backed by a GPU at all.
+pub trait Backend: Debug {
+    fn create_program(&self, vertex: &str, fragment: &str) -> Result<ProgramId, String>;
+    fn use_program(&self, program: Option<ProgramId>);
+    /// The location of a vertex attribute, or -1 if the program doesn't use it.
+    fn attrib_location(&self, program: ProgramId, name: &str) -> i32;
+    /// Writes a uniform of the program that is in use. Returns false if the program has no
+    /// uniform with that name.
+    fn set_uniform(&self, program: ProgramId, name: &str, value: Uniform) -> bool;
+
+    fn create_buffer(&self) -> BufferId;
+    fn bind_buffer(&self, target: u32, buffer: Option<BufferId>);
+    fn buffer_data_f32(&self, target: u32, data: &[f32], usage: u32);
+    fn buffer_data_u16(&self, target: u32, data: &[u16], usage: u32);
+    fn buffer_data_u32(&self, target: u32, data: &[u32], usage: u32);
+    fn delete_buffer(&self, buffer: BufferId);
+
+    fn create_vertex_array(&self) -> VaoId;
+    fn bind_vertex_array(&self, vao: Option<VaoId>);
+    fn delete_vertex_array(&self, vao: VaoId);
+    fn vertex_attrib_pointer(
+        &self,
+        location: u32,
+        size: i32,
+        kind: u32,
+        normalized: bool,
+        stride: i32,
+        offset: i32,
+    );
+    fn enable_vertex_attrib_array(&self, location: u32);
+    fn vertex_attrib_divisor(&self, location: u32, divisor: u32);
+
+    /// Creates a texture and starts loading the images at the urls into it, six of them for a
+    /// cubemap. The texture can be used right away and is filled in once the images arrive.
+    fn create_texture(&self, urls: &[String], tex_type: TextureType, is_img_obj: bool)
+        -> TextureId;
+    /// Whether every image of a texture created from urls has arrived.
+    fn texture_ready(&self, texture: TextureId) -> bool;
+    /// Creates an empty texture with storage for the given number of mip levels, on each of the
+    /// six faces for a cubemap target. Mirrors texStorage2D.
+    fn create_texture_storage(
+        &self,
+        target: u32,
+        levels: i32,
+        internal_format: u32,
+        width: i32,
+        height: i32,
+    ) -> TextureId;
+    /// Uploads floats to a level of the texture bound to the target, or to a face of the bound
+    /// cubemap.
+    fn tex_sub_image_f32(
+        &self,
+        target: u32,
+        level: i32,
+        width: i32,
+        height: i32,
+        format: u32,
+        data: &[f32],
+    );
+    fn tex_parameter(&self, target: u32, name: u32, value: u32);
+    fn generate_mipmap(&self, target: u32);
+    fn active_texture(&self, unit: u32);
+    fn bind_texture(&self, target: u32, texture: Option<TextureId>);
+    fn delete_texture(&self, texture: TextureId);
+
+    fn create_framebuffer(&self) -> FramebufferId;
+    fn bind_framebuffer(&self, target: u32, framebuffer: Option<FramebufferId>);
+    /// Attaches a mip level of a texture, or of one face of a cubemap, to the bound framebuffer.
+    fn framebuffer_texture_2d(
+        &self,
+        target: u32,
+        attachment: u32,
+        tex_target: u32,
+        texture: Option<TextureId>,
+        level: i32,
+    );
+    /// The color attachments of the bound framebuffer that fragment shader outputs go to, in
+    /// the order of their locations. Mirrors drawBuffers.
+    fn draw_buffers(&self, buffers: &[u32]);
+    fn delete_framebuffer(&self, framebuffer: FramebufferId);
+    /// Creates a renderbuffer with storage for the given number of samples per pixel. Mirrors
+    /// renderbufferStorageMultisample.
+    fn create_renderbuffer(
+        &self,
+        samples: i32,
+        internal_format: u32,
+        width: i32,
+        height: i32,
+    ) -> RenderbufferId;
+    /// Attaches a renderbuffer to the bound framebuffer.
+    fn framebuffer_renderbuffer(
+        &self,
+        target: u32,
+        attachment: u32,
+        renderbuffer: Option<RenderbufferId>,
+    );
+    fn delete_renderbuffer(&self, renderbuffer: RenderbufferId);
+    /// Copies a rectangle of the READ_FRAMEBUFFER into one of the DRAW_FRAMEBUFFER, resolving
+    /// the samples of a multisampled one. Rectangles are given as x0, y0, x1, y1. Mirrors
+    /// blitFramebuffer.
+    fn blit_framebuffer(&self, src: [i32; 4], dst: [i32; 4], mask: u32, filter: u32);
+
+    fn enable(&self, capability: u32);
+    fn disable(&self, capability: u32);
+    fn viewport(&self, x: i32, y: i32, width: i32, height: i32);
+    fn clear_color(&self, r: f32, g: f32, b: f32, a: f32);
+    fn clear_depth(&self, depth: f32);
+    fn clear(&self, mask: u32);
+    /// Clears a color attachment of an integer format, which `clear` can't. Mirrors
+    /// clearBufferuiv.
+    fn clear_buffer_u32(&self, buffer: u32, draw_buffer: i32, value: [u32; 4]);
+    fn depth_func(&self, func: u32);
+    fn front_face(&self, mode: u32);
+    fn cull_face(&self, mode: u32);
+    fn blend_func(&self, src: u32, dst: u32);
+    fn stencil_op(&self, fail: u32, zfail: u32, zpass: u32);
+    fn stencil_func(&self, func: u32, reference: i32, mask: u32);
+    fn stencil_mask(&self, mask: u32);
+    /// Reads back a rectangle of the READ_FRAMEBUFFER, whose color attachment has an unsigned
+    /// integer format, as RGBA_INTEGER values row by row from the bottom. Mirrors readPixels,
+    /// and stalls until the GPU has drawn them.
+    fn read_pixels_u32(&self, x: i32, y: i32, width: i32, height: i32) -> Vec<u32>;
+
+    fn draw_arrays(&self, mode: u32, first: i32, count: i32);
+    fn draw_elements(&self, mode: u32, count: i32, index_type: u32, offset: i32);
+    fn draw_arrays_instanced(&self, mode: u32, first: i32, count: i32, instances: i32);
+    fn draw_elements_instanced(
+        &self,
+        mode: u32,
+        count: i32,
+        index_type: u32,
+        offset: i32,
+        instances: i32,
+    );
+}
@@ -0,0 +1,78 @@
+//! Frustum culling: meshes whose bounds are wholly outside the view of the viewport aren't drawn.
+//! The meshes in view are found through the spatial index of Storage.
+
+use super::{Renderer, ShaderType};
+use crate::{controller::Viewport, mesh::Bounds, Storage};
+use nalgebra::{Matrix4, Vector3, Vector4};
+use ncollide3d::bounding_volume::AABB;
+
+/// The six planes of a view frustum, with their normals pointing in.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// The frustum of a view projection matrix, `proj * view`. Its planes are taken from the
+    /// rows of the matrix: a point is in view when each of x, y and z in clip space are within
+    /// -w and w.
+    pub fn new(view_proj: &Matrix4<f32>) -> Self {
+        let row = |r: usize| view_proj.row(r).transpose();
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+        let planes = [w + x, w - x, w + y, w - y, w + z, w - z].map(|p| p / p.xyz().norm());
+        Self { planes }
+    }
+    /// Whether some of the bounds may be in view. The sphere is tried first, and the box only
+    /// when the sphere is in view.
+    pub fn intersects(&self, bounds: &Bounds) -> bool {
+        let center = bounds.sphere.center().coords;
+        let radius = bounds.sphere.radius();
+        self.planes
+            .iter()
+            .all(|plane| plane.xyz().dot(&center) + plane.w >= -radius)
+            && self.intersects_aabb(&bounds.aabb)
+    }
+    /// Whether some of the box may be in view.
+    pub fn intersects_aabb(&self, aabb: &AABB<f32>) -> bool {
+        let (mins, maxs) = (aabb.mins(), aabb.maxs());
+        self.planes.iter().all(|plane| {
+            let normal = plane.xyz();
+            // the corner of the box that's farthest along the normal
+            let corner = Vector3::from_fn(|axis, _| {
+                if normal[axis] >= 0. {
+                    maxs[axis]
+                } else {
+                    mins[axis]
+                }
+            });
+            normal.dot(&corner) + plane.w >= 0.
+        })
+    }
+}
+
+impl Renderer {
+    /// How many meshes the last `render` left out for being outside the view.
+    pub fn culled(&self) -> usize {
+        self.culled.get()
+    }
+    /// Whether each slot is drawn and outside the view of the viewport, as found by the spatial
+    /// index. Skyboxes surround the view and are never culled.
+    pub(super) fn frustum_culled(&self, storage: &Storage, viewport: &Viewport) -> Vec<bool> {
+        let frustum = Frustum::new(&(viewport.proj() * viewport.view()));
+        let index = storage.spatial_index();
+        let mut culled: Vec<bool> = (0..storage.meshes().len())
+            .map(|i| {
+                storage.is_alive(i)
+                    && index.contains(i)
+                    && storage.info(i).render_flags.render
+                    && storage
+                        .mesh(i)
+                        .is_some_and(|mesh| mesh.material.shader_type != ShaderType::CubeMap)
+            })
+            .collect();
+        for i in index.query_frustum(&frustum) {
+            culled[i] = false;
+        }
+        culled
+    }
+}
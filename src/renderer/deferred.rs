@@ -0,0 +1,571 @@
+//! Deferred shading: opaque lit meshes are first drawn into a G-buffer, which holds everything
+//! the lights need to know about the surface at each pixel, and the G-buffer is lit afterwards.
+//! Point lights only light the pixels inside a sphere around them, their light volume, so a
+//! scene can have hundreds of them.
+
+use super::{
+    bind_buffer_and_attribute, bind_index_buffer, gl as GL, gl_index_type, set_bool, set_f32,
+    set_i32, set_mat4, set_vec3, set_vec4, Backend, BufferId, DrawMode, FramebufferId, ProgramId,
+    Renderer, Resource, ShaderType, Shadows, TextureId, VaoId, LIGHT_LINEAR, LIGHT_QUADRATIC,
+};
+use crate::{controller::Viewport, Geometry, LightType, Storage};
+use genmesh::generators::IcoSphere;
+use nalgebra::Vector3;
+
+/// How the renderer lights the meshes drawn with the Color and Pbr shaders.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Pipeline {
+    /// Every mesh is lit as it's drawn, by at most 20 lights of each type.
+    #[default]
+    Forward,
+    /// Opaque meshes are drawn into a G-buffer and lit afterwards, by any number of point
+    /// lights. Transparent meshes, outlined meshes and editor overlays are still drawn forward.
+    Deferred,
+}
+
+/// The texture unit of the G-buffer depth. The other G-buffer textures take the units before
+/// the environment maps.
+const DEPTH_UNIT: u32 = 8;
+/// Floats per point light in the light volume instance buffer: a position and radius, followed
+/// by a color and intensity.
+const VOLUME_STRIDE: usize = 8;
+/// The faintest light that shows on the canvas, where light volumes end.
+const MIN_LIGHT: f32 = 1. / 256.;
+
+/// The textures the deferred passes draw into, all the size of the canvas.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(super) struct GBuffer {
+    framebuffer: FramebufferId,
+    /// Draws into the light texture.
+    light_framebuffer: FramebufferId,
+    /// The albedo, and whether the surface has a pbr material.
+    albedo: TextureId,
+    /// The world space normal, and whether the surface receives shadows.
+    normal: TextureId,
+    /// Metallic, roughness and occlusion.
+    material: TextureId,
+    emissive: TextureId,
+    depth: TextureId,
+    /// The light reflected by every pixel, summed over the lights.
+    light: TextureId,
+    width: u32,
+    height: u32,
+}
+
+impl GBuffer {
+    /// The textures that describe the surface, with the sampler and texture unit of each.
+    fn surface(&self) -> [(&'static str, u32, TextureId); 4] {
+        [
+            ("albedo_buffer", 0, self.albedo),
+            ("normal_buffer", 1, self.normal),
+            ("material_buffer", 2, self.material),
+            ("depth_buffer", DEPTH_UNIT, self.depth),
+        ]
+    }
+}
+
+/// The sphere drawn around each point light, once per light.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(super) struct LightVolumes {
+    vao: VaoId,
+    buffers: [BufferId; 3],
+    /// The position, radius, color and intensity of every point light.
+    instance_buffer: BufferId,
+    count: i32,
+    index_type: u32,
+}
+
+/// The programs of the deferred passes.
+#[derive(Debug)]
+pub(super) struct DeferredPrograms {
+    /// Draw meshes into the G-buffer, one for each lit shader.
+    color: ProgramId,
+    pbr: ProgramId,
+    /// Lights the G-buffer with the ambient, directional and spot lights and the environment.
+    lighting: ProgramId,
+    light_volumes: ProgramId,
+    /// Copies the lit G-buffer to the canvas.
+    resolve: ProgramId,
+}
+
+impl DeferredPrograms {
+    pub(super) const COUNT: usize = 5;
+
+    pub(super) fn new(gl: &dyn Backend, color: ProgramId, pbr: ProgramId) -> Self {
+        let lighting = include_str!("shaders/deferred.frag");
+        let light_volumes = lighting.replacen(
+            "#version 300 es",
+            "#version 300 es\n#define LIGHT_VOLUMES",
+            1,
+        );
+        Self {
+            color: Self::create_gbuffer(gl, color),
+            pbr: Self::create_gbuffer(gl, pbr),
+            lighting: gl
+                .create_program(include_str!("shaders/fullscreen.vert"), lighting)
+                .expect("Can't create deferred lighting shader!"),
+            light_volumes: gl
+                .create_program(include_str!("shaders/light_volume.vert"), &light_volumes)
+                .expect("Can't create light volume shader!"),
+            resolve: gl
+                .create_program(
+                    include_str!("shaders/fullscreen.vert"),
+                    include_str!("shaders/resolve.frag"),
+                )
+                .expect("Can't create resolve shader!"),
+        }
+    }
+    /// A mesh's vao is set up for the attribute locations of its own shader, so the G-buffer
+    /// program takes the same locations. Attributes the lit shader doesn't have get locations
+    /// after all of its own.
+    fn create_gbuffer(gl: &dyn Backend, lit: ProgramId) -> ProgramId {
+        let attributes = [
+            ("position", 1),
+            ("normal", 1),
+            ("tex_coords", 1),
+            ("instance_model", 4),
+            ("instance_color", 1),
+        ];
+        let locations: Vec<i32> = attributes
+            .iter()
+            .map(|(name, _)| gl.attrib_location(lit, name))
+            .collect();
+        let mut free = attributes
+            .iter()
+            .zip(locations.iter())
+            .filter(|(_, &location)| location >= 0)
+            .map(|((_, size), &location)| location + size)
+            .max()
+            .unwrap_or(0);
+        let mut vertex = include_str!("shaders/gbuffer.vert").to_string();
+        for ((name, size), &location) in attributes.iter().zip(locations.iter()) {
+            let location = if location < 0 {
+                free += size;
+                free - size
+            } else {
+                location
+            };
+            vertex = vertex.replace(
+                &format!("{}_LOCATION", name.to_uppercase()),
+                &location.to_string(),
+            );
+        }
+        gl.create_program(&vertex, include_str!("shaders/gbuffer.frag"))
+            .expect("Can't create G-buffer shader!")
+    }
+    fn gbuffer(&self, shader_type: ShaderType) -> ProgramId {
+        match shader_type {
+            ShaderType::Pbr => self.pbr,
+            _ => self.color,
+        }
+    }
+}
+
+/// Whether a mesh is drawn into the G-buffer. Only opaque meshes with a lit shader are, and
+/// not the ones with a wireframe overlay or an outline.
+pub(super) fn is_deferred(storage: &Storage, i: usize) -> bool {
+    let mesh = match storage.mesh(i) {
+        Some(mesh) => mesh,
+        None => return false,
+    };
+    let info = storage.info(i);
+    let flags = info.render_flags;
+    let material = &mesh.material;
+    (material.shader_type == ShaderType::Color || material.shader_type == ShaderType::Pbr)
+        && flags.render
+        && flags.depth
+        && !flags.blend
+        && material.wire_overlay.is_none()
+        && material.outline.is_none()
+        && info.draw_mode != DrawMode::Points
+        && info.draw_mode != DrawMode::Lines
+}
+
+/// How far a point light of the given brightness reaches before its light fades below
+/// MIN_LIGHT, or None if it's never that bright.
+fn light_volume_radius(brightness: f32) -> Option<f32> {
+    // solves 1 / (1 + linear * d + quadratic * d²) = MIN_LIGHT / brightness
+    let c = 1. - brightness / MIN_LIGHT;
+    if c >= 0. {
+        return None;
+    }
+    let discriminant = LIGHT_LINEAR * LIGHT_LINEAR - 4. * LIGHT_QUADRATIC * c;
+    Some((-LIGHT_LINEAR + discriminant.sqrt()) / (2. * LIGHT_QUADRATIC))
+}
+
+/// The light volume instance data of every point light that is on.
+fn point_lights(storage: &Storage) -> Vec<f32> {
+    let mut data = Vec::new();
+    for id in storage.light_ids() {
+        let light = storage.light(id);
+        if !light.light || light.light_type != LightType::Point || !storage.is_valid(light.node_id)
+        {
+            continue;
+        }
+        let brightness = light.color.iter().cloned().fold(0., f32::max) * light.intensity;
+        let radius = match light_volume_radius(brightness) {
+            Some(radius) => radius,
+            None => continue,
+        };
+        let position = storage
+            .world_transform(light.node_id.index())
+            .isometry
+            .translation
+            .vector;
+        data.extend_from_slice(&[position.x, position.y, position.z, radius]);
+        data.extend_from_slice(&light.color);
+        data.push(light.intensity);
+    }
+    data
+}
+
+impl Renderer {
+    pub fn pipeline(&self) -> Pipeline {
+        self.config.pipeline
+    }
+    /// Switches between forward and deferred shading. The G-buffer is released when going back
+    /// to forward shading.
+    pub fn set_pipeline(&mut self, pipeline: Pipeline) {
+        self.config.pipeline = pipeline;
+        if pipeline == Pipeline::Forward {
+            if let Some(gbuffer) = self.gbuffer.take() {
+                self.delete_gbuffer(gbuffer);
+            }
+            if let Some(volumes) = self.light_volumes.take() {
+                let gl = &*self.backend;
+                for buffer in volumes.buffers.iter() {
+                    gl.delete_buffer(*buffer);
+                }
+                gl.delete_vertex_array(volumes.vao);
+                self.resources
+                    .deleted(Resource::Buffer, volumes.buffers.len());
+                self.resources.deleted(Resource::Vao, 1);
+            }
+        }
+    }
+    /// Draws the deferred meshes that aren't culled into the G-buffer and lights them. The lit
+    /// G-buffer is copied to the canvas or the HDR target by `resolve_deferred`.
+    pub(super) fn render_deferred(
+        &self,
+        storage: &Storage,
+        viewport: &Viewport,
+        shadows: &Shadows,
+        culled: &[bool],
+    ) {
+        let gl = &*self.backend;
+        let gbuffer = self.gbuffer();
+        gl.bind_framebuffer(GL::FRAMEBUFFER, Some(gbuffer.framebuffer));
+        gl.disable(GL::BLEND);
+        gl.disable(GL::SAMPLE_ALPHA_TO_COVERAGE);
+        gl.enable(GL::DEPTH_TEST);
+        // pixels are only read where a mesh was drawn, so only the depth needs clearing
+        gl.clear(GL::DEPTH_BUFFER_BIT);
+        for (shader_type, pbr) in [(ShaderType::Color, false), (ShaderType::Pbr, true)].iter() {
+            let program = self.deferred_programs.gbuffer(*shader_type);
+            gl.use_program(Some(program));
+            set_mat4(gl, program, "view", &viewport.view());
+            set_mat4(gl, program, "proj", &viewport.proj());
+            set_bool(gl, program, "pbr", *pbr);
+        }
+        for (i, culled) in culled.iter().enumerate() {
+            if storage.is_alive(i) && !culled && is_deferred(storage, i) {
+                self.render_gbuffer_mesh(storage, i);
+            }
+        }
+
+        gl.bind_framebuffer(GL::FRAMEBUFFER, Some(gbuffer.light_framebuffer));
+        gl.bind_vertex_array(None);
+        for capability in [GL::DEPTH_TEST, GL::STENCIL_TEST, GL::CULL_FACE].iter() {
+            gl.disable(*capability);
+        }
+        let inv_view_proj = (viewport.proj() * viewport.view())
+            .try_inverse()
+            .unwrap_or_else(nalgebra::Matrix4::identity);
+        let program = self.deferred_programs.lighting;
+        gl.use_program(Some(program));
+        self.bind_buffers(program, &gbuffer.surface());
+        self.bind_buffers(program, &[("emissive_buffer", 3, gbuffer.emissive)]);
+        set_mat4(gl, program, "inv_view_proj", &inv_view_proj);
+        set_mat4(gl, program, "view", &viewport.view());
+        set_vec3(gl, program, "eye", &viewport.eye());
+        set_bool(gl, program, "linear_output", self.config.hdr);
+        self.set_lights(storage, shadows, program, true, false);
+        self.set_environment_uniforms(program);
+        gl.draw_arrays(GL::TRIANGLES, 0, 3);
+
+        let lights = point_lights(storage);
+        if !lights.is_empty() {
+            let volumes = self.light_volumes();
+            let program = self.deferred_programs.light_volumes;
+            gl.use_program(Some(program));
+            self.bind_buffers(program, &gbuffer.surface());
+            set_mat4(gl, program, "inv_view_proj", &inv_view_proj);
+            set_mat4(gl, program, "view", &viewport.view());
+            set_mat4(gl, program, "proj", &viewport.proj());
+            set_vec3(gl, program, "eye", &viewport.eye());
+            set_f32(gl, program, "linear", LIGHT_LINEAR);
+            set_f32(gl, program, "quadratic", LIGHT_QUADRATIC);
+            set_bool(gl, program, "linear_output", self.config.hdr);
+            gl.bind_buffer(GL::ARRAY_BUFFER, Some(volumes.instance_buffer));
+            gl.buffer_data_f32(GL::ARRAY_BUFFER, &lights, GL::DYNAMIC_DRAW);
+            gl.bind_buffer(GL::ARRAY_BUFFER, None);
+            // the lights add up, and drawing the back faces keeps the volumes around the eye
+            gl.enable(GL::BLEND);
+            gl.blend_func(GL::ONE, GL::ONE);
+            gl.enable(GL::CULL_FACE);
+            gl.cull_face(GL::FRONT);
+            gl.bind_vertex_array(Some(volumes.vao));
+            gl.draw_elements_instanced(
+                GL::TRIANGLES,
+                volumes.count,
+                volumes.index_type,
+                0,
+                (lights.len() / VOLUME_STRIDE) as i32,
+            );
+            gl.bind_vertex_array(None);
+            gl.cull_face(self.render_config.cull_face);
+            gl.blend_func(GL::SRC_ALPHA, GL::ONE_MINUS_SRC_ALPHA);
+            gl.disable(GL::BLEND);
+        }
+        gl.bind_framebuffer(GL::FRAMEBUFFER, None);
+    }
+    /// Copies the lit G-buffer and its depth to the bound framebuffer, so that the meshes drawn forward
+    /// afterwards are hidden behind it.
+    pub(super) fn resolve_deferred(&self) {
+        let gbuffer = match self.gbuffer.get() {
+            Some(gbuffer) => gbuffer,
+            None => return,
+        };
+        let gl = &*self.backend;
+        let program = self.deferred_programs.resolve;
+        gl.use_program(Some(program));
+        gl.bind_vertex_array(None);
+        let buffers = [
+            ("light_buffer", 0, gbuffer.light),
+            ("albedo_buffer", 1, gbuffer.albedo),
+            ("depth_buffer", DEPTH_UNIT, gbuffer.depth),
+        ];
+        self.bind_buffers(program, &buffers);
+        set_bool(gl, program, "linear_output", self.config.hdr);
+        for capability in [GL::STENCIL_TEST, GL::CULL_FACE, GL::BLEND].iter() {
+            gl.disable(*capability);
+        }
+        // depth is only written with the depth test on
+        gl.enable(GL::DEPTH_TEST);
+        gl.depth_func(GL::ALWAYS);
+        gl.draw_arrays(GL::TRIANGLES, 0, 3);
+        gl.depth_func(self.render_config.depth_fn);
+        // WebGL refuses to draw into a texture that is bound to a unit, which the G-buffer
+        // would be during the next frame
+        for unit in [0, 1, 2, 3, DEPTH_UNIT].iter() {
+            gl.active_texture(GL::TEXTURE0 + unit);
+            gl.bind_texture(GL::TEXTURE_2D, None);
+        }
+    }
+    /// Draws the surface of a mesh into the G-buffer.
+    fn render_gbuffer_mesh(&self, storage: &Storage, i: usize) {
+        let mesh = match storage.mesh(i) {
+            Some(mesh) => mesh,
+            None => return,
+        };
+        let gl = &*self.backend;
+        let info = storage.info(i);
+        let material = &mesh.material;
+        let instances = storage.instances(i).map(|n| n.transforms.len() as i32);
+        let program = self.deferred_programs.gbuffer(material.shader_type);
+        gl.bind_vertex_array(storage.vao(i));
+        gl.use_program(Some(program));
+        set_mat4(
+            gl,
+            program,
+            "model",
+            &storage.world_transform(i).to_homogeneous(),
+        );
+        set_bool(gl, program, "instanced", instances.is_some());
+        set_bool(
+            gl,
+            program,
+            "receive_shadows",
+            info.render_flags.receive_shadows,
+        );
+        set_vec4(
+            gl,
+            program,
+            "color",
+            &material
+                .color
+                .expect("Can't render a color materaial without a color!"),
+        );
+        if material.shader_type == ShaderType::Pbr {
+            self.set_pbr_material(storage, program, material);
+        } else {
+            set_bool(gl, program, "flat_shade", material.flat_shade);
+            let albedo = self.albedo_texture(storage, material);
+            set_bool(gl, program, "has_albedo", albedo.is_some());
+            if let Some(texture) = albedo {
+                gl.active_texture(GL::TEXTURE0);
+                gl.bind_texture(GL::TEXTURE_2D, Some(texture));
+                set_i32(gl, program, "sampler", 0);
+            }
+        }
+        Self::set_flags(gl, info.render_flags);
+        Self::draw(
+            gl,
+            info.draw_mode,
+            mesh.geometry.indices.len() as i32,
+            gl_index_type(mesh.geometry.index_type()),
+            instances,
+        );
+    }
+    /// Binds G-buffer textures to the texture units of their samplers.
+    fn bind_buffers(&self, program: ProgramId, buffers: &[(&str, u32, TextureId)]) {
+        let gl = &*self.backend;
+        for &(sampler, unit, texture) in buffers {
+            gl.active_texture(GL::TEXTURE0 + unit);
+            gl.bind_texture(GL::TEXTURE_2D, Some(texture));
+            set_i32(gl, program, sampler, unit as i32);
+        }
+    }
+    /// The G-buffer, created again whenever the size of the canvas changes.
+    fn gbuffer(&self) -> GBuffer {
+        if let Some(gbuffer) = self.gbuffer.get() {
+            if (gbuffer.width, gbuffer.height) == (self.width, self.height) {
+                return gbuffer;
+            }
+            self.delete_gbuffer(gbuffer);
+        }
+        let gl = &*self.backend;
+        // G-buffer pixels are read one by one, and depth textures can't be filtered anyway
+        let texture = |internal_format: u32| {
+            let texture = self.create_map(
+                GL::TEXTURE_2D,
+                1,
+                internal_format,
+                self.width as usize,
+                self.height as usize,
+            );
+            gl.bind_texture(GL::TEXTURE_2D, Some(texture));
+            gl.tex_parameter(GL::TEXTURE_2D, GL::TEXTURE_MIN_FILTER, GL::NEAREST);
+            gl.tex_parameter(GL::TEXTURE_2D, GL::TEXTURE_MAG_FILTER, GL::NEAREST);
+            gl.bind_texture(GL::TEXTURE_2D, None);
+            texture
+        };
+        let gbuffer = GBuffer {
+            framebuffer: gl.create_framebuffer(),
+            light_framebuffer: gl.create_framebuffer(),
+            albedo: texture(GL::RGBA8),
+            normal: texture(GL::RGBA16F),
+            material: texture(GL::RGBA8),
+            emissive: texture(GL::RGBA16F),
+            depth: texture(GL::DEPTH_COMPONENT24),
+            light: texture(GL::RGBA16F),
+            width: self.width,
+            height: self.height,
+        };
+        self.resources.created(Resource::Framebuffer, 2);
+        let attach = |attachment: u32, texture: TextureId| {
+            gl.framebuffer_texture_2d(
+                GL::FRAMEBUFFER,
+                attachment,
+                GL::TEXTURE_2D,
+                Some(texture),
+                0,
+            );
+        };
+        gl.bind_framebuffer(GL::FRAMEBUFFER, Some(gbuffer.framebuffer));
+        let targets = [
+            gbuffer.albedo,
+            gbuffer.normal,
+            gbuffer.material,
+            gbuffer.emissive,
+        ];
+        for (i, texture) in targets.iter().enumerate() {
+            attach(GL::COLOR_ATTACHMENT0 + i as u32, *texture);
+        }
+        attach(GL::DEPTH_ATTACHMENT, gbuffer.depth);
+        let draw_buffers: Vec<u32> = (0..targets.len() as u32)
+            .map(|i| GL::COLOR_ATTACHMENT0 + i)
+            .collect();
+        gl.draw_buffers(&draw_buffers);
+        gl.bind_framebuffer(GL::FRAMEBUFFER, Some(gbuffer.light_framebuffer));
+        attach(GL::COLOR_ATTACHMENT0, gbuffer.light);
+        gl.bind_framebuffer(GL::FRAMEBUFFER, None);
+        self.gbuffer.set(Some(gbuffer));
+        gbuffer
+    }
+    fn delete_gbuffer(&self, gbuffer: GBuffer) {
+        let gl = &*self.backend;
+        gl.delete_framebuffer(gbuffer.framebuffer);
+        gl.delete_framebuffer(gbuffer.light_framebuffer);
+        self.resources.deleted(Resource::Framebuffer, 2);
+        for texture in [
+            gbuffer.albedo,
+            gbuffer.normal,
+            gbuffer.material,
+            gbuffer.emissive,
+            gbuffer.depth,
+            gbuffer.light,
+        ]
+        .iter()
+        {
+            self.delete_texture(*texture);
+        }
+    }
+    /// The light volume sphere, created the first time there are point lights to draw.
+    fn light_volumes(&self) -> LightVolumes {
+        if let Some(volumes) = self.light_volumes.get() {
+            return volumes;
+        }
+        let mut geometry = Geometry::from_genmesh_no_normals(&IcoSphere::subdivide(1));
+        // the faces of the icosphere cut inside the unit sphere, so it's grown until the
+        // nearest of them touches it
+        let vertex = |i: u32| {
+            let i = i as usize * 3;
+            Vector3::new(
+                geometry.vertices[i],
+                geometry.vertices[i + 1],
+                geometry.vertices[i + 2],
+            )
+        };
+        let inradius = geometry
+            .indices
+            .chunks(3)
+            .map(|face| {
+                let (a, b, c) = (vertex(face[0]), vertex(face[1]), vertex(face[2]));
+                (b - a).cross(&(c - a)).normalize().dot(&a).abs()
+            })
+            .fold(1., f32::min);
+        for v in geometry.vertices.iter_mut() {
+            *v /= inradius;
+        }
+
+        let gl = &*self.backend;
+        let program = self.deferred_programs.light_volumes;
+        let vao = gl.create_vertex_array();
+        gl.bind_vertex_array(Some(vao));
+        let vertices = bind_buffer_and_attribute(gl, program, "position", &geometry.vertices, 3);
+        let indices = bind_index_buffer(gl, &geometry.indices, geometry.index_type());
+        let instance_buffer = gl.create_buffer();
+        gl.bind_buffer(GL::ARRAY_BUFFER, Some(instance_buffer));
+        let stride = (VOLUME_STRIDE * 4) as i32;
+        for (name, offset) in [("light_position", 0), ("light_color", 16)].iter() {
+            let location = gl.attrib_location(program, name) as u32;
+            gl.vertex_attrib_pointer(location, 4, GL::FLOAT, false, stride, *offset);
+            gl.enable_vertex_attrib_array(location);
+            gl.vertex_attrib_divisor(location, 1);
+        }
+        gl.bind_buffer(GL::ARRAY_BUFFER, None);
+        gl.bind_vertex_array(None);
+        self.resources.created(Resource::Vao, 1);
+        self.resources.created(Resource::Buffer, 3);
+        let volumes = LightVolumes {
+            vao,
+            buffers: [vertices, indices, instance_buffer],
+            instance_buffer,
+            count: geometry.indices.len() as i32,
+            index_type: gl_index_type(geometry.index_type()),
+        };
+        self.light_volumes.set(Some(volumes));
+        volumes
+    }
+}
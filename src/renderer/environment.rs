@@ -0,0 +1,305 @@
+//! Image based lighting: the maps that let the skybox light pbr materials, rendered on the GPU
+//! from the skybox cubemap once its images have arrived.
+
+use super::{
+    gl as GL, set_bool, set_f32, set_i32, set_vec3, Backend, HdrImage, ProgramId, Renderer,
+    Resource, TextureId,
+};
+
+/// Size of each face of the diffuse irradiance cubemap, which holds no detail.
+pub const IRRADIANCE_SIZE: i32 = 32;
+/// Size of each face of the first level of the prefiltered cubemap.
+pub const PREFILTERED_SIZE: i32 = 128;
+/// Mip levels of the prefiltered cubemap, blurred from mirror-like to fully rough.
+pub const PREFILTERED_LEVELS: i32 = 5;
+pub const BRDF_LUT_SIZE: i32 = 128;
+/// Largest face of the cubemap an HDR image is converted into.
+const MAX_HDR_CUBE_SIZE: usize = 512;
+
+/// The forward, right and up direction of each face of a cubemap, in the order of the face
+/// targets. A texel at p in [-1, 1]² on the face holds the direction forward + p.x * right +
+/// p.y * up.
+const CUBE_FACES: [[[f32; 3]; 3]; 6] = [
+    [[1., 0., 0.], [0., 0., -1.], [0., -1., 0.]],
+    [[-1., 0., 0.], [0., 0., 1.], [0., -1., 0.]],
+    [[0., 1., 0.], [1., 0., 0.], [0., 0., 1.]],
+    [[0., -1., 0.], [1., 0., 0.], [0., 0., -1.]],
+    [[0., 0., 1.], [1., 0., 0.], [0., -1., 0.]],
+    [[0., 0., -1.], [-1., 0., 0.], [0., -1., 0.]],
+];
+
+/// The programs of the passes that generate the maps. They all draw a single triangle that
+/// covers the viewport.
+#[derive(Debug)]
+pub(super) struct EnvironmentPrograms {
+    equirect: ProgramId,
+    irradiance: ProgramId,
+    prefilter: ProgramId,
+    brdf: ProgramId,
+}
+
+impl EnvironmentPrograms {
+    pub(super) const COUNT: usize = 4;
+
+    pub(super) fn new(gl: &dyn Backend) -> Self {
+        let vertex = include_str!("shaders/fullscreen.vert");
+        Self {
+            equirect: gl
+                .create_program(vertex, include_str!("shaders/equirect.frag"))
+                .expect("Can't create equirect shader!"),
+            irradiance: gl
+                .create_program(vertex, include_str!("shaders/irradiance.frag"))
+                .expect("Can't create irradiance shader!"),
+            prefilter: gl
+                .create_program(vertex, include_str!("shaders/prefilter.frag"))
+                .expect("Can't create prefilter shader!"),
+            brdf: gl
+                .create_program(vertex, include_str!("shaders/brdf.frag"))
+                .expect("Can't create brdf shader!"),
+        }
+    }
+}
+
+/// The maps generated from the skybox.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct EnvironmentMaps {
+    /// The cosine weighted light arriving from each direction, for diffuse reflections.
+    pub irradiance: TextureId,
+    /// The skybox blurred by a rougher GGX lobe at every mip level, for specular reflections.
+    pub prefiltered: TextureId,
+}
+
+/// The skybox cubemap that lights pbr materials.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Environment {
+    pub source: TextureId,
+    /// Whether the cubemap holds linear HDR colors rather than sRGB ones.
+    pub hdr: bool,
+    /// None until every face of the cubemap has loaded.
+    pub maps: Option<EnvironmentMaps>,
+}
+
+impl Renderer {
+    /// Lights pbr materials with a cubemap, or stops lighting them with None. The maps are
+    /// generated by the first render after every face of the cubemap has loaded. The cubemap
+    /// itself is still owned by the caller.
+    pub fn set_environment(&self, source: Option<TextureId>, hdr: bool) {
+        let environment = source.map(|source| Environment {
+            source,
+            hdr,
+            maps: None,
+        });
+        if let Some(maps) = self.environment.replace(environment).and_then(|e| e.maps) {
+            self.delete_texture(maps.irradiance);
+            self.delete_texture(maps.prefiltered);
+        }
+    }
+    pub fn environment(&self) -> Option<Environment> {
+        self.environment.get()
+    }
+    /// Converts an equirectangular HDR image into a cubemap with a full mip chain.
+    pub fn create_hdr_cubemap(&self, image: &HdrImage) -> TextureId {
+        let gl = &*self.backend;
+        let equirect = self.create_map(GL::TEXTURE_2D, 1, GL::RGBA16F, image.width, image.height);
+        gl.bind_texture(GL::TEXTURE_2D, Some(equirect));
+        gl.tex_sub_image_f32(
+            GL::TEXTURE_2D,
+            0,
+            image.width as i32,
+            image.height as i32,
+            GL::RGBA,
+            &image.rgba(),
+        );
+        let size = (image.width / 4).next_power_of_two().clamp(16, MAX_HDR_CUBE_SIZE);
+        let levels = size.trailing_zeros() as i32 + 1;
+        let cubemap = self.create_map(GL::TEXTURE_CUBE_MAP, levels, GL::RGBA16F, size, size);
+
+        self.offscreen(|gl| {
+            let program = self.environment_programs.equirect;
+            gl.use_program(Some(program));
+            gl.active_texture(GL::TEXTURE0);
+            gl.bind_texture(GL::TEXTURE_2D, Some(equirect));
+            set_i32(gl, program, "equirect", 0);
+            self.render_to_cubemap(program, cubemap, size as i32, 0);
+        });
+        gl.bind_texture(GL::TEXTURE_CUBE_MAP, Some(cubemap));
+        gl.generate_mipmap(GL::TEXTURE_CUBE_MAP);
+        gl.bind_texture(GL::TEXTURE_CUBE_MAP, None);
+        self.delete_texture(equirect);
+        cubemap
+    }
+    /// Generates the maps of the environment once its cubemap is ready.
+    pub(super) fn update_environment(&self) {
+        let mut environment = match self.environment.get() {
+            Some(environment) if environment.maps.is_none() => environment,
+            _ => return,
+        };
+        if !self.backend.texture_ready(environment.source) {
+            return;
+        }
+        environment.maps = Some(self.generate_environment_maps(environment));
+        self.environment.set(Some(environment));
+    }
+    fn generate_environment_maps(&self, environment: Environment) -> EnvironmentMaps {
+        let gl = &*self.backend;
+        let irradiance = self.create_map(
+            GL::TEXTURE_CUBE_MAP,
+            1,
+            GL::RGBA16F,
+            IRRADIANCE_SIZE as usize,
+            IRRADIANCE_SIZE as usize,
+        );
+        let prefiltered = self.create_map(
+            GL::TEXTURE_CUBE_MAP,
+            PREFILTERED_LEVELS,
+            GL::RGBA16F,
+            PREFILTERED_SIZE as usize,
+            PREFILTERED_SIZE as usize,
+        );
+        // the passes read blurrier mips of the skybox where their samples are sparse
+        gl.bind_texture(GL::TEXTURE_CUBE_MAP, Some(environment.source));
+        gl.generate_mipmap(GL::TEXTURE_CUBE_MAP);
+        gl.tex_parameter(
+            GL::TEXTURE_CUBE_MAP,
+            GL::TEXTURE_MIN_FILTER,
+            GL::LINEAR_MIPMAP_LINEAR,
+        );
+        let brdf_lut = match self.brdf_lut.get() {
+            Some(_) => None,
+            None => {
+                let size = BRDF_LUT_SIZE as usize;
+                Some(self.create_map(GL::TEXTURE_2D, 1, GL::RG16F, size, size))
+            }
+        };
+
+        self.offscreen(|gl| {
+            let bind_source = |program: ProgramId| {
+                gl.use_program(Some(program));
+                gl.active_texture(GL::TEXTURE0);
+                gl.bind_texture(GL::TEXTURE_CUBE_MAP, Some(environment.source));
+                set_i32(gl, program, "environment", 0);
+                set_bool(gl, program, "srgb", !environment.hdr);
+            };
+            let program = self.environment_programs.irradiance;
+            bind_source(program);
+            self.render_to_cubemap(program, irradiance, IRRADIANCE_SIZE, 0);
+
+            let program = self.environment_programs.prefilter;
+            bind_source(program);
+            for level in 0..PREFILTERED_LEVELS {
+                let roughness = level as f32 / (PREFILTERED_LEVELS - 1) as f32;
+                set_f32(gl, program, "roughness", roughness);
+                self.render_to_cubemap(program, prefiltered, PREFILTERED_SIZE >> level, level);
+            }
+
+            if let Some(brdf_lut) = brdf_lut {
+                gl.use_program(Some(self.environment_programs.brdf));
+                gl.framebuffer_texture_2d(
+                    GL::FRAMEBUFFER,
+                    GL::COLOR_ATTACHMENT0,
+                    GL::TEXTURE_2D,
+                    Some(brdf_lut),
+                    0,
+                );
+                gl.viewport(0, 0, BRDF_LUT_SIZE, BRDF_LUT_SIZE);
+                gl.draw_arrays(GL::TRIANGLES, 0, 3);
+            }
+        });
+        if brdf_lut.is_some() {
+            self.brdf_lut.set(brdf_lut);
+        }
+        EnvironmentMaps {
+            irradiance,
+            prefiltered,
+        }
+    }
+    /// Creates an empty texture that is sampled linearly and clamped to its edges.
+    pub(super) fn create_map(
+        &self,
+        target: u32,
+        levels: i32,
+        internal_format: u32,
+        width: usize,
+        height: usize,
+    ) -> TextureId {
+        let gl = &*self.backend;
+        let texture =
+            gl.create_texture_storage(target, levels, internal_format, width as i32, height as i32);
+        self.resources.created(Resource::Texture, 1);
+        gl.bind_texture(target, Some(texture));
+        let min_filter = if levels > 1 {
+            GL::LINEAR_MIPMAP_LINEAR
+        } else {
+            GL::LINEAR
+        };
+        gl.tex_parameter(target, GL::TEXTURE_MIN_FILTER, min_filter);
+        gl.tex_parameter(target, GL::TEXTURE_MAG_FILTER, GL::LINEAR);
+        gl.tex_parameter(target, GL::TEXTURE_WRAP_S, GL::CLAMP_TO_EDGE);
+        gl.tex_parameter(target, GL::TEXTURE_WRAP_T, GL::CLAMP_TO_EDGE);
+        gl.bind_texture(target, None);
+        texture
+    }
+    /// Runs passes that draw into textures through a framebuffer of their own, then goes back
+    /// to drawing to the canvas.
+    pub(super) fn offscreen(&self, passes: impl FnOnce(&dyn Backend)) {
+        let gl = &*self.backend;
+        let framebuffer = gl.create_framebuffer();
+        self.resources.created(Resource::Framebuffer, 1);
+        gl.bind_framebuffer(GL::FRAMEBUFFER, Some(framebuffer));
+        gl.bind_vertex_array(None);
+        for capability in [
+            GL::DEPTH_TEST,
+            GL::STENCIL_TEST,
+            GL::CULL_FACE,
+            GL::BLEND,
+            GL::SAMPLE_ALPHA_TO_COVERAGE,
+        ]
+        .iter()
+        {
+            gl.disable(*capability);
+        }
+        passes(gl);
+        gl.bind_framebuffer(GL::FRAMEBUFFER, None);
+        gl.delete_framebuffer(framebuffer);
+        self.resources.deleted(Resource::Framebuffer, 1);
+        gl.viewport(0, 0, self.width as i32, self.height as i32);
+    }
+    /// Draws the program in use into every face of a level of the cubemap.
+    fn render_to_cubemap(&self, program: ProgramId, cubemap: TextureId, size: i32, level: i32) {
+        let gl = &*self.backend;
+        gl.viewport(0, 0, size, size);
+        for (i, [forward, right, up]) in CUBE_FACES.iter().enumerate() {
+            gl.framebuffer_texture_2d(
+                GL::FRAMEBUFFER,
+                GL::COLOR_ATTACHMENT0,
+                GL::TEXTURE_CUBE_MAP_POSITIVE_X + i as u32,
+                Some(cubemap),
+                level,
+            );
+            set_vec3(gl, program, "face_forward", forward);
+            set_vec3(gl, program, "face_right", right);
+            set_vec3(gl, program, "face_up", up);
+            gl.draw_arrays(GL::TRIANGLES, 0, 3);
+        }
+    }
+    /// Binds the maps of the environment to the texture units after the material's. The cube
+    /// samplers always get units of their own, since WebGL refuses to draw with a 2d and a cube
+    /// sampler on the same unit.
+    pub(super) fn set_environment_uniforms(&self, program: ProgramId) {
+        let gl = &*self.backend;
+        set_i32(gl, program, "irradiance_map", 4);
+        set_i32(gl, program, "prefiltered_map", 5);
+        set_i32(gl, program, "brdf_lut", 6);
+        let maps = self.environment.get().and_then(|e| e.maps);
+        set_bool(gl, program, "has_environment", maps.is_some());
+        if let (Some(maps), Some(brdf_lut)) = (maps, self.brdf_lut.get()) {
+            gl.active_texture(GL::TEXTURE0 + 4);
+            gl.bind_texture(GL::TEXTURE_CUBE_MAP, Some(maps.irradiance));
+            gl.active_texture(GL::TEXTURE0 + 5);
+            gl.bind_texture(GL::TEXTURE_CUBE_MAP, Some(maps.prefiltered));
+            gl.active_texture(GL::TEXTURE0 + 6);
+            gl.bind_texture(GL::TEXTURE_2D, Some(brdf_lut));
+            set_f32(gl, program, "max_reflection_lod", (PREFILTERED_LEVELS - 1) as f32);
+        }
+    }
+}
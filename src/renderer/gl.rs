@@ -0,0 +1,90 @@
+//! The WebGL 2 enum values used by the renderer. Backends other than WebGL receive the same
+//! values and are expected to interpret them the way WebGL would.
+
+pub const POINTS: u32 = 0x0000;
+pub const LINES: u32 = 0x0001;
+pub const TRIANGLES: u32 = 0x0004;
+
+pub const DEPTH_BUFFER_BIT: u32 = 0x0100;
+pub const STENCIL_BUFFER_BIT: u32 = 0x0400;
+pub const COLOR_BUFFER_BIT: u32 = 0x4000;
+/// The buffer clearBuffer* clears the color attachments of.
+pub const COLOR: u32 = 0x1800;
+
+pub const NEVER: u32 = 0x0200;
+pub const LESS: u32 = 0x0201;
+pub const EQUAL: u32 = 0x0202;
+pub const LEQUAL: u32 = 0x0203;
+pub const GREATER: u32 = 0x0204;
+pub const NOTEQUAL: u32 = 0x0205;
+pub const GEQUAL: u32 = 0x0206;
+pub const ALWAYS: u32 = 0x0207;
+
+pub const ZERO: u32 = 0;
+pub const ONE: u32 = 1;
+pub const SRC_ALPHA: u32 = 0x0302;
+pub const ONE_MINUS_SRC_ALPHA: u32 = 0x0303;
+
+pub const FRONT: u32 = 0x0404;
+pub const BACK: u32 = 0x0405;
+pub const FRONT_AND_BACK: u32 = 0x0408;
+pub const CW: u32 = 0x0900;
+pub const CCW: u32 = 0x0901;
+
+pub const CULL_FACE: u32 = 0x0B44;
+pub const DEPTH_TEST: u32 = 0x0B71;
+pub const STENCIL_TEST: u32 = 0x0B90;
+pub const BLEND: u32 = 0x0BE2;
+pub const SAMPLE_ALPHA_TO_COVERAGE: u32 = 0x809E;
+
+pub const INVERT: u32 = 0x150A;
+pub const KEEP: u32 = 0x1E00;
+pub const REPLACE: u32 = 0x1E01;
+pub const INCR: u32 = 0x1E02;
+pub const DECR: u32 = 0x1E03;
+
+pub const UNSIGNED_BYTE: u32 = 0x1401;
+pub const UNSIGNED_SHORT: u32 = 0x1403;
+pub const UNSIGNED_INT: u32 = 0x1405;
+pub const FLOAT: u32 = 0x1406;
+
+pub const TEXTURE_2D: u32 = 0x0DE1;
+pub const TEXTURE_CUBE_MAP: u32 = 0x8513;
+pub const TEXTURE_CUBE_MAP_POSITIVE_X: u32 = 0x8515;
+pub const TEXTURE0: u32 = 0x84C0;
+
+pub const TEXTURE_MAG_FILTER: u32 = 0x2800;
+pub const TEXTURE_MIN_FILTER: u32 = 0x2801;
+pub const TEXTURE_WRAP_S: u32 = 0x2802;
+pub const TEXTURE_WRAP_T: u32 = 0x2803;
+pub const TEXTURE_WRAP_R: u32 = 0x8072;
+pub const NEAREST: u32 = 0x2600;
+pub const LINEAR: u32 = 0x2601;
+pub const LINEAR_MIPMAP_LINEAR: u32 = 0x2703;
+pub const CLAMP_TO_EDGE: u32 = 0x812F;
+pub const TEXTURE_COMPARE_MODE: u32 = 0x884C;
+pub const TEXTURE_COMPARE_FUNC: u32 = 0x884D;
+pub const COMPARE_REF_TO_TEXTURE: u32 = 0x884E;
+
+pub const RGB: u32 = 0x1907;
+pub const RGBA: u32 = 0x1908;
+pub const RGBA8: u32 = 0x8058;
+pub const RG16F: u32 = 0x822F;
+pub const RGBA16F: u32 = 0x881A;
+pub const RGBA32UI: u32 = 0x8D70;
+pub const RGBA_INTEGER: u32 = 0x8D99;
+pub const DEPTH_COMPONENT24: u32 = 0x81A6;
+pub const DEPTH24_STENCIL8: u32 = 0x88F0;
+
+pub const FRAMEBUFFER: u32 = 0x8D40;
+pub const READ_FRAMEBUFFER: u32 = 0x8CA8;
+pub const DRAW_FRAMEBUFFER: u32 = 0x8CA9;
+pub const RENDERBUFFER: u32 = 0x8D41;
+pub const DEPTH_ATTACHMENT: u32 = 0x8D00;
+pub const DEPTH_STENCIL_ATTACHMENT: u32 = 0x821A;
+pub const COLOR_ATTACHMENT0: u32 = 0x8CE0;
+
+pub const ARRAY_BUFFER: u32 = 0x8892;
+pub const ELEMENT_ARRAY_BUFFER: u32 = 0x8893;
+pub const STATIC_DRAW: u32 = 0x88E4;
+pub const DYNAMIC_DRAW: u32 = 0x88E8;
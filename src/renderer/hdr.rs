@@ -0,0 +1,185 @@
+//! A reader for Radiance HDR (.hdr) images, the usual format of equirectangular environment
+//! maps.
+
+use std::fmt;
+
+/// Scanlines at least this wide and at most 0x7fff pixels may be run length encoded.
+const MIN_RLE_WIDTH: usize = 8;
+const MAX_RLE_WIDTH: usize = 0x7fff;
+
+/// An image of linear RGB floats, stored top to bottom.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HdrImage {
+    pub width: usize,
+    pub height: usize,
+    /// Three floats per pixel.
+    pub pixels: Vec<f32>,
+}
+
+/// Why an .hdr file couldn't be read.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HdrError {
+    /// The file doesn't start with the Radiance signature.
+    NotRadiance,
+    /// The pixels are stored in a format other than 32-bit_rle_rgbe, e.g. XYZE.
+    UnsupportedFormat(String),
+    /// The resolution line is missing, or the scanlines don't go top to bottom, left to right.
+    UnsupportedLayout(String),
+    /// The file ends before the last scanline.
+    Truncated,
+    /// A run length encoded scanline doesn't add up to the width of the image.
+    InvalidScanline(usize),
+}
+
+impl fmt::Display for HdrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HdrError::NotRadiance => write!(f, "Not a Radiance HDR file"),
+            HdrError::UnsupportedFormat(format) => {
+                write!(f, "Unsupported HDR pixel format {}", format)
+            }
+            HdrError::UnsupportedLayout(layout) => {
+                write!(f, "Unsupported HDR resolution line {:?}", layout)
+            }
+            HdrError::Truncated => write!(f, "The HDR file ends before its last scanline"),
+            HdrError::InvalidScanline(y) => write!(f, "Invalid HDR scanline {}", y),
+        }
+    }
+}
+
+impl std::error::Error for HdrError {}
+
+/// Converts a pixel with a shared exponent to linear floats.
+fn rgbe_to_rgb(rgbe: [u8; 4]) -> [f32; 3] {
+    if rgbe[3] == 0 {
+        return [0.; 3];
+    }
+    let scale = 2f32.powi(i32::from(rgbe[3]) - (128 + 8));
+    [
+        (f32::from(rgbe[0]) + 0.5) * scale,
+        (f32::from(rgbe[1]) + 0.5) * scale,
+        (f32::from(rgbe[2]) + 0.5) * scale,
+    ]
+}
+
+/// Reads the next line of the header, without its newline.
+fn line<'a>(data: &'a [u8], pos: &mut usize) -> Result<&'a str, HdrError> {
+    let rest = data.get(*pos..).ok_or(HdrError::Truncated)?;
+    let end = rest
+        .iter()
+        .position(|&byte| byte == b'\n')
+        .ok_or(HdrError::Truncated)?;
+    *pos += end + 1;
+    Ok(std::str::from_utf8(&rest[..end]).unwrap_or(""))
+}
+
+/// Reads one run length encoded scanline, whose four channels are stored one after the other.
+fn read_rle_scanline(
+    data: &[u8],
+    pos: &mut usize,
+    width: usize,
+    y: usize,
+    scanline: &mut [[u8; 4]],
+) -> Result<(), HdrError> {
+    let byte = |pos: &mut usize| {
+        let value = data.get(*pos).copied().ok_or(HdrError::Truncated);
+        *pos += 1;
+        value
+    };
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = byte(pos)? as usize;
+            if count > 128 {
+                let count = count - 128;
+                if x + count > width {
+                    return Err(HdrError::InvalidScanline(y));
+                }
+                let value = byte(pos)?;
+                for pixel in &mut scanline[x..x + count] {
+                    pixel[channel] = value;
+                }
+                x += count;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err(HdrError::InvalidScanline(y));
+                }
+                for pixel in &mut scanline[x..x + count] {
+                    pixel[channel] = byte(pos)?;
+                }
+                x += count;
+            }
+        }
+    }
+    Ok(())
+}
+
+impl HdrImage {
+    /// Decodes the bytes of a .hdr file, with either flat or run length encoded scanlines.
+    pub fn decode(data: &[u8]) -> Result<Self, HdrError> {
+        let mut pos = 0;
+        let signature = line(data, &mut pos).map_err(|_| HdrError::NotRadiance)?;
+        if signature != "#?RADIANCE" && signature != "#?RGBE" {
+            return Err(HdrError::NotRadiance);
+        }
+        loop {
+            let header = line(data, &mut pos)?;
+            if header.is_empty() {
+                break;
+            }
+            if let Some(format) = header.strip_prefix("FORMAT=") {
+                if format != "32-bit_rle_rgbe" {
+                    return Err(HdrError::UnsupportedFormat(format.into()));
+                }
+            }
+        }
+        let resolution = line(data, &mut pos)?;
+        let size: Vec<&str> = resolution.split_whitespace().collect();
+        let (height, width) = match size[..] {
+            ["-Y", height, "+X", width] => (height.parse().ok(), width.parse().ok()),
+            _ => (None, None),
+        };
+        let (width, height): (usize, usize) = match (width, height) {
+            (Some(width), Some(height)) => (width, height),
+            _ => return Err(HdrError::UnsupportedLayout(resolution.into())),
+        };
+
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        let mut scanline = vec![[0u8; 4]; width];
+        for y in 0..height {
+            let start = data.get(pos..pos + 4).ok_or(HdrError::Truncated)?;
+            let is_rle = (MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&width)
+                && start[0] == 2
+                && start[1] == 2
+                && start[2] & 0x80 == 0;
+            if is_rle {
+                if (usize::from(start[2]) << 8 | usize::from(start[3])) != width {
+                    return Err(HdrError::InvalidScanline(y));
+                }
+                pos += 4;
+                read_rle_scanline(data, &mut pos, width, y, &mut scanline)?;
+            } else {
+                let flat = data.get(pos..pos + width * 4).ok_or(HdrError::Truncated)?;
+                for (pixel, rgbe) in scanline.iter_mut().zip(flat.chunks(4)) {
+                    pixel.copy_from_slice(rgbe);
+                }
+                pos += width * 4;
+            }
+            for &rgbe in scanline.iter() {
+                pixels.extend_from_slice(&rgbe_to_rgb(rgbe));
+            }
+        }
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+    /// The pixels with an opaque alpha after each of them, the layout RGBA textures take.
+    pub fn rgba(&self) -> Vec<f32> {
+        self.pixels
+            .chunks(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 1.])
+            .collect()
+    }
+}
@@ -1,25 +1,65 @@
+mod backend;
+mod culling;
+mod deferred;
+mod environment;
+pub mod gl;
+mod hdr;
+mod picking;
+mod post;
+mod recording;
+mod render_target;
+mod resources;
 mod shader;
+mod shadow;
+#[cfg(feature = "software")]
+mod software;
+mod tone_mapping;
+#[cfg(feature = "web")]
+mod webgl;
+#[cfg(feature = "web")]
+use crate::dom_factory::{body, get_canvas, resize_canvas};
 use crate::{
     controller::Viewport,
-    dom_factory::{body, get_canvas, resize_canvas},
     log,
-    mesh::Mesh,
-    scene::Scene,
+    mesh::{Material, Mesh},
+    scene::{Instances, Scene},
     LightType, ProjectionType, Storage, TextureType, Transform,
 };
+use gl as GL;
+#[cfg(feature = "web")]
 use maud::html;
 use nalgebra::{UnitQuaternion, Vector3};
+pub use backend::*;
+pub use culling::*;
+pub use deferred::*;
+pub use environment::*;
+pub use hdr::*;
+pub use picking::*;
+pub use post::*;
+pub use recording::*;
+pub use render_target::*;
+pub use resources::*;
 pub use shader::*;
-use std::collections::HashMap;
+pub use shadow::*;
+#[cfg(feature = "software")]
+pub use software::*;
+pub use tone_mapping::*;
+#[cfg(feature = "web")]
+pub use webgl::*;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
 use std::f32::consts::PI;
+use std::rc::Rc;
 use strum::IntoEnumIterator;
+#[cfg(feature = "web")]
 use wasm_bindgen::{prelude::*, JsCast};
-use web_sys::{
-    HtmlCanvasElement, HtmlElement, WebGl2RenderingContext as GL, WebGlProgram,
-    WebGlVertexArrayObject,
-};
+#[cfg(feature = "web")]
+use web_sys::{HtmlCanvasElement, HtmlElement};
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+/// The sRGB color of the background.
+const CLEAR_COLOR: [f32; 4] = [0.1, 0.1, 0.1, 1.0];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum DrawMode {
     Points,
     Lines,
@@ -44,7 +84,8 @@ impl Default for RenderConfig {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct RenderFlags {
     pub render: bool,
     pub depth: bool,
@@ -52,6 +93,10 @@ pub struct RenderFlags {
     pub stencil: bool,
     pub view_transform: bool,
     pub cull_face: bool,
+    /// Whether the mesh is drawn into the shadow maps of lights. Only lit meshes cast shadows.
+    pub cast_shadows: bool,
+    /// Whether shadows darken the mesh.
+    pub receive_shadows: bool,
 }
 
 impl Default for RenderFlags {
@@ -63,6 +108,8 @@ impl Default for RenderFlags {
             blend: false,
             view_transform: true,
             cull_face: true,
+            cast_shadows: true,
+            receive_shadows: true,
         }
     }
 }
@@ -107,26 +154,90 @@ impl RenderFlags {
 pub struct RendererConfig {
     pub id: &'static str,
     pub pixel_ratio: f64,
+    pub pipeline: Pipeline,
+    /// Whether the scene is drawn into a floating point target and tone mapped to the canvas.
+    pub hdr: bool,
+    pub tone_mapping: ToneMapping,
+    /// The factor the HDR target is scaled by before tone mapping.
+    pub exposure: f32,
 }
 
-#[derive(Serialize)]
-pub struct ContextOptions {
-    pub stencil: bool,
+/// A vertex array object along with the buffers that were bound to it, so that both can be
+/// released together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VertexArray {
+    pub vao: VaoId,
+    pub buffers: Vec<BufferId>,
+    /// The per-instance transforms and colors of an instanced mesh, also listed in `buffers`.
+    pub instance_buffer: Option<BufferId>,
+}
+
+/// Floats per instance in the instance buffer: a model matrix followed by a color.
+const INSTANCE_STRIDE: usize = 20;
+/// The distance over which point and spot lights fade out.
+const LIGHT_RANGE: f32 = 100.;
+/// The attenuation of point and spot lights is 1 / (1 + linear * d + quadratic * d²).
+const LIGHT_LINEAR: f32 = 4.5 / LIGHT_RANGE;
+const LIGHT_QUADRATIC: f32 = 7.5 / (LIGHT_RANGE * LIGHT_RANGE);
+/// Half angle of the fully lit cone of a spot light.
+const SPOT_CUTOFF: f32 = PI / 30.;
+/// Half angle of the cone a spot light fades out at.
+const SPOT_OUTER_CUTOFF: f32 = PI / 25.;
+
+/// The direction of a directional or spot light node. A directional light shines from this
+/// direction, and a spot light towards it.
+fn light_direction(storage: &Storage, node_id: usize) -> Vector3<f32> {
+    let vector = storage
+        .world_transform(node_id)
+        .isometry
+        .rotation
+        .transform_vector(&Vector3::identity());
+    // The cone and arrows mesh is intrinsically oriented 90 deg
+    UnitQuaternion::from_euler_angles(0., PI / 2., 0.).transform_vector(&vector)
 }
 
-/// WebGL renderer that compiles, binds and executes all shaders; also capable of handling window resizes and configuration changes
-#[wasm_bindgen]
+/// Renderer that compiles, binds and executes all shaders through a Backend; also capable of
+/// handling window resizes and configuration changes
+#[cfg_attr(feature = "web", wasm_bindgen)]
 #[derive(Debug)]
 pub struct Renderer {
-    canvas: HtmlCanvasElement,
-    ctx: GL,
+    #[cfg(feature = "web")]
+    canvas: Option<HtmlCanvasElement>,
+    backend: Rc<dyn Backend>,
+    width: u32,
+    height: u32,
     aspect_ratio: f32,
-    shaders: HashMap<ShaderType, WebGlProgram>,
+    shaders: HashMap<ShaderType, ProgramId>,
     config: RendererConfig,
     render_config: RenderConfig,
+    resources: ResourceRegistry,
+    environment: Cell<Option<Environment>>,
+    environment_programs: EnvironmentPrograms,
+    brdf_lut: Cell<Option<TextureId>>,
+    shadow_programs: ShadowPrograms,
+    /// The depth texture holding every shadow map, along with its size.
+    shadow_atlas: Cell<Option<(TextureId, i32)>>,
+    deferred_programs: DeferredPrograms,
+    gbuffer: Cell<Option<GBuffer>>,
+    light_volumes: Cell<Option<LightVolumes>>,
+    post_programs: PostPrograms,
+    /// Run in order over the image of the scene.
+    post_effects: Vec<PostEffect>,
+    scene_target: Cell<Option<SceneTarget>>,
+    post_buffers: Cell<Option<PostBuffers>>,
+    /// The color grading LUTs, by url.
+    luts: RefCell<HashMap<String, TextureId>>,
+    render_targets: RefCell<BTreeMap<RenderTargetId, RenderTarget>>,
+    next_render_target: Cell<u32>,
+    pick_programs: PickPrograms,
+    pick_target: Cell<Option<PickTarget>>,
+    /// How many meshes the last frame left out for being outside the view.
+    culled: Cell<usize>,
 }
 
 impl Renderer {
+    /// Creates a canvas in the page and renders to it with WebGL 2.
+    #[cfg(feature = "web")]
     pub fn new(config: RendererConfig) -> Self {
         let dom = html! {
             canvas id=(config.id) oncontextmenu="return false;" {}
@@ -134,21 +245,38 @@ impl Renderer {
         body()
             .insert_adjacent_html("beforeend", dom.into_string().as_str())
             .expect("Couldn't insert markup into the DOM!");
-        let mut canvas = get_canvas(config.id);
-        let stencil_param = JsValue::from_serde(&ContextOptions { stencil: true }).unwrap();
-        let aspect_ratio = resize_canvas(&mut canvas, config.pixel_ratio);
-        let ctx = canvas
-            .get_context_with_context_options("webgl2", &stencil_param)
-            .expect("Can't create webgl2 context. Make sure your browser supports WebGL2")
-            .unwrap()
-            .dyn_into::<GL>()
-            .unwrap();
-
+        let canvas = get_canvas(config.id);
+        resize_canvas(&canvas, config.pixel_ratio);
+        let backend = Rc::new(WebGlBackend::new(&canvas));
+        let (width, height) = (canvas.width(), canvas.height());
+        let mut renderer = Self::from_backend(backend, config, width, height);
+        renderer.canvas = Some(canvas);
+        renderer
+    }
+    /// Creates a renderer that draws through the given backend, without a canvas. This is how
+    /// scenes are rendered outside a browser, e.g. with a RecordingBackend in tests.
+    pub fn with_backend(backend: Rc<dyn Backend>, width: u32, height: u32) -> Self {
+        let config = RendererConfig {
+            id: "",
+            pixel_ratio: 1.,
+            pipeline: Pipeline::Forward,
+            hdr: false,
+            tone_mapping: ToneMapping::Aces,
+            exposure: 1.,
+        };
+        Self::from_backend(backend, config, width, height)
+    }
+    fn from_backend(
+        backend: Rc<dyn Backend>,
+        config: RendererConfig,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let gl = &*backend;
         let mut shaders = HashMap::new();
         shaders.insert(
             ShaderType::Simple,
-            create_program(
-                &ctx,
+            gl.create_program(
                 include_str!("shaders/simple.vert"),
                 include_str!("shaders/simple.frag"),
             )
@@ -156,8 +284,7 @@ impl Renderer {
         );
         shaders.insert(
             ShaderType::Color,
-            create_program(
-                &ctx,
+            gl.create_program(
                 include_str!("shaders/color.vert"),
                 include_str!("shaders/color.frag"),
             )
@@ -165,8 +292,7 @@ impl Renderer {
         );
         shaders.insert(
             ShaderType::CubeMap,
-            create_program(
-                &ctx,
+            gl.create_program(
                 include_str!("shaders/cube.vert"),
                 include_str!("shaders/cube.frag"),
             )
@@ -174,40 +300,93 @@ impl Renderer {
         );
         shaders.insert(
             ShaderType::Wireframe,
-            create_program(
-                &ctx,
+            gl.create_program(
                 include_str!("shaders/wire.vert"),
                 include_str!("shaders/wire.frag"),
             )
             .expect("Can't create wire shader!"),
         );
+        shaders.insert(
+            ShaderType::Pbr,
+            gl.create_program(
+                include_str!("shaders/pbr.vert"),
+                include_str!("shaders/pbr.frag"),
+            )
+            .expect("Can't create pbr shader!"),
+        );
         shaders.insert(
             ShaderType::VertexColor,
-            create_vertex_color_program(&ctx).expect("Can't create vertex color shader!"),
+            create_vertex_color_program(gl).expect("Can't create vertex color shader!"),
         );
+        let environment_programs = EnvironmentPrograms::new(gl);
+        let shadow_programs =
+            ShadowPrograms::new(gl, shaders[&ShaderType::Color], shaders[&ShaderType::Pbr]);
+        let deferred_programs =
+            DeferredPrograms::new(gl, shaders[&ShaderType::Color], shaders[&ShaderType::Pbr]);
+        let post_programs = PostPrograms::new(gl);
+        let pick_programs = PickPrograms::new(gl, &shaders);
         log!("Renderer created");
+        let resources = ResourceRegistry::default();
+        resources.created(
+            Resource::Program,
+            shaders.len()
+                + EnvironmentPrograms::COUNT
+                + ShadowPrograms::COUNT
+                + DeferredPrograms::COUNT
+                + PostPrograms::COUNT
+                + PickPrograms::COUNT,
+        );
         let render_config = Default::default();
-        Self::setup_renderer(&ctx, render_config);
+        Self::setup_renderer(gl, render_config);
+        gl.viewport(0, 0, width as i32, height as i32);
         Self {
-            canvas,
-            ctx,
-            aspect_ratio,
+            #[cfg(feature = "web")]
+            canvas: None,
+            backend,
+            width,
+            height,
+            aspect_ratio: width as f32 / height as f32,
             shaders,
             config,
             render_config,
+            resources,
+            environment: Cell::new(None),
+            environment_programs,
+            brdf_lut: Cell::new(None),
+            shadow_programs,
+            shadow_atlas: Cell::new(None),
+            deferred_programs,
+            gbuffer: Cell::new(None),
+            light_volumes: Cell::new(None),
+            post_programs,
+            post_effects: Vec::new(),
+            scene_target: Cell::new(None),
+            post_buffers: Cell::new(None),
+            luts: RefCell::new(HashMap::new()),
+            render_targets: RefCell::new(BTreeMap::new()),
+            next_render_target: Cell::new(0),
+            pick_programs,
+            pick_target: Cell::new(None),
+            culled: Cell::new(0),
         }
     }
-    pub fn create_vao(&self, mesh: &Mesh) -> WebGlVertexArrayObject {
-        let shader_type = mesh.material.shader_type;
-        let program = self
+    fn program(&self, shader_type: ShaderType) -> ProgramId {
+        *self
             .shaders
             .get(&shader_type)
-            .expect("Can't find the program!");
-        let vao = self.ctx.create_vertex_array().expect("Can't creat VAO");
-        self.ctx.bind_vertex_array(Some(&vao));
+            .expect("Can't find the program!")
+    }
+    pub fn create_vao(&self, mesh: &Mesh) -> VertexArray {
+        let shader_type = mesh.material.shader_type;
+        let program = self.program(shader_type);
+        let gl = &*self.backend;
+        let vao = gl.create_vertex_array();
+        gl.bind_vertex_array(Some(vao));
+        let mut buffers = Vec::new();
         // bind vertices
-        bind_buffer_and_attribute(&self.ctx, &program, "position", &mesh.geometry.vertices, 3)
-            .expect("Can't bind postion");
+        buffers.push(
+            bind_buffer_and_attribute(gl, program, "position", &mesh.geometry.vertices, 3),
+        );
         if mesh.material.wire_overlay != None || shader_type == ShaderType::Wireframe {
             let mut bary_buffer = Vec::new();
             let barycentric: [f32; 9] = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0];
@@ -216,41 +395,148 @@ impl Renderer {
                     bary_buffer.push(*each);
                 }
             }
-            bind_buffer_and_attribute(&self.ctx, &program, "barycentric", &bary_buffer, 3)
-                .expect("Can't bind postion");
+            buffers.push(
+                bind_buffer_and_attribute(gl, program, "barycentric", &bary_buffer, 3),
+            );
         }
         // bind normals
-        if shader_type == ShaderType::Color {
-            bind_buffer_and_attribute(&self.ctx, &program, "normal", &mesh.geometry.normals, 3)
-                .expect("Can't bind normals");
+        if shader_type == ShaderType::Color || shader_type == ShaderType::Pbr {
+            buffers.push(
+                bind_buffer_and_attribute(gl, program, "normal", &mesh.geometry.normals, 3),
+            );
         }
-        // bind texture
+        // bind texture, which the maps of a pbr material need even without an albedo
         if let Some(coords) = mesh.material.tex_coords.as_ref() {
-            if mesh.material.tex_type == TextureType::Tex2d {
-                bind_buffer_and_attribute(&self.ctx, &program, "tex_coords", coords, 2)
-                    .expect("Couldn't bind tex coordinates");
+            if mesh.material.tex_type == TextureType::Tex2d || shader_type == ShaderType::Pbr {
+                buffers.push(
+                    bind_buffer_and_attribute(gl, program, "tex_coords", coords, 2),
+                );
             }
         }
         // bind vertex color
         if shader_type == ShaderType::VertexColor {
-            bind_buffer_and_attribute(
-                &self.ctx,
-                &program,
-                "color",
-                mesh.material
-                    .vertex_colors
-                    .as_ref()
-                    .expect("Expected vertex color, found nothing!"),
-                4,
-            )
-            .expect("Couldn't bind vertex colors.");
+            buffers.push(
+                bind_buffer_and_attribute(
+                    gl,
+                    program,
+                    "color",
+                    mesh.material
+                        .vertex_colors
+                        .as_ref()
+                        .expect("Expected vertex color, found nothing!"),
+                    4,
+                ),
+            );
+        }
+        // the element array binding is part of the vao, so it's only bound once here
+        buffers.push(bind_index_buffer(
+            gl,
+            &mesh.geometry.indices,
+            mesh.geometry.index_type(),
+        ));
+        gl.bind_buffer(GL::ARRAY_BUFFER, None);
+        gl.bind_vertex_array(None);
+        self.resources.created(Resource::Vao, 1);
+        self.resources.created(Resource::Buffer, buffers.len());
+        VertexArray {
+            vao,
+            buffers,
+            instance_buffer: None,
+        }
+    }
+    /// Creates a vao whose mesh is drawn once per instance, reading a model matrix and a color
+    /// for each instance from a dedicated buffer. Only the Simple and Color shaders can be
+    /// instanced.
+    pub fn create_instanced_vao(&self, mesh: &Mesh, instances: &Instances) -> VertexArray {
+        let shader_type = mesh.material.shader_type;
+        assert!(
+            shader_type == ShaderType::Simple || shader_type == ShaderType::Color,
+            "Can't instance a mesh drawn with the {} shader!",
+            shader_type
+        );
+        let mut vertex_array = self.create_vao(mesh);
+        let program = self.program(shader_type);
+        let gl = &*self.backend;
+        let buffer = gl.create_buffer();
+        self.resources.created(Resource::Buffer, 1);
+        gl.bind_vertex_array(Some(vertex_array.vao));
+        gl.bind_buffer(GL::ARRAY_BUFFER, Some(buffer));
+        let stride = (INSTANCE_STRIDE * 4) as i32;
+        // a mat4 attribute takes up four consecutive locations, one per column
+        let model = gl.attrib_location(program, "instance_model");
+        let color = gl.attrib_location(program, "instance_color");
+        let attributes = (0..4)
+            .map(|column| (model + column, column * 16))
+            .chain(std::iter::once((color, 64)));
+        for (location, offset) in attributes {
+            if location < 0 {
+                continue;
+            }
+            let location = location as u32;
+            gl.vertex_attrib_pointer(location, 4, GL::FLOAT, false, stride, offset);
+            gl.enable_vertex_attrib_array(location);
+            gl.vertex_attrib_divisor(location, 1);
+        }
+        gl.bind_buffer(GL::ARRAY_BUFFER, None);
+        gl.bind_vertex_array(None);
+        vertex_array.buffers.push(buffer);
+        vertex_array.instance_buffer = Some(buffer);
+        self.update_instances(&vertex_array, mesh, instances);
+        vertex_array
+    }
+    /// Uploads the instance transforms and colors. Instances without a color of their own take
+    /// the color of the material.
+    pub fn update_instances(&self, vertex_array: &VertexArray, mesh: &Mesh, instances: &Instances) {
+        let buffer = vertex_array
+            .instance_buffer
+            .expect("Can't update the instances of a mesh that isn't instanced!");
+        let default_color = mesh.material.color.unwrap_or([1., 1., 1., 1.]);
+        let mut data = Vec::with_capacity(instances.transforms.len() * INSTANCE_STRIDE);
+        for (i, transform) in instances.transforms.iter().enumerate() {
+            data.extend_from_slice(transform.to_homogeneous().as_slice());
+            let color = instances
+                .colors
+                .as_ref()
+                .and_then(|colors| colors.get(i))
+                .unwrap_or(&default_color);
+            data.extend_from_slice(color);
         }
-        self.ctx.bind_buffer(GL::ARRAY_BUFFER, None);
-        self.ctx.bind_vertex_array(None);
-        vao
+        let gl = &*self.backend;
+        gl.bind_buffer(GL::ARRAY_BUFFER, Some(buffer));
+        gl.buffer_data_f32(GL::ARRAY_BUFFER, &data, GL::DYNAMIC_DRAW);
+        gl.bind_buffer(GL::ARRAY_BUFFER, None);
+    }
+    /// Releases the vertex array object along with every buffer that was bound to it.
+    pub fn delete_vao(&self, vertex_array: &VertexArray) {
+        for buffer in vertex_array.buffers.iter() {
+            self.backend.delete_buffer(*buffer);
+        }
+        self.backend.delete_vertex_array(vertex_array.vao);
+        self.resources.deleted(Resource::Buffer, vertex_array.buffers.len());
+        self.resources.deleted(Resource::Vao, 1);
+    }
+    /// Creates a texture and starts loading the images at the urls into it.
+    pub fn create_texture(
+        &self,
+        urls: &[String],
+        tex_type: TextureType,
+        is_img_obj: bool,
+    ) -> TextureId {
+        let texture = self.backend.create_texture(urls, tex_type, is_img_obj);
+        self.resources.created(Resource::Texture, 1);
+        texture
     }
-    pub fn setup_renderer(gl: &GL, render_config: RenderConfig) {
-        gl.clear_color(0.1, 0.1, 0.1, 1.0);
+    pub fn delete_texture(&self, texture: TextureId) {
+        self.backend.delete_texture(texture);
+        self.resources.deleted(Resource::Texture, 1);
+    }
+    /// The number of GPU objects that are currently alive.
+    pub fn resource_counts(&self) -> ResourceCounts {
+        self.resources.live()
+    }
+    pub fn setup_renderer(gl: &dyn Backend, render_config: RenderConfig) {
+        let [r, g, b, a] = CLEAR_COLOR;
+        gl.clear_color(r, g, b, a);
         gl.clear_depth(1.0);
         gl.depth_func(render_config.depth_fn);
         gl.front_face(render_config.front_face);
@@ -259,16 +545,38 @@ impl Renderer {
         gl.blend_func(GL::SRC_ALPHA, GL::ONE_MINUS_SRC_ALPHA);
         log!("Renderer is ready to draw");
     }
-    fn setup_lights(&self, storage: &Storage) {
-        let gl = &self.ctx;
-        let program = self.shaders.get(&ShaderType::Color).unwrap();
-        gl.use_program(Some(&program));
+    fn setup_lights(&self, storage: &Storage, shadows: &Shadows) {
+        let color = self.program(ShaderType::Color);
+        self.backend.use_program(Some(color));
+        self.set_lights(storage, shadows, color, true, true);
+        // the pbr shader doesn't attenuate directional lights, so it has no use for their
+        // position
+        let pbr = self.program(ShaderType::Pbr);
+        self.backend.use_program(Some(pbr));
+        self.set_lights(storage, shadows, pbr, false, true);
+    }
+    /// Writes the lights of the scene, and their shadow maps, to the uniforms of the lit program
+    /// in use. Point lights are left out for programs that light them some other way.
+    fn set_lights(
+        &self,
+        storage: &Storage,
+        shadows: &Shadows,
+        program: ProgramId,
+        directional_position: bool,
+        point_lights: bool,
+    ) {
+        let gl = &*self.backend;
         let mut num_l_amb = 0;
         let mut num_l_point = 0;
         let mut num_l_dir = 0;
         let mut num_l_spot = 0;
-        for light in storage.lights() {
-            if !light.light {
+        self.set_shadow_uniforms(program, shadows);
+        for id in storage.light_ids() {
+            let light = storage.light(id);
+            if !light.light
+                || !storage.is_valid(light.node_id)
+                || (light.light_type == LightType::Point && !point_lights)
+            {
                 continue;
             }
             match light.light_type {
@@ -295,64 +603,70 @@ impl Renderer {
                     } else {
                         ("dir_lights", num_l_dir)
                     };
-                    let position = (storage.parent_tranform(light.node_id)
-                        * storage.transform(light.node_id))
-                    .isometry
-                    .translation
-                    .vector
-                    .data;
-                    let range = 100.;
-                    let linear = 4.5 / range;
-                    let quadratic = 7.5 / (range * range);
-                    set_f32(
-                        gl,
-                        program,
-                        &format!("{}[{}].linear", attrib, index),
-                        linear,
-                    );
-                    set_f32(
-                        gl,
-                        program,
-                        &format!("{}[{}].quadratic", attrib, index),
-                        quadratic,
-                    );
-                    set_vec3(
-                        gl,
-                        program,
-                        &format!("{}[{}].position", attrib, index),
-                        &position,
-                    );
+                    let node_id = light.node_id.index();
+                    let position = storage
+                        .world_transform(node_id)
+                        .isometry
+                        .translation
+                        .vector
+                        .data;
+                    if light.light_type != LightType::Directional || directional_position {
+                        set_f32(
+                            gl,
+                            program,
+                            &format!("{}[{}].linear", attrib, index),
+                            LIGHT_LINEAR,
+                        );
+                        set_f32(
+                            gl,
+                            program,
+                            &format!("{}[{}].quadratic", attrib, index),
+                            LIGHT_QUADRATIC,
+                        );
+                        set_vec3(
+                            gl,
+                            program,
+                            &format!("{}[{}].position", attrib, index),
+                            &position,
+                        );
+                    }
                     if light.light_type == LightType::Directional
                         || light.light_type == LightType::Spot
                     {
-                        let vector = (storage.parent_tranform(light.node_id)
-                            * storage.transform(light.node_id))
-                        .isometry
-                        .rotation
-                        .transform_vector(&Vector3::identity());
-                        // The cone and arrows mesh is intrinsically oriented 90 deg
-                        let direction = UnitQuaternion::from_euler_angles(0., PI / 2., 0.)
-                            .transform_vector(&vector)
-                            .data;
+                        let direction = light_direction(storage, node_id).data;
                         set_vec3(
                             gl,
                             program,
                             &format!("{}[{}].direction", attrib, index),
                             &direction,
                         );
+                        let (shadow, cascades, bias) = shadows.light(id);
+                        set_i32(gl, program, &format!("{}[{}].shadow", attrib, index), shadow);
+                        set_i32(
+                            gl,
+                            program,
+                            &format!("{}[{}].cascades", attrib, index),
+                            cascades,
+                        );
+                        set_f32(
+                            gl,
+                            program,
+                            &format!("{}[{}].shadow_bias", attrib, index),
+                            bias,
+                        );
                     }
                     if light.light_type == LightType::Spot {
                         set_f32(
                             gl,
                             program,
                             &format!("{}[{}].cutoff", attrib, index),
-                            f32::cos(PI / 30.),
+                            f32::cos(SPOT_CUTOFF),
                         );
                         set_f32(
                             gl,
                             program,
                             &format!("{}[{}].outer_cutoff", attrib, index),
-                            f32::cos(std::f32::consts::PI / 25.),
+                            f32::cos(SPOT_OUTER_CUTOFF),
                         );
                     }
                     set_vec3(
@@ -378,44 +692,46 @@ impl Renderer {
             }
         }
         set_i32(gl, program, "num_l_amb", num_l_amb as i32);
-        set_i32(gl, program, "num_l_point", num_l_point as i32);
+        if point_lights {
+            set_i32(gl, program, "num_l_point", num_l_point as i32);
+        }
         set_i32(gl, program, "num_l_dir", num_l_dir as i32);
         set_i32(gl, program, "num_l_spot", num_l_spot as i32);
     }
     fn update_viewport(&self, viewport: &Viewport) {
         for each in ShaderType::iter() {
-            if let Some(program) = self.shaders.get(&each) {
-                let gl = &self.ctx;
-                gl.use_program(Some(&program));
+            if let Some(program) = self.shaders.get(&each).copied() {
+                let gl = &*self.backend;
+                gl.use_program(Some(program));
                 if each == ShaderType::CubeMap {
                     set_mat4(
                         gl,
-                        &program,
+                        program,
                         "view",
                         &viewport.transform().rotation.to_homogeneous(),
                     );
                 } else {
-                    set_mat4(gl, &program, "view", &viewport.view());
+                    set_mat4(gl, program, "view", &viewport.view());
                 }
                 if each == ShaderType::CubeMap
                     && viewport.projection_type() == ProjectionType::Orthographic
                 {
                     set_mat4(
                         gl,
-                        &program,
+                        program,
                         "proj",
                         &viewport.get_proj(ProjectionType::Perspective).to_matrix(),
                     );
                 } else {
-                    set_mat4(gl, &program, "proj", &viewport.proj());
+                    set_mat4(gl, program, "proj", &viewport.proj());
                 }
-                if each == ShaderType::Color {
+                if each == ShaderType::Color || each == ShaderType::Pbr {
                     set_vec3(gl, program, "eye", &viewport.eye());
                 }
             }
         }
     }
-    fn set_flags(gl: &GL, render_flags: RenderFlags) {
+    fn set_flags(gl: &dyn Backend, render_flags: RenderFlags) {
         match render_flags.blend {
             true => {
                 gl.enable(GL::BLEND);
@@ -449,18 +765,73 @@ impl Renderer {
             }
         }
     }
+    /// Issues the draw call of a mesh, drawing it once per instance if it's instanced.
+    fn draw(gl: &dyn Backend, draw_mode: DrawMode, count: i32, index_type: u32, instances: Option<i32>) {
+        let mode = match draw_mode {
+            DrawMode::Points => GL::POINTS,
+            DrawMode::Lines => GL::LINES,
+            DrawMode::Triangle | DrawMode::Arrays => GL::TRIANGLES,
+        };
+        match (draw_mode, instances) {
+            (DrawMode::Arrays, None) => gl.draw_arrays(mode, 0, count),
+            (DrawMode::Arrays, Some(n)) => gl.draw_arrays_instanced(mode, 0, count, n),
+            (_, None) => gl.draw_elements(mode, count, index_type, 0),
+            (_, Some(n)) => gl.draw_elements_instanced(mode, count, index_type, 0, n),
+        }
+    }
+    /// Binds the factors and textures of a pbr material. The albedo takes the first texture
+    /// unit and the maps the ones after it.
+    fn set_pbr_material(&self, storage: &Storage, program: ProgramId, material: &Material) {
+        let gl = &*self.backend;
+        set_bool(gl, program, "flat_shade", material.flat_shade);
+        set_f32(gl, program, "metallic", material.metallic);
+        set_f32(gl, program, "roughness", material.roughness);
+        set_vec3(gl, program, "emissive", &material.emissive);
+        let bind = |unit: u32, sampler: &str, tex_i: usize| {
+            gl.active_texture(GL::TEXTURE0 + unit);
+            gl.bind_texture(GL::TEXTURE_2D, Some(storage.texture(tex_i)));
+            set_i32(gl, program, sampler, unit as i32);
+        };
+        let albedo = self.albedo_texture(storage, material);
+        set_bool(gl, program, "has_albedo", albedo.is_some());
+        if let Some(texture) = albedo {
+            gl.active_texture(GL::TEXTURE0);
+            gl.bind_texture(GL::TEXTURE_2D, Some(texture));
+            set_i32(gl, program, "sampler", 0);
+        }
+        let maps = [
+            (&material.normal_map, "normal_map", Some("normal_scale")),
+            (&material.occlusion_map, "occlusion_map", Some("occlusion_strength")),
+            (&material.metallic_roughness_map, "metallic_roughness_map", None),
+        ];
+        for (unit, (map, sampler, strength)) in maps.iter().enumerate() {
+            let flag = format!("has_{}", sampler);
+            match map.as_ref().and_then(|map| map.texture_index.map(|i| (map, i))) {
+                Some((map, tex_i)) => {
+                    set_bool(gl, program, &flag, true);
+                    bind(unit as u32 + 1, sampler, tex_i);
+                    if let Some(strength) = strength {
+                        set_f32(gl, program, strength, map.strength);
+                    }
+                }
+                None => set_bool(gl, program, &flag, false),
+            }
+        }
+    }
     fn render_mesh(&self, storage: &Storage, i: usize) {
-        let gl = &self.ctx;
+        let gl = &*self.backend;
         if let Some(mesh) = storage.mesh(i) {
             let info = storage.info(i);
+            let instances = storage.instances(i).map(|n| n.transforms.len() as i32);
             let vao = storage.vao(i);
             gl.bind_vertex_array(vao);
             let shader_type = mesh.material.shader_type;
-            let program = self.shaders.get(&shader_type).unwrap();
-            gl.use_program(Some(&program));
+            let program = self.program(shader_type);
+            gl.use_program(Some(program));
             if shader_type == ShaderType::Simple
                 || shader_type == ShaderType::Color
                 || shader_type == ShaderType::Wireframe
+                || shader_type == ShaderType::Pbr
             {
                 set_vec4(
                     gl,
@@ -472,111 +843,105 @@ impl Renderer {
                         .expect("Can't render a color materaial without a color!"),
                 );
             }
+            if shader_type == ShaderType::Simple || shader_type == ShaderType::Color {
+                set_bool(gl, program, "instanced", instances.is_some());
+            }
+            if shader_type == ShaderType::Color || shader_type == ShaderType::Pbr {
+                set_bool(
+                    gl,
+                    program,
+                    "receive_shadows",
+                    info.render_flags.receive_shadows,
+                );
+            }
             if shader_type == ShaderType::Color {
                 set_bool(gl, program, "flat_shade", mesh.material.flat_shade);
                 set_bool(gl, program, "blinn_shade", true);
-                if let Some(color) = mesh.material.wire_overlay {
+                if let Some(color) = mesh.material.wire_overlay.clone() {
                     let w_color: [f32; 4] = color.into();
                     set_bool(gl, program, "wire_overlay", true);
                     set_vec4(gl, program, "wire_color", &w_color[..]);
                 } else {
                     set_bool(gl, program, "wire_overlay", false);
                 }
-                if !mesh.material.texture_indices.is_empty() {
+                if let Some(texture) = self.albedo_texture(storage, &mesh.material) {
                     set_bool(gl, program, "has_albedo", true);
-                    let tex_i = mesh.material.texture_indices[0];
-                    let texture = storage.texture(tex_i);
                     gl.active_texture(GL::TEXTURE0);
-                    gl.bind_texture(GL::TEXTURE_2D, Some(&texture));
+                    gl.bind_texture(GL::TEXTURE_2D, Some(texture));
                     set_i32(gl, program, "sampler", 0);
                 } else {
                     set_bool(gl, program, "has_albedo", false);
                 }
             }
+            if shader_type == ShaderType::Pbr {
+                self.set_pbr_material(storage, program, &mesh.material);
+                self.set_environment_uniforms(program);
+            }
             if shader_type == ShaderType::CubeMap {
                 let tex_i = mesh.material.texture_indices[0];
                 let texture = storage.texture(tex_i);
                 gl.active_texture(GL::TEXTURE0);
-                gl.bind_texture(GL::TEXTURE_CUBE_MAP, Some(&texture));
+                gl.bind_texture(GL::TEXTURE_CUBE_MAP, Some(texture));
                 set_i32(gl, program, "sampler", 0);
+                let hdr = self
+                    .environment
+                    .get()
+                    .is_some_and(|e| e.source == texture && e.hdr);
+                set_bool(gl, program, "hdr", hdr);
             }
-            let model = storage.parent_tranform(i) * storage.transform(i);
+            let model = storage.world_transform(i);
             if shader_type != ShaderType::CubeMap {
                 set_mat4(gl, program, "model", &model.to_homogeneous());
             }
             let indices = &mesh.geometry.indices;
-            bind_index_buffer(gl, &indices).expect("Can't bind index buffer!");
+            let gl_index_type = gl_index_type(mesh.geometry.index_type());
 
             if shader_type == ShaderType::Wireframe {
                 gl.enable(GL::SAMPLE_ALPHA_TO_COVERAGE);
-                set_f32(gl, &program, "width", 1.0);
-                set_f32(gl, &program, "feather", 0.5);
+                set_f32(gl, program, "width", 1.0);
+                set_f32(gl, program, "feather", 0.5);
                 set_bool(gl, program, "drawing_points", false);
             } else {
                 gl.disable(GL::SAMPLE_ALPHA_TO_COVERAGE);
             }
-            Self::set_flags(&gl, info.render_flags);
+            Self::set_flags(gl, info.render_flags);
             if mesh.material.outline != None && shader_type != ShaderType::Wireframe {
                 // first pass for object outline
                 gl.stencil_func(GL::ALWAYS, 1, 0xFF);
                 gl.stencil_mask(0xFF);
             }
-            match info.draw_mode {
-                DrawMode::Arrays => {
-                    gl.draw_arrays(GL::TRIANGLES, 0, indices.len() as i32);
-                }
-                DrawMode::Lines => {
-                    gl.draw_elements_with_i32(
-                        GL::LINES,
-                        indices.len() as i32,
-                        GL::UNSIGNED_SHORT,
-                        0,
-                    );
-                }
-                DrawMode::Points => {
-                    gl.draw_elements_with_i32(
-                        GL::POINTS,
-                        indices.len() as i32,
-                        GL::UNSIGNED_SHORT,
-                        0,
-                    );
-                }
-                DrawMode::Triangle => {
-                    gl.draw_elements_with_i32(
-                        GL::TRIANGLES,
-                        indices.len() as i32,
-                        GL::UNSIGNED_SHORT,
-                        0,
-                    );
-                }
-            }
-            if let Some(scale) = mesh.material.outline {
+            Self::draw(
+                gl,
+                info.draw_mode,
+                indices.len() as i32,
+                gl_index_type,
+                instances,
+            );
+            // outlines are scaled around the node's origin, which doesn't suit instanced meshes
+            if let (Some(scale), None) = (mesh.material.outline, instances) {
                 // second pass for drawing outlines
                 if shader_type == ShaderType::Wireframe {
-                    set_f32(gl, &program, "width", 3.0);
-                    set_vec4(gl, &program, "color", &[1., 1., 0., 0.8]);
+                    set_f32(gl, program, "width", 3.0);
+                    set_vec4(gl, program, "color", &[1., 1., 0., 0.8]);
                     gl.draw_arrays(GL::TRIANGLES, 0, indices.len() as i32);
                 } else {
                     gl.stencil_func(GL::NOTEQUAL, 1, 0xFF);
                     gl.stencil_mask(0x00);
-                    let program = self.shaders.get(&ShaderType::Simple).unwrap();
-                    gl.use_program(Some(&program));
-                    let model = storage.parent_tranform(i)
-                        * storage.transform(i)
-                        * Transform::from_scale(mesh.material.outline.unwrap());
-                    set_mat4(gl, &program, "model", &model.to_homogeneous());
-                    set_vec4(gl, &program, "color", &[1., 1., 0., 1.]);
-                    let indices = &mesh.geometry.indices;
-                    bind_index_buffer(gl, &indices).expect("Can't bind index buffer!");
+                    let program = self.program(ShaderType::Simple);
+                    gl.use_program(Some(program));
+                    set_bool(gl, program, "instanced", false);
+                    let model = storage.world_transform(i) * Transform::from_scale(scale);
+                    set_mat4(gl, program, "model", &model.to_homogeneous());
+                    set_vec4(gl, program, "color", &[1., 1., 0., 1.]);
                     match storage.info(i).draw_mode {
                         DrawMode::Arrays => {
                             gl.draw_arrays(GL::TRIANGLES, 0, indices.len() as i32);
                         }
                         _ => {
-                            gl.draw_elements_with_i32(
+                            gl.draw_elements(
                                 GL::TRIANGLES,
                                 indices.len() as i32,
-                                GL::UNSIGNED_SHORT,
+                                gl_index_type,
                                 0,
                             );
                         }
@@ -588,23 +953,56 @@ impl Renderer {
         }
     }
     pub fn render(&self, scene: &Scene, viewport: &Viewport) {
-        let gl = &self.ctx;
-        gl.clear(GL::COLOR_BUFFER_BIT | GL::DEPTH_BUFFER_BIT | GL::STENCIL_BUFFER_BIT);
+        let gl = &*self.backend;
+        self.update_environment();
         let storage = scene.storage();
+        {
+            let mut storage = storage.borrow_mut();
+            storage.update_world_transforms();
+            storage.update_spatial_index();
+        }
         let storage = storage.borrow();
-        self.setup_lights(&storage);
-        let len = storage.meshes().len();
+        let culled = self.frustum_culled(&storage, viewport);
+        self.culled.set(culled.iter().filter(|c| **c).count());
+        let shadows = self.render_shadows(&storage, viewport);
+        let deferred = self.config.pipeline == Pipeline::Deferred;
+        if deferred {
+            self.render_deferred(&storage, viewport, &shadows, &culled);
+        }
+        let offscreen = self.begin_scene_target();
+        gl.clear(GL::COLOR_BUFFER_BIT | GL::DEPTH_BUFFER_BIT | GL::STENCIL_BUFFER_BIT);
+        self.setup_lights(&storage, &shadows);
         self.update_viewport(viewport);
+        if deferred {
+            self.resolve_deferred();
+        }
+        let skip = |i: usize| culled[i] || (deferred && is_deferred(&storage, i));
+        // editor overlays are drawn after post processing, so they keep their colors
+        self.render_stages(&storage, &skip, || {
+            if offscreen {
+                self.post_process(viewport);
+            }
+        });
+    }
+    /// Draws the meshes with depth, then the skybox, then calls `before_overlays` and draws the
+    /// meshes without depth. Meshes that `skip` returns true for are left out.
+    fn render_stages(
+        &self,
+        storage: &Storage,
+        skip: &dyn Fn(usize) -> bool,
+        before_overlays: impl FnOnce(),
+    ) {
+        let gl = &*self.backend;
+        let len = storage.meshes().len();
         let render_stage = |condition: Box<dyn Fn(RenderFlags, Option<ShaderType>) -> bool>| {
             for i in 0..len {
+                if !storage.is_alive(i) || skip(i) {
+                    continue;
+                }
                 let info = storage.info(i);
-                let shader_type = if let Some(mesh) = storage.mesh(i) {
-                    Some(mesh.material.shader_type)
-                } else {
-                    None
-                };
+                let shader_type = storage.mesh(i).map(|mesh| mesh.material.shader_type);
                 if info.render_flags.render && condition(info.render_flags, shader_type) {
-                    self.render_mesh(&storage, i);
+                    self.render_mesh(storage, i);
                 }
             }
         };
@@ -616,6 +1014,7 @@ impl Renderer {
         render_stage(Box::new(|r_f, shader_type| {
             shader_type == Some(ShaderType::CubeMap)
         }));
+        before_overlays();
         // render depthless mesh
         render_stage(Box::new(|r_f, _| !r_f.depth));
         gl.bind_vertex_array(None);
@@ -623,35 +1022,52 @@ impl Renderer {
         gl.bind_buffer(GL::ELEMENT_ARRAY_BUFFER, None);
         gl.use_program(None);
     }
+    /// Fits the canvas to the window again. A renderer without a canvas keeps its size.
     pub fn resize(&mut self) {
         log!("Renderer resized");
-        self.aspect_ratio = resize_canvas(&self.canvas, self.config.pixel_ratio);
+        #[cfg(feature = "web")]
+        if let Some(canvas) = self.canvas.as_ref() {
+            self.aspect_ratio = resize_canvas(canvas, self.config.pixel_ratio);
+            self.width = canvas.width();
+            self.height = canvas.height();
+        }
         // log!("New aspect ratio: {:?}", self.aspect_ratio());
-        self.ctx.viewport(
-            0,
-            0,
-            self.canvas.width() as i32,
-            self.canvas.height() as i32,
-        );
+        self.backend
+            .viewport(0, 0, self.width as i32, self.height as i32);
+        self.resize_render_targets();
+    }
+    /// Changes the size of a renderer without a canvas, as `resize` does for the canvas.
+    pub fn set_size(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.aspect_ratio = width as f32 / height as f32;
+        self.resize();
     }
-    pub fn context(&self) -> &GL {
-        &self.ctx
+    pub fn backend(&self) -> Rc<dyn Backend> {
+        self.backend.clone()
     }
+    #[cfg(feature = "web")]
     pub fn canvas(&self) -> &HtmlCanvasElement {
-        &self.canvas
+        self.canvas
+            .as_ref()
+            .expect("This renderer doesn't draw to a canvas!")
     }
     pub fn aspect_ratio(&self) -> f32 {
         self.aspect_ratio
     }
     pub fn width(&self) -> u32 {
-        self.canvas.width()
+        self.width
     }
     pub fn height(&self) -> u32 {
-        self.canvas.height()
+        self.height
     }
+    #[cfg(feature = "web")]
     pub fn change_cursor(&self, cursory_type: CursorType) {
-        let canvas_style = self
-            .canvas
+        let canvas = match self.canvas.as_ref() {
+            Some(canvas) => canvas,
+            None => return,
+        };
+        let canvas_style = canvas
             .clone()
             .dyn_into::<HtmlElement>()
             .unwrap()
@@ -0,0 +1,239 @@
+//! Picking: the scene is drawn once more into a target of a single pixel, through a projection
+//! narrowed down to the pixel that was clicked. Every mesh writes the index of its node and its
+//! instance into an integer attachment, which is read back. A pick costs a draw call per mesh
+//! whose bounds reach the pixel, however many vertices the meshes have, and finds whatever is
+//! drawn nearest under the pixel.
+
+use super::{
+    gl as GL, gl_index_type, set_bool, set_mat4, set_u32, Backend, FramebufferId, Frustum,
+    ProgramId, RenderbufferId, Renderer, Resource, ShaderType,
+};
+use crate::{controller::Viewport, scene::Handle, scene::Scene, Storage};
+use nalgebra::{Matrix4, Point3};
+use ncollide3d::query::Ray;
+use std::collections::HashMap;
+
+/// What is drawn under a pixel of the canvas.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Pick {
+    /// The node whose mesh is drawn there. Owned children, like the parts of a gizmo, are
+    /// picked themselves rather than their owner.
+    pub node: Handle,
+    /// The instance drawn there, 0 for meshes that aren't instanced.
+    pub instance: usize,
+    /// Where the mesh is drawn at the center of the pixel, in world space.
+    pub position: Point3<f32>,
+    /// The index of the triangle drawn there, for meshes drawn as triangles. It's found by
+    /// casting the ray through the center of the pixel at the node, so it's None when that
+    /// misses the triangles at their very edge.
+    pub triangle: Option<usize>,
+}
+
+/// The programs that write the ids of meshes, one for each shader whose vaos they draw.
+#[derive(Debug)]
+pub(super) struct PickPrograms {
+    programs: HashMap<ShaderType, ProgramId>,
+}
+
+impl PickPrograms {
+    pub(super) const COUNT: usize = 5;
+
+    pub(super) fn new(gl: &dyn Backend, shaders: &HashMap<ShaderType, ProgramId>) -> Self {
+        let programs = [
+            ShaderType::Simple,
+            ShaderType::Wireframe,
+            ShaderType::Color,
+            ShaderType::VertexColor,
+            ShaderType::Pbr,
+        ]
+        .iter()
+        .map(|shader_type| (*shader_type, Self::create(gl, shaders[shader_type])))
+        .collect();
+        Self { programs }
+    }
+    /// A mesh's vao is set up for the attribute locations of its own shader, so the pick
+    /// program takes the same locations.
+    fn create(gl: &dyn Backend, drawn: ProgramId) -> ProgramId {
+        let position = gl.attrib_location(drawn, "position");
+        let instance_model = match gl.attrib_location(drawn, "instance_model") {
+            location if location < 0 => position + 1,
+            location => location,
+        };
+        let vertex = include_str!("shaders/pick.vert")
+            .replace("POSITION_LOCATION", &position.to_string())
+            .replace("INSTANCE_MODEL_LOCATION", &instance_model.to_string());
+        gl.create_program(&vertex, include_str!("shaders/pick.frag"))
+            .expect("Can't create pick shader!")
+    }
+    fn program(&self, shader_type: ShaderType) -> Option<ProgramId> {
+        self.programs.get(&shader_type).copied()
+    }
+}
+
+/// The single pixel target the ids are drawn into.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(super) struct PickTarget {
+    framebuffer: FramebufferId,
+    /// RGBA32UI: the node index plus one, the instance, and the bits of the depth.
+    id: RenderbufferId,
+    depth: RenderbufferId,
+}
+
+impl Renderer {
+    /// Finds what's drawn under a pixel of the canvas, counted from its top left corner, when
+    /// the scene is seen from the viewport. Meshes without depth are drawn over the others, as
+    /// they are on the canvas, and skyboxes are never picked.
+    pub fn pick(&self, scene: &Scene, viewport: &Viewport, x: f32, y: f32) -> Option<Pick> {
+        let (width, height) = (self.width as f32, self.height as f32);
+        if x < 0. || y < 0. || x >= width || y >= height {
+            return None;
+        }
+        let gl = &*self.backend;
+        let storage = scene.storage();
+        {
+            let mut storage = storage.borrow_mut();
+            storage.update_world_transforms();
+            storage.update_spatial_index();
+        }
+        let storage = storage.borrow();
+        // the center of the pixel in normalized device coordinates
+        let center_x = (2. * x.floor() + 1.) / width - 1.;
+        let center_y = 1. - (2. * y.floor() + 1.) / height;
+        // scales clip space so that the pixel covers all of it
+        #[rustfmt::skip]
+        let narrow = Matrix4::new(
+            width, 0., 0., -center_x * width,
+            0., height, 0., -center_y * height,
+            0., 0., 1., 0.,
+            0., 0., 0., 1.,
+        );
+        let proj = narrow * viewport.proj();
+        let view = viewport.view();
+        // only the meshes that may cover the pixel are drawn
+        let mut nearby = storage
+            .spatial_index()
+            .query_frustum(&Frustum::new(&(proj * view)));
+        nearby.sort_unstable();
+        let target = self.pick_target();
+        gl.bind_framebuffer(GL::FRAMEBUFFER, Some(target.framebuffer));
+        gl.viewport(0, 0, 1, 1);
+        for capability in [GL::BLEND, GL::STENCIL_TEST, GL::SAMPLE_ALPHA_TO_COVERAGE].iter() {
+            gl.disable(*capability);
+        }
+        gl.clear_buffer_u32(GL::COLOR, 0, [0; 4]);
+        gl.clear(GL::DEPTH_BUFFER_BIT);
+        for depth in [true, false].iter() {
+            for i in nearby.iter() {
+                self.render_pick_id(&storage, *i, *depth, &view, &proj);
+            }
+        }
+        let pixel = gl.read_pixels_u32(0, 0, 1, 1);
+        gl.bind_framebuffer(GL::FRAMEBUFFER, None);
+        gl.viewport(0, 0, self.width as i32, self.height as i32);
+
+        let index = (pixel[0] as usize).checked_sub(1)?;
+        if !storage.is_alive(index) {
+            return None;
+        }
+        let node = storage.handle_at(index);
+        drop(storage);
+        let instance = pixel[1] as usize;
+        let inv_view_proj = (viewport.proj() * view).try_inverse()?;
+        let unproject = |z: f32| inv_view_proj.transform_point(&Point3::new(center_x, center_y, z));
+        let position = unproject(f32::from_bits(pixel[2]) * 2. - 1.);
+        let near = unproject(-1.);
+        let triangle = scene
+            .raycast(&Ray::new(near, unproject(1.) - near), |n| {
+                n.handle() == node
+            })
+            .into_iter()
+            .find(|hit| hit.instance == instance)
+            .map(|hit| hit.triangle);
+        Some(Pick {
+            node,
+            instance,
+            position,
+            triangle,
+        })
+    }
+    /// The pick target, created the first time something is picked.
+    fn pick_target(&self) -> PickTarget {
+        if let Some(target) = self.pick_target.get() {
+            return target;
+        }
+        let gl = &*self.backend;
+        let framebuffer = gl.create_framebuffer();
+        gl.bind_framebuffer(GL::FRAMEBUFFER, Some(framebuffer));
+        let id = gl.create_renderbuffer(0, GL::RGBA32UI, 1, 1);
+        gl.framebuffer_renderbuffer(GL::FRAMEBUFFER, GL::COLOR_ATTACHMENT0, Some(id));
+        let depth = gl.create_renderbuffer(0, GL::DEPTH_COMPONENT24, 1, 1);
+        gl.framebuffer_renderbuffer(GL::FRAMEBUFFER, GL::DEPTH_ATTACHMENT, Some(depth));
+        gl.bind_framebuffer(GL::FRAMEBUFFER, None);
+        self.resources.created(Resource::Framebuffer, 1);
+        self.resources.created(Resource::Renderbuffer, 2);
+        let target = PickTarget {
+            framebuffer,
+            id,
+            depth,
+        };
+        self.pick_target.set(Some(target));
+        target
+    }
+    /// Draws the id of a mesh that's rendered, if it has depth or doesn't as asked.
+    fn render_pick_id(
+        &self,
+        storage: &Storage,
+        i: usize,
+        depth: bool,
+        view: &Matrix4<f32>,
+        proj: &Matrix4<f32>,
+    ) {
+        if !storage.is_alive(i) {
+            return;
+        }
+        let mesh = match storage.mesh(i) {
+            Some(mesh) => mesh,
+            None => return,
+        };
+        let info = storage.info(i);
+        let flags = info.render_flags;
+        if !flags.render || flags.depth != depth {
+            return;
+        }
+        let program = match self.pick_programs.program(mesh.material.shader_type) {
+            Some(program) => program,
+            None => return,
+        };
+        let gl = &*self.backend;
+        if depth {
+            gl.enable(GL::DEPTH_TEST);
+        } else {
+            gl.disable(GL::DEPTH_TEST);
+        }
+        if flags.cull_face {
+            gl.enable(GL::CULL_FACE);
+        } else {
+            gl.disable(GL::CULL_FACE);
+        }
+        let instances = storage.instances(i).map(|n| n.transforms.len() as i32);
+        gl.bind_vertex_array(storage.vao(i));
+        gl.use_program(Some(program));
+        set_mat4(gl, program, "view", view);
+        set_mat4(gl, program, "proj", proj);
+        set_mat4(
+            gl,
+            program,
+            "model",
+            &storage.world_transform(i).to_homogeneous(),
+        );
+        set_bool(gl, program, "instanced", instances.is_some());
+        set_u32(gl, program, "node", i as u32 + 1);
+        Self::draw(
+            gl,
+            info.draw_mode,
+            mesh.geometry.indices.len() as i32,
+            gl_index_type(mesh.geometry.index_type()),
+            instances,
+        );
+    }
+}
@@ -0,0 +1,421 @@
+//! Post processing: when HDR or post effects are on, the scene is drawn into a floating point
+//! target instead of the canvas. Tone mapping and then each post effect run as fullscreen passes
+//! over it, reading the output of the pass before along with the depth of the scene, and the last
+//! pass draws to the canvas.
+
+use super::{
+    gl as GL, set_f32, set_i32, set_mat4, Backend, FramebufferId, ProgramId, Renderer, Resource,
+    TextureId, CLEAR_COLOR,
+};
+use crate::{controller::Viewport, TextureType};
+
+/// The texture unit of the depth of the scene. The color read by a pass takes unit 0.
+const DEPTH_UNIT: u32 = 1;
+/// The texture unit of the color grading LUT.
+const LUT_UNIT: u32 = 2;
+
+/// A screen space effect applied to the image of the scene.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PostEffect {
+    /// Fast approximate antialiasing, which smooths the edges it finds in the image.
+    Fxaa,
+    /// Makes the parts of the image brighter than the threshold glow.
+    Bloom { threshold: f32, intensity: f32 },
+    /// Screen space ambient occlusion, which darkens creases within `radius` world units.
+    Ssao { radius: f32, intensity: f32 },
+    /// Darkens the corners of the image. The darkening starts `smoothness` away from them,
+    /// where 1 is the center of the image.
+    Vignette { intensity: f32, smoothness: f32 },
+    /// Maps colors through a LUT image, a strip of N squares of N by N texels as used by most
+    /// engines. `intensity` blends between the original and graded colors.
+    ColorGrading { lut: String, intensity: f32 },
+    /// Blurs what's nearer or farther than the focus distance, at most by `max_blur` pixels
+    /// once it's `focus_range` away from it.
+    DepthOfField {
+        focus_distance: f32,
+        focus_range: f32,
+        max_blur: f32,
+    },
+}
+
+impl PostEffect {
+    /// Whether the pass reads lower mip levels of its input.
+    fn blurs(&self) -> bool {
+        matches!(
+            self,
+            PostEffect::Bloom { .. } | PostEffect::DepthOfField { .. }
+        )
+    }
+    /// Whether the pass reads the depth of the scene.
+    fn reads_depth(&self) -> bool {
+        matches!(
+            self,
+            PostEffect::Ssao { .. } | PostEffect::DepthOfField { .. }
+        )
+    }
+}
+
+/// A fullscreen pass of the post processing chain.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Pass<'a> {
+    ToneMapping,
+    Effect(&'a PostEffect),
+}
+
+/// The programs of the post processing passes.
+#[derive(Debug)]
+pub(super) struct PostPrograms {
+    tone_mapping: ProgramId,
+    fxaa: ProgramId,
+    bloom: ProgramId,
+    ssao: ProgramId,
+    vignette: ProgramId,
+    color_grading: ProgramId,
+    depth_of_field: ProgramId,
+}
+
+impl PostPrograms {
+    pub(super) const COUNT: usize = 7;
+
+    pub(super) fn new(gl: &dyn Backend) -> Self {
+        let create = |fragment: &str, name: &str| {
+            gl.create_program(include_str!("shaders/fullscreen.vert"), fragment)
+                .unwrap_or_else(|_| panic!("Can't create {} shader!", name))
+        };
+        Self {
+            tone_mapping: create(include_str!("shaders/tonemap.frag"), "tone mapping"),
+            fxaa: create(include_str!("shaders/fxaa.frag"), "fxaa"),
+            bloom: create(include_str!("shaders/bloom.frag"), "bloom"),
+            ssao: create(include_str!("shaders/ssao.frag"), "ssao"),
+            vignette: create(include_str!("shaders/vignette.frag"), "vignette"),
+            color_grading: create(include_str!("shaders/color_grading.frag"), "color grading"),
+            depth_of_field: create(
+                include_str!("shaders/depth_of_field.frag"),
+                "depth of field",
+            ),
+        }
+    }
+    fn program(&self, pass: Pass) -> ProgramId {
+        match pass {
+            Pass::ToneMapping => self.tone_mapping,
+            Pass::Effect(PostEffect::Fxaa) => self.fxaa,
+            Pass::Effect(PostEffect::Bloom { .. }) => self.bloom,
+            Pass::Effect(PostEffect::Ssao { .. }) => self.ssao,
+            Pass::Effect(PostEffect::Vignette { .. }) => self.vignette,
+            Pass::Effect(PostEffect::ColorGrading { .. }) => self.color_grading,
+            Pass::Effect(PostEffect::DepthOfField { .. }) => self.depth_of_field,
+        }
+    }
+}
+
+/// The floating point target the scene is drawn into, the size of the canvas.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(super) struct SceneTarget {
+    framebuffer: FramebufferId,
+    /// With a full mip chain, which the blurring passes fill in.
+    color: TextureId,
+    /// Depth and stencil, the latter for outlines.
+    depth_stencil: TextureId,
+    width: u32,
+    height: u32,
+}
+
+/// The two textures the passes between the scene target and the canvas take turns drawing
+/// into.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(super) struct PostBuffers {
+    framebuffers: [FramebufferId; 2],
+    textures: [TextureId; 2],
+    width: u32,
+    height: u32,
+}
+
+/// The mip levels of a full chain down to 1x1.
+fn mip_levels(width: u32, height: u32) -> i32 {
+    (32 - width.max(height).max(1).leading_zeros()) as i32
+}
+
+impl Renderer {
+    pub fn post_effects(&self) -> &[PostEffect] {
+        &self.post_effects
+    }
+    /// Replaces the post effects, which run in the given order. The targets they draw into are
+    /// released once there are none left.
+    pub fn set_post_effects(&mut self, effects: Vec<PostEffect>) {
+        self.post_effects = effects;
+        let luts: Vec<String> = self.luts.borrow().keys().cloned().collect();
+        for url in luts {
+            let used = self.post_effects.iter().any(|effect| match effect {
+                PostEffect::ColorGrading { lut, .. } => *lut == url,
+                _ => false,
+            });
+            if !used {
+                if let Some(texture) = self.luts.borrow_mut().remove(&url) {
+                    self.delete_texture(texture);
+                }
+            }
+        }
+        if self.post_effects.is_empty() {
+            if let Some(buffers) = self.post_buffers.take() {
+                self.delete_post_buffers(buffers);
+            }
+        }
+        self.release_scene_target();
+    }
+    /// Binds the scene target if the frame is tone mapped or post processed, and has the
+    /// forward shaders write linear colors with HDR on. Returns whether the frame goes through
+    /// the target.
+    pub(super) fn begin_scene_target(&self) -> bool {
+        if !self.config.hdr && self.post_effects.is_empty() {
+            return false;
+        }
+        let gl = &*self.backend;
+        let target = self.scene_target();
+        gl.bind_framebuffer(GL::FRAMEBUFFER, Some(target.framebuffer));
+        let [r, g, b, a] = self.scene_clear_color();
+        gl.clear_color(r, g, b, a);
+        self.set_linear_output(self.config.hdr);
+        true
+    }
+    /// Runs tone mapping and the post effects over the scene target, the last of them drawing to
+    /// the canvas, where the meshes without depth are drawn afterwards.
+    pub(super) fn post_process(&self, viewport: &Viewport) {
+        let target = match self.scene_target.get() {
+            Some(target) => target,
+            None => return,
+        };
+        let gl = &*self.backend;
+        let mut passes = Vec::new();
+        if self.config.hdr {
+            passes.push(Pass::ToneMapping);
+        }
+        passes.extend(self.post_effects.iter().map(Pass::Effect));
+        let buffers = if passes.len() > 1 {
+            Some(self.post_buffers())
+        } else {
+            None
+        };
+        gl.bind_vertex_array(None);
+        for capability in [GL::DEPTH_TEST, GL::STENCIL_TEST, GL::CULL_FACE, GL::BLEND].iter() {
+            gl.disable(*capability);
+        }
+        gl.active_texture(GL::TEXTURE0 + DEPTH_UNIT);
+        gl.bind_texture(GL::TEXTURE_2D, Some(target.depth_stencil));
+        let max_lod = (mip_levels(target.width, target.height) - 1) as f32;
+        let mut input = target.color;
+        for (i, pass) in passes.iter().enumerate() {
+            let output = buffers
+                .filter(|_| i + 1 < passes.len())
+                .map(|buffers| (buffers.framebuffers[i % 2], buffers.textures[i % 2]));
+            match output {
+                Some((framebuffer, _)) => gl.bind_framebuffer(GL::FRAMEBUFFER, Some(framebuffer)),
+                None => {
+                    gl.bind_framebuffer(GL::FRAMEBUFFER, None);
+                    let [r, g, b, a] = CLEAR_COLOR;
+                    gl.clear_color(r, g, b, a);
+                    gl.clear(GL::COLOR_BUFFER_BIT | GL::DEPTH_BUFFER_BIT | GL::STENCIL_BUFFER_BIT);
+                }
+            }
+            gl.active_texture(GL::TEXTURE0);
+            gl.bind_texture(GL::TEXTURE_2D, Some(input));
+            let program = self.post_programs.program(*pass);
+            gl.use_program(Some(program));
+            set_i32(gl, program, "color_buffer", 0);
+            match pass {
+                Pass::ToneMapping => self.set_tone_mapping_uniforms(program),
+                Pass::Effect(effect) => {
+                    if effect.blurs() {
+                        gl.generate_mipmap(GL::TEXTURE_2D);
+                        set_f32(gl, program, "max_lod", max_lod);
+                    }
+                    if effect.reads_depth() {
+                        set_i32(gl, program, "depth_buffer", DEPTH_UNIT as i32);
+                    }
+                    self.set_effect_uniforms(program, effect, viewport);
+                }
+            }
+            gl.draw_arrays(GL::TRIANGLES, 0, 3);
+            if let Some((_, texture)) = output {
+                input = texture;
+            }
+        }
+        // the targets are drawn into during the next frame, which WebGL refuses while they're
+        // bound
+        for unit in [0, DEPTH_UNIT, LUT_UNIT].iter() {
+            gl.active_texture(GL::TEXTURE0 + unit);
+            gl.bind_texture(GL::TEXTURE_2D, None);
+        }
+        gl.active_texture(GL::TEXTURE0);
+        if self.config.hdr {
+            self.set_linear_output(false);
+        }
+    }
+    fn set_effect_uniforms(&self, program: ProgramId, effect: &PostEffect, viewport: &Viewport) {
+        let gl = &*self.backend;
+        let inv_proj = viewport
+            .proj()
+            .try_inverse()
+            .unwrap_or_else(nalgebra::Matrix4::identity);
+        match effect {
+            PostEffect::Fxaa => {}
+            PostEffect::Bloom {
+                threshold,
+                intensity,
+            } => {
+                set_f32(gl, program, "threshold", *threshold);
+                set_f32(gl, program, "intensity", *intensity);
+            }
+            PostEffect::Ssao { radius, intensity } => {
+                set_mat4(gl, program, "proj", &viewport.proj());
+                set_mat4(gl, program, "inv_proj", &inv_proj);
+                set_f32(gl, program, "radius", *radius);
+                set_f32(gl, program, "intensity", *intensity);
+            }
+            PostEffect::Vignette {
+                intensity,
+                smoothness,
+            } => {
+                set_f32(gl, program, "intensity", *intensity);
+                set_f32(gl, program, "smoothness", *smoothness);
+            }
+            PostEffect::ColorGrading { lut, intensity } => {
+                let texture = self.lut(lut);
+                gl.active_texture(GL::TEXTURE0 + LUT_UNIT);
+                gl.bind_texture(GL::TEXTURE_2D, Some(texture));
+                set_i32(gl, program, "lut", LUT_UNIT as i32);
+                // colors go through untouched until the image arrives
+                let intensity = if gl.texture_ready(texture) {
+                    *intensity
+                } else {
+                    0.
+                };
+                set_f32(gl, program, "intensity", intensity);
+            }
+            PostEffect::DepthOfField {
+                focus_distance,
+                focus_range,
+                max_blur,
+            } => {
+                set_mat4(gl, program, "inv_proj", &inv_proj);
+                set_f32(gl, program, "focus_distance", *focus_distance);
+                set_f32(gl, program, "focus_range", focus_range.max(f32::EPSILON));
+                set_f32(gl, program, "max_blur", *max_blur);
+            }
+        }
+    }
+    /// The texture of a color grading LUT, which starts loading the first time it's used.
+    fn lut(&self, url: &str) -> TextureId {
+        if let Some(texture) = self.luts.borrow().get(url) {
+            return *texture;
+        }
+        let texture = self
+            .backend
+            .create_texture(&[url.to_owned()], TextureType::Tex2d, false);
+        self.resources.created(Resource::Texture, 1);
+        self.luts.borrow_mut().insert(url.to_owned(), texture);
+        texture
+    }
+    /// Releases the scene target once neither HDR nor post effects need it.
+    pub(super) fn release_scene_target(&self) {
+        if self.config.hdr || !self.post_effects.is_empty() {
+            return;
+        }
+        if let Some(target) = self.scene_target.take() {
+            self.delete_scene_target(target);
+        }
+    }
+    /// The scene target, created again whenever the size of the canvas changes.
+    fn scene_target(&self) -> SceneTarget {
+        if let Some(target) = self.scene_target.get() {
+            if (target.width, target.height) == (self.width, self.height) {
+                return target;
+            }
+            self.delete_scene_target(target);
+        }
+        let gl = &*self.backend;
+        let (width, height) = (self.width as usize, self.height as usize);
+        let levels = mip_levels(self.width, self.height);
+        let depth_stencil = self.create_map(GL::TEXTURE_2D, 1, GL::DEPTH24_STENCIL8, width, height);
+        // depth textures can't be filtered
+        gl.bind_texture(GL::TEXTURE_2D, Some(depth_stencil));
+        gl.tex_parameter(GL::TEXTURE_2D, GL::TEXTURE_MIN_FILTER, GL::NEAREST);
+        gl.tex_parameter(GL::TEXTURE_2D, GL::TEXTURE_MAG_FILTER, GL::NEAREST);
+        gl.bind_texture(GL::TEXTURE_2D, None);
+        let target = SceneTarget {
+            framebuffer: gl.create_framebuffer(),
+            color: self.create_map(GL::TEXTURE_2D, levels, GL::RGBA16F, width, height),
+            depth_stencil,
+            width: self.width,
+            height: self.height,
+        };
+        self.resources.created(Resource::Framebuffer, 1);
+        gl.bind_framebuffer(GL::FRAMEBUFFER, Some(target.framebuffer));
+        gl.framebuffer_texture_2d(
+            GL::FRAMEBUFFER,
+            GL::COLOR_ATTACHMENT0,
+            GL::TEXTURE_2D,
+            Some(target.color),
+            0,
+        );
+        gl.framebuffer_texture_2d(
+            GL::FRAMEBUFFER,
+            GL::DEPTH_STENCIL_ATTACHMENT,
+            GL::TEXTURE_2D,
+            Some(target.depth_stencil),
+            0,
+        );
+        gl.bind_framebuffer(GL::FRAMEBUFFER, None);
+        self.scene_target.set(Some(target));
+        target
+    }
+    fn delete_scene_target(&self, target: SceneTarget) {
+        self.backend.delete_framebuffer(target.framebuffer);
+        self.resources.deleted(Resource::Framebuffer, 1);
+        self.delete_texture(target.color);
+        self.delete_texture(target.depth_stencil);
+    }
+    /// The textures between the passes, created again whenever the size of the canvas changes.
+    fn post_buffers(&self) -> PostBuffers {
+        if let Some(buffers) = self.post_buffers.get() {
+            if (buffers.width, buffers.height) == (self.width, self.height) {
+                return buffers;
+            }
+            self.delete_post_buffers(buffers);
+        }
+        let gl = &*self.backend;
+        let levels = mip_levels(self.width, self.height);
+        let (width, height) = (self.width as usize, self.height as usize);
+        let buffers = PostBuffers {
+            framebuffers: [gl.create_framebuffer(), gl.create_framebuffer()],
+            textures: [
+                self.create_map(GL::TEXTURE_2D, levels, GL::RGBA16F, width, height),
+                self.create_map(GL::TEXTURE_2D, levels, GL::RGBA16F, width, height),
+            ],
+            width: self.width,
+            height: self.height,
+        };
+        self.resources.created(Resource::Framebuffer, 2);
+        for (framebuffer, texture) in buffers.framebuffers.iter().zip(buffers.textures.iter()) {
+            gl.bind_framebuffer(GL::FRAMEBUFFER, Some(*framebuffer));
+            gl.framebuffer_texture_2d(
+                GL::FRAMEBUFFER,
+                GL::COLOR_ATTACHMENT0,
+                GL::TEXTURE_2D,
+                Some(*texture),
+                0,
+            );
+        }
+        gl.bind_framebuffer(GL::FRAMEBUFFER, None);
+        self.post_buffers.set(Some(buffers));
+        buffers
+    }
+    fn delete_post_buffers(&self, buffers: PostBuffers) {
+        for framebuffer in buffers.framebuffers.iter() {
+            self.backend.delete_framebuffer(*framebuffer);
+        }
+        self.resources.deleted(Resource::Framebuffer, 2);
+        for texture in buffers.textures.iter() {
+            self.delete_texture(*texture);
+        }
+    }
+}
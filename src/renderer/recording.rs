@@ -0,0 +1,835 @@
+use super::{backend::*, gl};
+use crate::TextureType;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// A call made to the RecordingBackend. Uploaded data isn't repeated here, it can be looked up
+/// with `RecordingBackend::buffer`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    CreateProgram(ProgramId),
+    UseProgram(Option<ProgramId>),
+    SetUniform {
+        program: ProgramId,
+        name: String,
+        value: Uniform,
+    },
+    CreateBuffer(BufferId),
+    BindBuffer {
+        target: u32,
+        buffer: Option<BufferId>,
+    },
+    BufferData {
+        target: u32,
+        buffer: Option<BufferId>,
+        len: usize,
+        usage: u32,
+    },
+    DeleteBuffer(BufferId),
+    CreateVertexArray(VaoId),
+    BindVertexArray(Option<VaoId>),
+    DeleteVertexArray(VaoId),
+    VertexAttribPointer {
+        location: u32,
+        size: i32,
+        kind: u32,
+        normalized: bool,
+        stride: i32,
+        offset: i32,
+    },
+    EnableVertexAttribArray(u32),
+    VertexAttribDivisor {
+        location: u32,
+        divisor: u32,
+    },
+    CreateTexture {
+        texture: TextureId,
+        urls: Vec<String>,
+        tex_type: TextureType,
+    },
+    CreateTextureStorage {
+        texture: TextureId,
+        target: u32,
+        levels: i32,
+        internal_format: u32,
+        width: i32,
+        height: i32,
+    },
+    TexSubImage {
+        target: u32,
+        level: i32,
+        width: i32,
+        height: i32,
+        format: u32,
+        len: usize,
+    },
+    TexParameter(u32, u32, u32),
+    GenerateMipmap(u32),
+    ActiveTexture(u32),
+    BindTexture {
+        target: u32,
+        texture: Option<TextureId>,
+    },
+    DeleteTexture(TextureId),
+    CreateFramebuffer(FramebufferId),
+    BindFramebuffer {
+        target: u32,
+        framebuffer: Option<FramebufferId>,
+    },
+    FramebufferTexture2d {
+        attachment: u32,
+        tex_target: u32,
+        texture: Option<TextureId>,
+        level: i32,
+    },
+    DrawBuffers(Vec<u32>),
+    DeleteFramebuffer(FramebufferId),
+    CreateRenderbuffer {
+        renderbuffer: RenderbufferId,
+        samples: i32,
+        internal_format: u32,
+        width: i32,
+        height: i32,
+    },
+    FramebufferRenderbuffer {
+        attachment: u32,
+        renderbuffer: Option<RenderbufferId>,
+    },
+    DeleteRenderbuffer(RenderbufferId),
+    BlitFramebuffer {
+        read: Option<FramebufferId>,
+        draw: Option<FramebufferId>,
+        src: [i32; 4],
+        dst: [i32; 4],
+        mask: u32,
+    },
+    Enable(u32),
+    Disable(u32),
+    Viewport(i32, i32, i32, i32),
+    ClearColor([f32; 4]),
+    ClearDepth(f32),
+    Clear(u32),
+    ClearBuffer {
+        buffer: u32,
+        draw_buffer: i32,
+        value: [u32; 4],
+    },
+    DepthFunc(u32),
+    FrontFace(u32),
+    CullFace(u32),
+    BlendFunc(u32, u32),
+    StencilOp(u32, u32, u32),
+    StencilFunc(u32, i32, u32),
+    StencilMask(u32),
+    ReadPixels {
+        framebuffer: Option<FramebufferId>,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    },
+    Draw(DrawCall),
+}
+
+/// A draw call along with the state it was issued in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DrawCall {
+    pub mode: u32,
+    /// The first vertex for array draws, or the byte offset into the index buffer.
+    pub first: i32,
+    pub count: i32,
+    /// The type of the indices, None for array draws.
+    pub index_type: Option<u32>,
+    pub instances: Option<i32>,
+    pub program: Option<ProgramId>,
+    pub vao: Option<VaoId>,
+    /// The framebuffer drawn into, None for the canvas.
+    pub framebuffer: Option<FramebufferId>,
+    /// Every uniform of the program in use, as last written.
+    pub uniforms: BTreeMap<String, Uniform>,
+    /// The capabilities that are enabled, e.g. `gl::DEPTH_TEST`.
+    pub enabled: BTreeSet<u32>,
+    /// The texture bound to each texture unit, starting at 0.
+    pub textures: BTreeMap<u32, TextureId>,
+}
+
+impl DrawCall {
+    pub fn uniform(&self, name: &str) -> Option<Uniform> {
+        self.uniforms.get(name).copied()
+    }
+    pub fn is_enabled(&self, capability: u32) -> bool {
+        self.enabled.contains(&capability)
+    }
+}
+
+/// The data uploaded to a buffer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BufferContents {
+    F32(Vec<f32>),
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+/// Where a vertex attribute reads its data from.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AttribPointer {
+    pub buffer: BufferId,
+    pub size: i32,
+    /// Stride and offset in bytes, as given to WebGL.
+    pub stride: i32,
+    pub offset: i32,
+    pub enabled: bool,
+    pub divisor: u32,
+}
+
+/// The attribute layout and index buffer recorded into a vertex array object.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VertexArrayState {
+    pub attributes: BTreeMap<u32, AttribPointer>,
+    pub elements: Option<BufferId>,
+}
+
+/// What a framebuffer attachment renders into.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Attachment {
+    /// TEXTURE_2D, or the face of a cubemap.
+    pub tex_target: u32,
+    pub texture: TextureId,
+    pub level: i32,
+}
+
+/// The sources of a program, its attribute locations, the uniforms it declares, and its uniforms
+/// as last written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgramState {
+    pub vertex: String,
+    pub fragment: String,
+    pub attributes: BTreeMap<String, i32>,
+    pub declared: BTreeSet<String>,
+    pub uniforms: BTreeMap<String, Uniform>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    program: Option<ProgramId>,
+    array_buffer: Option<BufferId>,
+    vao: Option<VaoId>,
+    active_texture: u32,
+    enabled: BTreeSet<u32>,
+    bound_textures: BTreeMap<u32, TextureId>,
+    programs: HashMap<ProgramId, ProgramState>,
+    buffers: HashMap<BufferId, BufferContents>,
+    vaos: HashMap<VaoId, VertexArrayState>,
+    textures: HashMap<TextureId, (Vec<String>, TextureType)>,
+    /// Textures whose images haven't arrived yet.
+    loading: BTreeSet<TextureId>,
+    framebuffer: Option<FramebufferId>,
+    /// The framebuffer blits read from, which binding FRAMEBUFFER also sets.
+    read_framebuffer: Option<FramebufferId>,
+    framebuffers: HashMap<FramebufferId, BTreeMap<u32, Attachment>>,
+    /// What the next read of pixels returns.
+    pixels: Option<Vec<u32>>,
+}
+
+/// A Backend that draws nothing and records every call made to it instead, keeping track of
+/// the state that WebGL would have. Useful to check what a frame would draw outside a browser.
+#[derive(Debug, Default)]
+pub struct RecordingBackend {
+    next_id: Cell<u32>,
+    commands: RefCell<Vec<Command>>,
+    state: RefCell<State>,
+}
+
+/// Reads the locations that WebGL would assign to the `in` attributes of a vertex shader. A
+/// matrix takes up one location per column.
+fn attribute_locations(vertex: &str) -> BTreeMap<String, i32> {
+    let mut locations = BTreeMap::new();
+    let mut next = 0;
+    for line in vertex.lines() {
+        let declaration = match line.trim().strip_prefix("in ") {
+            Some(declaration) => declaration.trim_end_matches(';'),
+            None => continue,
+        };
+        let mut parts = declaration.trim().splitn(2, char::is_whitespace);
+        let kind = parts.next().unwrap_or("");
+        let size = match kind {
+            "mat2" => 2,
+            "mat3" => 3,
+            "mat4" => 4,
+            _ => 1,
+        };
+        for name in parts.next().unwrap_or("").split(',') {
+            locations.insert(name.trim().to_string(), next);
+            next += size;
+        }
+    }
+    locations
+}
+
+/// Reads the names of the uniforms a shader declares, without the sizes of arrays.
+fn uniform_names(source: &str) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    for line in source.lines() {
+        let declaration = match line.trim().strip_prefix("uniform ") {
+            Some(declaration) => declaration.split(';').next().unwrap_or(""),
+            None => continue,
+        };
+        let mut words = declaration
+            .split_whitespace()
+            .skip_while(|word| ["lowp", "mediump", "highp"].contains(word));
+        // the type comes before the names
+        words.next();
+        let names_part = words.collect::<Vec<_>>().join(" ");
+        for name in names_part.split(',') {
+            let name = name.split('[').next().unwrap_or("").trim();
+            if !name.is_empty() {
+                names.insert(name.to_string());
+            }
+        }
+    }
+    names
+}
+
+impl RecordingBackend {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /// Every call recorded since the backend was created or last cleared.
+    pub fn commands(&self) -> Vec<Command> {
+        self.commands.borrow().clone()
+    }
+    /// Forgets the recorded calls, but not the state they built up.
+    pub fn clear(&self) {
+        self.commands.borrow_mut().clear();
+    }
+    /// The recorded draw calls, in order.
+    pub fn draw_calls(&self) -> Vec<DrawCall> {
+        self.commands
+            .borrow()
+            .iter()
+            .filter_map(|c| match c {
+                Command::Draw(draw) => Some(draw.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+    pub fn buffer(&self, buffer: BufferId) -> Option<BufferContents> {
+        self.state.borrow().buffers.get(&buffer).cloned()
+    }
+    pub fn vertex_array(&self, vao: VaoId) -> Option<VertexArrayState> {
+        self.state.borrow().vaos.get(&vao).cloned()
+    }
+    pub fn program(&self, program: ProgramId) -> Option<ProgramState> {
+        self.state.borrow().programs.get(&program).cloned()
+    }
+    /// The urls a texture was created from, along with its type.
+    pub fn texture(&self, texture: TextureId) -> Option<(Vec<String>, TextureType)> {
+        self.state.borrow().textures.get(&texture).cloned()
+    }
+    /// Textures created from urls count as loading until this is called, as if their images
+    /// were still on their way.
+    pub fn load_textures(&self) {
+        self.state.borrow_mut().loading.clear();
+    }
+    /// Sets what the next `read_pixels_u32` returns, as if the GPU had drawn it. Reads return
+    /// zeros otherwise.
+    pub fn stage_pixels(&self, pixels: Vec<u32>) {
+        self.state.borrow_mut().pixels = Some(pixels);
+    }
+    /// The framebuffer that draws go to, None for the canvas.
+    pub fn bound_framebuffer(&self) -> Option<FramebufferId> {
+        self.state.borrow().framebuffer
+    }
+    /// The attachments of a framebuffer, keyed by attachment point.
+    pub fn framebuffer(&self, framebuffer: FramebufferId) -> Option<BTreeMap<u32, Attachment>> {
+        self.state.borrow().framebuffers.get(&framebuffer).cloned()
+    }
+    fn next_id(&self) -> u32 {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        id
+    }
+    fn record(&self, command: Command) {
+        self.commands.borrow_mut().push(command);
+    }
+    fn upload(&self, target: u32, contents: BufferContents, len: usize, usage: u32) {
+        let mut state = self.state.borrow_mut();
+        let buffer = if target == gl::ELEMENT_ARRAY_BUFFER {
+            state
+                .vao
+                .and_then(|vao| state.vaos.get(&vao))
+                .and_then(|vao| vao.elements)
+        } else {
+            state.array_buffer
+        };
+        if let Some(buffer) = buffer {
+            state.buffers.insert(buffer, contents);
+        }
+        drop(state);
+        self.record(Command::BufferData {
+            target,
+            buffer,
+            len,
+            usage,
+        });
+    }
+    fn update_vao(&self, f: impl FnOnce(&mut VertexArrayState, Option<BufferId>)) {
+        let mut state = self.state.borrow_mut();
+        let array_buffer = state.array_buffer;
+        if let Some(vao) = state.vao {
+            f(state.vaos.entry(vao).or_default(), array_buffer);
+        }
+    }
+    fn draw(&self, mode: u32, first: i32, count: i32, index_type: Option<u32>, instances: Option<i32>) {
+        let state = self.state.borrow();
+        let uniforms = state
+            .program
+            .and_then(|p| state.programs.get(&p))
+            .map(|p| p.uniforms.clone())
+            .unwrap_or_default();
+        let draw = DrawCall {
+            mode,
+            first,
+            count,
+            index_type,
+            instances,
+            program: state.program,
+            vao: state.vao,
+            framebuffer: state.framebuffer,
+            uniforms,
+            enabled: state.enabled.clone(),
+            textures: state.bound_textures.clone(),
+        };
+        drop(state);
+        self.record(Command::Draw(draw));
+    }
+}
+
+impl Backend for RecordingBackend {
+    fn create_program(&self, vertex: &str, fragment: &str) -> Result<ProgramId, String> {
+        let id = ProgramId(self.next_id());
+        self.state.borrow_mut().programs.insert(
+            id,
+            ProgramState {
+                vertex: vertex.to_string(),
+                fragment: fragment.to_string(),
+                attributes: attribute_locations(vertex),
+                declared: &uniform_names(vertex) | &uniform_names(fragment),
+                uniforms: BTreeMap::new(),
+            },
+        );
+        self.record(Command::CreateProgram(id));
+        Ok(id)
+    }
+    fn use_program(&self, program: Option<ProgramId>) {
+        self.state.borrow_mut().program = program;
+        self.record(Command::UseProgram(program));
+    }
+    fn attrib_location(&self, program: ProgramId, name: &str) -> i32 {
+        self.state
+            .borrow()
+            .programs
+            .get(&program)
+            .and_then(|p| p.attributes.get(name).copied())
+            .unwrap_or(-1)
+    }
+    fn set_uniform(&self, program: ProgramId, name: &str, value: Uniform) -> bool {
+        // elements of arrays and fields of structs are declared by the name of the whole
+        let declared_as = name.split(&['[', '.'][..]).next().unwrap_or(name);
+        match self.state.borrow_mut().programs.get_mut(&program) {
+            Some(p) if p.declared.contains(declared_as) => {
+                p.uniforms.insert(name.to_string(), value);
+            }
+            _ => return false,
+        }
+        self.record(Command::SetUniform {
+            program,
+            name: name.to_string(),
+            value,
+        });
+        true
+    }
+    fn create_buffer(&self) -> BufferId {
+        let id = BufferId(self.next_id());
+        self.record(Command::CreateBuffer(id));
+        id
+    }
+    fn bind_buffer(&self, target: u32, buffer: Option<BufferId>) {
+        if target == gl::ELEMENT_ARRAY_BUFFER {
+            self.update_vao(|vao, _| vao.elements = buffer);
+        } else {
+            self.state.borrow_mut().array_buffer = buffer;
+        }
+        self.record(Command::BindBuffer { target, buffer });
+    }
+    fn buffer_data_f32(&self, target: u32, data: &[f32], usage: u32) {
+        self.upload(target, BufferContents::F32(data.to_vec()), data.len(), usage);
+    }
+    fn buffer_data_u16(&self, target: u32, data: &[u16], usage: u32) {
+        self.upload(target, BufferContents::U16(data.to_vec()), data.len(), usage);
+    }
+    fn buffer_data_u32(&self, target: u32, data: &[u32], usage: u32) {
+        self.upload(target, BufferContents::U32(data.to_vec()), data.len(), usage);
+    }
+    fn delete_buffer(&self, buffer: BufferId) {
+        self.state.borrow_mut().buffers.remove(&buffer);
+        self.record(Command::DeleteBuffer(buffer));
+    }
+    fn create_vertex_array(&self) -> VaoId {
+        let id = VaoId(self.next_id());
+        self.state.borrow_mut().vaos.insert(id, Default::default());
+        self.record(Command::CreateVertexArray(id));
+        id
+    }
+    fn bind_vertex_array(&self, vao: Option<VaoId>) {
+        self.state.borrow_mut().vao = vao;
+        self.record(Command::BindVertexArray(vao));
+    }
+    fn delete_vertex_array(&self, vao: VaoId) {
+        self.state.borrow_mut().vaos.remove(&vao);
+        self.record(Command::DeleteVertexArray(vao));
+    }
+    fn vertex_attrib_pointer(
+        &self,
+        location: u32,
+        size: i32,
+        kind: u32,
+        normalized: bool,
+        stride: i32,
+        offset: i32,
+    ) {
+        self.update_vao(|vao, array_buffer| {
+            if let Some(buffer) = array_buffer {
+                let previous = vao.attributes.get(&location).copied();
+                vao.attributes.insert(
+                    location,
+                    AttribPointer {
+                        buffer,
+                        size,
+                        stride,
+                        offset,
+                        enabled: previous.is_some_and(|p| p.enabled),
+                        divisor: previous.map_or(0, |p| p.divisor),
+                    },
+                );
+            }
+        });
+        self.record(Command::VertexAttribPointer {
+            location,
+            size,
+            kind,
+            normalized,
+            stride,
+            offset,
+        });
+    }
+    fn enable_vertex_attrib_array(&self, location: u32) {
+        self.update_vao(|vao, _| {
+            if let Some(attribute) = vao.attributes.get_mut(&location) {
+                attribute.enabled = true;
+            }
+        });
+        self.record(Command::EnableVertexAttribArray(location));
+    }
+    fn vertex_attrib_divisor(&self, location: u32, divisor: u32) {
+        self.update_vao(|vao, _| {
+            if let Some(attribute) = vao.attributes.get_mut(&location) {
+                attribute.divisor = divisor;
+            }
+        });
+        self.record(Command::VertexAttribDivisor { location, divisor });
+    }
+    fn create_texture(
+        &self,
+        urls: &[String],
+        tex_type: TextureType,
+        _is_img_obj: bool,
+    ) -> TextureId {
+        let texture = TextureId(self.next_id());
+        {
+            let mut state = self.state.borrow_mut();
+            state.textures.insert(texture, (urls.to_vec(), tex_type));
+            state.loading.insert(texture);
+        }
+        self.record(Command::CreateTexture {
+            texture,
+            urls: urls.to_vec(),
+            tex_type,
+        });
+        texture
+    }
+    fn texture_ready(&self, texture: TextureId) -> bool {
+        !self.state.borrow().loading.contains(&texture)
+    }
+    fn create_texture_storage(
+        &self,
+        target: u32,
+        levels: i32,
+        internal_format: u32,
+        width: i32,
+        height: i32,
+    ) -> TextureId {
+        let texture = TextureId(self.next_id());
+        let tex_type = if target == gl::TEXTURE_CUBE_MAP {
+            TextureType::CubeMap
+        } else {
+            TextureType::Tex2d
+        };
+        self.state
+            .borrow_mut()
+            .textures
+            .insert(texture, (Vec::new(), tex_type));
+        self.record(Command::CreateTextureStorage {
+            texture,
+            target,
+            levels,
+            internal_format,
+            width,
+            height,
+        });
+        texture
+    }
+    fn tex_sub_image_f32(
+        &self,
+        target: u32,
+        level: i32,
+        width: i32,
+        height: i32,
+        format: u32,
+        data: &[f32],
+    ) {
+        self.record(Command::TexSubImage {
+            target,
+            level,
+            width,
+            height,
+            format,
+            len: data.len(),
+        });
+    }
+    fn tex_parameter(&self, target: u32, name: u32, value: u32) {
+        self.record(Command::TexParameter(target, name, value));
+    }
+    fn generate_mipmap(&self, target: u32) {
+        self.record(Command::GenerateMipmap(target));
+    }
+    fn active_texture(&self, unit: u32) {
+        self.state.borrow_mut().active_texture = unit - gl::TEXTURE0;
+        self.record(Command::ActiveTexture(unit));
+    }
+    fn bind_texture(&self, target: u32, texture: Option<TextureId>) {
+        {
+            let mut state = self.state.borrow_mut();
+            let unit = state.active_texture;
+            match texture {
+                Some(texture) => state.bound_textures.insert(unit, texture),
+                None => state.bound_textures.remove(&unit),
+            };
+        }
+        self.record(Command::BindTexture { target, texture });
+    }
+    fn delete_texture(&self, texture: TextureId) {
+        {
+            let mut state = self.state.borrow_mut();
+            state.textures.remove(&texture);
+            state.loading.remove(&texture);
+        }
+        self.record(Command::DeleteTexture(texture));
+    }
+    fn create_framebuffer(&self) -> FramebufferId {
+        let id = FramebufferId(self.next_id());
+        self.state
+            .borrow_mut()
+            .framebuffers
+            .insert(id, Default::default());
+        self.record(Command::CreateFramebuffer(id));
+        id
+    }
+    fn bind_framebuffer(&self, target: u32, framebuffer: Option<FramebufferId>) {
+        {
+            let mut state = self.state.borrow_mut();
+            if target != gl::DRAW_FRAMEBUFFER {
+                state.read_framebuffer = framebuffer;
+            }
+            if target != gl::READ_FRAMEBUFFER {
+                state.framebuffer = framebuffer;
+            }
+        }
+        self.record(Command::BindFramebuffer {
+            target,
+            framebuffer,
+        });
+    }
+    fn framebuffer_texture_2d(
+        &self,
+        _target: u32,
+        attachment: u32,
+        tex_target: u32,
+        texture: Option<TextureId>,
+        level: i32,
+    ) {
+        {
+            let mut state = self.state.borrow_mut();
+            if let Some(framebuffer) = state.framebuffer {
+                let attachments = state.framebuffers.entry(framebuffer).or_default();
+                match texture {
+                    Some(texture) => attachments.insert(
+                        attachment,
+                        Attachment {
+                            tex_target,
+                            texture,
+                            level,
+                        },
+                    ),
+                    None => attachments.remove(&attachment),
+                };
+            }
+        }
+        self.record(Command::FramebufferTexture2d {
+            attachment,
+            tex_target,
+            texture,
+            level,
+        });
+    }
+    fn draw_buffers(&self, buffers: &[u32]) {
+        self.record(Command::DrawBuffers(buffers.to_vec()));
+    }
+    fn delete_framebuffer(&self, framebuffer: FramebufferId) {
+        {
+            let mut state = self.state.borrow_mut();
+            state.framebuffers.remove(&framebuffer);
+            if state.framebuffer == Some(framebuffer) {
+                state.framebuffer = None;
+            }
+        }
+        self.record(Command::DeleteFramebuffer(framebuffer));
+    }
+    fn create_renderbuffer(
+        &self,
+        samples: i32,
+        internal_format: u32,
+        width: i32,
+        height: i32,
+    ) -> RenderbufferId {
+        let renderbuffer = RenderbufferId(self.next_id());
+        self.record(Command::CreateRenderbuffer {
+            renderbuffer,
+            samples,
+            internal_format,
+            width,
+            height,
+        });
+        renderbuffer
+    }
+    fn framebuffer_renderbuffer(
+        &self,
+        _target: u32,
+        attachment: u32,
+        renderbuffer: Option<RenderbufferId>,
+    ) {
+        self.record(Command::FramebufferRenderbuffer {
+            attachment,
+            renderbuffer,
+        });
+    }
+    fn delete_renderbuffer(&self, renderbuffer: RenderbufferId) {
+        self.record(Command::DeleteRenderbuffer(renderbuffer));
+    }
+    fn blit_framebuffer(&self, src: [i32; 4], dst: [i32; 4], mask: u32, _filter: u32) {
+        let (read, draw) = {
+            let state = self.state.borrow();
+            (state.read_framebuffer, state.framebuffer)
+        };
+        self.record(Command::BlitFramebuffer {
+            read,
+            draw,
+            src,
+            dst,
+            mask,
+        });
+    }
+    fn enable(&self, capability: u32) {
+        self.state.borrow_mut().enabled.insert(capability);
+        self.record(Command::Enable(capability));
+    }
+    fn disable(&self, capability: u32) {
+        self.state.borrow_mut().enabled.remove(&capability);
+        self.record(Command::Disable(capability));
+    }
+    fn viewport(&self, x: i32, y: i32, width: i32, height: i32) {
+        self.record(Command::Viewport(x, y, width, height));
+    }
+    fn clear_color(&self, r: f32, g: f32, b: f32, a: f32) {
+        self.record(Command::ClearColor([r, g, b, a]));
+    }
+    fn clear_depth(&self, depth: f32) {
+        self.record(Command::ClearDepth(depth));
+    }
+    fn clear(&self, mask: u32) {
+        self.record(Command::Clear(mask));
+    }
+    fn clear_buffer_u32(&self, buffer: u32, draw_buffer: i32, value: [u32; 4]) {
+        self.record(Command::ClearBuffer {
+            buffer,
+            draw_buffer,
+            value,
+        });
+    }
+    fn depth_func(&self, func: u32) {
+        self.record(Command::DepthFunc(func));
+    }
+    fn front_face(&self, mode: u32) {
+        self.record(Command::FrontFace(mode));
+    }
+    fn cull_face(&self, mode: u32) {
+        self.record(Command::CullFace(mode));
+    }
+    fn blend_func(&self, src: u32, dst: u32) {
+        self.record(Command::BlendFunc(src, dst));
+    }
+    fn stencil_op(&self, fail: u32, zfail: u32, zpass: u32) {
+        self.record(Command::StencilOp(fail, zfail, zpass));
+    }
+    fn stencil_func(&self, func: u32, reference: i32, mask: u32) {
+        self.record(Command::StencilFunc(func, reference, mask));
+    }
+    fn stencil_mask(&self, mask: u32) {
+        self.record(Command::StencilMask(mask));
+    }
+    fn read_pixels_u32(&self, x: i32, y: i32, width: i32, height: i32) -> Vec<u32> {
+        let mut state = self.state.borrow_mut();
+        let len = (width * height * 4).max(0) as usize;
+        let mut pixels = state.pixels.take().unwrap_or_default();
+        pixels.resize(len, 0);
+        let framebuffer = state.read_framebuffer;
+        drop(state);
+        self.record(Command::ReadPixels {
+            framebuffer,
+            x,
+            y,
+            width,
+            height,
+        });
+        pixels
+    }
+    fn draw_arrays(&self, mode: u32, first: i32, count: i32) {
+        self.draw(mode, first, count, None, None);
+    }
+    fn draw_elements(&self, mode: u32, count: i32, index_type: u32, offset: i32) {
+        self.draw(mode, offset, count, Some(index_type), None);
+    }
+    fn draw_arrays_instanced(&self, mode: u32, first: i32, count: i32, instances: i32) {
+        self.draw(mode, first, count, None, Some(instances));
+    }
+    fn draw_elements_instanced(
+        &self,
+        mode: u32,
+        count: i32,
+        index_type: u32,
+        offset: i32,
+        instances: i32,
+    ) {
+        self.draw(mode, offset, count, Some(index_type), Some(instances));
+    }
+}
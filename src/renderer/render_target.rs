@@ -0,0 +1,303 @@
+//! Offscreen targets that scenes can be rendered into, from any viewport, and whose color
+//! texture materials can draw with. Minimaps, mirrors and editor overlays are drawn this way.
+
+use super::{
+    gl as GL, Backend, FramebufferId, RenderbufferId, Renderer, Resource, TextureId, CLEAR_COLOR,
+};
+use crate::{controller::Viewport, mesh::Material, scene::Scene, Storage};
+
+/// An opaque reference to a render target created by the Renderer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct RenderTargetId(pub u32);
+
+/// How big a render target is.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TargetSize {
+    /// A size in pixels.
+    Fixed { width: u32, height: u32 },
+    /// A fraction of the size of the canvas, which the target follows as the renderer is
+    /// resized.
+    Canvas(f32),
+}
+
+/// The attachments of a render target.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenderTargetConfig {
+    pub size: TargetSize,
+    /// The internal format of the color texture, e.g. `gl::RGBA8`. None for a target that only
+    /// holds depth, like a shadow map.
+    pub color: Option<u32>,
+    pub depth: bool,
+    pub stencil: bool,
+    /// Samples per pixel. Above 1 the target is drawn multisampled, and resolved into its
+    /// textures once the scene is drawn.
+    pub samples: u32,
+}
+
+impl Default for RenderTargetConfig {
+    fn default() -> Self {
+        Self {
+            size: TargetSize::Canvas(1.),
+            color: Some(GL::RGBA8),
+            depth: true,
+            stencil: false,
+            samples: 1,
+        }
+    }
+}
+
+impl RenderTargetConfig {
+    /// The internal format and attachment of the depth and stencil texture, if there's one.
+    fn depth_format(&self) -> Option<(u32, u32)> {
+        if self.stencil {
+            Some((GL::DEPTH24_STENCIL8, GL::DEPTH_STENCIL_ATTACHMENT))
+        } else if self.depth {
+            Some((GL::DEPTH_COMPONENT24, GL::DEPTH_ATTACHMENT))
+        } else {
+            None
+        }
+    }
+    /// The buffers a multisampled target resolves.
+    fn resolve_mask(&self) -> u32 {
+        let mut mask = 0;
+        if self.color.is_some() {
+            mask |= GL::COLOR_BUFFER_BIT;
+        }
+        if self.depth {
+            mask |= GL::DEPTH_BUFFER_BIT;
+        }
+        if self.stencil {
+            mask |= GL::STENCIL_BUFFER_BIT;
+        }
+        mask
+    }
+}
+
+/// The GPU objects of a render target.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct RenderTarget {
+    config: RenderTargetConfig,
+    /// Holds the textures.
+    framebuffer: FramebufferId,
+    color: Option<TextureId>,
+    depth: Option<TextureId>,
+    /// The framebuffer that multisampled targets are drawn into, and its renderbuffers.
+    multisampled: Option<(FramebufferId, Vec<RenderbufferId>)>,
+    width: u32,
+    height: u32,
+}
+
+impl Renderer {
+    /// Creates an offscreen target to render scenes into with `render_to_target`.
+    pub fn create_render_target(&self, config: RenderTargetConfig) -> RenderTargetId {
+        let id = RenderTargetId(self.next_render_target.get());
+        self.next_render_target.set(id.0 + 1);
+        let target = self.build_render_target(config);
+        self.render_targets.borrow_mut().insert(id, target);
+        id
+    }
+    pub fn delete_render_target(&self, id: RenderTargetId) {
+        if let Some(target) = self.render_targets.borrow_mut().remove(&id) {
+            self.release_render_target(target);
+        }
+    }
+    /// The texture the colors of a target end up in, which materials can draw with.
+    pub fn render_target_texture(&self, id: RenderTargetId) -> Option<TextureId> {
+        self.render_targets.borrow().get(&id).and_then(|t| t.color)
+    }
+    /// The texture the depth of a target ends up in.
+    pub fn render_target_depth(&self, id: RenderTargetId) -> Option<TextureId> {
+        self.render_targets.borrow().get(&id).and_then(|t| t.depth)
+    }
+    /// The size of a target in pixels.
+    pub fn render_target_size(&self, id: RenderTargetId) -> Option<(u32, u32)> {
+        self.render_targets
+            .borrow()
+            .get(&id)
+            .map(|t| (t.width, t.height))
+    }
+    /// Draws a scene into a target, as seen from the viewport, whose aspect ratio should match
+    /// the target. Targets are always drawn forward, without HDR or post effects. Meshes whose
+    /// material draws with the target itself are left out, as are those outside the view.
+    pub fn render_to_target(&self, scene: &Scene, viewport: &Viewport, id: RenderTargetId) {
+        let target = match self.render_targets.borrow().get(&id).cloned() {
+            Some(target) => target,
+            None => return,
+        };
+        let gl = &*self.backend;
+        self.update_environment();
+        let storage = scene.storage();
+        {
+            let mut storage = storage.borrow_mut();
+            storage.update_world_transforms();
+            storage.update_spatial_index();
+        }
+        let storage = storage.borrow();
+        let culled = self.frustum_culled(&storage, viewport);
+        let shadows = self.render_shadows(&storage, viewport);
+        let draw_framebuffer = target
+            .multisampled
+            .as_ref()
+            .map_or(target.framebuffer, |(framebuffer, _)| *framebuffer);
+        gl.bind_framebuffer(GL::FRAMEBUFFER, Some(draw_framebuffer));
+        // the texture of the target is still bound if a material drew with it last frame
+        gl.active_texture(GL::TEXTURE0);
+        gl.bind_texture(GL::TEXTURE_2D, None);
+        gl.viewport(0, 0, target.width as i32, target.height as i32);
+        let [r, g, b, a] = CLEAR_COLOR;
+        gl.clear_color(r, g, b, a);
+        gl.clear(GL::COLOR_BUFFER_BIT | GL::DEPTH_BUFFER_BIT | GL::STENCIL_BUFFER_BIT);
+        self.setup_lights(&storage, &shadows);
+        self.update_viewport(viewport);
+        // WebGL refuses to draw with a texture into the framebuffer it's attached to
+        let skip = |i: usize| {
+            culled[i]
+                || storage
+                    .mesh(i)
+                    .is_some_and(|mesh| mesh.material.render_target == Some(id))
+        };
+        self.render_stages(&storage, &skip, || {});
+        if let Some((multisampled, _)) = target.multisampled {
+            gl.bind_framebuffer(GL::READ_FRAMEBUFFER, Some(multisampled));
+            gl.bind_framebuffer(GL::DRAW_FRAMEBUFFER, Some(target.framebuffer));
+            let rect = [0, 0, target.width as i32, target.height as i32];
+            gl.blit_framebuffer(rect, rect, target.config.resolve_mask(), GL::NEAREST);
+        }
+        gl.bind_framebuffer(GL::FRAMEBUFFER, None);
+        gl.viewport(0, 0, self.width as i32, self.height as i32);
+    }
+    /// The texture a material draws with: its render target, or else its first texture.
+    pub(super) fn albedo_texture(
+        &self,
+        storage: &Storage,
+        material: &Material,
+    ) -> Option<TextureId> {
+        match material.render_target {
+            Some(id) => self.render_target_texture(id),
+            None => material
+                .texture_indices
+                .first()
+                .map(|tex_i| storage.texture(*tex_i)),
+        }
+    }
+    /// Creates the targets that follow the canvas again at its new size.
+    pub(super) fn resize_render_targets(&self) {
+        let ids: Vec<RenderTargetId> = self.render_targets.borrow().keys().copied().collect();
+        for id in ids {
+            let config = match self.render_targets.borrow().get(&id) {
+                Some(target) => target.config,
+                None => continue,
+            };
+            let size = self.render_target_pixels(config.size);
+            if Some(size) == self.render_target_size(id) {
+                continue;
+            }
+            if let Some(target) = self.render_targets.borrow_mut().remove(&id) {
+                self.release_render_target(target);
+            }
+            let target = self.build_render_target(config);
+            self.render_targets.borrow_mut().insert(id, target);
+        }
+    }
+    fn render_target_pixels(&self, size: TargetSize) -> (u32, u32) {
+        match size {
+            TargetSize::Fixed { width, height } => (width.max(1), height.max(1)),
+            TargetSize::Canvas(scale) => (
+                ((self.width as f32 * scale).round() as u32).max(1),
+                ((self.height as f32 * scale).round() as u32).max(1),
+            ),
+        }
+    }
+    fn build_render_target(&self, config: RenderTargetConfig) -> RenderTarget {
+        let gl = &*self.backend;
+        let (width, height) = self.render_target_pixels(config.size);
+        let color = config.color.map(|format| {
+            self.create_map(GL::TEXTURE_2D, 1, format, width as usize, height as usize)
+        });
+        let depth = config.depth_format().map(|(format, _)| {
+            let depth = self.create_map(GL::TEXTURE_2D, 1, format, width as usize, height as usize);
+            // depth textures can't be filtered
+            gl.bind_texture(GL::TEXTURE_2D, Some(depth));
+            gl.tex_parameter(GL::TEXTURE_2D, GL::TEXTURE_MIN_FILTER, GL::NEAREST);
+            gl.tex_parameter(GL::TEXTURE_2D, GL::TEXTURE_MAG_FILTER, GL::NEAREST);
+            gl.bind_texture(GL::TEXTURE_2D, None);
+            depth
+        });
+        let framebuffer = gl.create_framebuffer();
+        self.resources.created(Resource::Framebuffer, 1);
+        gl.bind_framebuffer(GL::FRAMEBUFFER, Some(framebuffer));
+        if let Some(color) = color {
+            gl.framebuffer_texture_2d(
+                GL::FRAMEBUFFER,
+                GL::COLOR_ATTACHMENT0,
+                GL::TEXTURE_2D,
+                Some(color),
+                0,
+            );
+        }
+        if let (Some(depth), Some((_, attachment))) = (depth, config.depth_format()) {
+            gl.framebuffer_texture_2d(GL::FRAMEBUFFER, attachment, GL::TEXTURE_2D, Some(depth), 0);
+        }
+        let multisampled = if config.samples > 1 {
+            Some(Self::build_multisampled(gl, config, width, height))
+        } else {
+            None
+        };
+        if let Some((_, renderbuffers)) = multisampled.as_ref() {
+            self.resources.created(Resource::Framebuffer, 1);
+            self.resources
+                .created(Resource::Renderbuffer, renderbuffers.len());
+        }
+        gl.bind_framebuffer(GL::FRAMEBUFFER, None);
+        RenderTarget {
+            config,
+            framebuffer,
+            color,
+            depth,
+            multisampled,
+            width,
+            height,
+        }
+    }
+    /// A framebuffer with a multisampled renderbuffer for each texture of the target.
+    fn build_multisampled(
+        gl: &dyn Backend,
+        config: RenderTargetConfig,
+        width: u32,
+        height: u32,
+    ) -> (FramebufferId, Vec<RenderbufferId>) {
+        let framebuffer = gl.create_framebuffer();
+        gl.bind_framebuffer(GL::FRAMEBUFFER, Some(framebuffer));
+        let mut renderbuffers = Vec::new();
+        let attachments = config
+            .color
+            .map(|format| (format, GL::COLOR_ATTACHMENT0))
+            .into_iter()
+            .chain(config.depth_format());
+        for (format, attachment) in attachments {
+            let renderbuffer =
+                gl.create_renderbuffer(config.samples as i32, format, width as i32, height as i32);
+            gl.framebuffer_renderbuffer(GL::FRAMEBUFFER, attachment, Some(renderbuffer));
+            renderbuffers.push(renderbuffer);
+        }
+        (framebuffer, renderbuffers)
+    }
+    fn release_render_target(&self, target: RenderTarget) {
+        let gl = &*self.backend;
+        gl.delete_framebuffer(target.framebuffer);
+        self.resources.deleted(Resource::Framebuffer, 1);
+        for texture in target.color.iter().chain(target.depth.iter()) {
+            self.delete_texture(*texture);
+        }
+        if let Some((framebuffer, renderbuffers)) = target.multisampled {
+            gl.delete_framebuffer(framebuffer);
+            self.resources.deleted(Resource::Framebuffer, 1);
+            for renderbuffer in renderbuffers.iter() {
+                gl.delete_renderbuffer(*renderbuffer);
+            }
+            self.resources
+                .deleted(Resource::Renderbuffer, renderbuffers.len());
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::cell::Cell;
+
+/// The number of GPU objects of each kind that were created and haven't been deleted yet.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ResourceCounts {
+    pub buffers: usize,
+    pub vaos: usize,
+    pub textures: usize,
+    pub programs: usize,
+    pub framebuffers: usize,
+    pub renderbuffers: usize,
+}
+
+/// Every kind of GPU object the renderer keeps track of.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Resource {
+    Buffer,
+    Vao,
+    Texture,
+    Program,
+    Framebuffer,
+    Renderbuffer,
+}
+
+/// Keeps count of the GPU objects owned by the renderer. Every object is registered when it's
+/// created and unregistered when it's deleted, so any count that keeps climbing is a leak.
+#[derive(Debug, Default)]
+pub struct ResourceRegistry {
+    live: Cell<ResourceCounts>,
+}
+
+impl ResourceRegistry {
+    fn count(counts: &mut ResourceCounts, resource: Resource) -> &mut usize {
+        match resource {
+            Resource::Buffer => &mut counts.buffers,
+            Resource::Vao => &mut counts.vaos,
+            Resource::Texture => &mut counts.textures,
+            Resource::Program => &mut counts.programs,
+            Resource::Framebuffer => &mut counts.framebuffers,
+            Resource::Renderbuffer => &mut counts.renderbuffers,
+        }
+    }
+    pub fn created(&self, resource: Resource, amount: usize) {
+        let mut counts = self.live.get();
+        *Self::count(&mut counts, resource) += amount;
+        self.live.set(counts);
+    }
+    pub fn deleted(&self, resource: Resource, amount: usize) {
+        let mut counts = self.live.get();
+        let count = Self::count(&mut counts, resource);
+        assert!(*count >= amount, "Deleted more {:?}s than were created!", resource);
+        *count -= amount;
+        self.live.set(counts);
+    }
+    pub fn live(&self) -> ResourceCounts {
+        self.live.get()
+    }
+}
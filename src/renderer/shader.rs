@@ -1,33 +1,24 @@
-use crate::{dom_factory::add_event, rc_rcell, TextureType};
-use js_sys::{Float32Array, Uint16Array, Uint8Array};
-use nalgebra::Matrix4;
-use std::rc::Rc;
-use wasm_bindgen::JsValue;
-use web_sys::{
-    HtmlImageElement, Url, WebGl2RenderingContext as GL, WebGlProgram, WebGlShader, WebGlTexture,
+use super::{
+    backend::{Backend, BufferId, ProgramId, Uniform},
+    gl as GL,
 };
+use crate::mesh::IndexType;
+use nalgebra::Matrix4;
 
 use strum_macros::{Display, EnumIter};
 
-#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, Display, EnumIter)]
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, Display, EnumIter, Serialize, Deserialize)]
 pub enum ShaderType {
     Simple,
     Wireframe,
     Color,
     CubeMap,
     VertexColor,
+    Pbr,
 }
 
-pub fn create_program(gl: &GL, vertex: &str, fragment: &str) -> Result<WebGlProgram, String> {
-    let vert_shader = compile_shader(gl, GL::VERTEX_SHADER, vertex)?;
-    let frag_shader = compile_shader(gl, GL::FRAGMENT_SHADER, fragment)?;
-    let program = link_program(gl, &vert_shader, &frag_shader, true)?;
-    Ok(program)
-}
-
-pub fn create_vertex_color_program(gl: &GL) -> Result<WebGlProgram, String> {
-    let shader = create_program(
-        gl,
+pub fn create_vertex_color_program(gl: &dyn Backend) -> Result<ProgramId, String> {
+    let shader = gl.create_program(
         r#" #version 300 es
             in vec4 position;
 			in vec3  normal;
@@ -56,247 +47,101 @@ pub fn create_vertex_color_program(gl: &GL) -> Result<WebGlProgram, String> {
             precision mediump float;
             in vec4 f_color;
 			in vec3 lighting;
+            uniform bool linear_output;
             out vec4 outputColor;
 
+            vec3 to_linear(vec3 srgb) {
+                return mix(srgb / 12.92, pow((srgb + 0.055) / 1.055, vec3(2.4)), step(0.04045, srgb));
+            }
+
             void main() {
-				outputColor =vec4(f_color.xyz * lighting, 1.0);
+				vec3 color = linear_output ? to_linear(f_color.xyz) : f_color.xyz;
+				outputColor =vec4(color * lighting, 1.0);
             }
         "#,
     )?;
     Ok(shader)
 }
 
-pub fn compile_shader(gl: &GL, shader_type: u32, source: &str) -> Result<WebGlShader, String> {
-    let shader = gl
-        .create_shader(shader_type)
-        .ok_or_else(|| String::from("Unable to create shader object"))?;
-    gl.shader_source(&shader, source);
-    gl.compile_shader(&shader);
-
-    if gl
-        .get_shader_parameter(&shader, GL::COMPILE_STATUS)
-        .as_bool()
-        .unwrap_or(false)
-    {
-        Ok(shader)
-    } else {
-        Err(gl
-            .get_shader_info_log(&shader)
-            .unwrap_or_else(|| String::from("Unknown error creating shader")))
-    }
+pub fn bind_attribute(gl: &dyn Backend, program: ProgramId, name: &str, size: i32) {
+    let attribute = gl.attrib_location(program, name);
+    gl.vertex_attrib_pointer(attribute as u32, size, GL::FLOAT, false, 0, 0);
+    gl.enable_vertex_attrib_array(attribute as u32);
 }
-pub fn link_program(
-    gl: &GL,
-    vert_shader: &WebGlShader,
-    frag_shader: &WebGlShader,
-    validate: bool,
-) -> Result<WebGlProgram, String> {
-    let program = gl
-        .create_program()
-        .ok_or_else(|| String::from("Unable to create shader object"))?;
-
-    gl.attach_shader(&program, vert_shader);
-    gl.attach_shader(&program, frag_shader);
-    gl.link_program(&program);
-
-    if gl
-        .get_program_parameter(&program, GL::LINK_STATUS)
-        .as_bool()
-        .unwrap_or(false)
-    {
-        if validate {
-            gl.validate_program(&program);
-            if (gl.get_program_parameter(&program, GL::VALIDATE_STATUS))
-                .as_bool()
-                .unwrap_or(false)
-            {
-                Ok(program)
-            } else {
-                Err(gl
-                    .get_program_info_log(&program)
-                    .unwrap_or_else(|| String::from("Unknown error creating program object")))
-            }
-        } else {
-            Ok(program)
+pub fn bind_buffer_f32(gl: &dyn Backend, data: &[f32]) -> BufferId {
+    let buffer = gl.create_buffer();
+    gl.bind_buffer(GL::ARRAY_BUFFER, Some(buffer));
+    gl.buffer_data_f32(GL::ARRAY_BUFFER, data, GL::STATIC_DRAW);
+    buffer
+}
+pub fn bind_index_buffer(gl: &dyn Backend, data: &[u32], index_type: IndexType) -> BufferId {
+    let buffer = gl.create_buffer();
+    gl.bind_buffer(GL::ELEMENT_ARRAY_BUFFER, Some(buffer));
+    match index_type {
+        IndexType::U16 => {
+            let data: Vec<u16> = data.iter().map(|i| *i as u16).collect();
+            gl.buffer_data_u16(GL::ELEMENT_ARRAY_BUFFER, &data, GL::STATIC_DRAW);
         }
-    } else {
-        Err(gl
-            .get_program_info_log(&program)
-            .unwrap_or_else(|| String::from("Unknown error creating program object")))
-    }
-}
-pub fn bind_texture(
-    gl: &GL,
-    urls: &[String],
-    tex_type: TextureType,
-    is_img_obj: bool,
-) -> Result<Rc<WebGlTexture>, JsValue> {
-    let texture = gl.create_texture().expect("Can't create texture!");
-    let gl_tex_type = match tex_type {
-        TextureType::CubeMap => GL::TEXTURE_CUBE_MAP,
-        _ => GL::TEXTURE_2D,
-    };
-    gl.bind_texture(gl_tex_type, Some(&texture));
-    if tex_type == TextureType::CubeMap {
-        for i in 0..6 {
-            gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
-                GL::TEXTURE_CUBE_MAP_POSITIVE_X + i,
-                0,
-                GL::RGBA as i32,
-                1,
-                1,
-                0,
-                GL::RGBA,
-                GL::UNSIGNED_BYTE,
-                Some(&[255, 0, 255, 255]),
-            )?;
+        IndexType::U32 => {
+            gl.buffer_data_u32(GL::ELEMENT_ARRAY_BUFFER, data, GL::STATIC_DRAW);
         }
-    } else {
-        gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
-            GL::TEXTURE_2D,
-            0,
-            GL::RGBA as i32,
-            1,
-            1,
-            0,
-            GL::RGBA,
-            GL::UNSIGNED_BYTE,
-            Some(&[255, 0, 255, 255]),
-        )?;
-    }
-    let texture = Rc::new(texture);
-    // couldn't avoid this
-    let gl = Rc::new(gl.clone());
-    for (i, url) in urls.iter().enumerate() {
-        let image = Rc::new(HtmlImageElement::new().expect("Can't create Image Element"));
-        let img = image.clone();
-        let tex = texture.clone();
-        let last = urls.len() - 1;
-        let gl = gl.clone();
-        add_event(&image, "load", move |_| {
-            if is_img_obj {
-                Url::revoke_object_url(&img.src());
-            }
-            use crate::log;
-            crate::log!("Loaded Image" img.src());
-            match tex_type {
-                TextureType::Tex2d => {
-                    if i == 0 {
-                        gl.bind_texture(GL::TEXTURE_2D, Some(&tex));
-                    }
-                    gl.tex_image_2d_with_u32_and_u32_and_html_image_element(
-                        GL::TEXTURE_2D,
-                        0,
-                        GL::RGBA as i32,
-                        GL::RGBA,
-                        GL::UNSIGNED_BYTE,
-                        &img,
-                    )
-                    .expect("Couldn't bind image as texture!");
-                    if i == last {
-                        gl.generate_mipmap(GL::TEXTURE_2D);
-                    }
-                }
-                TextureType::CubeMap => {
-                    if i == 0 {
-                        gl.bind_texture(GL::TEXTURE_CUBE_MAP, Some(&tex));
-                    }
-                    gl.tex_image_2d_with_u32_and_u32_and_html_image_element(
-                        GL::TEXTURE_CUBE_MAP_POSITIVE_X + i as u32,
-                        0,
-                        GL::RGBA as i32,
-                        GL::RGBA,
-                        GL::UNSIGNED_BYTE,
-                        &img,
-                    )
-                    .expect("Couldn't bind image as texture!");
-                    if i == last {
-                        gl.generate_mipmap(GL::TEXTURE_CUBE_MAP);
-                    }
-                }
-                _ => (),
-            }
-            //gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MAG_FILTER as u32, GL::NEARESR as i32);
-        });
-        image.set_src(url);
     }
-    Ok(texture)
-}
-pub fn bind_attribute(gl: &GL, program: &WebGlProgram, name: &str, size: i32) {
-    let attribute = gl.get_attrib_location(program, name);
-    gl.vertex_attrib_pointer_with_i32(attribute as u32, size, GL::FLOAT, false, 0, 0);
-    gl.enable_vertex_attrib_array(attribute as u32);
-}
-pub fn bind_buffer_f32(gl: &GL, data: &[f32]) -> Result<(), JsValue> {
-    let buffer = gl.create_buffer().ok_or("failed to create buffer")?;
-    gl.bind_buffer(GL::ARRAY_BUFFER, Some(&buffer));
-    let buffer_array = unsafe { Float32Array::view(&data) };
-    gl.buffer_data_with_array_buffer_view(GL::ARRAY_BUFFER, &buffer_array, GL::STATIC_DRAW);
-    Ok(())
+    buffer
 }
-pub fn bind_index_buffer(gl: &GL, data: &[u16]) -> Result<(), JsValue> {
-    let buffer = gl.create_buffer().ok_or("failed to create buffer")?;
-    gl.bind_buffer(GL::ELEMENT_ARRAY_BUFFER, Some(&buffer));
-    let buffer_array = unsafe { Uint16Array::view(&data) };
-    gl.buffer_data_with_array_buffer_view(GL::ELEMENT_ARRAY_BUFFER, &buffer_array, GL::STATIC_DRAW);
-    Ok(())
+pub fn gl_index_type(index_type: IndexType) -> u32 {
+    match index_type {
+        IndexType::U16 => GL::UNSIGNED_SHORT,
+        IndexType::U32 => GL::UNSIGNED_INT,
+    }
 }
 pub fn bind_buffer_and_attribute(
-    gl: &GL,
-    program: &WebGlProgram,
+    gl: &dyn Backend,
+    program: ProgramId,
     attribute: &str,
     data: &[f32],
     size: i32,
-) -> Result<(), JsValue> {
-    bind_buffer_f32(gl, data)?;
+) -> BufferId {
+    let buffer = bind_buffer_f32(gl, data);
     bind_attribute(gl, program, attribute, size);
-    Ok(())
+    buffer
+}
+fn set_uniform(gl: &dyn Backend, program: ProgramId, name: &str, value: Uniform) {
+    if !gl.set_uniform(program, name, value) {
+        panic!("Can't bind uniform: {}", name);
+    }
 }
-pub fn set_bool(gl: &GL, program: &WebGlProgram, name: &str, value: bool) {
-    set_u32(gl, program, name, value as u32);
+pub fn set_bool(gl: &dyn Backend, program: ProgramId, name: &str, value: bool) {
+    set_uniform(gl, program, name, Uniform::Bool(value));
 }
-pub fn set_u32(gl: &GL, program: &WebGlProgram, name: &str, value: u32) {
-    let attrib = gl
-        .get_uniform_location(program, name)
-        .unwrap_or_else(|| panic!("Can't bind uniform: {}", name));
-    gl.uniform1ui(Some(&attrib), value);
+pub fn set_u32(gl: &dyn Backend, program: ProgramId, name: &str, value: u32) {
+    set_uniform(gl, program, name, Uniform::U32(value));
 }
-pub fn set_i32(gl: &GL, program: &WebGlProgram, name: &str, value: i32) {
-    let attrib = gl
-        .get_uniform_location(program, name)
-        .unwrap_or_else(|| panic!("Can't bind uniform: {}", name));
-    gl.uniform1i(Some(&attrib), value);
+pub fn set_i32(gl: &dyn Backend, program: ProgramId, name: &str, value: i32) {
+    set_uniform(gl, program, name, Uniform::I32(value));
 }
-pub fn set_f32(gl: &GL, program: &WebGlProgram, name: &str, value: f32) {
-    let attrib = gl
-        .get_uniform_location(program, name)
-        .unwrap_or_else(|| panic!("Can't bind uniform: {}", name));
-    gl.uniform1f(Some(&attrib), value);
+pub fn set_f32(gl: &dyn Backend, program: ProgramId, name: &str, value: f32) {
+    set_uniform(gl, program, name, Uniform::F32(value));
 }
-pub fn set_vec3(gl: &GL, program: &WebGlProgram, attribute: &str, vector: &[f32]) {
-    let mat_attrib = gl
-        .get_uniform_location(program, attribute)
-        .unwrap_or_else(|| panic!("Can't bind uniform: {}", attribute));
-    gl.uniform3f(Some(&mat_attrib), vector[0], vector[1], vector[2]);
+pub fn set_vec3(gl: &dyn Backend, program: ProgramId, attribute: &str, vector: &[f32]) {
+    set_uniform(
+        gl,
+        program,
+        attribute,
+        Uniform::Vec3([vector[0], vector[1], vector[2]]),
+    );
 }
-pub fn set_vec4(gl: &GL, program: &WebGlProgram, attribute: &str, vector: &[f32]) {
-    let mat_attrib = gl
-        .get_uniform_location(program, attribute)
-        .unwrap_or_else(|| panic!("Can't bind uniform: {}", attribute));
-    gl.uniform4f(
-        Some(&mat_attrib),
-        vector[0],
-        vector[1],
-        vector[2],
-        vector[3],
+pub fn set_vec4(gl: &dyn Backend, program: ProgramId, attribute: &str, vector: &[f32]) {
+    set_uniform(
+        gl,
+        program,
+        attribute,
+        Uniform::Vec4([vector[0], vector[1], vector[2], vector[3]]),
     );
 }
-pub fn set_mat4(gl: &GL, program: &WebGlProgram, attribute: &str, matrix: &Matrix4<f32>) {
-    let mat = matrix.as_slice();
-    let mat_attrib = gl
-        .get_uniform_location(program, attribute)
-        .unwrap_or_else(|| panic!("Can't bind uniform: {}", attribute));
-    gl.uniform_matrix4fv_with_f32_array(Some(&mat_attrib), false, &mat);
+pub fn set_mat4(gl: &dyn Backend, program: ProgramId, attribute: &str, matrix: &Matrix4<f32>) {
+    let mut mat = [0.; 16];
+    mat.copy_from_slice(matrix.as_slice());
+    set_uniform(gl, program, attribute, Uniform::Mat4(mat));
 }
 
 fn is_power_of_2(val: u32) -> bool {
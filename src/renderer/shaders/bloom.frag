@@ -0,0 +1,27 @@
+#version 300 es
+precision highp float;
+in vec2 frag_uv;
+uniform sampler2D color_buffer;
+uniform float threshold, intensity, max_lod;
+
+out vec4 outputColor;
+
+#define FIRST_LEVEL 1
+#define LAST_LEVEL 6
+
+float luma(vec3 color) {
+	return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+// Adds the parts of the image brighter than the threshold, blurred by reading them from ever
+// smaller mip levels
+void main() {
+	vec3 color = textureLod(color_buffer, frag_uv, 0.0).rgb;
+	vec3 bloom = vec3(0.0);
+	for (int i = FIRST_LEVEL; i <= LAST_LEVEL; i++) {
+		vec3 blurred = textureLod(color_buffer, frag_uv, min(float(i), max_lod)).rgb;
+		float brightness = luma(blurred);
+		bloom += blurred * max(brightness - threshold, 0.0) / max(brightness, 1e-4);
+	}
+	outputColor = vec4(color + bloom * intensity / float(LAST_LEVEL - FIRST_LEVEL + 1), 1.0);
+}
@@ -0,0 +1,58 @@
+#version 300 es
+precision highp float;
+in vec2 frag_uv;
+out vec4 outputColor;
+
+#define PI 3.14159265359
+#define SAMPLE_COUNT 512u
+
+float radical_inverse(uint bits) {
+	bits = (bits << 16u) | (bits >> 16u);
+	bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+	bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+	bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+	bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+	return float(bits) * 2.3283064365386963e-10;
+}
+
+vec3 importance_sample_ggx(vec2 xi, float alpha) {
+	float phi = 2.0 * PI * xi.x;
+	float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y));
+	float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+	return vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+}
+
+// The same height correlated Smith visibility as pbr.frag
+float visibility(float n_dot_l, float n_dot_v, float alpha) {
+	float a2 = alpha * alpha;
+	float ggx_v = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - a2) + a2);
+	float ggx_l = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - a2) + a2);
+	float ggx = ggx_v + ggx_l;
+	return ggx > 0.0 ? 0.5 / ggx : 0.0;
+}
+
+// Integrates the specular BRDF over the hemisphere for a view angle (x) and a roughness (y),
+// as a scale (red) and a bias (green) to the reflectance at normal incidence
+void main() {
+	float n_dot_v = max(frag_uv.x, 1e-3);
+	float alpha = max(frag_uv.y * frag_uv.y, 1e-3);
+	vec3 view_dir = vec3(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+
+	float scale = 0.0;
+	float bias = 0.0;
+	for (uint i = 0u; i < SAMPLE_COUNT; i++) {
+		vec2 xi = vec2(float(i) / float(SAMPLE_COUNT), radical_inverse(i));
+		vec3 halfway = importance_sample_ggx(xi, alpha);
+		vec3 light_dir = normalize(2.0 * dot(view_dir, halfway) * halfway - view_dir);
+		float n_dot_l = max(light_dir.z, 0.0);
+		float n_dot_h = max(halfway.z, 0.0);
+		float v_dot_h = max(dot(view_dir, halfway), 0.0);
+		if (n_dot_l > 0.0) {
+			float g = 4.0 * visibility(n_dot_l, n_dot_v, alpha) * n_dot_l * v_dot_h / n_dot_h;
+			float fc = pow(1.0 - v_dot_h, 5.0);
+			scale += (1.0 - fc) * g;
+			bias += fc * g;
+		}
+	}
+	outputColor = vec4(scale / float(SAMPLE_COUNT), bias / float(SAMPLE_COUNT), 0.0, 1.0);
+}
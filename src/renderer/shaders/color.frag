@@ -1,7 +1,16 @@
 #version 300 es
 precision mediump float;
-in vec3 object_pos, surface_normal, view_dir, frag_bc;
+in highp vec3 object_pos;
+in vec3 surface_normal, view_dir, frag_bc;
+in highp float view_depth;
+flat in vec4 frag_instance_color;
 uniform vec4 color;
+uniform bool instanced, linear_output;
+
+// The sRGB transfer function
+vec3 to_linear(vec3 srgb) {
+	return mix(srgb / 12.92, pow((srgb + 0.055) / 1.055, vec3(2.4)), step(0.04045, srgb));
+}
 
 float edgeFactor(){
 	vec3 d = fwidth(frag_bc);
@@ -13,6 +22,7 @@ float edgeFactor(){
 #define POINT 1
 #define SPOT 2
 #define MAX_NUM_LIGHTS 20
+#define MAX_SHADOWS 8
 uniform bool blinn_shade;
 
 struct Light {
@@ -26,8 +36,51 @@ struct Light {
 	float linear;
 	float intensity;
 	float quadratic;
+
+	// the first tile of the light in the shadow atlas, -1 without shadows
+	int shadow;
+	int cascades;
+	float shadow_bias;
 };
 
+uniform highp mat4 shadow_matrices[MAX_SHADOWS];
+uniform highp vec4 shadow_rects[MAX_SHADOWS];
+uniform highp float shadow_splits[MAX_SHADOWS];
+uniform highp sampler2DShadow shadow_atlas;
+uniform bool receive_shadows;
+// The fraction of the light that reaches the fragment, filtered over 3x3 texels of the light's
+// tile in the shadow atlas. Directional lights pick the cascade that covers the view depth.
+float shadow_factor(Light light, vec3 normal, vec3 light_dir) {
+	if (!receive_shadows || light.shadow < 0) {
+		return 1.0;
+	}
+	int tile = light.shadow;
+	for (int i = 1; i < light.cascades && view_depth > shadow_splits[tile]; i++) {
+		tile++;
+	}
+	if (view_depth > shadow_splits[tile]) {
+		return 1.0;
+	}
+	// surfaces are pushed along their normal, more so when they face away from the light
+	float n_dot_l = clamp(dot(normal, light_dir), 0.0, 1.0);
+	highp vec3 pos = object_pos + normal * light.shadow_bias * (2.0 - n_dot_l);
+	highp vec4 coords = shadow_matrices[tile] * vec4(pos, 1.0);
+	coords.xyz /= coords.w;
+	highp vec4 rect = shadow_rects[tile];
+	if (coords.z > 1.0 || any(lessThan(coords.xy, rect.xy)) || any(greaterThan(coords.xy, rect.zw))) {
+		return 1.0;
+	}
+	highp vec2 texel = 1.0 / vec2(textureSize(shadow_atlas, 0));
+	float lit = 0.0;
+	for (int x = -1; x <= 1; x++) {
+		for (int y = -1; y <= 1; y++) {
+			highp vec2 uv = clamp(coords.xy + vec2(x, y) * texel, rect.xy + 0.5 * texel, rect.zw - 0.5 * texel);
+			lit += texture(shadow_atlas, vec3(uv, coords.z));
+		}
+	}
+	return lit / 9.0;
+}
+
 vec3 calc_amb_light(Light light, vec3 f_color) {
 	return light.color * f_color * light.intensity;
 }
@@ -56,15 +109,17 @@ vec3 calc_light(Light light, vec3 normal, int type, vec3 f_color) {
 	float distance = length(light.position - object_pos);
 	float attenuation = 1.0 / (1.0 + light.linear * distance + light.quadratic * (distance * distance));
 
+	float shadow = type == POINT ? 1.0 : shadow_factor(light, normal, light_dir);
+
 	// spot
 	if (type == SPOT) {
 		float theta = dot(light_dir , normalize(-light.direction));
 		float epsilon = light.cutoff - light.outer_cutoff;
 		float intensity = clamp((theta - light.outer_cutoff) / epsilon, 0.0, 1.0);
-		return (diffuse + specular) * attenuation * f_color * light.intensity * intensity;
+		return (diffuse + specular) * attenuation * f_color * light.intensity * intensity * shadow;
 	}
 
-	return (diffuse + specular) * attenuation * f_color * light.intensity;
+	return (diffuse + specular) * attenuation * f_color * light.intensity * shadow;
 }
 
 //struct Material {
@@ -92,7 +147,13 @@ void main() {
 	vec3 result = vec3(0.0,0.0,0.0);
 	vec3 frag_color = has_albedo?
 		texture(sampler, frag_tex).rgb:
-		color.rgb;
+		(instanced ? frag_instance_color.rgb : color.rgb);
+	// colors are given in sRGB, and the HDR target holds linear ones
+	vec3 w_color = wire_color.rgb;
+	if (linear_output) {
+		frag_color = to_linear(frag_color);
+		w_color = to_linear(w_color);
+	}
 
 	for (int i = 0; i < num_l_amb; i++) {
 		result += calc_amb_light(amb_lights[i], frag_color);
@@ -108,6 +169,6 @@ void main() {
 	}
 
 	outputColor = wire_overlay?
-		vec4(mix(wire_color.rgb,result, edgeFactor()), 1.0):
+		vec4(mix(w_color, result, edgeFactor()), 1.0):
 		vec4(result, 1.0);
 }
@@ -1,17 +1,25 @@
 #version 300 es
 uniform mat4 model, view, proj;
 uniform vec3 eye;
-uniform bool wire_overlay, has_albedo;
+uniform bool wire_overlay, has_albedo, instanced;
 
 in vec3 position, normal, barycentric;
 in vec2 tex_coords;
+in mat4 instance_model;
+in vec4 instance_color;
+out float view_depth;
 out vec3 surface_normal, object_pos, view_dir, frag_bc;
 out vec2 frag_tex;
+flat out vec4 frag_instance_color;
 
 void main() {
-	object_pos = vec3(model * vec4(position, 1.0));
-	gl_Position = proj * view * vec4(object_pos, 1.0);
-	surface_normal = mat3(transpose(inverse(model))) * normal;
+	mat4 world = instanced ? model * instance_model : model;
+	object_pos = vec3(world * vec4(position, 1.0));
+	vec4 view_pos = view * vec4(object_pos, 1.0);
+	gl_Position = proj * view_pos;
+	view_depth = -view_pos.z;
+	surface_normal = mat3(transpose(inverse(world))) * normal;
+	frag_instance_color = instance_color;
 	view_dir = normalize(eye - object_pos);
 	if (has_albedo) {
 		frag_tex = tex_coords;
@@ -0,0 +1,26 @@
+#version 300 es
+precision highp float;
+in vec2 frag_uv;
+uniform sampler2D color_buffer, lut;
+uniform float intensity;
+
+out vec4 outputColor;
+
+// Looks a color up in the LUT: a strip of N squares of N by N texels, each one a slice of blue,
+// with red growing to the right and green downwards
+vec3 grade(vec3 color) {
+	vec2 lut_size = vec2(textureSize(lut, 0));
+	float size = lut_size.y;
+	color = clamp(color, 0.0, 1.0) * (size - 1.0);
+	float slice = floor(color.b);
+	vec2 uv = (color.rg + 0.5) / lut_size;
+	vec2 next = vec2(size / lut_size.x, 0.0);
+	vec3 low = textureLod(lut, uv + next * slice, 0.0).rgb;
+	vec3 high = textureLod(lut, uv + next * min(slice + 1.0, size - 1.0), 0.0).rgb;
+	return mix(low, high, color.b - slice);
+}
+
+void main() {
+	vec3 color = textureLod(color_buffer, frag_uv, 0.0).rgb;
+	outputColor = vec4(mix(color, grade(color), intensity), 1.0);
+}
@@ -3,7 +3,25 @@ precision mediump float;
 in vec3 frag_tex;
 out vec4 outputColor;
 uniform samplerCube sampler;
+uniform bool hdr, linear_output;
+
+// The sRGB transfer functions
+vec3 to_linear(vec3 srgb) {
+	return mix(srgb / 12.92, pow((srgb + 0.055) / 1.055, vec3(2.4)), step(0.04045, srgb));
+}
+
+vec3 to_srgb(vec3 linear) {
+	linear = max(linear, 0.0);
+	return mix(linear * 12.92, 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, linear));
+}
 
 void main() {
 	outputColor = texture(sampler, frag_tex);
+	// HDR skyboxes hold linear colors and other ones sRGB, the canvas expects sRGB and the HDR
+	// target linear colors
+	if (hdr && !linear_output) {
+		outputColor.rgb = to_srgb(outputColor.rgb);
+	} else if (!hdr && linear_output) {
+		outputColor.rgb = to_linear(outputColor.rgb);
+	}
 }
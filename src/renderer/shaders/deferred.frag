@@ -0,0 +1,265 @@
+#version 300 es
+precision highp float;
+
+#define DIR 0
+#define POINT 1
+#define SPOT 2
+#define MAX_NUM_LIGHTS 20
+#define MAX_SHADOWS 8
+#define PI 3.14159265359
+
+struct Light {
+	vec3 position;
+	vec3 color;
+
+	vec3 direction;
+	float cutoff;
+	float outer_cutoff;
+
+	float linear;
+	float intensity;
+	float quadratic;
+
+	// the first tile of the light in the shadow atlas, -1 without shadows
+	int shadow;
+	int cascades;
+	float shadow_bias;
+};
+
+// A pixel of the G-buffer
+struct Surface {
+	vec3 position;
+	vec3 normal;
+	vec3 albedo;
+	float metal;
+	float rough;
+	float occlusion;
+	// whether it's shaded like pbr.frag rather than color.frag
+	bool pbr;
+	bool receive_shadows;
+};
+
+uniform sampler2D albedo_buffer, normal_buffer, material_buffer, depth_buffer;
+uniform mat4 inv_view_proj;
+uniform vec3 eye;
+// whether the light goes to the HDR target, and color materials are lit in linear space
+uniform bool linear_output;
+
+out vec4 outputColor;
+
+// The sRGB transfer function
+vec3 to_linear(vec3 srgb) {
+	return mix(srgb / 12.92, pow((srgb + 0.055) / 1.055, vec3(2.4)), step(0.04045, srgb));
+}
+
+// Reads the surface drawn at a pixel, or returns false if nothing was drawn there
+bool read_surface(ivec2 pixel, out Surface surface) {
+	float depth = texelFetch(depth_buffer, pixel, 0).r;
+	if (depth >= 1.0) {
+		return false;
+	}
+	vec2 uv = (vec2(pixel) + 0.5) / vec2(textureSize(depth_buffer, 0));
+	vec4 position = inv_view_proj * vec4(vec3(uv, depth) * 2.0 - 1.0, 1.0);
+	surface.position = position.xyz / position.w;
+	vec4 albedo = texelFetch(albedo_buffer, pixel, 0);
+	vec4 normal = texelFetch(normal_buffer, pixel, 0);
+	vec4 material = texelFetch(material_buffer, pixel, 0);
+	surface.pbr = albedo.a > 0.5;
+	surface.albedo = surface.pbr || linear_output ? to_linear(albedo.rgb) : albedo.rgb;
+	surface.normal = normalize(normal.xyz);
+	surface.receive_shadows = normal.w > 0.5;
+	surface.metal = material.r;
+	surface.rough = material.g;
+	surface.occlusion = material.b;
+	return true;
+}
+
+// Trowbridge-Reitz (GGX) normal distribution
+float distribution(float n_dot_h, float alpha) {
+	float a2 = alpha * alpha;
+	float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+	return a2 / (PI * d * d);
+}
+
+// Height correlated Smith visibility, which includes the 1 / (4 n.l n.v) of the BRDF
+float visibility(float n_dot_l, float n_dot_v, float alpha) {
+	float a2 = alpha * alpha;
+	float ggx_v = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - a2) + a2);
+	float ggx_l = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - a2) + a2);
+	float ggx = ggx_v + ggx_l;
+	return ggx > 0.0 ? 0.5 / ggx : 0.0;
+}
+
+vec3 fresnel(float v_dot_h, vec3 f0) {
+	return f0 + (1.0 - f0) * pow(1.0 - v_dot_h, 5.0);
+}
+
+#ifndef LIGHT_VOLUMES
+uniform highp mat4 view;
+uniform highp mat4 shadow_matrices[MAX_SHADOWS];
+uniform highp vec4 shadow_rects[MAX_SHADOWS];
+uniform highp float shadow_splits[MAX_SHADOWS];
+uniform highp sampler2DShadow shadow_atlas;
+
+// The fraction of the light that reaches the surface, as in color.frag
+float shadow_factor(Light light, Surface surface, vec3 light_dir) {
+	if (!surface.receive_shadows || light.shadow < 0) {
+		return 1.0;
+	}
+	float view_depth = -(view * vec4(surface.position, 1.0)).z;
+	int tile = light.shadow;
+	for (int i = 1; i < light.cascades && view_depth > shadow_splits[tile]; i++) {
+		tile++;
+	}
+	if (view_depth > shadow_splits[tile]) {
+		return 1.0;
+	}
+	float n_dot_l = clamp(dot(surface.normal, light_dir), 0.0, 1.0);
+	vec3 pos = surface.position + surface.normal * light.shadow_bias * (2.0 - n_dot_l);
+	vec4 coords = shadow_matrices[tile] * vec4(pos, 1.0);
+	coords.xyz /= coords.w;
+	vec4 rect = shadow_rects[tile];
+	if (coords.z > 1.0 || any(lessThan(coords.xy, rect.xy)) || any(greaterThan(coords.xy, rect.zw))) {
+		return 1.0;
+	}
+	vec2 texel = 1.0 / vec2(textureSize(shadow_atlas, 0));
+	float lit = 0.0;
+	for (int x = -1; x <= 1; x++) {
+		for (int y = -1; y <= 1; y++) {
+			vec2 uv = clamp(coords.xy + vec2(x, y) * texel, rect.xy + 0.5 * texel, rect.zw - 0.5 * texel);
+			lit += texture(shadow_atlas, vec3(uv, coords.z));
+		}
+	}
+	return lit / 9.0;
+}
+#endif
+
+// The light reflected towards the eye, with the model of color.frag or pbr.frag. Attenuation
+// reaches 0 at the cutoff, so that point lights end at the edge of their volume.
+vec3 shade(Light light, int type, Surface surface, vec3 view_dir, float cutoff) {
+	vec3 light_dir = (type == DIR) ? normalize(light.direction) : normalize(light.position - surface.position);
+	float distance = length(light.position - surface.position);
+	float attenuation = 1.0 / (1.0 + light.linear * distance + light.quadratic * (distance * distance));
+	attenuation = max(attenuation - cutoff, 0.0) / (1.0 - cutoff);
+	float spot = 1.0;
+	if (type == SPOT) {
+		float theta = dot(light_dir, normalize(-light.direction));
+		float epsilon = light.cutoff - light.outer_cutoff;
+		spot = clamp((theta - light.outer_cutoff) / epsilon, 0.0, 1.0);
+	}
+	float shadow = 1.0;
+#ifndef LIGHT_VOLUMES
+	if (type != POINT) {
+		shadow = shadow_factor(light, surface, light_dir);
+	}
+#endif
+	vec3 normal = surface.normal;
+
+	if (!surface.pbr) {
+		float diff = max(dot(normal, light_dir), 0.0);
+		vec3 halfway_dir = normalize(light_dir + view_dir);
+		float spec = pow(max(dot(normal, halfway_dir), 0.0), 64.0);
+		return (diff + spec) * light.color * attenuation * surface.albedo * light.intensity * spot * shadow;
+	}
+
+	float n_dot_l = dot(normal, light_dir);
+	if (n_dot_l <= 0.0) {
+		return vec3(0.0);
+	}
+	float alpha = clamp(surface.rough, 0.03, 1.0);
+	alpha *= alpha;
+	vec3 halfway = normalize(light_dir + view_dir);
+	float n_dot_v = max(dot(normal, view_dir), 1e-4);
+	float n_dot_h = max(dot(normal, halfway), 0.0);
+	float v_dot_h = max(dot(view_dir, halfway), 0.0);
+
+	vec3 f0 = mix(vec3(0.04), surface.albedo, surface.metal);
+	vec3 f = fresnel(v_dot_h, f0);
+	vec3 diffuse = (1.0 - f) * (1.0 - surface.metal) * surface.albedo / PI;
+	vec3 specular = f * distribution(n_dot_h, alpha) * visibility(n_dot_l, n_dot_v, alpha);
+	vec3 radiance = light.color * light.intensity * PI * spot * shadow;
+	// directional lights are infinitely far away, so they aren't attenuated
+	if (type != DIR) {
+		radiance *= attenuation;
+	}
+	return (diffuse + specular) * radiance * n_dot_l;
+}
+
+#ifdef LIGHT_VOLUMES
+flat in vec4 frag_light_position, frag_light_color;
+uniform float linear, quadratic;
+
+// Adds the light of a point light to the pixels inside its volume
+void main() {
+	Surface surface;
+	if (!read_surface(ivec2(gl_FragCoord.xy), surface)) {
+		discard;
+	}
+	Light light;
+	light.position = frag_light_position.xyz;
+	light.color = frag_light_color.rgb;
+	light.intensity = frag_light_color.a;
+	light.linear = linear;
+	light.quadratic = quadratic;
+	float radius = frag_light_position.w;
+	float cutoff = 1.0 / (1.0 + linear * radius + quadratic * radius * radius);
+	vec3 view_dir = normalize(eye - surface.position);
+	outputColor = vec4(shade(light, POINT, surface, view_dir, cutoff), 1.0);
+}
+#else
+uniform int num_l_amb, num_l_dir, num_l_spot;
+uniform Light amb_lights[MAX_NUM_LIGHTS];
+uniform Light dir_lights[MAX_NUM_LIGHTS];
+uniform Light spot_lights[MAX_NUM_LIGHTS];
+uniform sampler2D emissive_buffer;
+uniform bool has_environment;
+uniform samplerCube irradiance_map, prefiltered_map;
+uniform sampler2D brdf_lut;
+uniform float max_reflection_lod;
+
+// Fresnel averaged over the rough microfacets that reflect the environment
+vec3 fresnel_roughness(float n_dot_v, vec3 f0, float rough) {
+	return f0 + (max(vec3(1.0 - rough), f0) - f0) * pow(1.0 - n_dot_v, 5.0);
+}
+
+// Light from the skybox, as in pbr.frag
+vec3 environment_light(Surface surface, vec3 view_dir) {
+	float n_dot_v = max(dot(surface.normal, view_dir), 1e-4);
+	vec3 f0 = mix(vec3(0.04), surface.albedo, surface.metal);
+	vec3 f = fresnel_roughness(n_dot_v, f0, surface.rough);
+	vec3 diffuse = (1.0 - f) * (1.0 - surface.metal) * surface.albedo * texture(irradiance_map, surface.normal).rgb;
+	vec3 reflected = reflect(-view_dir, surface.normal);
+	vec3 prefiltered = textureLod(prefiltered_map, reflected, surface.rough * max_reflection_lod).rgb;
+	vec2 brdf = texture(brdf_lut, vec2(n_dot_v, surface.rough)).rg;
+	return diffuse + prefiltered * (f0 * brdf.x + brdf.y);
+}
+
+// Lights every pixel with the ambient, directional and spot lights, and the environment
+void main() {
+	ivec2 pixel = ivec2(gl_FragCoord.xy);
+	Surface surface;
+	if (!read_surface(pixel, surface)) {
+		discard;
+	}
+	vec3 view_dir = normalize(eye - surface.position);
+
+	vec3 ambient = vec3(0.0);
+	for (int i = 0; i < num_l_amb; i++) {
+		ambient += amb_lights[i].color * amb_lights[i].intensity;
+	}
+	vec3 result = ambient * surface.albedo * surface.occlusion;
+	if (surface.pbr) {
+		if (has_environment) {
+			result += environment_light(surface, view_dir) * surface.occlusion;
+		}
+		result += texelFetch(emissive_buffer, pixel, 0).rgb;
+	}
+	for (int i = 0; i < num_l_dir; i++) {
+		result += shade(dir_lights[i], DIR, surface, view_dir, 0.0);
+	}
+	for (int i = 0; i < num_l_spot; i++) {
+		result += shade(spot_lights[i], SPOT, surface, view_dir, 0.0);
+	}
+	outputColor = vec4(result, 1.0);
+}
+#endif
@@ -0,0 +1,20 @@
+#version 300 es
+precision highp float;
+in vec2 frag_uv;
+uniform sampler2D color_buffer, depth_buffer;
+uniform mat4 inv_proj;
+uniform float focus_distance, focus_range, max_blur, max_lod;
+
+out vec4 outputColor;
+
+// Blurs what's out of focus by reading it from smaller mip levels, the farther from the focus
+// the smaller
+void main() {
+	float depth = textureLod(depth_buffer, frag_uv, 0.0).r;
+	vec4 position = inv_proj * vec4(vec3(frag_uv, depth) * 2.0 - 1.0, 1.0);
+	float distance = -position.z / position.w;
+	float blur = clamp(abs(distance - focus_distance) / focus_range, 0.0, 1.0) * max_blur;
+	// a mip level halves the resolution, so the blur radius in pixels is about 2^lod
+	float lod = min(log2(1.0 + blur), max_lod);
+	outputColor = vec4(textureLod(color_buffer, frag_uv, lod).rgb, 1.0);
+}
@@ -0,0 +1,16 @@
+#version 300 es
+precision highp float;
+in vec2 frag_uv;
+out vec4 outputColor;
+
+#define PI 3.14159265359
+
+uniform vec3 face_forward, face_right, face_up;
+uniform sampler2D equirect;
+
+void main() {
+	vec2 p = frag_uv * 2.0 - 1.0;
+	vec3 dir = normalize(face_forward + p.x * face_right + p.y * face_up);
+	vec2 uv = vec2(atan(dir.z, dir.x) / (2.0 * PI) + 0.5, 0.5 - asin(clamp(dir.y, -1.0, 1.0)) / PI);
+	outputColor = vec4(texture(equirect, uv).rgb, 1.0);
+}
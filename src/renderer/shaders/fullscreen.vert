@@ -0,0 +1,9 @@
+#version 300 es
+out vec2 frag_uv;
+
+// a triangle that covers the whole viewport, drawn without any vertex buffers
+void main() {
+	vec2 corner = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2);
+	frag_uv = corner;
+	gl_Position = vec4(corner * 2.0 - 1.0, 0.0, 1.0);
+}
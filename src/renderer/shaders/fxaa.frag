@@ -0,0 +1,47 @@
+#version 300 es
+precision highp float;
+in vec2 frag_uv;
+uniform sampler2D color_buffer;
+
+out vec4 outputColor;
+
+#define EDGE_THRESHOLD_MIN 0.0312
+#define EDGE_THRESHOLD_MAX 0.125
+#define REDUCE_MUL (1.0 / 8.0)
+#define REDUCE_MIN (1.0 / 128.0)
+#define SPAN_MAX 8.0
+
+vec3 fetch(vec2 uv) {
+	return textureLod(color_buffer, uv, 0.0).rgb;
+}
+
+float luma(vec3 color) {
+	return dot(color, vec3(0.299, 0.587, 0.114));
+}
+
+// Timothy Lottes' FXAA: finds the direction of the edges from the luma around each pixel, and
+// blurs along them
+void main() {
+	vec2 texel = 1.0 / vec2(textureSize(color_buffer, 0));
+	vec3 color = fetch(frag_uv);
+	float m = luma(color);
+	float nw = luma(fetch(frag_uv + vec2(-1.0, -1.0) * texel));
+	float ne = luma(fetch(frag_uv + vec2(1.0, -1.0) * texel));
+	float sw = luma(fetch(frag_uv + vec2(-1.0, 1.0) * texel));
+	float se = luma(fetch(frag_uv + vec2(1.0, 1.0) * texel));
+	float luma_min = min(m, min(min(nw, ne), min(sw, se)));
+	float luma_max = max(m, max(max(nw, ne), max(sw, se)));
+	if (luma_max - luma_min < max(EDGE_THRESHOLD_MIN, luma_max * EDGE_THRESHOLD_MAX)) {
+		outputColor = vec4(color, 1.0);
+		return;
+	}
+	vec2 dir = vec2((sw + se) - (nw + ne), (nw + sw) - (ne + se));
+	float reduce = max((nw + ne + sw + se) * 0.25 * REDUCE_MUL, REDUCE_MIN);
+	float scale = 1.0 / (min(abs(dir.x), abs(dir.y)) + reduce);
+	dir = clamp(dir * scale, -SPAN_MAX, SPAN_MAX) * texel;
+	vec3 near = 0.5 * (fetch(frag_uv - dir / 6.0) + fetch(frag_uv + dir / 6.0));
+	vec3 far = 0.5 * near + 0.25 * (fetch(frag_uv - dir * 0.5) + fetch(frag_uv + dir * 0.5));
+	// the wider blur is dropped when it reaches past the edge
+	float luma_far = luma(far);
+	outputColor = vec4(luma_far < luma_min || luma_far > luma_max ? near : far, 1.0);
+}
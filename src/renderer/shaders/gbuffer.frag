@@ -0,0 +1,95 @@
+#version 300 es
+precision highp float;
+in vec3 object_pos, surface_normal;
+in vec2 frag_tex;
+flat in vec4 frag_instance_color;
+
+// whether the mesh has a pbr material rather than a color one
+uniform bool pbr;
+uniform vec4 color;
+uniform bool instanced, receive_shadows;
+uniform float metallic, roughness;
+uniform vec3 emissive;
+uniform bool flat_shade, has_albedo, has_normal_map, has_occlusion_map, has_metallic_roughness_map;
+uniform float normal_scale, occlusion_strength;
+uniform sampler2D sampler, normal_map, occlusion_map, metallic_roughness_map;
+
+// rgb: the albedo in sRGB, which keeps the precision of dark colors. a: 1 for pbr materials
+layout(location = 0) out vec4 albedo_out;
+// xyz: the world space normal. w: 1 if shadows darken the surface
+layout(location = 1) out vec4 normal_out;
+// r: metallic, g: roughness, b: occlusion
+layout(location = 2) out vec4 material_out;
+layout(location = 3) out vec4 emissive_out;
+
+// The sRGB transfer functions
+vec3 to_linear(vec3 srgb) {
+	return mix(srgb / 12.92, pow((srgb + 0.055) / 1.055, vec3(2.4)), step(0.04045, srgb));
+}
+
+vec3 to_srgb(vec3 linear) {
+	linear = max(linear, 0.0);
+	return mix(linear * 12.92, 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, linear));
+}
+
+// Builds the tangent frame from screen space derivatives, as in pbr.frag
+vec3 perturb_normal(vec3 normal) {
+	vec3 dp1 = dFdx(object_pos);
+	vec3 dp2 = dFdy(object_pos);
+	vec2 duv1 = dFdx(frag_tex);
+	vec2 duv2 = dFdy(frag_tex);
+	vec3 dp2perp = cross(dp2, normal);
+	vec3 dp1perp = cross(normal, dp1);
+	vec3 tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+	vec3 bitangent = dp2perp * duv1.y + dp1perp * duv2.y;
+	float inv_max = inversesqrt(max(max(dot(tangent, tangent), dot(bitangent, bitangent)), 1e-12));
+	mat3 tbn = mat3(tangent * inv_max, bitangent * inv_max, normal);
+	vec3 mapped = texture(normal_map, frag_tex).xyz * 2.0 - 1.0;
+	mapped.xy *= normal_scale;
+	return normalize(tbn * mapped);
+}
+
+void main() {
+	vec3 normal;
+	if (flat_shade) {
+		normal = normalize(cross(dFdx(object_pos), dFdy(object_pos)));
+	} else {
+		normal = normalize(surface_normal);
+		if (pbr && !gl_FrontFacing) {
+			normal = -normal;
+		}
+	}
+	normal_out = vec4(normal, receive_shadows ? 1.0 : 0.0);
+
+	if (!pbr) {
+		vec3 albedo = has_albedo ?
+			texture(sampler, frag_tex).rgb :
+			(instanced ? frag_instance_color.rgb : color.rgb);
+		albedo_out = vec4(albedo, 0.0);
+		material_out = vec4(0.0, 1.0, 1.0, 1.0);
+		emissive_out = vec4(0.0);
+		return;
+	}
+
+	if (has_normal_map) {
+		normal_out.xyz = perturb_normal(normal);
+	}
+	vec3 base = color.rgb;
+	if (has_albedo) {
+		base *= to_linear(texture(sampler, frag_tex).rgb);
+	}
+	float metal = metallic;
+	float rough = roughness;
+	if (has_metallic_roughness_map) {
+		vec4 texel = texture(metallic_roughness_map, frag_tex);
+		rough *= texel.g;
+		metal *= texel.b;
+	}
+	float occlusion = 1.0;
+	if (has_occlusion_map) {
+		occlusion = 1.0 + occlusion_strength * (texture(occlusion_map, frag_tex).r - 1.0);
+	}
+	albedo_out = vec4(to_srgb(base), 1.0);
+	material_out = vec4(clamp(metal, 0.0, 1.0), clamp(rough, 0.0, 1.0), occlusion, 1.0);
+	emissive_out = vec4(emissive, 1.0);
+}
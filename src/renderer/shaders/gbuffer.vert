@@ -0,0 +1,23 @@
+#version 300 es
+// the attribute locations are filled in to match the lit shader whose vaos this draws
+layout(location = POSITION_LOCATION) in vec3 position;
+layout(location = NORMAL_LOCATION) in vec3 normal;
+layout(location = TEX_COORDS_LOCATION) in vec2 tex_coords;
+layout(location = INSTANCE_MODEL_LOCATION) in mat4 instance_model;
+layout(location = INSTANCE_COLOR_LOCATION) in vec4 instance_color;
+
+uniform mat4 model, view, proj;
+uniform bool instanced;
+
+out vec3 surface_normal, object_pos;
+out vec2 frag_tex;
+flat out vec4 frag_instance_color;
+
+void main() {
+	mat4 world = instanced ? model * instance_model : model;
+	object_pos = vec3(world * vec4(position, 1.0));
+	gl_Position = proj * view * vec4(object_pos, 1.0);
+	surface_normal = mat3(transpose(inverse(world))) * normal;
+	frag_tex = tex_coords;
+	frag_instance_color = instance_color;
+}
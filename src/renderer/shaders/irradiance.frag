@@ -0,0 +1,42 @@
+#version 300 es
+precision highp float;
+in vec2 frag_uv;
+out vec4 outputColor;
+
+#define PI 3.14159265359
+#define STEP 0.05
+
+uniform vec3 face_forward, face_right, face_up;
+uniform samplerCube environment;
+uniform bool srgb;
+
+vec3 sample_environment(vec3 dir, float lod) {
+	vec3 color = textureLod(environment, dir, lod).rgb;
+	return srgb ? pow(color, vec3(2.2)) : color;
+}
+
+// Convolves the environment with a cosine lobe around each direction, so that multiplying the
+// result by the albedo gives the diffuse light reflected towards the normal
+void main() {
+	vec2 p = frag_uv * 2.0 - 1.0;
+	vec3 normal = normalize(face_forward + p.x * face_right + p.y * face_up);
+	vec3 up = abs(normal.y) < 0.999 ? vec3(0.0, 1.0, 0.0) : vec3(0.0, 0.0, 1.0);
+	vec3 right = normalize(cross(up, normal));
+	up = cross(normal, right);
+
+	// read a mip whose texels are about as far apart as the samples
+	float size = float(textureSize(environment, 0).x);
+	float lod = max(log2(size * STEP / (0.5 * PI)), 0.0);
+
+	vec3 irradiance = vec3(0.0);
+	float samples = 0.0;
+	for (float phi = 0.0; phi < 2.0 * PI; phi += STEP) {
+		for (float theta = 0.0; theta < 0.5 * PI; theta += STEP) {
+			vec3 t = vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+			vec3 dir = t.x * right + t.y * up + t.z * normal;
+			irradiance += sample_environment(dir, lod) * cos(theta) * sin(theta);
+			samples += 1.0;
+		}
+	}
+	outputColor = vec4(PI * irradiance / samples, 1.0);
+}
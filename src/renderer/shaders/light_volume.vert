@@ -0,0 +1,19 @@
+#version 300 es
+in vec3 position;
+// xyz: the position of the point light, w: the radius it lights
+in vec4 light_position;
+// rgb: the color of the light, a: its intensity
+in vec4 light_color;
+
+uniform mat4 view, proj;
+
+flat out vec4 frag_light_position, frag_light_color;
+
+// a sphere around each point light, drawn once per light
+void main() {
+	frag_light_position = light_position;
+	frag_light_color = light_color;
+	gl_Position = proj * view * vec4(light_position.xyz + position * light_position.w, 1.0);
+	// the back faces are drawn, so they're kept when they're past the far plane
+	gl_Position.z = min(gl_Position.z, gl_Position.w);
+}
@@ -0,0 +1,247 @@
+#version 300 es
+precision highp float;
+in vec3 object_pos, surface_normal;
+in float view_depth;
+in vec2 frag_tex;
+
+#define DIR 0
+#define POINT 1
+#define SPOT 2
+#define MAX_NUM_LIGHTS 20
+#define MAX_SHADOWS 8
+#define PI 3.14159265359
+
+struct Light {
+	vec3 position;
+	vec3 color;
+
+	vec3 direction;
+	float cutoff;
+	float outer_cutoff;
+
+	float linear;
+	float intensity;
+	float quadratic;
+
+	// the first tile of the light in the shadow atlas, -1 without shadows
+	int shadow;
+	int cascades;
+	float shadow_bias;
+};
+
+uniform int num_l_amb, num_l_point, num_l_dir, num_l_spot;
+uniform Light amb_lights[MAX_NUM_LIGHTS];
+uniform Light point_lights[MAX_NUM_LIGHTS];
+uniform Light dir_lights[MAX_NUM_LIGHTS];
+uniform Light spot_lights[MAX_NUM_LIGHTS];
+
+uniform vec3 eye;
+uniform vec4 color;
+uniform float metallic, roughness;
+uniform vec3 emissive;
+uniform bool flat_shade, has_albedo, has_normal_map, has_occlusion_map, has_metallic_roughness_map;
+uniform float normal_scale, occlusion_strength;
+uniform sampler2D sampler, normal_map, occlusion_map, metallic_roughness_map;
+uniform bool has_environment;
+uniform samplerCube irradiance_map, prefiltered_map;
+uniform sampler2D brdf_lut;
+uniform float max_reflection_lod;
+uniform highp mat4 shadow_matrices[MAX_SHADOWS];
+uniform highp vec4 shadow_rects[MAX_SHADOWS];
+uniform highp float shadow_splits[MAX_SHADOWS];
+uniform highp sampler2DShadow shadow_atlas;
+uniform bool receive_shadows;
+uniform bool linear_output;
+
+out vec4 outputColor;
+
+// The sRGB transfer functions
+vec3 to_linear(vec3 srgb) {
+	return mix(srgb / 12.92, pow((srgb + 0.055) / 1.055, vec3(2.4)), step(0.04045, srgb));
+}
+
+vec3 to_srgb(vec3 linear) {
+	linear = max(linear, 0.0);
+	return mix(linear * 12.92, 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, linear));
+}
+
+// Trowbridge-Reitz (GGX) normal distribution
+float distribution(float n_dot_h, float alpha) {
+	float a2 = alpha * alpha;
+	float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+	return a2 / (PI * d * d);
+}
+
+// Height correlated Smith visibility, which includes the 1 / (4 n.l n.v) of the BRDF
+float visibility(float n_dot_l, float n_dot_v, float alpha) {
+	float a2 = alpha * alpha;
+	float ggx_v = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - a2) + a2);
+	float ggx_l = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - a2) + a2);
+	float ggx = ggx_v + ggx_l;
+	return ggx > 0.0 ? 0.5 / ggx : 0.0;
+}
+
+vec3 fresnel(float v_dot_h, vec3 f0) {
+	return f0 + (1.0 - f0) * pow(1.0 - v_dot_h, 5.0);
+}
+
+// Fresnel averaged over the rough microfacets that reflect the environment
+vec3 fresnel_roughness(float n_dot_v, vec3 f0, float rough) {
+	return f0 + (max(vec3(1.0 - rough), f0) - f0) * pow(1.0 - n_dot_v, 5.0);
+}
+
+// Light from the skybox, with the split sum approximation: the prefiltered environment is
+// scaled and biased by the BRDF integrated over the hemisphere
+vec3 environment_light(vec3 normal, vec3 view_dir, vec3 albedo, float metal, float rough) {
+	float n_dot_v = max(dot(normal, view_dir), 1e-4);
+	vec3 f0 = mix(vec3(0.04), albedo, metal);
+	vec3 f = fresnel_roughness(n_dot_v, f0, rough);
+	vec3 diffuse = (1.0 - f) * (1.0 - metal) * albedo * texture(irradiance_map, normal).rgb;
+	vec3 reflected = reflect(-view_dir, normal);
+	vec3 prefiltered = textureLod(prefiltered_map, reflected, rough * max_reflection_lod).rgb;
+	vec2 brdf = texture(brdf_lut, vec2(n_dot_v, rough)).rg;
+	return diffuse + prefiltered * (f0 * brdf.x + brdf.y);
+}
+
+// Builds the tangent frame from screen space derivatives, so meshes don't need tangents
+vec3 perturb_normal(vec3 normal) {
+	vec3 dp1 = dFdx(object_pos);
+	vec3 dp2 = dFdy(object_pos);
+	vec2 duv1 = dFdx(frag_tex);
+	vec2 duv2 = dFdy(frag_tex);
+	vec3 dp2perp = cross(dp2, normal);
+	vec3 dp1perp = cross(normal, dp1);
+	vec3 tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+	vec3 bitangent = dp2perp * duv1.y + dp1perp * duv2.y;
+	float inv_max = inversesqrt(max(max(dot(tangent, tangent), dot(bitangent, bitangent)), 1e-12));
+	mat3 tbn = mat3(tangent * inv_max, bitangent * inv_max, normal);
+	vec3 mapped = texture(normal_map, frag_tex).xyz * 2.0 - 1.0;
+	mapped.xy *= normal_scale;
+	return normalize(tbn * mapped);
+}
+
+// The fraction of the light that reaches the fragment, filtered over 3x3 texels of the light's
+// tile in the shadow atlas. Directional lights pick the cascade that covers the view depth.
+float shadow_factor(Light light, vec3 normal, vec3 light_dir) {
+	if (!receive_shadows || light.shadow < 0) {
+		return 1.0;
+	}
+	int tile = light.shadow;
+	for (int i = 1; i < light.cascades && view_depth > shadow_splits[tile]; i++) {
+		tile++;
+	}
+	if (view_depth > shadow_splits[tile]) {
+		return 1.0;
+	}
+	// surfaces are pushed along their normal, more so when they face away from the light
+	float n_dot_l = clamp(dot(normal, light_dir), 0.0, 1.0);
+	highp vec3 pos = object_pos + normal * light.shadow_bias * (2.0 - n_dot_l);
+	highp vec4 coords = shadow_matrices[tile] * vec4(pos, 1.0);
+	coords.xyz /= coords.w;
+	highp vec4 rect = shadow_rects[tile];
+	if (coords.z > 1.0 || any(lessThan(coords.xy, rect.xy)) || any(greaterThan(coords.xy, rect.zw))) {
+		return 1.0;
+	}
+	highp vec2 texel = 1.0 / vec2(textureSize(shadow_atlas, 0));
+	float lit = 0.0;
+	for (int x = -1; x <= 1; x++) {
+		for (int y = -1; y <= 1; y++) {
+			highp vec2 uv = clamp(coords.xy + vec2(x, y) * texel, rect.xy + 0.5 * texel, rect.zw - 0.5 * texel);
+			lit += texture(shadow_atlas, vec3(uv, coords.z));
+		}
+	}
+	return lit / 9.0;
+}
+
+// Cook-Torrance with a Lambertian diffuse, following the BRDF in the glTF specification. Light
+// intensities are scaled by PI so that a light lights a white surface as much as in color.frag.
+vec3 calc_light(Light light, int type, vec3 normal, vec3 view_dir, vec3 albedo, float metal, float alpha) {
+	vec3 light_dir = (type == DIR) ? normalize(light.direction) : normalize(light.position - object_pos);
+	float n_dot_l = dot(normal, light_dir);
+	if (n_dot_l <= 0.0) {
+		return vec3(0.0);
+	}
+	vec3 halfway = normalize(light_dir + view_dir);
+	float n_dot_v = max(dot(normal, view_dir), 1e-4);
+	float n_dot_h = max(dot(normal, halfway), 0.0);
+	float v_dot_h = max(dot(view_dir, halfway), 0.0);
+
+	vec3 f0 = mix(vec3(0.04), albedo, metal);
+	vec3 f = fresnel(v_dot_h, f0);
+	vec3 diffuse = (1.0 - f) * (1.0 - metal) * albedo / PI;
+	vec3 specular = f * distribution(n_dot_h, alpha) * visibility(n_dot_l, n_dot_v, alpha);
+	vec3 radiance = light.color * light.intensity * PI;
+
+	// directional lights are infinitely far away, so they aren't attenuated
+	if (type != DIR) {
+		float distance = length(light.position - object_pos);
+		radiance /= 1.0 + light.linear * distance + light.quadratic * (distance * distance);
+	}
+	if (type == SPOT) {
+		float theta = dot(light_dir, normalize(-light.direction));
+		float epsilon = light.cutoff - light.outer_cutoff;
+		radiance *= clamp((theta - light.outer_cutoff) / epsilon, 0.0, 1.0);
+	}
+	if (type != POINT) {
+		radiance *= shadow_factor(light, normal, light_dir);
+	}
+	return (diffuse + specular) * radiance * n_dot_l;
+}
+
+void main() {
+	vec3 normal;
+	if (flat_shade) {
+		normal = normalize(cross(dFdx(object_pos), dFdy(object_pos)));
+	} else {
+		normal = normalize(surface_normal);
+		// double sided meshes are lit from the side that is seen
+		normal = gl_FrontFacing ? normal : -normal;
+	}
+	if (has_normal_map) {
+		normal = perturb_normal(normal);
+	}
+	vec3 view_dir = normalize(eye - object_pos);
+
+	vec4 base = color;
+	if (has_albedo) {
+		vec4 texel = texture(sampler, frag_tex);
+		base *= vec4(to_linear(texel.rgb), texel.a);
+	}
+	float metal = metallic;
+	float rough = roughness;
+	if (has_metallic_roughness_map) {
+		vec4 texel = texture(metallic_roughness_map, frag_tex);
+		rough *= texel.g;
+		metal *= texel.b;
+	}
+	metal = clamp(metal, 0.0, 1.0);
+	float alpha = clamp(rough, 0.03, 1.0);
+	alpha *= alpha;
+
+	vec3 ambient = vec3(0.0);
+	for (int i = 0; i < num_l_amb; i++) {
+		ambient += amb_lights[i].color * amb_lights[i].intensity;
+	}
+	float occlusion = 1.0;
+	if (has_occlusion_map) {
+		occlusion = 1.0 + occlusion_strength * (texture(occlusion_map, frag_tex).r - 1.0);
+	}
+	vec3 result = ambient * base.rgb * occlusion;
+	if (has_environment) {
+		result += environment_light(normal, view_dir, base.rgb, metal, clamp(rough, 0.0, 1.0)) * occlusion;
+	}
+
+	for (int i = 0; i < num_l_dir; i++) {
+		result += calc_light(dir_lights[i], DIR, normal, view_dir, base.rgb, metal, alpha);
+	}
+	for (int i = 0; i < num_l_point; i++) {
+		result += calc_light(point_lights[i], POINT, normal, view_dir, base.rgb, metal, alpha);
+	}
+	for (int i = 0; i < num_l_spot; i++) {
+		result += calc_light(spot_lights[i], SPOT, normal, view_dir, base.rgb, metal, alpha);
+	}
+	result += emissive;
+
+	// shading happens in linear space, and the canvas expects sRGB
+	outputColor = vec4(linear_output ? result : to_srgb(result), base.a);
+}
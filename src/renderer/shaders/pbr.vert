@@ -0,0 +1,17 @@
+#version 300 es
+uniform mat4 model, view, proj;
+
+in vec3 position, normal;
+in vec2 tex_coords;
+out float view_depth;
+out vec3 surface_normal, object_pos;
+out vec2 frag_tex;
+
+void main() {
+	object_pos = vec3(model * vec4(position, 1.0));
+	vec4 view_pos = view * vec4(object_pos, 1.0);
+	gl_Position = proj * view_pos;
+	view_depth = -view_pos.z;
+	surface_normal = mat3(transpose(inverse(model))) * normal;
+	frag_tex = tex_coords;
+}
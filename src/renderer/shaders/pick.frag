@@ -0,0 +1,14 @@
+#version 300 es
+precision highp float;
+precision highp int;
+
+// the storage index of the node plus one, 0 is left for the background
+uniform uint node;
+
+flat in uint f_instance;
+
+out uvec4 id;
+
+void main() {
+	id = uvec4(node, f_instance, floatBitsToUint(gl_FragCoord.z), 0u);
+}
@@ -0,0 +1,16 @@
+#version 300 es
+// the attribute locations are filled in to match the shader whose vaos this draws
+layout(location = POSITION_LOCATION) in vec3 position;
+layout(location = INSTANCE_MODEL_LOCATION) in mat4 instance_model;
+
+uniform mat4 model, view, proj;
+uniform bool instanced;
+
+flat out uint f_instance;
+
+void main() {
+	mat4 world = instanced ? model * instance_model : model;
+	gl_Position = proj * view * world * vec4(position, 1.0);
+	gl_PointSize = 10.0;
+	f_instance = uint(gl_InstanceID);
+}
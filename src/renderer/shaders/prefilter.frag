@@ -0,0 +1,71 @@
+#version 300 es
+precision highp float;
+in vec2 frag_uv;
+out vec4 outputColor;
+
+#define PI 3.14159265359
+#define SAMPLE_COUNT 256u
+
+uniform vec3 face_forward, face_right, face_up;
+uniform samplerCube environment;
+uniform bool srgb;
+uniform float roughness;
+
+vec3 sample_environment(vec3 dir, float lod) {
+	vec3 color = textureLod(environment, dir, lod).rgb;
+	return srgb ? pow(color, vec3(2.2)) : color;
+}
+
+float radical_inverse(uint bits) {
+	bits = (bits << 16u) | (bits >> 16u);
+	bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+	bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+	bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+	bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+	return float(bits) * 2.3283064365386963e-10;
+}
+
+vec2 hammersley(uint i) {
+	return vec2(float(i) / float(SAMPLE_COUNT), radical_inverse(i));
+}
+
+// Picks a halfway vector around the normal, distributed like the GGX lobe
+vec3 importance_sample_ggx(vec2 xi, vec3 normal, float alpha) {
+	float phi = 2.0 * PI * xi.x;
+	float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y));
+	float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+	vec3 up = abs(normal.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+	vec3 tangent = normalize(cross(up, normal));
+	vec3 bitangent = cross(normal, tangent);
+	return normalize(tangent * cos(phi) * sin_theta + bitangent * sin(phi) * sin_theta + normal * cos_theta);
+}
+
+// Blurs the environment with the GGX lobe of the roughness, assuming the view direction is the
+// normal, for the split sum approximation
+void main() {
+	vec2 p = frag_uv * 2.0 - 1.0;
+	vec3 normal = normalize(face_forward + p.x * face_right + p.y * face_up);
+	float alpha = roughness * roughness;
+	float size = float(textureSize(environment, 0).x);
+	float texel = 4.0 * PI / (6.0 * size * size);
+
+	vec3 color = vec3(0.0);
+	float weight = 0.0;
+	for (uint i = 0u; i < SAMPLE_COUNT; i++) {
+		vec3 halfway = importance_sample_ggx(hammersley(i), normal, alpha);
+		vec3 light_dir = normalize(2.0 * dot(normal, halfway) * halfway - normal);
+		float n_dot_l = dot(normal, light_dir);
+		if (n_dot_l > 0.0) {
+			// sparse samples read a blurrier mip, so bright texels don't show up as dots
+			float n_dot_h = max(dot(normal, halfway), 0.0);
+			float a2 = alpha * alpha;
+			float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+			float pdf = a2 / (PI * d * d) / 4.0 + 1e-4;
+			float solid_angle = 1.0 / (float(SAMPLE_COUNT) * pdf);
+			float lod = roughness == 0.0 ? 0.0 : max(0.5 * log2(solid_angle / texel) + 1.0, 0.0);
+			color += sample_environment(light_dir, lod) * n_dot_l;
+			weight += n_dot_l;
+		}
+	}
+	outputColor = vec4(color / weight, 1.0);
+}
@@ -0,0 +1,27 @@
+#version 300 es
+precision highp float;
+uniform sampler2D light_buffer, albedo_buffer, depth_buffer;
+uniform bool linear_output;
+
+out vec4 outputColor;
+
+// The sRGB transfer function
+vec3 to_srgb(vec3 linear) {
+	linear = max(linear, 0.0);
+	return mix(linear * 12.92, 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, linear));
+}
+
+// Copies the lit G-buffer to the canvas or the HDR target, along with its depth so that forward
+// rendered meshes are hidden behind it
+void main() {
+	ivec2 pixel = ivec2(gl_FragCoord.xy);
+	float depth = texelFetch(depth_buffer, pixel, 0).r;
+	if (depth >= 1.0) {
+		discard;
+	}
+	vec3 light = texelFetch(light_buffer, pixel, 0).rgb;
+	bool pbr = texelFetch(albedo_buffer, pixel, 0).a > 0.5;
+	// pbr materials are shaded in linear space, and the canvas expects sRGB
+	outputColor = vec4(pbr && !linear_output ? to_srgb(light) : light, 1.0);
+	gl_FragDepth = depth;
+}
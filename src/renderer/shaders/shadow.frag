@@ -0,0 +1,6 @@
+#version 300 es
+precision mediump float;
+
+// only depth is written
+void main() {
+}
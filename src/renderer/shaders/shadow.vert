@@ -0,0 +1,12 @@
+#version 300 es
+// the attribute locations are filled in to match the lit shader whose vaos this draws
+layout(location = POSITION_LOCATION) in vec3 position;
+layout(location = INSTANCE_MODEL_LOCATION) in mat4 instance_model;
+
+uniform mat4 model, light_matrix;
+uniform bool instanced;
+
+void main() {
+	mat4 world = instanced ? model * instance_model : model;
+	gl_Position = light_matrix * world * vec4(position, 1.0);
+}
@@ -1,7 +1,18 @@
 #version 300 es
 precision mediump float;
 in vec4 f_color;
+uniform bool linear_output;
 out vec4 outputColor;
+
+// The sRGB transfer function
+vec3 to_linear(vec3 srgb) {
+	return mix(srgb / 12.92, pow((srgb + 0.055) / 1.055, vec3(2.4)), step(0.04045, srgb));
+}
+
 void main() {
 	outputColor = f_color;
+	// colors are given in sRGB, and the HDR target holds linear ones
+	if (linear_output) {
+		outputColor.rgb = to_linear(outputColor.rgb);
+	}
 }
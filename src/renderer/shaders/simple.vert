@@ -1,12 +1,16 @@
 #version 300 es
 in vec3 position;
+in mat4 instance_model;
+in vec4 instance_color;
 
 uniform mat4 model, view, proj;
 uniform vec4 color;
+uniform bool instanced;
 
 out vec4 f_color;
 
 void main() {
-	gl_Position = proj * view * model * vec4(position, 1.0);
-	f_color = color;
+	mat4 world = instanced ? model * instance_model : model;
+	gl_Position = proj * view * world * vec4(position, 1.0);
+	f_color = instanced ? instance_color : color;
 }
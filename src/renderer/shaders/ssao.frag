@@ -0,0 +1,51 @@
+#version 300 es
+precision highp float;
+in vec2 frag_uv;
+uniform sampler2D color_buffer, depth_buffer;
+uniform mat4 proj, inv_proj;
+uniform float radius, intensity;
+
+out vec4 outputColor;
+
+#define SAMPLES 16
+#define GOLDEN_ANGLE 2.39996323
+#define PI 3.14159265359
+
+vec3 view_position(vec2 uv) {
+	float depth = textureLod(depth_buffer, uv, 0.0).r;
+	vec4 position = inv_proj * vec4(vec3(uv, depth) * 2.0 - 1.0, 1.0);
+	return position.xyz / position.w;
+}
+
+// Darkens the creases of the image by testing points in the hemisphere above each pixel against
+// the depth buffer. Normals are rebuilt from the depth.
+void main() {
+	vec3 color = textureLod(color_buffer, frag_uv, 0.0).rgb;
+	if (textureLod(depth_buffer, frag_uv, 0.0).r >= 1.0) {
+		outputColor = vec4(color, 1.0);
+		return;
+	}
+	vec3 position = view_position(frag_uv);
+	vec3 normal = normalize(cross(dFdx(position), dFdy(position)));
+	vec3 tangent = normalize(cross(normal, abs(normal.y) < 0.99 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0)));
+	vec3 bitangent = cross(normal, tangent);
+	// a different rotation per pixel turns banding into noise
+	float rotation = fract(sin(dot(gl_FragCoord.xy, vec2(12.9898, 78.233))) * 43758.5453) * 2.0 * PI;
+	float occlusion = 0.0;
+	for (int i = 0; i < SAMPLES; i++) {
+		float t = (float(i) + 0.5) / float(SAMPLES);
+		float angle = float(i) * GOLDEN_ANGLE + rotation;
+		vec3 dir = vec3(cos(angle) * sqrt(t), sin(angle) * sqrt(t), sqrt(1.0 - t));
+		// more points close to the surface, where occluders matter most
+		float scale = radius * mix(0.1, 1.0, t * t);
+		vec3 point = position + (tangent * dir.x + bitangent * dir.y + normal * dir.z) * scale;
+		vec4 projected = proj * vec4(point, 1.0);
+		vec2 uv = projected.xy / projected.w * 0.5 + 0.5;
+		float depth = view_position(uv).z;
+		// occluders far in front of the pixel belong to another object
+		float range = smoothstep(0.0, 1.0, radius / abs(position.z - depth));
+		occlusion += (depth >= point.z + 0.025 * radius ? 1.0 : 0.0) * range;
+	}
+	float ambient = 1.0 - intensity * occlusion / float(SAMPLES);
+	outputColor = vec4(color * clamp(ambient, 0.0, 1.0), 1.0);
+}
@@ -0,0 +1,39 @@
+#version 300 es
+precision highp float;
+in vec2 frag_uv;
+uniform sampler2D color_buffer;
+uniform float exposure;
+// 0: linear, 1: Reinhard, 2: ACES, 3: filmic
+uniform int tone_mapping;
+
+out vec4 outputColor;
+
+vec3 to_srgb(vec3 linear) {
+	linear = max(linear, 0.0);
+	return mix(linear * 12.92, 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, linear));
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+vec3 aces(vec3 x) {
+	return (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+}
+
+// John Hable's curve from Uncharted 2
+vec3 hable(vec3 x) {
+	const float a = 0.15, b = 0.50, c = 0.10, d = 0.20, e = 0.02, f = 0.30;
+	return ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f;
+}
+
+// Maps the HDR target to the range of the canvas, and encodes it in sRGB
+void main() {
+	vec3 color = textureLod(color_buffer, frag_uv, 0.0).rgb * exposure;
+	if (tone_mapping == 1) {
+		color = color / (1.0 + color);
+	} else if (tone_mapping == 2) {
+		color = aces(color);
+	} else if (tone_mapping == 3) {
+		const float white = 11.2;
+		color = hable(color * 2.0) / hable(vec3(white));
+	}
+	outputColor = vec4(to_srgb(clamp(color, 0.0, 1.0)), 1.0);
+}
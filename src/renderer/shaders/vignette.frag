@@ -0,0 +1,16 @@
+#version 300 es
+precision highp float;
+in vec2 frag_uv;
+uniform sampler2D color_buffer;
+uniform float intensity, smoothness;
+
+out vec4 outputColor;
+
+// Darkens the image towards its corners
+void main() {
+	vec3 color = textureLod(color_buffer, frag_uv, 0.0).rgb;
+	// 1 at the corners
+	float distance = length(frag_uv - 0.5) * sqrt(2.0);
+	float darkening = intensity * smoothstep(1.0 - smoothness, 1.0, distance);
+	outputColor = vec4(color * (1.0 - darkening), 1.0);
+}
@@ -4,11 +4,16 @@
 // <http://codeflow.org/entries/2012/aug/02/easy-wireframe-display-with-barycentric-coordinates/>
 precision mediump float;
 uniform vec4 color;
-uniform bool drawing_points;
+uniform bool drawing_points, linear_output;
 uniform float width, feather;
 in vec3 frag_bc;
 out vec4 outputColor;
 
+// The sRGB transfer function
+vec3 to_linear(vec3 srgb) {
+	return mix(srgb / 12.92, pow((srgb + 0.055) / 1.055, vec3(2.4)), step(0.04045, srgb));
+}
+
 float edgeFactor(){
 	float w1 = width - feather * 0.5;
 	vec3 bary = vec3(frag_bc.x, frag_bc.y, 1.0 - frag_bc.x - frag_bc.y);
@@ -28,5 +33,8 @@ void main() {
 		outputColor = gl_FrontFacing?
 			vec4(color.rgb, (color.a-edgeFactor())*0.95): 
 			vec4(color.rgb, (color.a-edgeFactor())*0.5);
+		if (linear_output) {
+			outputColor.rgb = to_linear(outputColor.rgb);
+		}
 	}
 }
@@ -0,0 +1,410 @@
+//! Shadow maps for directional and spot lights. Every shadow map of a frame is a square tile of
+//! a single depth texture, the shadow atlas, so the lit shaders sample all of them through one
+//! sampler.
+
+use super::{
+    gl as GL, gl_index_type, light_direction, set_bool, set_f32, set_i32, set_mat4, set_vec4,
+    Backend, DrawMode, ProgramId, Renderer, ShaderType, TextureId, LIGHT_RANGE,
+    SPOT_OUTER_CUTOFF,
+};
+use crate::{controller::Viewport, log, LightType, Storage};
+use nalgebra::{Matrix4, Orthographic3, Perspective3, Point3, Vector3, Vector4};
+use std::collections::HashMap;
+
+/// Shadow maps the lit shaders can sample in a frame, counting every cascade.
+pub const MAX_SHADOWS: usize = 8;
+/// Size of the largest shadow atlas, which most WebGL 2 implementations support.
+pub const MAX_SHADOW_ATLAS_SIZE: i32 = 4096;
+/// The texture unit of the shadow atlas, after the ones of the materials and the environment.
+pub(super) const SHADOW_ATLAS_UNIT: u32 = 7;
+
+/// How a directional or spot light casts shadows.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShadowConfig {
+    /// Texels along each side of the shadow map, rounded up to a power of two.
+    pub resolution: u32,
+    /// How far, in world units, surfaces are pushed along their normal before they're looked
+    /// up in the shadow map. Keeps surfaces from shadowing themselves.
+    pub bias: f32,
+    /// Shadow maps that a directional light splits the view into, each covering a farther and
+    /// larger part of it. Spot lights always have a single one.
+    pub cascades: u32,
+    /// The view depth up to which a directional light casts shadows.
+    pub distance: f32,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            resolution: 1024,
+            bias: 0.05,
+            cascades: 3,
+            distance: 50.,
+        }
+    }
+}
+
+/// A shadow map in the atlas.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct ShadowTile {
+    /// From world space to the clip space of the light, for the shadow pass.
+    light_matrix: Matrix4<f32>,
+    /// The farthest view depth that this map covers.
+    split: f32,
+    size: i32,
+    x: i32,
+    y: i32,
+}
+
+/// The shadow maps rendered for a frame.
+#[derive(Debug, Default)]
+pub(super) struct Shadows {
+    /// The first tile, the number of tiles and the bias of every light that casts shadows, by
+    /// light id.
+    lights: HashMap<usize, (usize, usize, f32)>,
+    tiles: Vec<ShadowTile>,
+    atlas_size: i32,
+}
+
+impl Shadows {
+    /// The uniforms of a light's shadows: its first tile, -1 without shadows, its number of
+    /// tiles and its bias.
+    pub(super) fn light(&self, light_id: usize) -> (i32, i32, f32) {
+        self.lights
+            .get(&light_id)
+            .map_or((-1, 0, 0.), |&(first, count, bias)| {
+                (first as i32, count as i32, bias)
+            })
+    }
+}
+
+/// The depth only programs that render the shadow maps, one for each lit shader.
+#[derive(Debug)]
+pub(super) struct ShadowPrograms {
+    color: ProgramId,
+    pbr: ProgramId,
+}
+
+impl ShadowPrograms {
+    pub(super) const COUNT: usize = 2;
+
+    pub(super) fn new(gl: &dyn Backend, color: ProgramId, pbr: ProgramId) -> Self {
+        Self {
+            color: Self::create(gl, color),
+            pbr: Self::create(gl, pbr),
+        }
+    }
+    /// A mesh's vao is set up for the attribute locations of its own shader, so the depth only
+    /// program takes the same locations.
+    fn create(gl: &dyn Backend, lit: ProgramId) -> ProgramId {
+        let position = gl.attrib_location(lit, "position");
+        let instance_model = match gl.attrib_location(lit, "instance_model") {
+            location if location < 0 => position + 1,
+            location => location,
+        };
+        let vertex = include_str!("shaders/shadow.vert")
+            .replace("POSITION_LOCATION", &position.to_string())
+            .replace("INSTANCE_MODEL_LOCATION", &instance_model.to_string());
+        gl.create_program(&vertex, include_str!("shaders/shadow.frag"))
+            .expect("Can't create shadow shader!")
+    }
+    fn program(&self, shader_type: ShaderType) -> Option<ProgramId> {
+        match shader_type {
+            ShaderType::Color => Some(self.color),
+            ShaderType::Pbr => Some(self.pbr),
+            _ => None,
+        }
+    }
+}
+
+/// An up vector for a view looking along the direction.
+fn up_for(direction: &Vector3<f32>) -> Vector3<f32> {
+    if direction.y.abs() > 0.99 {
+        Vector3::z()
+    } else {
+        Vector3::y()
+    }
+}
+
+/// Splits the view between its near plane and the shadow distance, each cascade covered by an
+/// orthographic view from the light. Half logarithmic and half uniform splits keep the nearest
+/// cascades detailed without making the farthest ones too long.
+fn cascades(
+    viewport: &Viewport,
+    to_light: &Vector3<f32>,
+    config: &ShadowConfig,
+    size: i32,
+) -> Vec<(Matrix4<f32>, f32)> {
+    let view = viewport.view();
+    let inverse = (viewport.proj() * view)
+        .try_inverse()
+        .unwrap_or_else(Matrix4::identity);
+    let unproject = |x: f32, y: f32, z: f32| {
+        let p = inverse * Vector4::new(x, y, z, 1.);
+        p.xyz() / p.w
+    };
+    let depth = |p: &Vector3<f32>| -(view * p.push(1.)).z;
+    // the edges of the view frustum, from the near plane to the far plane
+    let edges: Vec<(Vector3<f32>, Vector3<f32>)> = [(-1., -1.), (1., -1.), (-1., 1.), (1., 1.)]
+        .iter()
+        .map(|&(x, y)| (unproject(x, y, -1.), unproject(x, y, 1.)))
+        .collect();
+    let near = depth(&edges[0].0);
+    let far = depth(&edges[0].1);
+    let shadow_far = far.min(config.distance).max(near + 1e-3);
+    let count = (config.cascades.max(1) as usize).min(MAX_SHADOWS);
+    let splits: Vec<f32> = (0..=count)
+        .map(|k| {
+            let t = k as f32 / count as f32;
+            let log = near.max(1e-2) * (shadow_far / near.max(1e-2)).powf(t);
+            let uniform = near + (shadow_far - near) * t;
+            (log + uniform) / 2.
+        })
+        .collect();
+    let at_depth = |edge: &(Vector3<f32>, Vector3<f32>), d: f32| {
+        edge.0 + (edge.1 - edge.0) * ((d - near) / (far - near))
+    };
+    (0..count)
+        .map(|k| {
+            let corners: Vec<Vector3<f32>> = edges
+                .iter()
+                .flat_map(|edge| vec![at_depth(edge, splits[k]), at_depth(edge, splits[k + 1])])
+                .collect();
+            let center = corners.iter().sum::<Vector3<f32>>() / corners.len() as f32;
+            // a bounding sphere keeps the size of the cascade the same as the view turns
+            let radius = corners
+                .iter()
+                .map(|c| (c - center).norm())
+                .fold(0., f32::max);
+            let radius = (radius * 16.).ceil() / 16.;
+            let eye = center + to_light * (radius + config.distance);
+            let view = Matrix4::look_at_rh(
+                &Point3::from(eye),
+                &Point3::from(center),
+                &up_for(to_light),
+            );
+            let proj = Orthographic3::new(
+                -radius,
+                radius,
+                -radius,
+                radius,
+                0.,
+                2. * radius + config.distance,
+            );
+            let mut matrix = proj.to_homogeneous() * view;
+            // moving the view by whole texels keeps the edges of shadows from crawling
+            let origin = matrix * Vector4::new(0., 0., 0., 1.);
+            let half = size as f32 / 2.;
+            matrix[(0, 3)] += ((origin.x * half).round() - origin.x * half) / half;
+            matrix[(1, 3)] += ((origin.y * half).round() - origin.y * half) / half;
+            (matrix, splits[k + 1])
+        })
+        .collect()
+}
+
+/// The position of a tile in a square made of tiles of its size, following the Z-order curve.
+/// Tiles placed largest first this way never overlap and fill the atlas without gaps.
+fn z_order(mut index: usize) -> (i32, i32) {
+    let (mut x, mut y) = (0, 0);
+    let mut bit = 0;
+    while index > 0 {
+        x |= ((index & 1) as i32) << bit;
+        y |= (((index >> 1) & 1) as i32) << bit;
+        index >>= 2;
+        bit += 1;
+    }
+    (x, y)
+}
+
+impl Renderer {
+    /// Renders the shadow maps of the lights that cast shadows into the shadow atlas, which is
+    /// resized to fit them. Lights that don't fit in the largest atlas go without shadows.
+    pub(super) fn render_shadows(&self, storage: &Storage, viewport: &Viewport) -> Shadows {
+        let mut shadows = Shadows::default();
+        let mut area = 0;
+        for id in storage.light_ids() {
+            let light = storage.light(id);
+            let config = match light.shadows {
+                Some(config) if light.light && storage.is_valid(light.node_id) => config,
+                _ => continue,
+            };
+            let size = (config.resolution.next_power_of_two() as i32).min(MAX_SHADOW_ATLAS_SIZE);
+            let node_id = light.node_id.index();
+            let direction = light_direction(storage, node_id);
+            let views = match light.light_type {
+                LightType::Directional => cascades(viewport, &direction, &config, size),
+                LightType::Spot => {
+                    let position = storage.world_transform(node_id).isometry.translation.vector;
+                    let view = Matrix4::look_at_rh(
+                        &Point3::from(position),
+                        &Point3::from(position + direction),
+                        &up_for(&direction),
+                    );
+                    let proj = Perspective3::new(1., 2. * SPOT_OUTER_CUTOFF + 0.05, 0.1, LIGHT_RANGE);
+                    vec![(proj.to_homogeneous() * view, f32::MAX)]
+                }
+                _ => continue,
+            };
+            let light_area = views.len() * (size * size) as usize;
+            let max_area = (MAX_SHADOW_ATLAS_SIZE * MAX_SHADOW_ATLAS_SIZE) as usize;
+            if shadows.tiles.len() + views.len() > MAX_SHADOWS || area + light_area > max_area {
+                log!("There is no room left in the shadow atlas for light" id);
+                continue;
+            }
+            area += light_area;
+            shadows
+                .lights
+                .insert(id, (shadows.tiles.len(), views.len(), config.bias));
+            shadows
+                .tiles
+                .extend(views.into_iter().map(|(light_matrix, split)| ShadowTile {
+                    light_matrix,
+                    split,
+                    size,
+                    x: 0,
+                    y: 0,
+                }));
+        }
+        if shadows.tiles.is_empty() {
+            return shadows;
+        }
+
+        // place the tiles largest first, each at the offset of the area taken before it
+        let mut order: Vec<usize> = (0..shadows.tiles.len()).collect();
+        order.sort_by_key(|&i| -shadows.tiles[i].size);
+        let mut offset = 0;
+        for i in order {
+            let tile = &mut shadows.tiles[i];
+            let (x, y) = z_order(offset / (tile.size * tile.size) as usize);
+            tile.x = x * tile.size;
+            tile.y = y * tile.size;
+            offset += (tile.size * tile.size) as usize;
+        }
+        let mut atlas_size = 1;
+        while ((atlas_size * atlas_size) as usize) < area {
+            atlas_size *= 2;
+        }
+        shadows.atlas_size = atlas_size;
+        let atlas = self.shadow_atlas(atlas_size);
+
+        self.offscreen(|gl| {
+            gl.framebuffer_texture_2d(
+                GL::FRAMEBUFFER,
+                GL::DEPTH_ATTACHMENT,
+                GL::TEXTURE_2D,
+                Some(atlas),
+                0,
+            );
+            gl.enable(GL::DEPTH_TEST);
+            gl.clear(GL::DEPTH_BUFFER_BIT);
+            for tile in shadows.tiles.iter() {
+                gl.viewport(tile.x, tile.y, tile.size, tile.size);
+                for i in 0..storage.meshes().len() {
+                    self.render_shadow_caster(storage, i, &tile.light_matrix);
+                }
+            }
+        });
+        self.backend.active_texture(GL::TEXTURE0 + SHADOW_ATLAS_UNIT);
+        self.backend.bind_texture(GL::TEXTURE_2D, Some(atlas));
+        shadows
+    }
+    /// The shadow atlas, created again whenever its size changes.
+    fn shadow_atlas(&self, size: i32) -> TextureId {
+        if let Some((atlas, atlas_size)) = self.shadow_atlas.get() {
+            if atlas_size == size {
+                return atlas;
+            }
+            self.delete_texture(atlas);
+        }
+        let atlas = self.create_map(
+            GL::TEXTURE_2D,
+            1,
+            GL::DEPTH_COMPONENT24,
+            size as usize,
+            size as usize,
+        );
+        let gl = &*self.backend;
+        gl.bind_texture(GL::TEXTURE_2D, Some(atlas));
+        gl.tex_parameter(
+            GL::TEXTURE_2D,
+            GL::TEXTURE_COMPARE_MODE,
+            GL::COMPARE_REF_TO_TEXTURE,
+        );
+        gl.tex_parameter(GL::TEXTURE_2D, GL::TEXTURE_COMPARE_FUNC, GL::LEQUAL);
+        gl.bind_texture(GL::TEXTURE_2D, None);
+        self.shadow_atlas.set(Some((atlas, size)));
+        atlas
+    }
+    /// Draws the depth of a mesh that casts shadows. Only meshes drawn with a lit shader do.
+    fn render_shadow_caster(&self, storage: &Storage, i: usize, light_matrix: &Matrix4<f32>) {
+        if !storage.is_alive(i) {
+            return;
+        }
+        let mesh = match storage.mesh(i) {
+            Some(mesh) => mesh,
+            None => return,
+        };
+        let info = storage.info(i);
+        let flags = info.render_flags;
+        if !flags.render
+            || !flags.depth
+            || !flags.cast_shadows
+            || info.draw_mode == DrawMode::Points
+            || info.draw_mode == DrawMode::Lines
+        {
+            return;
+        }
+        let program = match self.shadow_programs.program(mesh.material.shader_type) {
+            Some(program) => program,
+            None => return,
+        };
+        let gl = &*self.backend;
+        let instances = storage.instances(i).map(|n| n.transforms.len() as i32);
+        gl.bind_vertex_array(storage.vao(i));
+        gl.use_program(Some(program));
+        set_mat4(gl, program, "light_matrix", light_matrix);
+        set_mat4(gl, program, "model", &storage.world_transform(i).to_homogeneous());
+        set_bool(gl, program, "instanced", instances.is_some());
+        Self::draw(
+            gl,
+            info.draw_mode,
+            mesh.geometry.indices.len() as i32,
+            gl_index_type(mesh.geometry.index_type()),
+            instances,
+        );
+    }
+    /// Writes where each shadow map is in the atlas to the uniforms of a lit shader.
+    pub(super) fn set_shadow_uniforms(&self, program: ProgramId, shadows: &Shadows) {
+        let gl = &*self.backend;
+        let atlas = shadows.atlas_size as f32;
+        for (i, tile) in shadows.tiles.iter().enumerate() {
+            let (x, y, size) = (tile.x as f32, tile.y as f32, tile.size as f32);
+            // from the clip space of the light to texture coordinates in the tile, and to depth
+            // in [0, 1]
+            let to_tile = Matrix4::new_translation(&Vector3::new(
+                (x + size / 2.) / atlas,
+                (y + size / 2.) / atlas,
+                0.5,
+            )) * Matrix4::new_nonuniform_scaling(&Vector3::new(
+                size / 2. / atlas,
+                size / 2. / atlas,
+                0.5,
+            ));
+            set_mat4(
+                gl,
+                program,
+                &format!("shadow_matrices[{}]", i),
+                &(to_tile * tile.light_matrix),
+            );
+            set_vec4(
+                gl,
+                program,
+                &format!("shadow_rects[{}]", i),
+                &[x / atlas, y / atlas, (x + size) / atlas, (y + size) / atlas],
+            );
+            set_f32(gl, program, &format!("shadow_splits[{}]", i), tile.split);
+        }
+        set_i32(gl, program, "shadow_atlas", SHADOW_ATLAS_UNIT as i32);
+    }
+}
@@ -0,0 +1,235 @@
+use super::inflate::{deflate_stored, inflate};
+use std::fmt;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// Why a PNG file couldn't be read.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PngError {
+    /// The file is truncated or isn't a PNG at all.
+    Format(String),
+    /// The file is a valid PNG, but uses e.g. a bit depth or color type that isn't supported.
+    Unsupported(String),
+}
+
+impl fmt::Display for PngError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PngError::Format(e) => write!(f, "Invalid PNG: {}", e),
+            PngError::Unsupported(e) => write!(f, "Unsupported PNG: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PngError {}
+
+/// An 8 bit RGBA image, stored row by row from the top.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Image {
+    /// Creates a transparent black image.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; (width * height * 4) as usize],
+        }
+    }
+    pub fn from_pixels(width: u32, height: u32, pixels: Vec<u8>) -> Self {
+        assert_eq!(
+            pixels.len(),
+            (width * height * 4) as usize,
+            "The pixels don't fit the size of the image!"
+        );
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * self.width + x) * 4) as usize;
+        [
+            self.pixels[i],
+            self.pixels[i + 1],
+            self.pixels[i + 2],
+            self.pixels[i + 3],
+        ]
+    }
+    pub fn set_pixel(&mut self, x: u32, y: u32, color: [u8; 4]) {
+        let i = ((y * self.width + x) * 4) as usize;
+        self.pixels[i..i + 4].copy_from_slice(&color);
+    }
+    /// Counts the pixels that have a channel differing from the other image by more than the
+    /// tolerance. Returns None if the images aren't the same size.
+    pub fn diff(&self, other: &Image, tolerance: u8) -> Option<usize> {
+        if self.width != other.width || self.height != other.height {
+            return None;
+        }
+        let differing = self
+            .pixels
+            .chunks(4)
+            .zip(other.pixels.chunks(4))
+            .filter(|(a, b)| {
+                a.iter()
+                    .zip(b.iter())
+                    .any(|(a, b)| (*a as i16 - *b as i16).abs() > tolerance as i16)
+            })
+            .count();
+        Some(differing)
+    }
+    /// Encodes the image as an uncompressed RGBA PNG.
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut png = PNG_SIGNATURE.to_vec();
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&self.width.to_be_bytes());
+        header.extend_from_slice(&self.height.to_be_bytes());
+        // 8 bits per channel, RGBA, default compression and filtering, no interlacing
+        header.extend_from_slice(&[8, 6, 0, 0, 0]);
+        write_chunk(&mut png, b"IHDR", &header);
+        let row = (self.width * 4) as usize;
+        let mut raw = Vec::with_capacity((row + 1) * self.height as usize);
+        for line in self.pixels.chunks(row.max(1)).take(self.height as usize) {
+            raw.push(0);
+            raw.extend_from_slice(line);
+        }
+        write_chunk(&mut png, b"IDAT", &deflate_stored(&raw));
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+    /// Decodes a non interlaced 8 bit PNG in RGB or RGBA.
+    pub fn from_png(data: &[u8]) -> Result<Self, PngError> {
+        if !data.starts_with(&PNG_SIGNATURE) {
+            return Err(PngError::Format("Missing signature".into()));
+        }
+        let mut pos = PNG_SIGNATURE.len();
+        let mut header = None;
+        let mut compressed = Vec::new();
+        while pos + 8 <= data.len() {
+            let len = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+                as usize;
+            let kind = &data[pos + 4..pos + 8];
+            let body = data
+                .get(pos + 8..pos + 8 + len)
+                .ok_or_else(|| PngError::Format("Truncated chunk".into()))?;
+            match kind {
+                b"IHDR" => header = Some(body.to_vec()),
+                b"IDAT" => compressed.extend_from_slice(body),
+                b"IEND" => break,
+                _ => (),
+            }
+            // skip the chunk along with its crc
+            pos += 12 + len;
+        }
+        let header = header.ok_or_else(|| PngError::Format("Missing IHDR chunk".into()))?;
+        if header.len() != 13 {
+            return Err(PngError::Format("Invalid IHDR chunk".into()));
+        }
+        let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        let (depth, color_type, interlace) = (header[8], header[9], header[12]);
+        let channels = match color_type {
+            2 => 3,
+            6 => 4,
+            _ => return Err(PngError::Unsupported(format!("Color type {}", color_type))),
+        };
+        if depth != 8 {
+            return Err(PngError::Unsupported(format!("Bit depth {}", depth)));
+        }
+        if interlace != 0 {
+            return Err(PngError::Unsupported("Interlacing".into()));
+        }
+        let raw = inflate(&compressed).map_err(PngError::Format)?;
+        let row = width as usize * channels;
+        if raw.len() < (row + 1) * height as usize {
+            return Err(PngError::Format("Not enough image data".into()));
+        }
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        let mut previous = vec![0; row];
+        for line in raw.chunks(row + 1).take(height as usize) {
+            let current = unfilter(line[0], &line[1..], &previous, channels)?;
+            for pixel in current.chunks(channels) {
+                pixels.extend_from_slice(pixel);
+                if channels == 3 {
+                    pixels.push(255);
+                }
+            }
+            previous = current;
+        }
+        Ok(Self::from_pixels(width, height, pixels))
+    }
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                0xEDB8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Reverses the filter a PNG encoder applied to a row.
+fn unfilter(filter: u8, line: &[u8], previous: &[u8], bpp: usize) -> Result<Vec<u8>, PngError> {
+    let mut out = line.to_vec();
+    for i in 0..out.len() {
+        let left = if i >= bpp { out[i - bpp] } else { 0 };
+        let up = previous[i];
+        let up_left = if i >= bpp { previous[i - bpp] } else { 0 };
+        let prediction = match filter {
+            0 => 0,
+            1 => left,
+            2 => up,
+            3 => ((u16::from(left) + u16::from(up)) / 2) as u8,
+            4 => paeth(left, up, up_left),
+            _ => return Err(PngError::Format(format!("Unknown filter {}", filter))),
+        };
+        out[i] = out[i].wrapping_add(prediction);
+    }
+    Ok(out)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
@@ -0,0 +1,254 @@
+//! A small zlib decompressor, enough to read back the PNG files written by image editors and
+//! other encoders.
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// The order in which the code lengths of the code length alphabet are stored.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+struct Bits<'a> {
+    data: &'a [u8],
+    /// Position in bits.
+    pos: usize,
+}
+
+impl<'a> Bits<'a> {
+    fn bit(&mut self) -> Result<u32, String> {
+        let byte = self
+            .data
+            .get(self.pos / 8)
+            .ok_or_else(|| String::from("Unexpected end of the deflate stream"))?;
+        let bit = (byte >> (self.pos % 8)) & 1;
+        self.pos += 1;
+        Ok(bit as u32)
+    }
+    fn bits(&mut self, count: u8) -> Result<u32, String> {
+        let mut value = 0;
+        for i in 0..count {
+            value |= self.bit()? << i;
+        }
+        Ok(value)
+    }
+    fn align(&mut self) {
+        self.pos = self.pos.div_ceil(8) * 8;
+    }
+}
+
+/// A canonical Huffman code, stored as the number of codes of each length and the symbols
+/// ordered by code.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0; 16];
+        for i in 1..16 {
+            offsets[i] = offsets[i - 1] + counts[i - 1];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Self { counts, symbols }
+    }
+    fn decode(&self, bits: &mut Bits) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..16 {
+            code |= bits.bit()? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("Invalid Huffman code".into())
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0; 288];
+    for (i, length) in lengths.iter_mut().enumerate() {
+        *length = match i {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman), String> {
+    let literals = bits.bits(5)? as usize + 257;
+    let distances = bits.bits(5)? as usize + 1;
+    let code_lengths = bits.bits(4)? as usize + 4;
+    let mut lengths = [0; 19];
+    for &i in CODE_LENGTH_ORDER.iter().take(code_lengths) {
+        lengths[i] = bits.bits(3)? as u8;
+    }
+    let code = Huffman::new(&lengths);
+    let mut lengths = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let symbol = code.decode(bits)?;
+        let (length, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or_else(|| String::from("Repeated a code length before the first one"))?;
+                (previous, 3 + bits.bits(2)?)
+            }
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        for _ in 0..repeat {
+            lengths.push(length);
+        }
+    }
+    if lengths.len() > literals + distances {
+        return Err("Code lengths overflow the alphabets".into());
+    }
+    Ok((
+        Huffman::new(&lengths[..literals]),
+        Huffman::new(&lengths[literals..]),
+    ))
+}
+
+fn inflate_block(
+    bits: &mut Bits,
+    out: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+        if symbol < 256 {
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+        let i = symbol - 257;
+        if i >= LENGTH_BASE.len() {
+            return Err(format!("Invalid length symbol {}", symbol));
+        }
+        let length = LENGTH_BASE[i] as usize + bits.bits(LENGTH_EXTRA[i])? as usize;
+        let i = distances.decode(bits)? as usize;
+        if i >= DIST_BASE.len() {
+            return Err(format!("Invalid distance symbol {}", i));
+        }
+        let distance = DIST_BASE[i] as usize + bits.bits(DIST_EXTRA[i])? as usize;
+        if distance > out.len() {
+            return Err("Distance reaches before the start of the output".into());
+        }
+        let start = out.len() - distance;
+        for j in 0..length {
+            out.push(out[start + j]);
+        }
+    }
+}
+
+/// Decompresses a zlib stream.
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < 2
+        || data[0] & 0x0F != 8
+        || (u16::from(data[0]) << 8 | u16::from(data[1])) % 31 != 0
+    {
+        return Err("Not a zlib stream".into());
+    }
+    if data[1] & 0x20 != 0 {
+        return Err("Preset dictionaries aren't supported".into());
+    }
+    let mut bits = Bits {
+        data: &data[2..],
+        pos: 0,
+    };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.bit()? == 1;
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let start = bits.pos / 8;
+                let header = bits
+                    .data
+                    .get(start..start + 4)
+                    .ok_or_else(|| String::from("Unexpected end of the deflate stream"))?;
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let stored = bits
+                    .data
+                    .get(start + 4..start + 4 + len)
+                    .ok_or_else(|| String::from("Unexpected end of the deflate stream"))?;
+                out.extend_from_slice(stored);
+                bits.pos = (start + 4 + len) * 8;
+            }
+            1 => {
+                let (literals, distances) = fixed_codes();
+                inflate_block(&mut bits, &mut out, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut bits)?;
+                inflate_block(&mut bits, &mut out, &literals, &distances)?;
+            }
+            _ => return Err("Invalid deflate block type".into()),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+/// Wraps the data in a zlib stream made of uncompressed blocks.
+pub fn deflate_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut chunks = data.chunks(0xFFFF).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(chunk) = chunks.next() {
+        out.push(if chunks.peek().is_none() { 1 } else { 0 });
+        let len = chunk.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
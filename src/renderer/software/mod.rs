@@ -0,0 +1,829 @@
+//! A Backend that rasterizes on the CPU, so that scenes can be rendered to images outside a
+//! browser, e.g. for golden image tests. It only knows approximations of the Simple, Color,
+//! Wireframe and Pbr shaders; textures aren't sampled, draws into framebuffers are ignored and
+//! draws with other programs are skipped.
+
+mod image;
+mod inflate;
+mod shading;
+
+pub use self::image::{Image, PngError};
+
+use self::shading::{Fragment, ShaderModel, Uniforms, Varyings, VertexInput};
+use super::{
+    backend::{
+        Backend, BufferId, FramebufferId, ProgramId, RenderbufferId, TextureId, Uniform, VaoId,
+    },
+    gl,
+    recording::{BufferContents, DrawCall, ProgramState, RecordingBackend, VertexArrayState},
+};
+use crate::TextureType;
+use nalgebra::{Matrix4, Vector3, Vector4};
+use std::{cell::RefCell, collections::HashMap};
+
+/// The pipeline state that decides how fragments end up in the framebuffer.
+#[derive(Debug)]
+struct Target {
+    width: u32,
+    height: u32,
+    /// Rows start from the bottom, as in WebGL.
+    color: Vec<[f32; 4]>,
+    depth: Vec<f32>,
+    stencil: Vec<u8>,
+    viewport: [i32; 4],
+    clear_color: [f32; 4],
+    clear_depth: f32,
+    depth_func: u32,
+    front_face: u32,
+    cull_face: u32,
+    blend_func: (u32, u32),
+    stencil_op: (u32, u32, u32),
+    stencil_func: (u32, i32, u32),
+    stencil_mask: u32,
+}
+
+/// A vertex after the vertex shader: its clip space position and varyings.
+type Shaded = (Vector4<f32>, Varyings);
+
+/// A vertex after the vertex shader, in window coordinates.
+#[derive(Debug, Copy, Clone)]
+struct ScreenVertex {
+    x: f32,
+    y: f32,
+    z: f32,
+    inv_w: f32,
+    varyings: Varyings,
+}
+
+/// A Backend that draws into an image in memory. Calls are tracked with a RecordingBackend, and
+/// every draw call is rasterized with the state it was issued in.
+#[derive(Debug)]
+pub struct SoftwareBackend {
+    recorder: RecordingBackend,
+    target: RefCell<Target>,
+}
+
+fn compare(func: u32, value: f32, stored: f32) -> bool {
+    match func {
+        gl::NEVER => false,
+        gl::LESS => value < stored,
+        gl::EQUAL => (value - stored).abs() < f32::EPSILON,
+        gl::LEQUAL => value <= stored,
+        gl::GREATER => value > stored,
+        gl::NOTEQUAL => (value - stored).abs() >= f32::EPSILON,
+        gl::GEQUAL => value >= stored,
+        _ => true,
+    }
+}
+
+fn blend_factor(factor: u32, alpha: f32) -> f32 {
+    match factor {
+        gl::ZERO => 0.,
+        gl::SRC_ALPHA => alpha,
+        gl::ONE_MINUS_SRC_ALPHA => 1. - alpha,
+        _ => 1.,
+    }
+}
+
+fn edge(a: (f32, f32), b: (f32, f32), p: (f32, f32)) -> f32 {
+    (b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0)
+}
+
+impl Target {
+    fn new(width: u32, height: u32) -> Self {
+        let len = (width * height) as usize;
+        Self {
+            width,
+            height,
+            color: vec![[0.; 4]; len],
+            depth: vec![1.; len],
+            stencil: vec![0; len],
+            viewport: [0, 0, width as i32, height as i32],
+            clear_color: [0.; 4],
+            clear_depth: 1.,
+            depth_func: gl::LESS,
+            front_face: gl::CCW,
+            cull_face: gl::BACK,
+            blend_func: (gl::ONE, gl::ZERO),
+            stencil_op: (gl::KEEP, gl::KEEP, gl::KEEP),
+            stencil_func: (gl::ALWAYS, 0, 0xFF),
+            stencil_mask: 0xFF,
+        }
+    }
+    fn clear(&mut self, mask: u32) {
+        if mask & gl::COLOR_BUFFER_BIT != 0 {
+            let color = self.clear_color;
+            self.color.iter_mut().for_each(|c| *c = color);
+        }
+        if mask & gl::DEPTH_BUFFER_BIT != 0 {
+            let depth = self.clear_depth;
+            self.depth.iter_mut().for_each(|d| *d = depth);
+        }
+        if mask & gl::STENCIL_BUFFER_BIT != 0 {
+            self.stencil.iter_mut().for_each(|s| *s = 0);
+        }
+    }
+    fn stencil_update(&mut self, i: usize, op: u32) {
+        let stored = self.stencil[i];
+        let value = match op {
+            gl::ZERO => 0,
+            gl::REPLACE => self.stencil_func.1 as u8,
+            gl::INCR => stored.saturating_add(1),
+            gl::DECR => stored.saturating_sub(1),
+            gl::INVERT => !stored,
+            _ => stored,
+        };
+        let mask = self.stencil_mask as u8;
+        self.stencil[i] = (stored & !mask) | (value & mask);
+    }
+    /// Runs the per fragment operations and writes the color of a fragment at window
+    /// coordinates x and y.
+    fn write(&mut self, draw: &DrawCall, x: i32, y: i32, z: f32, color: Vector4<f32>) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return;
+        }
+        if !(0. ..=1.).contains(&z) {
+            return;
+        }
+        let i = (y as u32 * self.width + x as u32) as usize;
+        let stencil_test = draw.is_enabled(gl::STENCIL_TEST);
+        if stencil_test {
+            let (func, reference, mask) = self.stencil_func;
+            let reference = (reference as u32 & mask) as f32;
+            let stored = (u32::from(self.stencil[i]) & mask) as f32;
+            if !compare(func, reference, stored) {
+                self.stencil_update(i, self.stencil_op.0);
+                return;
+            }
+        }
+        let depth_test = draw.is_enabled(gl::DEPTH_TEST);
+        if depth_test && !compare(self.depth_func, z, self.depth[i]) {
+            if stencil_test {
+                self.stencil_update(i, self.stencil_op.1);
+            }
+            return;
+        }
+        if stencil_test {
+            self.stencil_update(i, self.stencil_op.2);
+        }
+        if depth_test {
+            self.depth[i] = z;
+        }
+        let src = color.map(|c| c.clamp(0., 1.));
+        let dst = self.color[i];
+        self.color[i] = if draw.is_enabled(gl::BLEND) {
+            let src_factor = blend_factor(self.blend_func.0, src.w);
+            let dst_factor = blend_factor(self.blend_func.1, src.w);
+            let mut out = [0.; 4];
+            for (c, out) in out.iter_mut().enumerate() {
+                *out = (src[c] * src_factor + dst[c] * dst_factor).min(1.);
+            }
+            out
+        } else if draw.is_enabled(gl::SAMPLE_ALPHA_TO_COVERAGE) {
+            // coverage is approximated by blending with the alpha, as if all samples resolved
+            let mut out = [0.; 4];
+            for (c, out) in out.iter_mut().enumerate() {
+                *out = src[c] * src.w + dst[c] * (1. - src.w);
+            }
+            out
+        } else {
+            [src.x, src.y, src.z, src.w]
+        };
+    }
+    fn to_screen(&self, clip: Vector4<f32>, varyings: Varyings) -> ScreenVertex {
+        let [x, y, width, height] = self.viewport;
+        let inv_w = 1. / clip.w;
+        ScreenVertex {
+            x: x as f32 + (clip.x * inv_w + 1.) * 0.5 * width as f32,
+            y: y as f32 + (clip.y * inv_w + 1.) * 0.5 * height as f32,
+            z: (clip.z * inv_w + 1.) * 0.5,
+            inv_w,
+            varyings,
+        }
+    }
+    fn triangle(
+        &mut self,
+        draw: &DrawCall,
+        model: ShaderModel,
+        uniforms: &Uniforms,
+        vertices: [Shaded; 3],
+    ) {
+        let polygon = clip_near(&vertices);
+        if polygon.len() < 3 {
+            return;
+        }
+        let screen: Vec<ScreenVertex> = polygon
+            .iter()
+            .map(|(clip, varyings)| self.to_screen(*clip, *varyings))
+            .collect();
+        for i in 1..screen.len() - 1 {
+            self.fill(draw, model, uniforms, [screen[0], screen[i], screen[i + 1]]);
+        }
+    }
+    fn fill(
+        &mut self,
+        draw: &DrawCall,
+        model: ShaderModel,
+        uniforms: &Uniforms,
+        v: [ScreenVertex; 3],
+    ) {
+        let p: Vec<(f32, f32)> = v.iter().map(|v| (v.x, v.y)).collect();
+        let area = edge(p[0], p[1], p[2]);
+        if area.abs() < f32::EPSILON {
+            return;
+        }
+        let front_facing = (area > 0.) == (self.front_face == gl::CCW);
+        if draw.is_enabled(gl::CULL_FACE) {
+            let culled = match self.cull_face {
+                gl::FRONT => front_facing,
+                gl::FRONT_AND_BACK => true,
+                _ => !front_facing,
+            };
+            if culled {
+                return;
+            }
+        }
+        // the barycentric varying is linear in screen space, ignoring perspective
+        let gradient = |a: [f32; 3]| {
+            let dx = ((a[1] - a[0]) * (p[2].1 - p[0].1) - (a[2] - a[0]) * (p[1].1 - p[0].1)) / area;
+            let dy = ((a[2] - a[0]) * (p[1].0 - p[0].0) - (a[1] - a[0]) * (p[2].0 - p[0].0)) / area;
+            (dx, dy)
+        };
+        let mut barycentric_dx = Vector3::zeros();
+        let mut barycentric_dy = Vector3::zeros();
+        for c in 0..3 {
+            let (dx, dy) = gradient([
+                v[0].varyings.barycentric[c],
+                v[1].varyings.barycentric[c],
+                v[2].varyings.barycentric[c],
+            ]);
+            barycentric_dx[c] = dx;
+            barycentric_dy[c] = dy;
+        }
+        let face_normal = (v[1].varyings.world - v[0].varyings.world)
+            .cross(&(v[2].varyings.world - v[0].varyings.world))
+            .try_normalize(0.)
+            .unwrap_or_else(Vector3::zeros);
+        let [vx, vy, vw, vh] = self.viewport;
+        let min_x = p
+            .iter()
+            .map(|p| p.0)
+            .fold(f32::MAX, f32::min)
+            .floor()
+            .max(vx as f32) as i32;
+        let max_x = p
+            .iter()
+            .map(|p| p.0)
+            .fold(f32::MIN, f32::max)
+            .ceil()
+            .min((vx + vw) as f32) as i32;
+        let min_y = p
+            .iter()
+            .map(|p| p.1)
+            .fold(f32::MAX, f32::min)
+            .floor()
+            .max(vy as f32) as i32;
+        let max_y = p
+            .iter()
+            .map(|p| p.1)
+            .fold(f32::MIN, f32::max)
+            .ceil()
+            .min((vy + vh) as f32) as i32;
+        let varyings = [&v[0].varyings, &v[1].varyings, &v[2].varyings];
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let center = (x as f32 + 0.5, y as f32 + 0.5);
+                let b = [
+                    edge(p[1], p[2], center) / area,
+                    edge(p[2], p[0], center) / area,
+                    edge(p[0], p[1], center) / area,
+                ];
+                if b.iter().any(|b| *b < 0.) {
+                    continue;
+                }
+                let z = b[0] * v[0].z + b[1] * v[1].z + b[2] * v[2].z;
+                let w = [b[0] * v[0].inv_w, b[1] * v[1].inv_w, b[2] * v[2].inv_w];
+                let sum = w[0] + w[1] + w[2];
+                let fragment = Fragment {
+                    varyings: Varyings::blend(&varyings, &[w[0] / sum, w[1] / sum, w[2] / sum]),
+                    face_normal,
+                    barycentric_dx,
+                    barycentric_dy,
+                    front_facing,
+                };
+                let color = model.fragment(uniforms, &fragment);
+                self.write(draw, x, y, z, color);
+            }
+        }
+    }
+    fn line(
+        &mut self,
+        draw: &DrawCall,
+        model: ShaderModel,
+        uniforms: &Uniforms,
+        vertices: [Shaded; 2],
+    ) {
+        let (a, b) = match clip_line(vertices) {
+            Some(line) => line,
+            None => return,
+        };
+        let (a, b) = (self.to_screen(a.0, a.1), self.to_screen(b.0, b.1));
+        let steps = (b.x - a.x).abs().max((b.y - a.y).abs()).ceil().max(1.) as i32;
+        for step in 0..=steps {
+            let t = step as f32 / steps as f32;
+            let fragment = Fragment {
+                varyings: Varyings::blend(&[&a.varyings, &b.varyings], &[1. - t, t]),
+                face_normal: Vector3::zeros(),
+                barycentric_dx: Vector3::zeros(),
+                barycentric_dy: Vector3::zeros(),
+                front_facing: true,
+            };
+            let color = model.fragment(uniforms, &fragment);
+            let x = a.x + (b.x - a.x) * t;
+            let y = a.y + (b.y - a.y) * t;
+            let z = a.z + (b.z - a.z) * t;
+            self.write(draw, x.floor() as i32, y.floor() as i32, z, color);
+        }
+    }
+    fn point(&mut self, draw: &DrawCall, model: ShaderModel, uniforms: &Uniforms, vertex: Shaded) {
+        if vertex.0.z < -vertex.0.w {
+            return;
+        }
+        let v = self.to_screen(vertex.0, vertex.1);
+        let fragment = Fragment {
+            varyings: v.varyings,
+            face_normal: Vector3::zeros(),
+            barycentric_dx: Vector3::zeros(),
+            barycentric_dy: Vector3::zeros(),
+            front_facing: true,
+        };
+        let color = model.fragment(uniforms, &fragment);
+        self.write(draw, v.x.floor() as i32, v.y.floor() as i32, v.z, color);
+    }
+}
+
+/// The distance of a clip space position from the near plane, negative behind it.
+fn near_distance(clip: &Vector4<f32>) -> f32 {
+    clip.z + clip.w
+}
+
+fn lerp_vertex(a: &Shaded, b: &Shaded) -> Shaded {
+    let (da, db) = (near_distance(&a.0), near_distance(&b.0));
+    let t = da / (da - db);
+    (
+        a.0 + (b.0 - a.0) * t,
+        Varyings::blend(&[&a.1, &b.1], &[1. - t, t]),
+    )
+}
+
+/// Cuts off the part of a triangle behind the near plane, leaving a convex polygon.
+fn clip_near(triangle: &[Shaded; 3]) -> Vec<Shaded> {
+    let mut polygon = Vec::with_capacity(4);
+    for i in 0..3 {
+        let (a, b) = (&triangle[i], &triangle[(i + 1) % 3]);
+        let (a_in, b_in) = (near_distance(&a.0) >= 0., near_distance(&b.0) >= 0.);
+        if a_in {
+            polygon.push(*a);
+        }
+        if a_in != b_in {
+            polygon.push(lerp_vertex(a, b));
+        }
+    }
+    polygon
+}
+
+fn clip_line(line: [Shaded; 2]) -> Option<(Shaded, Shaded)> {
+    let [a, b] = line;
+    match (near_distance(&a.0) >= 0., near_distance(&b.0) >= 0.) {
+        (true, true) => Some((a, b)),
+        (true, false) => Some((a, lerp_vertex(&a, &b))),
+        (false, true) => Some((lerp_vertex(&a, &b), b)),
+        (false, false) => None,
+    }
+}
+
+/// Reads vertex attributes out of the buffers bound to a vertex array.
+struct VertexFetch<'a> {
+    program: &'a ProgramState,
+    vao: &'a VertexArrayState,
+    buffers: HashMap<BufferId, Vec<f32>>,
+}
+
+impl<'a> VertexFetch<'a> {
+    fn new(
+        recorder: &RecordingBackend,
+        program: &'a ProgramState,
+        vao: &'a VertexArrayState,
+    ) -> Self {
+        let buffers = vao
+            .attributes
+            .values()
+            .filter_map(|pointer| match recorder.buffer(pointer.buffer) {
+                Some(BufferContents::F32(data)) => Some((pointer.buffer, data)),
+                _ => None,
+            })
+            .collect();
+        Self {
+            program,
+            vao,
+            buffers,
+        }
+    }
+    /// Reads the attribute at a location, or None if the location isn't an enabled attribute.
+    fn read(&self, location: u32, vertex: usize, instance: usize) -> Option<[f32; 4]> {
+        let pointer = self.vao.attributes.get(&location).filter(|p| p.enabled)?;
+        let data = self.buffers.get(&pointer.buffer)?;
+        let index = match pointer.divisor {
+            0 => vertex,
+            divisor => instance / divisor as usize,
+        };
+        let stride = match pointer.stride {
+            0 => pointer.size as usize * 4,
+            stride => stride as usize,
+        };
+        let start = (pointer.offset as usize + index * stride) / 4;
+        let mut value = [0., 0., 0., 1.];
+        for (c, value) in value.iter_mut().enumerate().take(pointer.size as usize) {
+            *value = *data.get(start + c)?;
+        }
+        Some(value)
+    }
+    fn location(&self, name: &str) -> Option<u32> {
+        self.program
+            .attributes
+            .get(name)
+            .filter(|location| **location >= 0)
+            .map(|location| *location as u32)
+    }
+    /// Reads an attribute, falling back to the value WebGL gives disabled attributes.
+    fn attribute(&self, name: &str, vertex: usize, instance: usize) -> Vector4<f32> {
+        let value = self
+            .location(name)
+            .and_then(|location| self.read(location, vertex, instance))
+            .unwrap_or([0., 0., 0., 1.]);
+        Vector4::from_column_slice(&value)
+    }
+    fn matrix(&self, name: &str, vertex: usize, instance: usize) -> Option<Matrix4<f32>> {
+        let location = self.location(name)?;
+        let mut columns = [0.; 16];
+        for column in 0..4 {
+            let value = self.read(location + column as u32, vertex, instance)?;
+            columns[column * 4..column * 4 + 4].copy_from_slice(&value);
+        }
+        Some(Matrix4::from_column_slice(&columns))
+    }
+    fn vertex(&self, vertex: usize, instance: usize) -> VertexInput {
+        VertexInput {
+            position: self.attribute("position", vertex, instance).xyz(),
+            normal: self.attribute("normal", vertex, instance).xyz(),
+            barycentric: self.attribute("barycentric", vertex, instance).xyz(),
+            instance_model: self.matrix("instance_model", vertex, instance),
+            instance_color: self.attribute("instance_color", vertex, instance),
+        }
+    }
+}
+
+impl SoftwareBackend {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            recorder: RecordingBackend::new(),
+            target: RefCell::new(Target::new(width, height)),
+        }
+    }
+    /// The framebuffer as an image, with the top row first.
+    pub fn image(&self) -> Image {
+        let target = self.target.borrow();
+        let mut image = Image::new(target.width, target.height);
+        for y in 0..target.height {
+            for x in 0..target.width {
+                let color = target.color[(y * target.width + x) as usize];
+                let mut pixel = [0; 4];
+                for (c, pixel) in pixel.iter_mut().enumerate() {
+                    *pixel = (color[c] * 255.).round() as u8;
+                }
+                image.set_pixel(x, target.height - 1 - y, pixel);
+            }
+        }
+        image
+    }
+    /// Rasterizes the draw call that was just recorded.
+    fn rasterize(&self) {
+        let draw = match self.recorder.draw_calls().pop() {
+            Some(draw) => draw,
+            None => return,
+        };
+        // the state is kept, so the calls themselves are no longer needed
+        self.recorder.clear();
+        // only the canvas is rasterized, offscreen targets are left empty
+        if draw.framebuffer.is_some() {
+            return;
+        }
+        let program = match draw.program.and_then(|p| self.recorder.program(p)) {
+            Some(program) => program,
+            None => return,
+        };
+        let model = match ShaderModel::of(&program) {
+            Some(model) => model,
+            None => return,
+        };
+        let vao = draw
+            .vao
+            .and_then(|vao| self.recorder.vertex_array(vao))
+            .unwrap_or_default();
+        let fetch = VertexFetch::new(&self.recorder, &program, &vao);
+        let (first, count) = (draw.first.max(0) as usize, draw.count.max(0) as usize);
+        let indices: Vec<usize> = match draw.index_type {
+            None => (first..first + count).collect(),
+            Some(index_type) => {
+                let elements = vao.elements.and_then(|buffer| self.recorder.buffer(buffer));
+                match (index_type, elements) {
+                    (gl::UNSIGNED_SHORT, Some(BufferContents::U16(data))) => data
+                        .iter()
+                        .skip(first / 2)
+                        .take(count)
+                        .map(|i| *i as usize)
+                        .collect(),
+                    (gl::UNSIGNED_INT, Some(BufferContents::U32(data))) => data
+                        .iter()
+                        .skip(first / 4)
+                        .take(count)
+                        .map(|i| *i as usize)
+                        .collect(),
+                    _ => return,
+                }
+            }
+        };
+        let uniforms = Uniforms::new(&draw);
+        let mut target = self.target.borrow_mut();
+        for instance in 0..draw.instances.unwrap_or(1).max(0) as usize {
+            let shaded: Vec<Shaded> = indices
+                .iter()
+                .map(|&i| model.vertex(&uniforms, &fetch.vertex(i, instance)))
+                .collect();
+            match draw.mode {
+                gl::TRIANGLES => {
+                    for v in shaded.chunks_exact(3) {
+                        target.triangle(&draw, model, &uniforms, [v[0], v[1], v[2]]);
+                    }
+                }
+                gl::LINES => {
+                    for v in shaded.chunks_exact(2) {
+                        target.line(&draw, model, &uniforms, [v[0], v[1]]);
+                    }
+                }
+                gl::POINTS => {
+                    for v in shaded {
+                        target.point(&draw, model, &uniforms, v);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+impl Backend for SoftwareBackend {
+    fn create_program(&self, vertex: &str, fragment: &str) -> Result<ProgramId, String> {
+        self.recorder.create_program(vertex, fragment)
+    }
+    fn use_program(&self, program: Option<ProgramId>) {
+        self.recorder.use_program(program)
+    }
+    fn attrib_location(&self, program: ProgramId, name: &str) -> i32 {
+        self.recorder.attrib_location(program, name)
+    }
+    fn set_uniform(&self, program: ProgramId, name: &str, value: Uniform) -> bool {
+        self.recorder.set_uniform(program, name, value)
+    }
+
+    fn create_buffer(&self) -> BufferId {
+        self.recorder.create_buffer()
+    }
+    fn bind_buffer(&self, target: u32, buffer: Option<BufferId>) {
+        self.recorder.bind_buffer(target, buffer)
+    }
+    fn buffer_data_f32(&self, target: u32, data: &[f32], usage: u32) {
+        self.recorder.buffer_data_f32(target, data, usage)
+    }
+    fn buffer_data_u16(&self, target: u32, data: &[u16], usage: u32) {
+        self.recorder.buffer_data_u16(target, data, usage)
+    }
+    fn buffer_data_u32(&self, target: u32, data: &[u32], usage: u32) {
+        self.recorder.buffer_data_u32(target, data, usage)
+    }
+    fn delete_buffer(&self, buffer: BufferId) {
+        self.recorder.delete_buffer(buffer)
+    }
+
+    fn create_vertex_array(&self) -> VaoId {
+        self.recorder.create_vertex_array()
+    }
+    fn bind_vertex_array(&self, vao: Option<VaoId>) {
+        self.recorder.bind_vertex_array(vao)
+    }
+    fn delete_vertex_array(&self, vao: VaoId) {
+        self.recorder.delete_vertex_array(vao)
+    }
+    fn vertex_attrib_pointer(
+        &self,
+        location: u32,
+        size: i32,
+        kind: u32,
+        normalized: bool,
+        stride: i32,
+        offset: i32,
+    ) {
+        self.recorder
+            .vertex_attrib_pointer(location, size, kind, normalized, stride, offset)
+    }
+    fn enable_vertex_attrib_array(&self, location: u32) {
+        self.recorder.enable_vertex_attrib_array(location)
+    }
+    fn vertex_attrib_divisor(&self, location: u32, divisor: u32) {
+        self.recorder.vertex_attrib_divisor(location, divisor)
+    }
+
+    fn create_texture(
+        &self,
+        urls: &[String],
+        tex_type: TextureType,
+        is_img_obj: bool,
+    ) -> TextureId {
+        self.recorder.create_texture(urls, tex_type, is_img_obj)
+    }
+    fn texture_ready(&self, texture: TextureId) -> bool {
+        self.recorder.texture_ready(texture)
+    }
+    fn create_texture_storage(
+        &self,
+        target: u32,
+        levels: i32,
+        internal_format: u32,
+        width: i32,
+        height: i32,
+    ) -> TextureId {
+        self.recorder
+            .create_texture_storage(target, levels, internal_format, width, height)
+    }
+    fn tex_sub_image_f32(
+        &self,
+        target: u32,
+        level: i32,
+        width: i32,
+        height: i32,
+        format: u32,
+        data: &[f32],
+    ) {
+        self.recorder
+            .tex_sub_image_f32(target, level, width, height, format, data)
+    }
+    fn tex_parameter(&self, target: u32, name: u32, value: u32) {
+        self.recorder.tex_parameter(target, name, value)
+    }
+    fn generate_mipmap(&self, target: u32) {
+        self.recorder.generate_mipmap(target)
+    }
+    fn active_texture(&self, unit: u32) {
+        self.recorder.active_texture(unit)
+    }
+    fn bind_texture(&self, target: u32, texture: Option<TextureId>) {
+        self.recorder.bind_texture(target, texture)
+    }
+    fn delete_texture(&self, texture: TextureId) {
+        self.recorder.delete_texture(texture)
+    }
+
+    fn create_framebuffer(&self) -> FramebufferId {
+        self.recorder.create_framebuffer()
+    }
+    fn bind_framebuffer(&self, target: u32, framebuffer: Option<FramebufferId>) {
+        self.recorder.bind_framebuffer(target, framebuffer)
+    }
+    fn framebuffer_texture_2d(
+        &self,
+        target: u32,
+        attachment: u32,
+        tex_target: u32,
+        texture: Option<TextureId>,
+        level: i32,
+    ) {
+        self.recorder
+            .framebuffer_texture_2d(target, attachment, tex_target, texture, level)
+    }
+    fn draw_buffers(&self, buffers: &[u32]) {
+        self.recorder.draw_buffers(buffers)
+    }
+    fn delete_framebuffer(&self, framebuffer: FramebufferId) {
+        self.recorder.delete_framebuffer(framebuffer)
+    }
+    fn create_renderbuffer(
+        &self,
+        samples: i32,
+        internal_format: u32,
+        width: i32,
+        height: i32,
+    ) -> RenderbufferId {
+        self.recorder
+            .create_renderbuffer(samples, internal_format, width, height)
+    }
+    fn framebuffer_renderbuffer(
+        &self,
+        target: u32,
+        attachment: u32,
+        renderbuffer: Option<RenderbufferId>,
+    ) {
+        self.recorder
+            .framebuffer_renderbuffer(target, attachment, renderbuffer)
+    }
+    fn delete_renderbuffer(&self, renderbuffer: RenderbufferId) {
+        self.recorder.delete_renderbuffer(renderbuffer)
+    }
+    fn blit_framebuffer(&self, src: [i32; 4], dst: [i32; 4], mask: u32, filter: u32) {
+        self.recorder.blit_framebuffer(src, dst, mask, filter)
+    }
+
+    fn enable(&self, capability: u32) {
+        self.recorder.enable(capability)
+    }
+    fn disable(&self, capability: u32) {
+        self.recorder.disable(capability)
+    }
+    fn viewport(&self, x: i32, y: i32, width: i32, height: i32) {
+        self.recorder.viewport(x, y, width, height);
+        self.target.borrow_mut().viewport = [x, y, width, height];
+    }
+    fn clear_color(&self, r: f32, g: f32, b: f32, a: f32) {
+        self.recorder.clear_color(r, g, b, a);
+        self.target.borrow_mut().clear_color = [r, g, b, a];
+    }
+    fn clear_depth(&self, depth: f32) {
+        self.recorder.clear_depth(depth);
+        self.target.borrow_mut().clear_depth = depth;
+    }
+    fn clear(&self, mask: u32) {
+        Backend::clear(&self.recorder, mask);
+        if self.recorder.bound_framebuffer().is_none() {
+            self.target.borrow_mut().clear(mask);
+        }
+    }
+    fn clear_buffer_u32(&self, buffer: u32, draw_buffer: i32, value: [u32; 4]) {
+        self.recorder.clear_buffer_u32(buffer, draw_buffer, value)
+    }
+    fn depth_func(&self, func: u32) {
+        self.recorder.depth_func(func);
+        self.target.borrow_mut().depth_func = func;
+    }
+    fn front_face(&self, mode: u32) {
+        self.recorder.front_face(mode);
+        self.target.borrow_mut().front_face = mode;
+    }
+    fn cull_face(&self, mode: u32) {
+        self.recorder.cull_face(mode);
+        self.target.borrow_mut().cull_face = mode;
+    }
+    fn blend_func(&self, src: u32, dst: u32) {
+        self.recorder.blend_func(src, dst);
+        self.target.borrow_mut().blend_func = (src, dst);
+    }
+    fn stencil_op(&self, fail: u32, zfail: u32, zpass: u32) {
+        self.recorder.stencil_op(fail, zfail, zpass);
+        self.target.borrow_mut().stencil_op = (fail, zfail, zpass);
+    }
+    fn stencil_func(&self, func: u32, reference: i32, mask: u32) {
+        self.recorder.stencil_func(func, reference, mask);
+        self.target.borrow_mut().stencil_func = (func, reference, mask);
+    }
+    fn stencil_mask(&self, mask: u32) {
+        self.recorder.stencil_mask(mask);
+        self.target.borrow_mut().stencil_mask = mask;
+    }
+    fn read_pixels_u32(&self, x: i32, y: i32, width: i32, height: i32) -> Vec<u32> {
+        self.recorder.read_pixels_u32(x, y, width, height)
+    }
+
+    fn draw_arrays(&self, mode: u32, first: i32, count: i32) {
+        self.recorder.draw_arrays(mode, first, count);
+        self.rasterize();
+    }
+    fn draw_elements(&self, mode: u32, count: i32, index_type: u32, offset: i32) {
+        self.recorder.draw_elements(mode, count, index_type, offset);
+        self.rasterize();
+    }
+    fn draw_arrays_instanced(&self, mode: u32, first: i32, count: i32, instances: i32) {
+        self.recorder
+            .draw_arrays_instanced(mode, first, count, instances);
+        self.rasterize();
+    }
+    fn draw_elements_instanced(
+        &self,
+        mode: u32,
+        count: i32,
+        index_type: u32,
+        offset: i32,
+        instances: i32,
+    ) {
+        self.recorder
+            .draw_elements_instanced(mode, count, index_type, offset, instances);
+        self.rasterize();
+    }
+}
@@ -0,0 +1,460 @@
+//! Approximations of the renderer's GLSL shaders, evaluated on the CPU.
+
+use crate::renderer::{DrawCall, ProgramState, Uniform};
+use nalgebra::{Matrix3, Matrix4, Vector3, Vector4, U3};
+use std::f32::consts::PI;
+
+/// The shader programs of the renderer that the rasterizer knows how to draw. Draws with any
+/// other program are skipped.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ShaderModel {
+    Simple,
+    Color,
+    Wireframe,
+    Pbr,
+}
+
+impl ShaderModel {
+    /// Recognizes a program by its sources.
+    pub fn of(program: &ProgramState) -> Option<Self> {
+        let sources = (program.vertex.as_str(), program.fragment.as_str());
+        if sources
+            == (
+                include_str!("../shaders/simple.vert"),
+                include_str!("../shaders/simple.frag"),
+            )
+        {
+            Some(ShaderModel::Simple)
+        } else if sources
+            == (
+                include_str!("../shaders/color.vert"),
+                include_str!("../shaders/color.frag"),
+            )
+        {
+            Some(ShaderModel::Color)
+        } else if sources
+            == (
+                include_str!("../shaders/wire.vert"),
+                include_str!("../shaders/wire.frag"),
+            )
+        {
+            Some(ShaderModel::Wireframe)
+        } else if sources
+            == (
+                include_str!("../shaders/pbr.vert"),
+                include_str!("../shaders/pbr.frag"),
+            )
+        {
+            Some(ShaderModel::Pbr)
+        } else {
+            None
+        }
+    }
+}
+
+/// The attributes of a vertex. Instance attributes are None for draws that aren't instanced.
+#[derive(Debug, Copy, Clone)]
+pub struct VertexInput {
+    pub position: Vector3<f32>,
+    pub normal: Vector3<f32>,
+    pub barycentric: Vector3<f32>,
+    pub instance_model: Option<Matrix4<f32>>,
+    pub instance_color: Vector4<f32>,
+}
+
+/// What the vertex stage hands to the fragment stage, interpolated across the primitive.
+#[derive(Debug, Copy, Clone)]
+pub struct Varyings {
+    pub world: Vector3<f32>,
+    pub normal: Vector3<f32>,
+    pub barycentric: Vector3<f32>,
+    pub color: Vector4<f32>,
+}
+
+impl Varyings {
+    /// Blends the varyings of a primitive's vertices with the given weights.
+    pub fn blend(vertices: &[&Varyings], weights: &[f32]) -> Self {
+        let mut out = Self {
+            world: Vector3::zeros(),
+            normal: Vector3::zeros(),
+            barycentric: Vector3::zeros(),
+            color: Vector4::zeros(),
+        };
+        for (v, &w) in vertices.iter().zip(weights) {
+            out.world += v.world * w;
+            out.normal += v.normal * w;
+            out.barycentric += v.barycentric * w;
+            out.color += v.color * w;
+        }
+        out
+    }
+}
+
+/// A fragment along with the screen space information that GLSL gets from derivatives.
+#[derive(Debug, Copy, Clone)]
+pub struct Fragment {
+    pub varyings: Varyings,
+    /// The normal of the triangle in world space.
+    pub face_normal: Vector3<f32>,
+    /// The change of the barycentric varying per pixel along x and y.
+    pub barycentric_dx: Vector3<f32>,
+    pub barycentric_dy: Vector3<f32>,
+    pub front_facing: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum LightKind {
+    Ambient,
+    Directional,
+    Point,
+    Spot,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Light {
+    kind: LightKind,
+    position: Vector3<f32>,
+    color: Vector3<f32>,
+    direction: Vector3<f32>,
+    cutoff: f32,
+    outer_cutoff: f32,
+    linear: f32,
+    quadratic: f32,
+    intensity: f32,
+}
+
+/// The uniforms of a draw call, read once before shading its vertices and fragments.
+#[derive(Debug, Clone)]
+pub struct Uniforms {
+    model: Matrix4<f32>,
+    view_proj: Matrix4<f32>,
+    eye: Vector3<f32>,
+    color: Vector4<f32>,
+    instanced: bool,
+    flat_shade: bool,
+    blinn_shade: bool,
+    wire_overlay: bool,
+    wire_color: Vector4<f32>,
+    width: f32,
+    feather: f32,
+    drawing_points: bool,
+    metallic: f32,
+    roughness: f32,
+    emissive: Vector3<f32>,
+    lights: Vec<Light>,
+}
+
+/// Reads uniforms the way a shader sees them: anything that was never written is zero.
+struct Reader<'a>(&'a DrawCall);
+
+impl<'a> Reader<'a> {
+    fn mat4(&self, name: &str) -> Matrix4<f32> {
+        match self.0.uniform(name) {
+            Some(Uniform::Mat4(m)) => Matrix4::from_column_slice(&m),
+            _ => Matrix4::zeros(),
+        }
+    }
+    fn vec4(&self, name: &str) -> Vector4<f32> {
+        match self.0.uniform(name) {
+            Some(Uniform::Vec4(v)) => Vector4::from_column_slice(&v),
+            _ => Vector4::zeros(),
+        }
+    }
+    fn vec3(&self, name: &str) -> Vector3<f32> {
+        match self.0.uniform(name) {
+            Some(Uniform::Vec3(v)) => Vector3::from_column_slice(&v),
+            _ => Vector3::zeros(),
+        }
+    }
+    fn f32(&self, name: &str) -> f32 {
+        match self.0.uniform(name) {
+            Some(Uniform::F32(v)) => v,
+            _ => 0.,
+        }
+    }
+    fn i32(&self, name: &str) -> i32 {
+        match self.0.uniform(name) {
+            Some(Uniform::I32(v)) => v,
+            _ => 0,
+        }
+    }
+    fn bool(&self, name: &str) -> bool {
+        match self.0.uniform(name) {
+            Some(Uniform::Bool(v)) => v,
+            Some(Uniform::U32(v)) => v != 0,
+            Some(Uniform::I32(v)) => v != 0,
+            _ => false,
+        }
+    }
+    fn lights(&self, array: &str, count: &str, kind: LightKind) -> Vec<Light> {
+        (0..self.i32(count))
+            .map(|i| {
+                let field = |name: &str| format!("{}[{}].{}", array, i, name);
+                Light {
+                    kind,
+                    position: self.vec3(&field("position")),
+                    color: self.vec3(&field("color")),
+                    direction: self.vec3(&field("direction")),
+                    cutoff: self.f32(&field("cutoff")),
+                    outer_cutoff: self.f32(&field("outer_cutoff")),
+                    linear: self.f32(&field("linear")),
+                    quadratic: self.f32(&field("quadratic")),
+                    intensity: self.f32(&field("intensity")),
+                }
+            })
+            .collect()
+    }
+}
+
+impl Uniforms {
+    pub fn new(draw: &DrawCall) -> Self {
+        let r = Reader(draw);
+        let mut lights = r.lights("amb_lights", "num_l_amb", LightKind::Ambient);
+        lights.extend(r.lights("dir_lights", "num_l_dir", LightKind::Directional));
+        lights.extend(r.lights("point_lights", "num_l_point", LightKind::Point));
+        lights.extend(r.lights("spot_lights", "num_l_spot", LightKind::Spot));
+        Self {
+            model: r.mat4("model"),
+            view_proj: r.mat4("proj") * r.mat4("view"),
+            eye: r.vec3("eye"),
+            color: r.vec4("color"),
+            instanced: r.bool("instanced"),
+            flat_shade: r.bool("flat_shade"),
+            blinn_shade: r.bool("blinn_shade"),
+            wire_overlay: r.bool("wire_overlay"),
+            wire_color: r.vec4("wire_color"),
+            width: r.f32("width"),
+            feather: r.f32("feather"),
+            drawing_points: r.bool("drawing_points"),
+            metallic: r.f32("metallic"),
+            roughness: r.f32("roughness"),
+            emissive: r.vec3("emissive"),
+            lights,
+        }
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    if edge1 <= edge0 {
+        return if x < edge0 { 0. } else { 1. };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
+
+fn fwidth(dx: Vector3<f32>, dy: Vector3<f32>) -> Vector3<f32> {
+    dx.abs() + dy.abs()
+}
+
+/// Encodes a linear channel in sRGB, like `to_srgb` in pbr.frag.
+fn to_srgb(c: f32) -> f32 {
+    let c = c.max(0.);
+    if c < 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    }
+}
+
+impl ShaderModel {
+    /// Runs the vertex shader, returning the clip space position and the varyings.
+    pub fn vertex(self, u: &Uniforms, input: &VertexInput) -> (Vector4<f32>, Varyings) {
+        let world = match (self, u.instanced, input.instance_model) {
+            (ShaderModel::Wireframe, _, _) | (_, false, _) | (_, true, None) => u.model,
+            (_, true, Some(instance_model)) => u.model * instance_model,
+        };
+        let position = world * input.position.push(1.);
+        let normal_matrix = world
+            .fixed_slice::<U3, U3>(0, 0)
+            .into_owned()
+            .try_inverse()
+            .unwrap_or_else(Matrix3::identity)
+            .transpose();
+        let color = if u.instanced && self != ShaderModel::Wireframe {
+            input.instance_color
+        } else {
+            u.color
+        };
+        let varyings = Varyings {
+            world: position.xyz(),
+            normal: normal_matrix * input.normal,
+            barycentric: input.barycentric,
+            color,
+        };
+        (u.view_proj * position, varyings)
+    }
+    /// Runs the fragment shader, returning a color that isn't clamped yet.
+    pub fn fragment(self, u: &Uniforms, f: &Fragment) -> Vector4<f32> {
+        match self {
+            ShaderModel::Simple => f.varyings.color,
+            ShaderModel::Color => {
+                let v = &f.varyings;
+                let normal = if u.flat_shade {
+                    f.face_normal
+                } else {
+                    v.normal.try_normalize(0.).unwrap_or_else(Vector3::zeros)
+                };
+                let view_dir = (u.eye - v.world)
+                    .try_normalize(0.)
+                    .unwrap_or_else(Vector3::zeros);
+                let frag_color = v.color.xyz();
+                let mut result = Vector3::zeros();
+                for light in &u.lights {
+                    result += shade(u, light, normal, view_dir, v.world, frag_color);
+                }
+                if u.wire_overlay {
+                    let d = fwidth(f.barycentric_dx, f.barycentric_dy) * 1.5;
+                    let bc = v.barycentric;
+                    let edge = smoothstep(0., d.x, bc.x)
+                        .min(smoothstep(0., d.y, bc.y))
+                        .min(smoothstep(0., d.z, bc.z));
+                    result = u.wire_color.xyz().lerp(&result, edge);
+                }
+                result.push(1.)
+            }
+            ShaderModel::Wireframe => {
+                if u.drawing_points {
+                    return Vector4::new(0., 0., 0., 1.);
+                }
+                let w1 = u.width - u.feather * 0.5;
+                let bc = f.varyings.barycentric;
+                let bary = Vector3::new(bc.x, bc.y, 1. - bc.x - bc.y);
+                let to_bary = |d: Vector3<f32>| Vector3::new(d.x, d.y, -d.x - d.y);
+                let d = fwidth(to_bary(f.barycentric_dx), to_bary(f.barycentric_dy));
+                let a = |i: usize| smoothstep(d[i] * w1, d[i] * (w1 + u.feather), bary[i]);
+                let edge = a(0).min(a(1)).min(a(2));
+                let alpha = if f.front_facing { 0.95 } else { 0.5 };
+                u.color.xyz().push((u.color.w - edge) * alpha)
+            }
+            ShaderModel::Pbr => {
+                let v = &f.varyings;
+                let normal = if u.flat_shade {
+                    f.face_normal
+                } else {
+                    let normal = v.normal.try_normalize(0.).unwrap_or_else(Vector3::zeros);
+                    if f.front_facing {
+                        normal
+                    } else {
+                        -normal
+                    }
+                };
+                let view_dir = (u.eye - v.world)
+                    .try_normalize(0.)
+                    .unwrap_or_else(Vector3::zeros);
+                let albedo = v.color.xyz();
+                let metal = u.metallic.clamp(0., 1.);
+                let alpha = u.roughness.clamp(0.03, 1.).powi(2);
+                let mut result = u.emissive;
+                for light in &u.lights {
+                    result += shade_pbr(light, normal, view_dir, v.world, albedo, metal, alpha);
+                }
+                result.map(to_srgb).push(v.color.w)
+            }
+        }
+    }
+}
+
+/// The contribution of a light, following calc_light and the ambient term in pbr.frag. Texture
+/// maps aren't sampled.
+fn shade_pbr(
+    light: &Light,
+    normal: Vector3<f32>,
+    view_dir: Vector3<f32>,
+    position: Vector3<f32>,
+    albedo: Vector3<f32>,
+    metal: f32,
+    alpha: f32,
+) -> Vector3<f32> {
+    if light.kind == LightKind::Ambient {
+        return light.color.component_mul(&albedo) * light.intensity;
+    }
+    let light_dir = if light.kind == LightKind::Directional {
+        light.direction
+    } else {
+        light.position - position
+    }
+    .try_normalize(0.)
+    .unwrap_or_else(Vector3::zeros);
+    let n_dot_l = normal.dot(&light_dir);
+    if n_dot_l <= 0. {
+        return Vector3::zeros();
+    }
+    let halfway = (light_dir + view_dir)
+        .try_normalize(0.)
+        .unwrap_or_else(Vector3::zeros);
+    let n_dot_v = normal.dot(&view_dir).max(1e-4);
+    let n_dot_h = normal.dot(&halfway).max(0.);
+    let v_dot_h = view_dir.dot(&halfway).max(0.);
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.) + 1.;
+    let distribution = a2 / (PI * d * d);
+    let ggx = n_dot_l * (n_dot_v * n_dot_v * (1. - a2) + a2).sqrt()
+        + n_dot_v * (n_dot_l * n_dot_l * (1. - a2) + a2).sqrt();
+    let visibility = if ggx > 0. { 0.5 / ggx } else { 0. };
+    let f0 = Vector3::repeat(0.04).lerp(&albedo, metal);
+    let f = f0 + (Vector3::repeat(1.) - f0) * (1. - v_dot_h).powi(5);
+    let diffuse = (Vector3::repeat(1.) - f).component_mul(&albedo) * (1. - metal) / PI;
+    let specular = f * distribution * visibility;
+    let mut radiance = light.color * light.intensity * PI;
+    if light.kind != LightKind::Directional {
+        let distance = (light.position - position).norm();
+        radiance /= 1. + light.linear * distance + light.quadratic * distance * distance;
+    }
+    if light.kind == LightKind::Spot {
+        let theta = light_dir.dot(
+            &-light
+                .direction
+                .try_normalize(0.)
+                .unwrap_or_else(Vector3::zeros),
+        );
+        let epsilon = light.cutoff - light.outer_cutoff;
+        radiance *= ((theta - light.outer_cutoff) / epsilon).clamp(0., 1.);
+    }
+    (diffuse + specular).component_mul(&radiance) * n_dot_l
+}
+
+/// The contribution of a light, following calc_light and calc_amb_light in color.frag.
+fn shade(
+    u: &Uniforms,
+    light: &Light,
+    normal: Vector3<f32>,
+    view_dir: Vector3<f32>,
+    position: Vector3<f32>,
+    color: Vector3<f32>,
+) -> Vector3<f32> {
+    if light.kind == LightKind::Ambient {
+        return light.color.component_mul(&color) * light.intensity;
+    }
+    let light_dir = if light.kind == LightKind::Directional {
+        light.direction
+    } else {
+        light.position - position
+    }
+    .try_normalize(0.)
+    .unwrap_or_else(Vector3::zeros);
+    let diffuse = normal.dot(&light_dir).max(0.) * light.color;
+    let spec = if u.blinn_shade {
+        let halfway = (light_dir + view_dir)
+            .try_normalize(0.)
+            .unwrap_or_else(Vector3::zeros);
+        normal.dot(&halfway).max(0.).powf(64.)
+    } else {
+        let reflection = -light_dir - 2. * normal.dot(&-light_dir) * normal;
+        view_dir.dot(&reflection).max(0.).powf(64.)
+    };
+    let specular = spec * light.color;
+    let distance = (light.position - position).norm();
+    let attenuation = 1. / (1. + light.linear * distance + light.quadratic * distance * distance);
+    let mut result = (diffuse + specular).component_mul(&color) * attenuation * light.intensity;
+    if light.kind == LightKind::Spot {
+        let theta = light_dir.dot(
+            &-light
+                .direction
+                .try_normalize(0.)
+                .unwrap_or_else(Vector3::zeros),
+        );
+        let epsilon = light.cutoff - light.outer_cutoff;
+        result *= ((theta - light.outer_cutoff) / epsilon).clamp(0., 1.);
+    }
+    result
+}
@@ -0,0 +1,90 @@
+//! High dynamic range rendering: the scene is drawn in linear space into a floating point
+//! target, where lights can be brighter than the canvas shows, and a tone mapping pass brings it
+//! back into the range of the canvas before encoding it in sRGB.
+
+use super::{set_bool, set_f32, set_i32, ProgramId, Renderer, CLEAR_COLOR};
+
+/// The curve that maps the linear colors of the HDR target to the range of the canvas.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ToneMapping {
+    /// Colors are left as they are, and clipped above 1.
+    Linear,
+    /// `c / (1 + c)`, which never clips but washes out bright colors.
+    Reinhard,
+    /// The ACES filmic curve, as fitted by Krzysztof Narkowicz.
+    #[default]
+    Aces,
+    /// John Hable's filmic curve from Uncharted 2.
+    Filmic,
+}
+
+impl ToneMapping {
+    /// The value of the `tone_mapping` uniform of tonemap.frag.
+    fn id(self) -> i32 {
+        match self {
+            ToneMapping::Linear => 0,
+            ToneMapping::Reinhard => 1,
+            ToneMapping::Aces => 2,
+            ToneMapping::Filmic => 3,
+        }
+    }
+}
+
+/// Decodes an sRGB channel to linear.
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+impl Renderer {
+    pub fn hdr(&self) -> bool {
+        self.config.hdr
+    }
+    /// Switches between drawing into the HDR target and drawing straight to the canvas.
+    pub fn set_hdr(&mut self, hdr: bool) {
+        self.config.hdr = hdr;
+        if !hdr {
+            self.set_linear_output(false);
+            self.release_scene_target();
+        }
+    }
+    pub fn tone_mapping(&self) -> ToneMapping {
+        self.config.tone_mapping
+    }
+    pub fn set_tone_mapping(&mut self, tone_mapping: ToneMapping) {
+        self.config.tone_mapping = tone_mapping;
+    }
+    pub fn exposure(&self) -> f32 {
+        self.config.exposure
+    }
+    /// Sets the factor the HDR target is scaled by before tone mapping.
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.config.exposure = exposure.max(0.);
+    }
+    /// The color the scene target is cleared to. It holds linear colors with HDR on.
+    pub(super) fn scene_clear_color(&self) -> [f32; 4] {
+        let [r, g, b, a] = CLEAR_COLOR;
+        if self.config.hdr {
+            [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a]
+        } else {
+            CLEAR_COLOR
+        }
+    }
+    pub(super) fn set_tone_mapping_uniforms(&self, program: ProgramId) {
+        let gl = &*self.backend;
+        set_f32(gl, program, "exposure", self.config.exposure);
+        set_i32(gl, program, "tone_mapping", self.config.tone_mapping.id());
+    }
+    /// Sets whether the forward shaders write linear colors for the HDR target, or sRGB ones
+    /// for the canvas.
+    pub(super) fn set_linear_output(&self, linear: bool) {
+        let gl = &*self.backend;
+        for program in self.shaders.values() {
+            gl.use_program(Some(*program));
+            set_bool(gl, *program, "linear_output", linear);
+        }
+    }
+}
@@ -0,0 +1,610 @@
+use super::backend::*;
+use crate::{dom_factory::add_event, log, TextureType};
+use js_sys::{Array, Float32Array, Uint16Array, Uint32Array};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use wasm_bindgen::JsCast;
+use web_sys::{
+    HtmlCanvasElement, HtmlImageElement, Url, WebGl2RenderingContext as GL, WebGlBuffer,
+    WebGlFramebuffer, WebGlProgram, WebGlRenderbuffer, WebGlShader, WebGlTexture,
+    WebGlVertexArrayObject,
+};
+
+#[derive(Serialize)]
+pub struct ContextOptions {
+    pub stencil: bool,
+}
+
+/// The Backend that draws to a canvas through its WebGL 2 context. The ids it hands out index
+/// into tables of the underlying WebGL objects.
+#[derive(Debug)]
+pub struct WebGlBackend {
+    ctx: GL,
+    next_id: Cell<u32>,
+    programs: RefCell<HashMap<ProgramId, WebGlProgram>>,
+    buffers: RefCell<HashMap<BufferId, WebGlBuffer>>,
+    vaos: RefCell<HashMap<VaoId, WebGlVertexArrayObject>>,
+    textures: RefCell<HashMap<TextureId, WebGlTexture>>,
+    /// The number of images each texture created from urls is still waiting for.
+    loading: RefCell<HashMap<TextureId, Rc<Cell<usize>>>>,
+    framebuffers: RefCell<HashMap<FramebufferId, WebGlFramebuffer>>,
+    renderbuffers: RefCell<HashMap<RenderbufferId, WebGlRenderbuffer>>,
+}
+
+impl WebGlBackend {
+    pub fn new(canvas: &HtmlCanvasElement) -> Self {
+        let stencil_param =
+            wasm_bindgen::JsValue::from_serde(&ContextOptions { stencil: true }).unwrap();
+        let ctx = canvas
+            .get_context_with_context_options("webgl2", &stencil_param)
+            .expect("Can't create webgl2 context. Make sure your browser supports WebGL2")
+            .unwrap()
+            .dyn_into::<GL>()
+            .unwrap();
+        // lets float textures be rendered to, which the environment maps of pbr materials need
+        if let Ok(None) | Err(_) = ctx.get_extension("EXT_color_buffer_float") {
+            log!("EXT_color_buffer_float isn't supported, environment lighting won't work");
+        }
+        Self {
+            ctx,
+            next_id: Cell::new(0),
+            programs: RefCell::new(HashMap::new()),
+            buffers: RefCell::new(HashMap::new()),
+            vaos: RefCell::new(HashMap::new()),
+            textures: RefCell::new(HashMap::new()),
+            loading: RefCell::new(HashMap::new()),
+            framebuffers: RefCell::new(HashMap::new()),
+            renderbuffers: RefCell::new(HashMap::new()),
+        }
+    }
+    pub fn context(&self) -> &GL {
+        &self.ctx
+    }
+    fn next_id(&self) -> u32 {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        id
+    }
+    fn program(&self, program: ProgramId) -> WebGlProgram {
+        self.programs
+            .borrow()
+            .get(&program)
+            .cloned()
+            .expect("No such program found!")
+    }
+    fn buffer(&self, buffer: BufferId) -> WebGlBuffer {
+        self.buffers
+            .borrow()
+            .get(&buffer)
+            .cloned()
+            .expect("No such buffer found!")
+    }
+    fn vao(&self, vao: VaoId) -> WebGlVertexArrayObject {
+        self.vaos
+            .borrow()
+            .get(&vao)
+            .cloned()
+            .expect("No such vao found!")
+    }
+    fn texture(&self, texture: TextureId) -> WebGlTexture {
+        self.textures
+            .borrow()
+            .get(&texture)
+            .cloned()
+            .expect("No such texture found!")
+    }
+}
+
+pub fn compile_shader(gl: &GL, shader_type: u32, source: &str) -> Result<WebGlShader, String> {
+    let shader = gl
+        .create_shader(shader_type)
+        .ok_or_else(|| String::from("Unable to create shader object"))?;
+    gl.shader_source(&shader, source);
+    gl.compile_shader(&shader);
+
+    if gl
+        .get_shader_parameter(&shader, GL::COMPILE_STATUS)
+        .as_bool()
+        .unwrap_or(false)
+    {
+        Ok(shader)
+    } else {
+        Err(gl
+            .get_shader_info_log(&shader)
+            .unwrap_or_else(|| String::from("Unknown error creating shader")))
+    }
+}
+pub fn link_program(
+    gl: &GL,
+    vert_shader: &WebGlShader,
+    frag_shader: &WebGlShader,
+    validate: bool,
+) -> Result<WebGlProgram, String> {
+    let program = gl
+        .create_program()
+        .ok_or_else(|| String::from("Unable to create shader object"))?;
+
+    gl.attach_shader(&program, vert_shader);
+    gl.attach_shader(&program, frag_shader);
+    gl.link_program(&program);
+
+    if gl
+        .get_program_parameter(&program, GL::LINK_STATUS)
+        .as_bool()
+        .unwrap_or(false)
+    {
+        if validate {
+            gl.validate_program(&program);
+            if (gl.get_program_parameter(&program, GL::VALIDATE_STATUS))
+                .as_bool()
+                .unwrap_or(false)
+            {
+                Ok(program)
+            } else {
+                Err(gl
+                    .get_program_info_log(&program)
+                    .unwrap_or_else(|| String::from("Unknown error creating program object")))
+            }
+        } else {
+            Ok(program)
+        }
+    } else {
+        Err(gl
+            .get_program_info_log(&program)
+            .unwrap_or_else(|| String::from("Unknown error creating program object")))
+    }
+}
+
+/// Creates a texture filled with a placeholder pixel, then loads the images into it as they
+/// arrive, counting down `remaining` for each of them.
+pub fn bind_texture(
+    gl: &GL,
+    urls: &[String],
+    tex_type: TextureType,
+    is_img_obj: bool,
+    remaining: Rc<Cell<usize>>,
+) -> Result<WebGlTexture, wasm_bindgen::JsValue> {
+    let texture = gl.create_texture().expect("Can't create texture!");
+    let gl_tex_type = match tex_type {
+        TextureType::CubeMap => GL::TEXTURE_CUBE_MAP,
+        _ => GL::TEXTURE_2D,
+    };
+    gl.bind_texture(gl_tex_type, Some(&texture));
+    if tex_type == TextureType::CubeMap {
+        for i in 0..6 {
+            gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+                GL::TEXTURE_CUBE_MAP_POSITIVE_X + i,
+                0,
+                GL::RGBA as i32,
+                1,
+                1,
+                0,
+                GL::RGBA,
+                GL::UNSIGNED_BYTE,
+                Some(&[255, 0, 255, 255]),
+            )?;
+        }
+    } else {
+        gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+            GL::TEXTURE_2D,
+            0,
+            GL::RGBA as i32,
+            1,
+            1,
+            0,
+            GL::RGBA,
+            GL::UNSIGNED_BYTE,
+            Some(&[255, 0, 255, 255]),
+        )?;
+    }
+    let tex = Rc::new(texture.clone());
+    // couldn't avoid this
+    let gl = Rc::new(gl.clone());
+    for (i, url) in urls.iter().enumerate() {
+        let image = Rc::new(HtmlImageElement::new().expect("Can't create Image Element"));
+        let img = image.clone();
+        let tex = tex.clone();
+        let last = urls.len() - 1;
+        let gl = gl.clone();
+        let remaining = remaining.clone();
+        add_event(&image, "load", move |_| {
+            remaining.set(remaining.get().saturating_sub(1));
+            if is_img_obj {
+                Url::revoke_object_url(&img.src());
+            }
+            log!("Loaded Image" img.src());
+            match tex_type {
+                TextureType::Tex2d => {
+                    if i == 0 {
+                        gl.bind_texture(GL::TEXTURE_2D, Some(&tex));
+                    }
+                    gl.tex_image_2d_with_u32_and_u32_and_html_image_element(
+                        GL::TEXTURE_2D,
+                        0,
+                        GL::RGBA as i32,
+                        GL::RGBA,
+                        GL::UNSIGNED_BYTE,
+                        &img,
+                    )
+                    .expect("Couldn't bind image as texture!");
+                    if i == last {
+                        gl.generate_mipmap(GL::TEXTURE_2D);
+                    }
+                }
+                TextureType::CubeMap => {
+                    if i == 0 {
+                        gl.bind_texture(GL::TEXTURE_CUBE_MAP, Some(&tex));
+                    }
+                    gl.tex_image_2d_with_u32_and_u32_and_html_image_element(
+                        GL::TEXTURE_CUBE_MAP_POSITIVE_X + i as u32,
+                        0,
+                        GL::RGBA as i32,
+                        GL::RGBA,
+                        GL::UNSIGNED_BYTE,
+                        &img,
+                    )
+                    .expect("Couldn't bind image as texture!");
+                    if i == last {
+                        gl.generate_mipmap(GL::TEXTURE_CUBE_MAP);
+                    }
+                }
+                _ => (),
+            }
+            //gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MAG_FILTER as u32, GL::NEARESR as i32);
+        });
+        image.set_src(url);
+    }
+    Ok(texture)
+}
+
+impl Backend for WebGlBackend {
+    fn create_program(&self, vertex: &str, fragment: &str) -> Result<ProgramId, String> {
+        let vert_shader = compile_shader(&self.ctx, GL::VERTEX_SHADER, vertex)?;
+        let frag_shader = compile_shader(&self.ctx, GL::FRAGMENT_SHADER, fragment)?;
+        let program = link_program(&self.ctx, &vert_shader, &frag_shader, true)?;
+        let id = ProgramId(self.next_id());
+        self.programs.borrow_mut().insert(id, program);
+        Ok(id)
+    }
+    fn use_program(&self, program: Option<ProgramId>) {
+        self.ctx
+            .use_program(program.map(|p| self.program(p)).as_ref());
+    }
+    fn attrib_location(&self, program: ProgramId, name: &str) -> i32 {
+        self.ctx.get_attrib_location(&self.program(program), name)
+    }
+    fn set_uniform(&self, program: ProgramId, name: &str, value: Uniform) -> bool {
+        let location = match self.ctx.get_uniform_location(&self.program(program), name) {
+            Some(location) => location,
+            None => return false,
+        };
+        let location = Some(&location);
+        match value {
+            Uniform::Bool(v) => self.ctx.uniform1ui(location, v as u32),
+            Uniform::U32(v) => self.ctx.uniform1ui(location, v),
+            Uniform::I32(v) => self.ctx.uniform1i(location, v),
+            Uniform::F32(v) => self.ctx.uniform1f(location, v),
+            Uniform::Vec3(v) => self.ctx.uniform3f(location, v[0], v[1], v[2]),
+            Uniform::Vec4(v) => self.ctx.uniform4f(location, v[0], v[1], v[2], v[3]),
+            Uniform::Mat4(v) => self
+                .ctx
+                .uniform_matrix4fv_with_f32_array(location, false, &v),
+        }
+        true
+    }
+    fn create_buffer(&self) -> BufferId {
+        let buffer = self.ctx.create_buffer().expect("failed to create buffer");
+        let id = BufferId(self.next_id());
+        self.buffers.borrow_mut().insert(id, buffer);
+        id
+    }
+    fn bind_buffer(&self, target: u32, buffer: Option<BufferId>) {
+        self.ctx
+            .bind_buffer(target, buffer.map(|b| self.buffer(b)).as_ref());
+    }
+    fn buffer_data_f32(&self, target: u32, data: &[f32], usage: u32) {
+        let buffer_array = unsafe { Float32Array::view(data) };
+        self.ctx
+            .buffer_data_with_array_buffer_view(target, &buffer_array, usage);
+    }
+    fn buffer_data_u16(&self, target: u32, data: &[u16], usage: u32) {
+        let buffer_array = unsafe { Uint16Array::view(data) };
+        self.ctx
+            .buffer_data_with_array_buffer_view(target, &buffer_array, usage);
+    }
+    fn buffer_data_u32(&self, target: u32, data: &[u32], usage: u32) {
+        let buffer_array = unsafe { Uint32Array::view(data) };
+        self.ctx
+            .buffer_data_with_array_buffer_view(target, &buffer_array, usage);
+    }
+    fn delete_buffer(&self, buffer: BufferId) {
+        if let Some(buffer) = self.buffers.borrow_mut().remove(&buffer) {
+            self.ctx.delete_buffer(Some(&buffer));
+        }
+    }
+    fn create_vertex_array(&self) -> VaoId {
+        let vao = self.ctx.create_vertex_array().expect("Can't creat VAO");
+        let id = VaoId(self.next_id());
+        self.vaos.borrow_mut().insert(id, vao);
+        id
+    }
+    fn bind_vertex_array(&self, vao: Option<VaoId>) {
+        self.ctx
+            .bind_vertex_array(vao.map(|v| self.vao(v)).as_ref());
+    }
+    fn delete_vertex_array(&self, vao: VaoId) {
+        if let Some(vao) = self.vaos.borrow_mut().remove(&vao) {
+            self.ctx.delete_vertex_array(Some(&vao));
+        }
+    }
+    fn vertex_attrib_pointer(
+        &self,
+        location: u32,
+        size: i32,
+        kind: u32,
+        normalized: bool,
+        stride: i32,
+        offset: i32,
+    ) {
+        self.ctx
+            .vertex_attrib_pointer_with_i32(location, size, kind, normalized, stride, offset);
+    }
+    fn enable_vertex_attrib_array(&self, location: u32) {
+        self.ctx.enable_vertex_attrib_array(location);
+    }
+    fn vertex_attrib_divisor(&self, location: u32, divisor: u32) {
+        self.ctx.vertex_attrib_divisor(location, divisor);
+    }
+    fn create_texture(
+        &self,
+        urls: &[String],
+        tex_type: TextureType,
+        is_img_obj: bool,
+    ) -> TextureId {
+        let remaining = Rc::new(Cell::new(urls.len()));
+        let texture = bind_texture(&self.ctx, urls, tex_type, is_img_obj, remaining.clone())
+            .expect("Couldn't bind texture");
+        let id = TextureId(self.next_id());
+        self.textures.borrow_mut().insert(id, texture);
+        self.loading.borrow_mut().insert(id, remaining);
+        id
+    }
+    fn texture_ready(&self, texture: TextureId) -> bool {
+        self.loading
+            .borrow()
+            .get(&texture)
+            .is_none_or(|remaining| remaining.get() == 0)
+    }
+    fn create_texture_storage(
+        &self,
+        target: u32,
+        levels: i32,
+        internal_format: u32,
+        width: i32,
+        height: i32,
+    ) -> TextureId {
+        let texture = self.ctx.create_texture().expect("Can't create texture!");
+        self.ctx.bind_texture(target, Some(&texture));
+        self.ctx
+            .tex_storage_2d(target, levels, internal_format, width, height);
+        self.ctx.bind_texture(target, None);
+        let id = TextureId(self.next_id());
+        self.textures.borrow_mut().insert(id, texture);
+        id
+    }
+    fn tex_sub_image_f32(
+        &self,
+        target: u32,
+        level: i32,
+        width: i32,
+        height: i32,
+        format: u32,
+        data: &[f32],
+    ) {
+        let pixels = unsafe { Float32Array::view(data) };
+        self.ctx
+            .tex_sub_image_2d_with_i32_and_i32_and_u32_and_type_and_opt_array_buffer_view(
+                target,
+                level,
+                0,
+                0,
+                width,
+                height,
+                format,
+                GL::FLOAT,
+                Some(&pixels),
+            )
+            .expect("Couldn't upload the texture data!");
+    }
+    fn tex_parameter(&self, target: u32, name: u32, value: u32) {
+        self.ctx.tex_parameteri(target, name, value as i32);
+    }
+    fn generate_mipmap(&self, target: u32) {
+        self.ctx.generate_mipmap(target);
+    }
+    fn active_texture(&self, unit: u32) {
+        self.ctx.active_texture(unit);
+    }
+    fn bind_texture(&self, target: u32, texture: Option<TextureId>) {
+        self.ctx
+            .bind_texture(target, texture.map(|t| self.texture(t)).as_ref());
+    }
+    fn delete_texture(&self, texture: TextureId) {
+        self.loading.borrow_mut().remove(&texture);
+        if let Some(texture) = self.textures.borrow_mut().remove(&texture) {
+            self.ctx.delete_texture(Some(&texture));
+        }
+    }
+    fn create_framebuffer(&self) -> FramebufferId {
+        let framebuffer = self
+            .ctx
+            .create_framebuffer()
+            .expect("Can't create framebuffer!");
+        let id = FramebufferId(self.next_id());
+        self.framebuffers.borrow_mut().insert(id, framebuffer);
+        id
+    }
+    fn bind_framebuffer(&self, target: u32, framebuffer: Option<FramebufferId>) {
+        let framebuffers = self.framebuffers.borrow();
+        self.ctx
+            .bind_framebuffer(target, framebuffer.and_then(|f| framebuffers.get(&f)));
+    }
+    fn framebuffer_texture_2d(
+        &self,
+        target: u32,
+        attachment: u32,
+        tex_target: u32,
+        texture: Option<TextureId>,
+        level: i32,
+    ) {
+        self.ctx.framebuffer_texture_2d(
+            target,
+            attachment,
+            tex_target,
+            texture.map(|t| self.texture(t)).as_ref(),
+            level,
+        );
+    }
+    fn draw_buffers(&self, buffers: &[u32]) {
+        let buffers: Array = buffers
+            .iter()
+            .map(|b| wasm_bindgen::JsValue::from(*b))
+            .collect();
+        self.ctx.draw_buffers(&buffers);
+    }
+    fn delete_framebuffer(&self, framebuffer: FramebufferId) {
+        if let Some(framebuffer) = self.framebuffers.borrow_mut().remove(&framebuffer) {
+            self.ctx.delete_framebuffer(Some(&framebuffer));
+        }
+    }
+    fn create_renderbuffer(
+        &self,
+        samples: i32,
+        internal_format: u32,
+        width: i32,
+        height: i32,
+    ) -> RenderbufferId {
+        let renderbuffer = self
+            .ctx
+            .create_renderbuffer()
+            .expect("Can't create renderbuffer!");
+        self.ctx
+            .bind_renderbuffer(GL::RENDERBUFFER, Some(&renderbuffer));
+        self.ctx.renderbuffer_storage_multisample(
+            GL::RENDERBUFFER,
+            samples,
+            internal_format,
+            width,
+            height,
+        );
+        self.ctx.bind_renderbuffer(GL::RENDERBUFFER, None);
+        let id = RenderbufferId(self.next_id());
+        self.renderbuffers.borrow_mut().insert(id, renderbuffer);
+        id
+    }
+    fn framebuffer_renderbuffer(
+        &self,
+        target: u32,
+        attachment: u32,
+        renderbuffer: Option<RenderbufferId>,
+    ) {
+        let renderbuffers = self.renderbuffers.borrow();
+        self.ctx.framebuffer_renderbuffer(
+            target,
+            attachment,
+            GL::RENDERBUFFER,
+            renderbuffer.and_then(|r| renderbuffers.get(&r)),
+        );
+    }
+    fn delete_renderbuffer(&self, renderbuffer: RenderbufferId) {
+        if let Some(renderbuffer) = self.renderbuffers.borrow_mut().remove(&renderbuffer) {
+            self.ctx.delete_renderbuffer(Some(&renderbuffer));
+        }
+    }
+    fn blit_framebuffer(&self, src: [i32; 4], dst: [i32; 4], mask: u32, filter: u32) {
+        self.ctx.blit_framebuffer(
+            src[0], src[1], src[2], src[3], dst[0], dst[1], dst[2], dst[3], mask, filter,
+        );
+    }
+    fn enable(&self, capability: u32) {
+        self.ctx.enable(capability);
+    }
+    fn disable(&self, capability: u32) {
+        self.ctx.disable(capability);
+    }
+    fn viewport(&self, x: i32, y: i32, width: i32, height: i32) {
+        self.ctx.viewport(x, y, width, height);
+    }
+    fn clear_color(&self, r: f32, g: f32, b: f32, a: f32) {
+        self.ctx.clear_color(r, g, b, a);
+    }
+    fn clear_depth(&self, depth: f32) {
+        self.ctx.clear_depth(depth);
+    }
+    fn clear(&self, mask: u32) {
+        self.ctx.clear(mask);
+    }
+    fn clear_buffer_u32(&self, buffer: u32, draw_buffer: i32, value: [u32; 4]) {
+        self.ctx
+            .clear_bufferuiv_with_u32_array(buffer, draw_buffer, &value);
+    }
+    fn depth_func(&self, func: u32) {
+        self.ctx.depth_func(func);
+    }
+    fn front_face(&self, mode: u32) {
+        self.ctx.front_face(mode);
+    }
+    fn cull_face(&self, mode: u32) {
+        self.ctx.cull_face(mode);
+    }
+    fn blend_func(&self, src: u32, dst: u32) {
+        self.ctx.blend_func(src, dst);
+    }
+    fn stencil_op(&self, fail: u32, zfail: u32, zpass: u32) {
+        self.ctx.stencil_op(fail, zfail, zpass);
+    }
+    fn stencil_func(&self, func: u32, reference: i32, mask: u32) {
+        self.ctx.stencil_func(func, reference, mask);
+    }
+    fn stencil_mask(&self, mask: u32) {
+        self.ctx.stencil_mask(mask);
+    }
+    fn read_pixels_u32(&self, x: i32, y: i32, width: i32, height: i32) -> Vec<u32> {
+        let pixels = Uint32Array::new_with_length((width * height * 4).max(0) as u32);
+        self.ctx
+            .read_pixels_with_opt_array_buffer_view(
+                x,
+                y,
+                width,
+                height,
+                GL::RGBA_INTEGER,
+                GL::UNSIGNED_INT,
+                Some(&pixels),
+            )
+            .expect("Couldn't read the pixels back!");
+        pixels.to_vec()
+    }
+    fn draw_arrays(&self, mode: u32, first: i32, count: i32) {
+        self.ctx.draw_arrays(mode, first, count);
+    }
+    fn draw_elements(&self, mode: u32, count: i32, index_type: u32, offset: i32) {
+        self.ctx
+            .draw_elements_with_i32(mode, count, index_type, offset);
+    }
+    fn draw_arrays_instanced(&self, mode: u32, first: i32, count: i32, instances: i32) {
+        self.ctx
+            .draw_arrays_instanced(mode, first, count, instances);
+    }
+    fn draw_elements_instanced(
+        &self,
+        mode: u32,
+        count: i32,
+        index_type: u32,
+        offset: i32,
+        instances: i32,
+    ) {
+        self.ctx
+            .draw_elements_instanced_with_i32(mode, count, index_type, offset, instances);
+    }
+}
@@ -0,0 +1,179 @@
+use crate::{
+    rc_rcell,
+    renderer::ShadowConfig,
+    scene::{Instances, LightType, Node, Scene, SkyboxSource},
+    Mesh, ObjectInfo, Transform,
+};
+
+/// Version of the scene document written by Scene::save. Documents with a newer version are
+/// rejected by Scene::load.
+pub const SCENE_FORMAT_VERSION: u32 = 1;
+
+/// Serialized form of a whole scene: every persistent node below the root and the skybox.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SceneDocument {
+    pub version: u32,
+    pub skybox: Option<SkyboxSource>,
+    pub nodes: Vec<NodeDocument>,
+}
+
+/// Serialized form of a node and its subtree.
+///
+/// Light nodes don't store their mesh or owned children since those are recreated along with
+/// the light.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeDocument {
+    pub info: ObjectInfo,
+    pub transform: Transform,
+    pub mesh: Option<Mesh>,
+    /// The copies drawn by an instanced node.
+    #[serde(default)]
+    pub instances: Option<Instances>,
+    pub light: Option<LightDocument>,
+    pub children: Vec<NodeDocument>,
+    pub owned_children: Vec<NodeDocument>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LightDocument {
+    pub light_type: LightType,
+    pub intensity: f32,
+    pub color: [f32; 3],
+    pub light: bool,
+    #[serde(default)]
+    pub shadows: Option<ShadowConfig>,
+}
+
+impl Scene {
+    /// Serializes the scene into a versioned JSON document.
+    pub fn save(&self) -> String {
+        serde_json::to_string(&self.to_document()).expect("Couldn't serialize the scene!")
+    }
+    /// Replaces every persistent node of the scene, and the skybox, with the ones described by
+    /// a document written by `save`.
+    pub fn load(&self, src: &str) -> Result<(), String> {
+        let document: SceneDocument =
+            serde_json::from_str(src).map_err(|e| format!("Invalid scene document: {}", e))?;
+        self.load_document(&document)
+    }
+    pub fn to_document(&self) -> SceneDocument {
+        let nodes = self
+            .root()
+            .borrow()
+            .children()
+            .iter()
+            .filter(|n| n.borrow().info().persist)
+            .map(|n| self.node_to_document(&n.borrow()))
+            .collect();
+        SceneDocument {
+            version: SCENE_FORMAT_VERSION,
+            skybox: self.skybox(),
+            nodes,
+        }
+    }
+    fn node_to_document(&self, node: &Node) -> NodeDocument {
+        let storage = self.storage();
+        let light = storage
+            .borrow()
+            .lights()
+            .find(|l| l.node_id == node.handle())
+            .map(|l| LightDocument {
+                light_type: l.light_type,
+                intensity: l.intensity,
+                color: l.color,
+                light: l.light,
+                shadows: l.shadows,
+            });
+        let children = node
+            .children()
+            .iter()
+            .filter(|n| n.borrow().info().persist)
+            .map(|n| self.node_to_document(&n.borrow()))
+            .collect();
+        let instances = storage.borrow().instances(node.index()).cloned();
+        let (mesh, owned_children) = if light.is_some() {
+            (None, Vec::new())
+        } else {
+            let owned_children = node
+                .owned_children()
+                .iter()
+                .map(|n| self.node_to_document(n))
+                .collect();
+            (node.mesh().map(|m| (*m).clone()), owned_children)
+        };
+        NodeDocument {
+            info: node.info(),
+            transform: node.transform(),
+            mesh,
+            instances,
+            light,
+            children,
+            owned_children,
+        }
+    }
+    pub fn load_document(&self, document: &SceneDocument) -> Result<(), String> {
+        if document.version > SCENE_FORMAT_VERSION {
+            return Err(format!(
+                "Scene document version {} is newer than the supported version {}",
+                document.version, SCENE_FORMAT_VERSION
+            ));
+        }
+        let root = self.root();
+        for child in root.borrow().children() {
+            if child.borrow().info().persist {
+                self.delete(&child.borrow());
+            }
+        }
+        if self.skybox() != document.skybox {
+            match &document.skybox {
+                Some(skybox) => self.set_skybox(&skybox.dir, &skybox.ext),
+                None => self.remove_skybox(),
+            }
+        }
+        for each in document.nodes.iter() {
+            self.load_node(each, &root.borrow(), false);
+        }
+        Ok(())
+    }
+    fn load_node(&self, document: &NodeDocument, parent: &Node, owned: bool) -> Node {
+        let node = if let Some(light) = document.light {
+            let l = self.light(light.light_type, light.color, light.intensity);
+            {
+                let s = self.storage();
+                let mut storage = s.borrow_mut();
+                let info = storage.mut_light_info(l.index());
+                info.light = light.light;
+                info.shadows = light.shadows;
+            }
+            let node = l.node().borrow().clone();
+            self.show(&node);
+            node
+        } else if let (Some(mesh), Some(instances)) = (&document.mesh, &document.instances) {
+            self.instanced(mesh.clone(), document.info.clone(), instances.clone())
+        } else {
+            Self::object(
+                self.storage(),
+                &self.renderer.borrow(),
+                document.mesh.clone(),
+                document.transform,
+                document.info.clone(),
+                false,
+                false,
+            )
+        };
+        node.set_transform(document.transform);
+        node.set_info(document.info.clone());
+        if owned {
+            parent.own(node.clone());
+        } else {
+            parent.add(rc_rcell(node.clone()));
+        }
+        for child in document.children.iter() {
+            self.load_node(child, &node, false);
+        }
+        for child in document.owned_children.iter() {
+            self.load_node(child, &node, true);
+        }
+        node
+    }
+}
@@ -0,0 +1,78 @@
+use crate::{
+    dom_factory::{add_event, window},
+    renderer::{CursorType, Renderer},
+    MouseButton, RcRcell, Scene, Viewport,
+};
+use wasm_bindgen::JsCast;
+use web_sys::{MouseEvent, WheelEvent};
+
+impl Scene {
+    pub fn new(renderer: RcRcell<Renderer>, viewport: RcRcell<Viewport>) -> Self {
+        let scene = Self::headless(renderer, viewport);
+        scene.add_viewport_events();
+        scene
+    }
+    fn add_viewport_events(&self) {
+        let window = window();
+        let perf = window.performance().unwrap();
+
+        let renderer = self.renderer.borrow();
+        let canvas = renderer.canvas();
+
+        let a_view = self.viewport.clone();
+        add_event(&canvas, "mousemove", move |e| {
+            let me = e.dyn_into::<MouseEvent>().unwrap();
+            let dt = perf.now();
+            a_view
+                .borrow_mut()
+                .update_rot(me.movement_x(), me.movement_y(), dt as f32);
+        });
+
+        let b_view = self.viewport.clone();
+        add_event(&canvas, "wheel", move |e| {
+            let mut view = b_view.borrow_mut();
+            let we = e.dyn_into::<WheelEvent>().unwrap();
+            view.enable_zoom();
+            view.update_zoom(we.delta_y() as i32);
+            view.disable_zoom();
+        });
+
+        if let Some(button) = self.viewport.borrow().button() {
+            let a_view = self.viewport.clone();
+            let a_rndr = self.renderer.clone();
+            add_event(canvas, "mousedown", move |e| {
+                let mut view = a_view.borrow_mut();
+                let renderer = a_rndr.borrow_mut();
+                let me = e.dyn_into::<MouseEvent>().unwrap();
+                if me.button() == button as i16 {
+                    renderer.change_cursor(CursorType::Grab);
+                    view.enable_rotation();
+                }
+                if me.button() == MouseButton::MIDDLE as i16 {
+                    view.enable_zoom();
+                }
+            });
+            let a_view = self.viewport.clone();
+            let a_rndr = self.renderer.clone();
+            add_event(&window, "mouseup", move |e| {
+                let mut view = a_view.borrow_mut();
+                let renderer = a_rndr.borrow_mut();
+                let me = e.dyn_into::<MouseEvent>().unwrap();
+                let pressed_btn = me.button();
+                if (pressed_btn == button as i16) || (pressed_btn == MouseButton::MIDDLE as i16) {
+                    renderer.change_cursor(CursorType::Pointer);
+                    view.disable_rotation();
+                    view.disable_zoom()
+                }
+            });
+        }
+
+        let a_rndr = self.renderer.clone();
+        let a_view = self.viewport.clone();
+        add_event(&window, "resize", move |_| {
+            let mut renderer = a_rndr.borrow_mut();
+            renderer.resize();
+            a_view.borrow_mut().resize(renderer.aspect_ratio());
+        });
+    }
+}
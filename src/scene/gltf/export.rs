@@ -0,0 +1,376 @@
+use super::*;
+use crate::{
+    mesh::IndexType,
+    renderer::{DrawMode, ShaderType},
+    scene::{LightInfo, LightType, Node, Scene},
+    Material, Mesh, ObjectInfo, RcRcell, Storage, TextureType,
+};
+use nalgebra::{UnitQuaternion, Vector3};
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+/// Collects the glTF document and its binary buffer while walking the scene.
+struct Exporter {
+    root: Root,
+    bin: Vec<u8>,
+    lights: Vec<LightDef>,
+    images: HashMap<String, usize>,
+}
+
+impl Exporter {
+    fn push_view(&mut self, bytes: Vec<u8>, target: Option<u32>) -> usize {
+        // every view starts 4-byte aligned so that any component type can be read from it
+        self.bin.resize((self.bin.len() + 3) & !3, 0);
+        self.root.buffer_views.push(BufferView {
+            buffer: 0,
+            byte_offset: self.bin.len(),
+            byte_length: bytes.len(),
+            byte_stride: None,
+            target,
+        });
+        self.bin.extend(bytes);
+        self.root.buffer_views.len() - 1
+    }
+    fn push_accessor(&mut self, accessor: Accessor) -> usize {
+        self.root.accessors.push(accessor);
+        self.root.accessors.len() - 1
+    }
+    /// Writes a float attribute, along with its bounds if asked to.
+    fn push_floats(
+        &mut self,
+        values: &[f32],
+        kind: &str,
+        components: usize,
+        bounds: bool,
+    ) -> usize {
+        let bytes = values
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect();
+        let view = self.push_view(bytes, Some(ARRAY_BUFFER));
+        let (min, max) = if bounds {
+            let mut min = vec![f32::INFINITY; components];
+            let mut max = vec![f32::NEG_INFINITY; components];
+            for element in values.chunks(components) {
+                for (i, v) in element.iter().enumerate() {
+                    min[i] = min[i].min(*v);
+                    max[i] = max[i].max(*v);
+                }
+            }
+            (Some(min), Some(max))
+        } else {
+            (None, None)
+        };
+        self.push_accessor(Accessor {
+            buffer_view: Some(view),
+            component_type: FLOAT,
+            count: values.len() / components,
+            kind: kind.into(),
+            min,
+            max,
+            ..Default::default()
+        })
+    }
+    fn push_indices(&mut self, indices: &[u32], index_type: IndexType) -> usize {
+        let (bytes, component_type) = match index_type {
+            IndexType::U16 => (
+                indices
+                    .iter()
+                    .flat_map(|i| (*i as u16).to_le_bytes().to_vec())
+                    .collect(),
+                UNSIGNED_SHORT,
+            ),
+            IndexType::U32 => (
+                indices
+                    .iter()
+                    .flat_map(|i| i.to_le_bytes().to_vec())
+                    .collect(),
+                UNSIGNED_INT,
+            ),
+        };
+        let view = self.push_view(bytes, Some(ELEMENT_ARRAY_BUFFER));
+        self.push_accessor(Accessor {
+            buffer_view: Some(view),
+            component_type,
+            count: indices.len(),
+            kind: "SCALAR".into(),
+            ..Default::default()
+        })
+    }
+    /// Embeds images held in data uris into the buffer. Any other url is written as is.
+    fn image(&mut self, url: &str) -> usize {
+        if let Some(index) = self.images.get(url) {
+            return *index;
+        }
+        let image = match decode_data_uri(url) {
+            Some(Ok(data)) => Image {
+                buffer_view: Some(self.push_view(data, None)),
+                mime_type: url[5..].split(';').next().map(|m| m.to_string()),
+                ..Default::default()
+            },
+            _ => Image {
+                uri: Some(url.to_string()),
+                ..Default::default()
+            },
+        };
+        self.root.images.push(image);
+        self.images
+            .insert(url.to_string(), self.root.images.len() - 1);
+        self.root.images.len() - 1
+    }
+    /// Adds a texture that samples the image at the url with the first texture coordinates.
+    fn texture(&mut self, url: &str) -> TextureInfo {
+        let source = self.image(url);
+        self.root.textures.push(TextureDef {
+            source: Some(source),
+        });
+        TextureInfo {
+            index: self.root.textures.len() - 1,
+            ..Default::default()
+        }
+    }
+    fn material(&mut self, info: &ObjectInfo, material: &Material) -> usize {
+        // Only pbr materials are exported with their metalness and roughness, anything else is
+        // written as a rough dielectric.
+        let is_pbr = material.shader_type == ShaderType::Pbr;
+        let mut pbr = PbrMetallicRoughness {
+            base_color_factor: material.color,
+            metallic_factor: Some(if is_pbr { material.metallic } else { 0. }),
+            roughness_factor: Some(if is_pbr { material.roughness } else { 1. }),
+            ..Default::default()
+        };
+        let has_tex_coords = material.tex_coords.is_some();
+        if material.tex_type == TextureType::Tex2d && has_tex_coords {
+            if let Some(url) = material.texture_urls.first() {
+                pbr.base_color_texture = Some(self.texture(url));
+            }
+        }
+        let (mut normal_texture, mut occlusion_texture) = (None, None);
+        if is_pbr && has_tex_coords {
+            if let Some(map) = material.normal_map.as_ref() {
+                normal_texture = Some(TextureInfo {
+                    scale: Some(map.strength),
+                    ..self.texture(&map.url)
+                });
+            }
+            if let Some(map) = material.occlusion_map.as_ref() {
+                occlusion_texture = Some(TextureInfo {
+                    strength: Some(map.strength),
+                    ..self.texture(&map.url)
+                });
+            }
+            if let Some(map) = material.metallic_roughness_map.as_ref() {
+                pbr.metallic_roughness_texture = Some(self.texture(&map.url));
+            }
+        }
+        let emissive_factor = if is_pbr && material.emissive != [0., 0., 0.] {
+            Some(material.emissive)
+        } else {
+            None
+        };
+        self.root.materials.push(MaterialDef {
+            name: Some(info.name.clone()),
+            pbr_metallic_roughness: Some(pbr),
+            normal_texture,
+            occlusion_texture,
+            emissive_factor,
+            alpha_mode: if info.render_flags.blend {
+                Some("BLEND".into())
+            } else {
+                None
+            },
+            double_sided: !info.render_flags.cull_face,
+        });
+        self.root.materials.len() - 1
+    }
+    fn mesh(&mut self, info: &ObjectInfo, mesh: &Mesh) -> usize {
+        let geometry = &mesh.geometry;
+        let count = geometry.vertices.len() / 3;
+        let mut attributes = BTreeMap::new();
+        attributes.insert(
+            "POSITION".to_string(),
+            self.push_floats(&geometry.vertices, "VEC3", 3, true),
+        );
+        if geometry.normals.len() == geometry.vertices.len() {
+            let normals: Vec<f32> = geometry
+                .normals
+                .chunks(3)
+                .flat_map(|n| {
+                    let n = Vector3::new(n[0], n[1], n[2])
+                        .try_normalize(f32::EPSILON)
+                        .unwrap_or_else(Vector3::y);
+                    vec![n.x, n.y, n.z]
+                })
+                .collect();
+            attributes.insert(
+                "NORMAL".into(),
+                self.push_floats(&normals, "VEC3", 3, false),
+            );
+        }
+        if let Some(tex_coords) = mesh.material.tex_coords.as_ref() {
+            if tex_coords.len() == count * 2 {
+                let accessor = self.push_floats(tex_coords, "VEC2", 2, false);
+                attributes.insert("TEXCOORD_0".into(), accessor);
+            }
+        }
+        if let Some(colors) = mesh.material.vertex_colors.as_ref() {
+            if colors.len() == count * 4 {
+                let accessor = self.push_floats(colors, "VEC4", 4, false);
+                attributes.insert("COLOR_0".into(), accessor);
+            }
+        }
+        // Arrays draws the vertices in order, which is what a primitive without indices means.
+        let (mode, indices) = match info.draw_mode {
+            DrawMode::Arrays => (4, None),
+            DrawMode::Points => (0, Some(&geometry.indices)),
+            DrawMode::Lines => (1, Some(&geometry.indices)),
+            DrawMode::Triangle => (4, Some(&geometry.indices)),
+        };
+        let indices = indices.map(|i| self.push_indices(i, geometry.index_type()));
+        let material = self.material(info, &mesh.material);
+        self.root.meshes.push(MeshDef {
+            name: Some(info.name.clone()),
+            primitives: vec![PrimitiveDef {
+                attributes,
+                indices,
+                material: Some(material),
+                mode: Some(mode),
+            }],
+        });
+        self.root.meshes.len() - 1
+    }
+    /// Writes the light as a child node whose -Z axis points where the light shines. Ambient
+    /// lights have no counterpart in KHR_lights_punctual and are left out.
+    fn light(&mut self, light: &LightInfo, node: &Node) -> Option<usize> {
+        let rotation = (node.parent_transform() * node.transform())
+            .isometry
+            .rotation;
+        let quarter = |angle: f32| UnitQuaternion::from_euler_angles(0., angle, 0.);
+        // The renderer turns the node's X axis by a quarter turn around the world's Y axis to
+        // find the light direction, see Renderer::setup_lights.
+        let (kind, world, spot) = match light.light_type {
+            LightType::Ambient => return None,
+            LightType::Point => ("point", rotation, None),
+            LightType::Directional => (
+                "directional",
+                quarter(PI / 2.) * rotation * quarter(PI / 2.),
+                None,
+            ),
+            LightType::Spot => (
+                "spot",
+                quarter(PI / 2.) * rotation * quarter(-PI / 2.),
+                Some(Spot {
+                    inner_cone_angle: PI / 30.,
+                    outer_cone_angle: PI / 25.,
+                }),
+            ),
+        };
+        let name = node.info().name;
+        self.lights.push(LightDef {
+            name: Some(name.clone()),
+            kind: kind.into(),
+            color: light.color,
+            intensity: light.intensity,
+            spot,
+        });
+        let q = (rotation.inverse() * world).into_inner();
+        self.root.nodes.push(NodeDef {
+            name: Some(format!("{} light", name)),
+            rotation: Some([q.i, q.j, q.k, q.w]),
+            extensions: Some(NodeExtensions {
+                light: Some(NodeLight {
+                    light: self.lights.len() - 1,
+                }),
+            }),
+            ..Default::default()
+        });
+        Some(self.root.nodes.len() - 1)
+    }
+    fn node(&mut self, node: &Node, storage: &RcRcell<Storage>) -> usize {
+        let info = node.info();
+        let light = storage
+            .borrow()
+            .lights()
+            .find(|l| l.node_id == node.handle())
+            .copied();
+        let mut children: Vec<usize> = node
+            .children()
+            .iter()
+            .filter(|n| n.borrow().info().persist)
+            .map(|n| self.node(&n.borrow(), storage))
+            .collect();
+        let mut mesh = None;
+        if let Some(light) = light {
+            children.extend(self.light(&light, node));
+        } else {
+            mesh = node.mesh().map(|m| self.mesh(&info, &m));
+            for child in node.owned_children() {
+                children.push(self.node(&child, storage));
+            }
+        }
+        let transform = node.transform();
+        let t = transform.isometry.translation.vector;
+        let q = transform.isometry.rotation.into_inner();
+        let s = transform.scale;
+        self.root.nodes.push(NodeDef {
+            name: Some(info.name),
+            children,
+            mesh,
+            translation: Some([t.x, t.y, t.z]),
+            rotation: Some([q.i, q.j, q.k, q.w]),
+            scale: Some([s.x, s.y, s.z]),
+            ..Default::default()
+        });
+        self.root.nodes.len() - 1
+    }
+}
+
+impl Scene {
+    /// Exports every persistent node below the root as a binary glTF 2.0 file (.glb), with
+    /// lights written through the KHR_lights_punctual extension.
+    pub fn to_glb(&self) -> Vec<u8> {
+        let mut exporter = Exporter {
+            root: Root {
+                asset: Asset {
+                    version: "2.0".into(),
+                    generator: Some("Moksha".into()),
+                },
+                ..Default::default()
+            },
+            bin: Vec::new(),
+            lights: Vec::new(),
+            images: HashMap::new(),
+        };
+        let storage = self.storage();
+        let root = self.root();
+        let nodes = root
+            .borrow()
+            .children()
+            .iter()
+            .filter(|n| n.borrow().info().persist)
+            .map(|n| exporter.node(&n.borrow(), &storage))
+            .collect();
+        let mut document = exporter.root;
+        document.scene = Some(0);
+        document.scenes.push(SceneDef {
+            name: Some(root.borrow().info().name),
+            nodes,
+        });
+        if !exporter.lights.is_empty() {
+            document.extensions_used.push("KHR_lights_punctual".into());
+            document.extensions = Some(RootExtensions {
+                lights_punctual: Some(LightsPunctual {
+                    lights: exporter.lights,
+                }),
+            });
+        }
+        if !exporter.bin.is_empty() {
+            document.buffers.push(Buffer {
+                uri: None,
+                byte_length: exporter.bin.len(),
+            });
+        }
+        let json = serde_json::to_string(&document).expect("Couldn't serialize the glTF document!");
+        write_glb(&json, &exporter.bin)
+    }
+}
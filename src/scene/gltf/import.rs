@@ -0,0 +1,580 @@
+use super::*;
+use crate::{
+    rc_rcell,
+    renderer::DrawMode,
+    scene::{Node, Scene},
+    Geometry, Material, Mesh, ObjectInfo, TextureType, Transform,
+};
+use nalgebra::{Isometry3, Matrix3, Quaternion, Rotation3, Translation3, UnitQuaternion, Vector3};
+use std::collections::HashMap;
+
+/// Reads accessors out of the buffers of a glTF document.
+struct Reader<'a> {
+    root: &'a Root,
+    buffers: Vec<Vec<u8>>,
+}
+
+impl<'a> Reader<'a> {
+    fn new(
+        root: &'a Root,
+        bin: Option<&[u8]>,
+        external: &HashMap<String, Vec<u8>>,
+    ) -> Result<Self, GltfError> {
+        let mut buffers = Vec::new();
+        for (i, buffer) in root.buffers.iter().enumerate() {
+            let data = match &buffer.uri {
+                None if i == 0 => bin
+                    .map(|b| b.to_vec())
+                    .ok_or_else(|| GltfError::Glb("Missing binary chunk".into()))?,
+                None => return Err(GltfError::InvalidData(format!("Buffer {} has no uri", i))),
+                Some(uri) => match decode_data_uri(uri) {
+                    Some(data) => data?,
+                    None => {
+                        let uri = decode_uri(uri);
+                        let file_name = uri.rsplit('/').next().unwrap_or(&uri);
+                        external
+                            .get(&uri)
+                            .or_else(|| external.get(file_name))
+                            .cloned()
+                            .ok_or(GltfError::MissingBuffer(uri))?
+                    }
+                },
+            };
+            if data.len() < buffer.byte_length {
+                return Err(GltfError::InvalidData(format!("Buffer {} is too short", i)));
+            }
+            buffers.push(data);
+        }
+        Ok(Self { root, buffers })
+    }
+    fn buffer_view(&self, index: usize) -> Result<&[u8], GltfError> {
+        let view = self
+            .root
+            .buffer_views
+            .get(index)
+            .ok_or(GltfError::InvalidReference("bufferView", index))?;
+        let out_of_range =
+            || GltfError::InvalidData(format!("BufferView {} is out of range", index));
+        let end = view
+            .byte_offset
+            .checked_add(view.byte_length)
+            .ok_or_else(out_of_range)?;
+        self.buffers
+            .get(view.buffer)
+            .ok_or(GltfError::InvalidReference("buffer", view.buffer))?
+            .get(view.byte_offset..end)
+            .ok_or_else(out_of_range)
+    }
+    /// Reads every component of the accessor in order, along with the accessor itself.
+    fn read(&self, index: usize) -> Result<(&Accessor, Vec<f64>), GltfError> {
+        let accessor = self
+            .root
+            .accessors
+            .get(index)
+            .ok_or(GltfError::InvalidReference("accessor", index))?;
+        if accessor.sparse.is_some() {
+            return Err(GltfError::Unsupported("Sparse accessors".into()));
+        }
+        let components = match accessor.kind.as_str() {
+            "SCALAR" => 1,
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" | "MAT2" => 4,
+            "MAT3" => 9,
+            "MAT4" => 16,
+            kind => return Err(GltfError::InvalidData(format!("Accessor type {}", kind))),
+        };
+        let size = match accessor.component_type {
+            BYTE | UNSIGNED_BYTE => 1,
+            SHORT | UNSIGNED_SHORT => 2,
+            UNSIGNED_INT | FLOAT => 4,
+            t => return Err(GltfError::InvalidData(format!("Component type {}", t))),
+        };
+        let too_large = || GltfError::InvalidData(format!("Accessor {} is too large", index));
+        let len = accessor
+            .count
+            .checked_mul(components)
+            .ok_or_else(too_large)?;
+        let view_index = match accessor.buffer_view {
+            Some(view) => view,
+            None => {
+                // there's no view to bound the count by, so only what can be allocated is read
+                let mut values = Vec::new();
+                values.try_reserve_exact(len).map_err(|_| too_large())?;
+                values.resize(len, 0.);
+                return Ok((accessor, values));
+            }
+        };
+        let view = self.buffer_view(view_index)?;
+        let stride = self.root.buffer_views[view_index]
+            .byte_stride
+            .unwrap_or(components * size);
+        if stride < components * size {
+            return Err(GltfError::InvalidData(format!(
+                "BufferView {} is strided tighter than accessor {}",
+                view_index, index
+            )));
+        }
+        let out_of_range =
+            || GltfError::InvalidData(format!("Accessor {} reads past its bufferView", index));
+        // the last element has to end within the view before anything is allocated for them
+        if accessor.count > 0 {
+            let end = (accessor.count - 1)
+                .checked_mul(stride)
+                .and_then(|last| last.checked_add(accessor.byte_offset))
+                .and_then(|last| last.checked_add(components * size))
+                .ok_or_else(out_of_range)?;
+            if end > view.len() {
+                return Err(out_of_range());
+            }
+        }
+        let mut values = Vec::with_capacity(len);
+        for i in 0..accessor.count {
+            for c in 0..components {
+                let at = accessor.byte_offset + i * stride + c * size;
+                let b = view.get(at..at + size).ok_or_else(out_of_range)?;
+                values.push(match accessor.component_type {
+                    BYTE => f64::from(b[0] as i8),
+                    UNSIGNED_BYTE => f64::from(b[0]),
+                    SHORT => f64::from(i16::from_le_bytes([b[0], b[1]])),
+                    UNSIGNED_SHORT => f64::from(u16::from_le_bytes([b[0], b[1]])),
+                    UNSIGNED_INT => f64::from(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+                    _ => f64::from(f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+                });
+            }
+        }
+        Ok((accessor, values))
+    }
+    /// Reads the accessor as floats, resolving normalized integers to the [0, 1] or [-1, 1]
+    /// range.
+    fn read_f32(&self, index: usize) -> Result<Vec<f32>, GltfError> {
+        let (accessor, values) = self.read(index)?;
+        let max = match accessor.component_type {
+            BYTE => 127.,
+            UNSIGNED_BYTE => 255.,
+            SHORT => 32767.,
+            UNSIGNED_SHORT => 65535.,
+            _ => 1.,
+        };
+        Ok(values
+            .iter()
+            .map(|v| {
+                if accessor.normalized {
+                    (v / max).max(-1.) as f32
+                } else {
+                    *v as f32
+                }
+            })
+            .collect())
+    }
+    fn read_indices(&self, index: usize) -> Result<Vec<u32>, GltfError> {
+        Ok(self.read(index)?.1.iter().map(|v| *v as u32).collect())
+    }
+    fn attribute(
+        &self,
+        primitive: &PrimitiveDef,
+        name: &str,
+    ) -> Result<Option<Vec<f32>>, GltfError> {
+        match primitive.attributes.get(name) {
+            Some(index) => self.read_f32(*index).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Converts the primitive's topology into one that the renderer can draw.
+fn primitive_indices(mode: u32, indices: Vec<u32>) -> Result<(DrawMode, Vec<u32>), GltfError> {
+    let n = indices.len();
+    Ok(match mode {
+        0 => (DrawMode::Points, indices),
+        1 => (DrawMode::Lines, indices),
+        2 | 3 => {
+            let mut lines = Vec::new();
+            for i in 1..n {
+                lines.push(indices[i - 1]);
+                lines.push(indices[i]);
+            }
+            if mode == 2 && n > 2 {
+                lines.push(indices[n - 1]);
+                lines.push(indices[0]);
+            }
+            (DrawMode::Lines, lines)
+        }
+        4 => (DrawMode::Triangle, indices),
+        5 => {
+            let mut triangles = Vec::new();
+            for i in 2..n {
+                if i % 2 == 0 {
+                    triangles.extend(&[indices[i - 2], indices[i - 1], indices[i]]);
+                } else {
+                    triangles.extend(&[indices[i - 1], indices[i - 2], indices[i]]);
+                }
+            }
+            (DrawMode::Triangle, triangles)
+        }
+        6 => {
+            let mut triangles = Vec::new();
+            for i in 2..n {
+                triangles.extend(&[indices[0], indices[i - 1], indices[i]]);
+            }
+            (DrawMode::Triangle, triangles)
+        }
+        mode => return Err(GltfError::InvalidData(format!("Primitive mode {}", mode))),
+    })
+}
+
+fn node_transform(node: &NodeDef) -> Transform {
+    if let Some(m) = node.matrix {
+        let column = |j: usize| Vector3::new(m[j * 4], m[j * 4 + 1], m[j * 4 + 2]);
+        let mut scale = Vector3::new(column(0).norm(), column(1).norm(), column(2).norm());
+        let mut basis = Matrix3::from_columns(&[
+            column(0) / scale.x,
+            column(1) / scale.y,
+            column(2) / scale.z,
+        ]);
+        if basis.determinant() < 0. {
+            scale.x = -scale.x;
+            basis.set_column(0, &-basis.column(0));
+        }
+        let rotation =
+            UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix_unchecked(basis));
+        Transform {
+            isometry: Isometry3::from_parts(Translation3::new(m[12], m[13], m[14]), rotation),
+            scale,
+        }
+    } else {
+        let [x, y, z] = node.translation.unwrap_or([0., 0., 0.]);
+        let [i, j, k, w] = node.rotation.unwrap_or([0., 0., 0., 1.]);
+        Transform {
+            isometry: Isometry3::from_parts(
+                Translation3::new(x, y, z),
+                UnitQuaternion::from_quaternion(Quaternion::new(w, i, j, k)),
+            ),
+            scale: node.scale.unwrap_or([1., 1., 1.]).into(),
+        }
+    }
+}
+
+/// Builds Moksha nodes out of a parsed glTF document.
+struct Importer<'a> {
+    scene: &'a Scene,
+    reader: Reader<'a>,
+    dir: &'a str,
+    img_urls: Option<&'a HashMap<String, String>>,
+    image_cache: HashMap<usize, Option<String>>,
+    visited: Vec<bool>,
+}
+
+impl<'a> Importer<'a> {
+    fn image_url(&mut self, index: usize) -> Result<Option<String>, GltfError> {
+        if let Some(url) = self.image_cache.get(&index) {
+            return Ok(url.clone());
+        }
+        let image = self
+            .reader
+            .root
+            .images
+            .get(index)
+            .ok_or(GltfError::InvalidReference("image", index))?;
+        let url = match (&image.uri, image.buffer_view) {
+            (Some(uri), _) if uri.starts_with("data:") => Some(uri.clone()),
+            (Some(uri), _) => {
+                let uri = decode_uri(uri);
+                let file_name = uri.rsplit('/').next().unwrap_or(&uri);
+                match self.img_urls {
+                    Some(urls) => urls.get(&uri).or_else(|| urls.get(file_name)).cloned(),
+                    None if !self.dir.is_empty() => Some(format!("{}/{}", self.dir, uri)),
+                    None => None,
+                }
+            }
+            (None, Some(view)) => {
+                let mime_type = image.mime_type.as_ref().map_or("image/png", |m| m.as_str());
+                let data = base64_encode(self.reader.buffer_view(view)?);
+                Some(format!("data:{};base64,{}", mime_type, data))
+            }
+            (None, None) => None,
+        };
+        self.image_cache.insert(index, url.clone());
+        Ok(url)
+    }
+    fn material(
+        &mut self,
+        primitive: &PrimitiveDef,
+        info: &mut ObjectInfo,
+    ) -> Result<Material, GltfError> {
+        let root = self.reader.root;
+        let def = match primitive.material {
+            Some(i) => root
+                .materials
+                .get(i)
+                .ok_or(GltfError::InvalidReference("material", i))?,
+            None => return Ok(Material::new_pbr(1., 1., 1., 1.)),
+        };
+        info.render_flags.cull_face = !def.double_sided;
+        info.render_flags.blend = def.alpha_mode.as_deref() == Some("BLEND");
+        let pbr = def.pbr_metallic_roughness.clone().unwrap_or_default();
+        let [r, g, b, a] = pbr.base_color_factor.unwrap_or([1., 1., 1., 1.]);
+        let [er, eg, eb] = def.emissive_factor.unwrap_or([0., 0., 0.]);
+        let mut material = Material::new_pbr(r, g, b, a)
+            .metallic(pbr.metallic_factor.unwrap_or(1.))
+            .roughness(pbr.roughness_factor.unwrap_or(1.))
+            .emissive(er, eg, eb);
+        if let Some(url) = self.texture(primitive, pbr.base_color_texture.as_ref(), &mut material)? {
+            material = material.tex_type(TextureType::Tex2d).texture(&url);
+        }
+        if let Some(url) = self.texture(primitive, def.normal_texture.as_ref(), &mut material)? {
+            let scale = def.normal_texture.as_ref().and_then(|t| t.scale);
+            material = material.normal_map(&url, scale.unwrap_or(1.));
+        }
+        if let Some(url) = self.texture(primitive, def.occlusion_texture.as_ref(), &mut material)? {
+            let strength = def.occlusion_texture.as_ref().and_then(|t| t.strength);
+            material = material.occlusion_map(&url, strength.unwrap_or(1.));
+        }
+        let metallic_roughness = pbr.metallic_roughness_texture.as_ref();
+        if let Some(url) = self.texture(primitive, metallic_roughness, &mut material)? {
+            material = material.metallic_roughness_map(&url);
+        }
+        Ok(material)
+    }
+    /// Finds the image of a texture along with the texture coordinates it is sampled with. A
+    /// material only holds one set of coordinates, so every texture is sampled with the set of
+    /// the first one, and textures whose image or coordinates are missing are left out.
+    fn texture(
+        &mut self,
+        primitive: &PrimitiveDef,
+        texture_info: Option<&TextureInfo>,
+        material: &mut Material,
+    ) -> Result<Option<String>, GltfError> {
+        let texture_info = match texture_info {
+            Some(texture_info) => texture_info,
+            None => return Ok(None),
+        };
+        let root = self.reader.root;
+        let texture = root
+            .textures
+            .get(texture_info.index)
+            .ok_or(GltfError::InvalidReference("texture", texture_info.index))?;
+        let url = match texture.source {
+            Some(source) => self.image_url(source)?,
+            None => None,
+        };
+        if material.tex_coords.is_none() {
+            material.tex_coords = self
+                .reader
+                .attribute(primitive, &format!("TEXCOORD_{}", texture_info.tex_coord))?;
+        }
+        Ok(url.filter(|_| material.tex_coords.is_some()))
+    }
+    fn primitive(
+        &mut self,
+        primitive: &PrimitiveDef,
+        name: String,
+    ) -> Result<(Mesh, ObjectInfo), GltfError> {
+        let vertices = self
+            .reader
+            .attribute(primitive, "POSITION")?
+            .ok_or_else(|| GltfError::InvalidData(format!("{} has no positions", name)))?;
+        let indices = match primitive.indices {
+            Some(i) => self.reader.read_indices(i)?,
+            None => (0..(vertices.len() / 3) as u32).collect(),
+        };
+        if indices.iter().any(|i| *i as usize >= vertices.len() / 3) {
+            return Err(GltfError::InvalidData(format!(
+                "{} has out of range indices",
+                name
+            )));
+        }
+        let (draw_mode, indices) = primitive_indices(primitive.mode.unwrap_or(4), indices)?;
+        let mut info = ObjectInfo {
+            name,
+            draw_mode,
+            ..Default::default()
+        };
+        let mut material = self.material(primitive, &mut info)?;
+        let normals = self.reader.attribute(primitive, "NORMAL")?;
+        let mut geometry = Geometry {
+            vertices,
+            indices,
+            normals: normals.clone().unwrap_or_default(),
+        };
+        if normals.is_none() {
+            // glTF asks for flat shading when normals are left out, but the color shader still
+            // needs something bound to its normal attribute.
+            geometry.compute_normals();
+            material = material.flat();
+        }
+        Ok((Mesh { geometry, material }, info))
+    }
+    fn node(&mut self, index: usize) -> Result<Node, GltfError> {
+        let root = self.reader.root;
+        let def = root
+            .nodes
+            .get(index)
+            .ok_or(GltfError::InvalidReference("node", index))?;
+        if std::mem::replace(&mut self.visited[index], true) {
+            return Err(GltfError::InvalidData(format!(
+                "Node {} is referenced more than once",
+                index
+            )));
+        }
+        let name = def
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("node {}", index));
+        let transform = node_transform(def);
+        let primitives = match def.mesh {
+            Some(i) => {
+                let mesh = root
+                    .meshes
+                    .get(i)
+                    .ok_or(GltfError::InvalidReference("mesh", i))?;
+                let mesh_name = mesh.name.clone().unwrap_or_else(|| name.clone());
+                let mut primitives = Vec::new();
+                for (i, primitive) in mesh.primitives.iter().enumerate() {
+                    primitives.push(self.primitive(primitive, format!("{} {}", mesh_name, i))?);
+                }
+                primitives
+            }
+            None => Vec::new(),
+        };
+        let renderer = self.scene.renderer.borrow();
+        let node = if primitives.len() == 1 {
+            let (mesh, info) = primitives.into_iter().next().unwrap();
+            let info = ObjectInfo { name, ..info };
+            Scene::object(
+                self.scene.storage(),
+                &renderer,
+                Some(mesh),
+                transform,
+                info,
+                false,
+                false,
+            )
+        } else {
+            let info = ObjectInfo {
+                name,
+                ..Default::default()
+            };
+            let node = Scene::object(
+                self.scene.storage(),
+                &renderer,
+                None,
+                transform,
+                info,
+                false,
+                false,
+            );
+            // the primitives of a mesh are parts of the same object
+            for (mesh, info) in primitives {
+                node.own(Scene::object(
+                    self.scene.storage(),
+                    &renderer,
+                    Some(mesh),
+                    Default::default(),
+                    info,
+                    false,
+                    false,
+                ));
+            }
+            node
+        };
+        drop(renderer);
+        for child in def.children.iter() {
+            match self.node(*child) {
+                Ok(child) => node.add(rc_rcell(child)),
+                Err(e) => {
+                    self.scene.delete(&node);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(node)
+    }
+}
+
+impl Scene {
+    /// Loads a glTF 2.0 document (.gltf) as a tree of nodes.
+    ///
+    /// Buffers can be embedded as data uris or passed in `buffers` keyed by their uri. Images are
+    /// looked up in `img_urls` by their uri, like for object_from_obj, or loaded relative to
+    /// `dir` otherwise.
+    pub fn object_from_gltf(
+        &self,
+        dir: &str,
+        src: &str,
+        buffers: &HashMap<String, Vec<u8>>,
+        img_urls: Option<&HashMap<String, String>>,
+    ) -> Result<Node, GltfError> {
+        self.load_gltf(dir, src, None, buffers, img_urls)
+    }
+    /// Loads a binary glTF 2.0 file (.glb) as a tree of nodes. See object_from_gltf for how
+    /// external buffers and images are resolved.
+    pub fn object_from_glb(
+        &self,
+        dir: &str,
+        bytes: &[u8],
+        buffers: &HashMap<String, Vec<u8>>,
+        img_urls: Option<&HashMap<String, String>>,
+    ) -> Result<Node, GltfError> {
+        let (src, bin) = parse_glb(bytes)?;
+        self.load_gltf(dir, src, bin, buffers, img_urls)
+    }
+    fn load_gltf(
+        &self,
+        dir: &str,
+        src: &str,
+        bin: Option<&[u8]>,
+        buffers: &HashMap<String, Vec<u8>>,
+        img_urls: Option<&HashMap<String, String>>,
+    ) -> Result<Node, GltfError> {
+        let root: Root = serde_json::from_str(src).map_err(|e| GltfError::Json(e.to_string()))?;
+        if !root.asset.version.starts_with("2.") {
+            return Err(GltfError::Unsupported(format!(
+                "Version {}",
+                root.asset.version
+            )));
+        }
+        if let Some(extension) = root.extensions_required.first() {
+            return Err(GltfError::Unsupported(format!("Extension {}", extension)));
+        }
+        let roots: Vec<usize> = match root.scenes.get(root.scene.unwrap_or(0)) {
+            Some(scene) => scene.nodes.clone(),
+            None => (0..root.nodes.len())
+                .filter(|i| !root.nodes.iter().any(|n| n.children.contains(i)))
+                .collect(),
+        };
+        let scene_name = root
+            .scenes
+            .get(root.scene.unwrap_or(0))
+            .and_then(|s| s.name.clone());
+        let mut importer = Importer {
+            scene: self,
+            reader: Reader::new(&root, bin, buffers)?,
+            dir,
+            img_urls,
+            image_cache: HashMap::new(),
+            visited: vec![false; root.nodes.len()],
+        };
+        let mut nodes = Vec::new();
+        for index in roots {
+            match importer.node(index) {
+                Ok(node) => nodes.push(node),
+                Err(e) => {
+                    for node in nodes.iter() {
+                        self.delete(node);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        if nodes.len() == 1 {
+            Ok(nodes.pop().unwrap())
+        } else {
+            let node = self.empty(&scene_name.unwrap_or_else(|| "glTF scene".into()));
+            for each in nodes {
+                node.add(rc_rcell(each));
+            }
+            Ok(node)
+        }
+    }
+}
@@ -0,0 +1,448 @@
+//! A minimal glTF 2.0 reader and writer. Only the parts of the schema that Moksha can make use
+//! of are modeled; everything else is ignored while parsing.
+mod export;
+mod import;
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+const GLB_MAGIC: u32 = 0x4654_6c67;
+const GLB_JSON_CHUNK: u32 = 0x4e4f_534a;
+const GLB_BIN_CHUNK: u32 = 0x004e_4942;
+
+pub const BYTE: u32 = 5120;
+pub const UNSIGNED_BYTE: u32 = 5121;
+pub const SHORT: u32 = 5122;
+pub const UNSIGNED_SHORT: u32 = 5123;
+pub const UNSIGNED_INT: u32 = 5125;
+pub const FLOAT: u32 = 5126;
+
+pub const ARRAY_BUFFER: u32 = 34962;
+pub const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+/// Errors that can occur while reading a glTF asset.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GltfError {
+    /// The JSON document couldn't be parsed.
+    Json(String),
+    /// The binary container is malformed.
+    Glb(String),
+    /// An external buffer that wasn't provided to the loader.
+    MissingBuffer(String),
+    /// An index into one of the top level arrays (e.g. "accessor", "node") is out of range.
+    InvalidReference(&'static str, usize),
+    /// The data is inconsistent, e.g. an accessor reads past the end of its buffer.
+    InvalidData(String),
+    /// The asset relies on a feature that isn't supported.
+    Unsupported(String),
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GltfError::Json(e) => write!(f, "Invalid glTF document: {}", e),
+            GltfError::Glb(e) => write!(f, "Invalid glb container: {}", e),
+            GltfError::MissingBuffer(uri) => write!(f, "Buffer {} was not provided", uri),
+            GltfError::InvalidReference(kind, i) => write!(f, "No {} with index {}", kind, i),
+            GltfError::InvalidData(e) => write!(f, "Invalid glTF data: {}", e),
+            GltfError::Unsupported(e) => write!(f, "Unsupported glTF feature: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for GltfError {}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn is_zero(value: &usize) -> bool {
+    *value == 0
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct Root {
+    pub asset: Asset,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scene: Option<usize>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub scenes: Vec<SceneDef>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub nodes: Vec<NodeDef>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub meshes: Vec<MeshDef>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub accessors: Vec<Accessor>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub buffer_views: Vec<BufferView>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub buffers: Vec<Buffer>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub materials: Vec<MaterialDef>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub textures: Vec<TextureDef>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<Image>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extensions_used: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extensions_required: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<RootExtensions>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct Asset {
+    pub version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generator: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct RootExtensions {
+    #[serde(rename = "KHR_lights_punctual", skip_serializing_if = "Option::is_none")]
+    pub lights_punctual: Option<LightsPunctual>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct LightsPunctual {
+    pub lights: Vec<LightDef>,
+}
+
+/// A light of the KHR_lights_punctual extension. Lights shine along the -Z axis of their node.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct LightDef {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spot: Option<Spot>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct Spot {
+    pub inner_cone_angle: f32,
+    pub outer_cone_angle: f32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct SceneDef {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub nodes: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct NodeDef {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mesh: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matrix: Option<[f32; 16]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub translation: Option<[f32; 3]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotation: Option<[f32; 4]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale: Option<[f32; 3]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<NodeExtensions>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct NodeExtensions {
+    #[serde(rename = "KHR_lights_punctual", skip_serializing_if = "Option::is_none")]
+    pub light: Option<NodeLight>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct NodeLight {
+    pub light: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct MeshDef {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub primitives: Vec<PrimitiveDef>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct PrimitiveDef {
+    pub attributes: BTreeMap<String, usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub indices: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub material: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct Accessor {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buffer_view: Option<usize>,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub byte_offset: usize,
+    pub component_type: u32,
+    #[serde(skip_serializing_if = "is_false")]
+    pub normalized: bool,
+    pub count: usize,
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<Vec<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<Vec<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sparse: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct BufferView {
+    pub buffer: usize,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub byte_offset: usize,
+    pub byte_length: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub byte_stride: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct Buffer {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+    pub byte_length: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct MaterialDef {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pbr_metallic_roughness: Option<PbrMetallicRoughness>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normal_texture: Option<TextureInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub occlusion_texture: Option<TextureInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub emissive_factor: Option<[f32; 3]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alpha_mode: Option<String>,
+    #[serde(skip_serializing_if = "is_false")]
+    pub double_sided: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct PbrMetallicRoughness {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_color_factor: Option<[f32; 4]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_color_texture: Option<TextureInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metallic_factor: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub roughness_factor: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metallic_roughness_texture: Option<TextureInfo>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct TextureInfo {
+    pub index: usize,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub tex_coord: usize,
+    /// Only used by normal textures.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale: Option<f32>,
+    /// Only used by occlusion textures.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strength: Option<f32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct TextureDef {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct Image {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buffer_view: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Result<u32, GltfError> {
+    bytes
+        .get(at..at + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| GltfError::Glb("Unexpected end of file".into()))
+}
+
+/// Splits a binary glTF container into its JSON document and the optional binary chunk.
+pub(crate) fn parse_glb(bytes: &[u8]) -> Result<(&str, Option<&[u8]>), GltfError> {
+    if read_u32(bytes, 0)? != GLB_MAGIC {
+        return Err(GltfError::Glb("Missing glTF magic".into()));
+    }
+    let version = read_u32(bytes, 4)?;
+    if version != 2 {
+        return Err(GltfError::Unsupported(format!("glb version {}", version)));
+    }
+    let length = (read_u32(bytes, 8)? as usize).min(bytes.len());
+    let mut at = 12;
+    let (mut json, mut bin) = (None, None);
+    while at + 8 <= length {
+        let chunk_length = read_u32(bytes, at)? as usize;
+        let chunk_type = read_u32(bytes, at + 4)?;
+        let too_long = || GltfError::Glb("Chunk exceeds the file length".into());
+        let end = (at + 8).checked_add(chunk_length).ok_or_else(too_long)?;
+        let chunk = bytes.get(at + 8..end).ok_or_else(too_long)?;
+        match chunk_type {
+            GLB_JSON_CHUNK if json.is_none() => {
+                let src = std::str::from_utf8(chunk)
+                    .map_err(|_| GltfError::Glb("JSON chunk isn't valid UTF-8".into()))?;
+                json = Some(src);
+            }
+            GLB_BIN_CHUNK if bin.is_none() => bin = Some(chunk),
+            _ => (),
+        }
+        at = end;
+    }
+    json.map(|json| (json, bin))
+        .ok_or_else(|| GltfError::Glb("Missing JSON chunk".into()))
+}
+
+/// Packs a JSON document and a binary chunk into a binary glTF container.
+pub(crate) fn write_glb(json: &str, bin: &[u8]) -> Vec<u8> {
+    let pad = |len: usize| (4 - len % 4) % 4;
+    let json_length = json.len() + pad(json.len());
+    let bin_length = bin.len() + pad(bin.len());
+    let mut length = 12 + 8 + json_length;
+    if !bin.is_empty() {
+        length += 8 + bin_length;
+    }
+    let mut out = Vec::with_capacity(length);
+    for word in [GLB_MAGIC, 2, length as u32, json_length as u32, GLB_JSON_CHUNK].iter() {
+        out.extend_from_slice(&word.to_le_bytes());
+    }
+    out.extend_from_slice(json.as_bytes());
+    out.resize(20 + json_length, b' ');
+    if !bin.is_empty() {
+        out.extend_from_slice(&(bin_length as u32).to_le_bytes());
+        out.extend_from_slice(&GLB_BIN_CHUNK.to_le_bytes());
+        out.extend_from_slice(bin);
+        out.resize(length, 0);
+    }
+    out
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub(crate) fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity((bytes.len() / 3 + 1) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+pub(crate) fn base64_decode(src: &str) -> Result<Vec<u8>, GltfError> {
+    let mut out = Vec::with_capacity(src.len() / 4 * 3);
+    let (mut n, mut bits) = (0u32, 0);
+    for c in src
+        .bytes()
+        .filter(|c| !c.is_ascii_whitespace() && *c != b'=')
+    {
+        let value = BASE64
+            .iter()
+            .position(|b| *b == c)
+            .ok_or_else(|| GltfError::InvalidData("Invalid base64 data".into()))?;
+        n = n << 6 | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((n >> bits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+/// Decodes the payload of a base64 data uri.
+pub(crate) fn decode_data_uri(uri: &str) -> Option<Result<Vec<u8>, GltfError>> {
+    if !uri.starts_with("data:") {
+        return None;
+    }
+    Some(match uri.find(";base64,") {
+        Some(i) => base64_decode(&uri[i + 8..]),
+        None => Err(GltfError::Unsupported(
+            "Data uri without base64 encoding".into(),
+        )),
+    })
+}
+
+/// Resolves the percent-encoded characters of a relative uri.
+pub(crate) fn decode_uri(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = uri
+            .get(i + 1..i + 3)
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -1,31 +1,42 @@
+mod document;
+#[cfg(feature = "web")]
+mod events;
+mod gltf;
 mod node;
+mod obj;
 pub mod primitives;
+mod raycast;
+mod spatial;
 mod storage;
 
-use std::collections::HashMap;
-
 #[doc(inline)]
 pub use primitives::Primitive;
 
 #[doc(inline)]
 pub use node::Node;
-pub use storage::Storage;
+pub use document::{LightDocument, NodeDocument, SceneDocument, SCENE_FORMAT_VERSION};
+pub use gltf::GltfError;
+pub use obj::ObjError;
+pub use raycast::{MeshCollider, RaycastHit};
+pub use spatial::SpatialIndex;
+pub use storage::{Handle, RemovedSlot, Storage};
 
 use crate::{
-    dom_factory::{add_event, window, now, set_timeout, request_animation_frame},
-    log, node, rc_rcell, TextureType,
-    renderer::{bind_texture, CursorType, DrawMode, RenderFlags, Renderer},
+    node, rc_rcell,
+    renderer::{
+        DrawMode, HdrError, HdrImage, RenderFlags, Renderer, ShaderType, ShadowConfig,
+        VertexArray,
+    },
     scene::primitives::create_light_node,
-    Geometry, Material, Mesh, MouseButton, RcRcell, Transform, Viewport,
+    Geometry, Material, Mesh, RcRcell, TextureType, Transform, Viewport,
 };
 use genmesh::generators::Cube;
-use nalgebra::Vector3;
+use std::rc::Rc;
 use strum_macros::{Display, EnumIter, EnumString};
-use wasm_bindgen::{JsCast, closure::Closure};
-use wavefront_obj::{mtl, obj};
-use web_sys::{MouseEvent, WheelEvent};
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Display, EnumIter, EnumString)]
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, Display, EnumIter, EnumString, Serialize, Deserialize,
+)]
 pub enum LightType {
     Ambient,
     Point,
@@ -38,8 +49,18 @@ pub struct LightInfo {
     pub light_type: LightType,
     pub intensity: f32,
     pub color: [f32; 3],
-    pub node_id: usize,
+    pub node_id: Handle,
     pub light: bool,
+    /// How the light casts shadows, None for no shadows. Only directional and spot lights do.
+    pub shadows: Option<ShadowConfig>,
+}
+
+/// The copies drawn by an instanced node in a single draw call. Instance transforms are relative
+/// to the node, and instances without a color use the color of the material.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Instances {
+    pub transforms: Vec<Transform>,
+    pub colors: Option<Vec<[f32; 4]>>,
 }
 
 pub struct Light {
@@ -57,11 +78,14 @@ impl Light {
 }
 
 /// Information about an object in the scene (name, render flag, drawing mode)
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ObjectInfo {
     pub name: String,
     pub draw_mode: DrawMode,
     pub render_flags: RenderFlags,
+    /// Whether the node is written out by Scene::save. Editor helpers turn this off.
+    pub persist: bool,
 }
 
 impl Default for ObjectInfo {
@@ -70,6 +94,7 @@ impl Default for ObjectInfo {
             name: "node".into(),
             draw_mode: DrawMode::Triangle,
             render_flags: Default::default(),
+            persist: true,
         }
     }
 }
@@ -80,10 +105,21 @@ pub struct Scene {
     root: RcRcell<Node>,
     renderer: RcRcell<Renderer>,
     viewport: RcRcell<Viewport>,
+    /// The skybox node, along with where it was loaded from. HDR skyboxes have no source.
+    skybox: RcRcell<Option<(Option<SkyboxSource>, Node)>>,
+}
+
+/// Where the six faces of the skybox cubemap are loaded from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SkyboxSource {
+    pub dir: String,
+    pub ext: String,
 }
 
 impl Scene {
-    pub fn new(renderer: RcRcell<Renderer>, viewport: RcRcell<Viewport>) -> Self {
+    /// Creates a scene that doesn't listen to mouse events on the canvas, for renderers that
+    /// don't have one. Scene::new also hooks the viewport up to the canvas.
+    pub fn headless(renderer: RcRcell<Renderer>, viewport: RcRcell<Viewport>) -> Self {
         let storage = rc_rcell(Default::default());
         let root = rc_rcell(Self::object(
             storage,
@@ -97,13 +133,12 @@ impl Scene {
             false,
             false,
         ));
-        let scene = Self {
+        Self {
             root,
             renderer,
             viewport,
-        };
-        scene.add_viewport_events();
-        scene
+            skybox: rc_rcell(None),
+        }
     }
     pub fn root(&self) -> RcRcell<Node> {
         self.root.clone()
@@ -117,13 +152,20 @@ impl Scene {
     pub fn turn_lights_visiblity(&self, node: &Node, visible: bool) {
         let s = self.storage();
         let mut storage = s.borrow_mut();
-        for i in 0..storage.lights().len() {
-            if storage.light(i).node_id == node.index() {
+        let light_ids: Vec<usize> = storage.light_ids().collect();
+        for i in light_ids {
+            if storage.light(i).node_id == node.handle() {
                 storage.mut_light_info(i).light = visible;
                 break;
             }
         }
     }
+    /// Makes a directional or spot light cast shadows, or stops it with None.
+    pub fn set_shadows(&self, light: &Light, shadows: Option<ShadowConfig>) {
+        let s = self.storage();
+        let mut storage = s.borrow_mut();
+        storage.mut_light_info(light.index()).shadows = shadows;
+    }
     pub fn turn_lights_on(&self, node: &Node) {
         self.turn_lights_visiblity(node, true);
     }
@@ -141,7 +183,7 @@ impl Scene {
             self.show(&child.borrow());
         }
         for child in node.owned_children() {
-            self.show(child);
+            self.show(&child);
         }
     }
     pub fn set_visibility_only(&self, node: &Node, visible: bool) {
@@ -152,9 +194,11 @@ impl Scene {
             info.render_flags.render = visible;
         }
         for child in node.owned_children() {
-            self.set_visibility_only(child, visible);
+            self.set_visibility_only(&child, visible);
         }
     }
+    /// Loads a skybox from the six faces posx, negx, posy, negy, posz and negz in the directory.
+    /// Once they have loaded, the skybox also lights pbr materials.
     pub fn set_skybox(&self, dir: &str, ext: &str) {
         let mesh = Mesh::new(
             Geometry::from_genmesh(&Cube::new()),
@@ -169,8 +213,53 @@ impl Scene {
                 ],
             ),
         );
+        let source = SkyboxSource {
+            dir: dir.to_string(),
+            ext: ext.to_string(),
+        };
+        self.replace_skybox(Some(source), mesh, false);
+    }
+    /// Sets an equirectangular Radiance HDR (.hdr) image as the skybox, converted into a
+    /// cubemap. Like set_skybox, it lights pbr materials. Its source isn't kept, so scene
+    /// documents are saved without it.
+    pub fn set_hdr_skybox(&self, bytes: &[u8]) -> Result<(), HdrError> {
+        let image = HdrImage::decode(bytes)?;
+        let cubemap = self.renderer.borrow().create_hdr_cubemap(&image);
+        let mut material = Material::new_color(1., 1., 1., 1.)
+            .shader_type(ShaderType::CubeMap)
+            .tex_type(TextureType::CubeMap);
+        let tex_i = self.storage().borrow_mut().add_texture(cubemap);
+        material.texture_indices.push(tex_i);
+        let mesh = Mesh::new(Geometry::from_genmesh(&Cube::new()), material);
+        self.replace_skybox(None, mesh, true);
+        Ok(())
+    }
+    fn replace_skybox(&self, source: Option<SkyboxSource>, mesh: Mesh, hdr: bool) {
         let cube = node!(&self, Some(mesh), "Skybox", RenderFlags::no_cull());
         self.show(&cube);
+        let texture = {
+            let s = self.storage();
+            let storage = s.borrow();
+            let mesh = storage.mesh(cube.index()).expect("The skybox has no mesh!");
+            storage.texture(mesh.material.texture_indices[0])
+        };
+        self.renderer.borrow().set_environment(Some(texture), hdr);
+        if let Some((_, old)) = self.skybox.borrow_mut().replace((source, cube)) {
+            self.free(&old);
+        }
+    }
+    /// Where the skybox was loaded from, None without a skybox or for an HDR one.
+    pub fn skybox(&self) -> Option<SkyboxSource> {
+        self.skybox
+            .borrow()
+            .as_ref()
+            .and_then(|(source, _)| source.clone())
+    }
+    pub fn remove_skybox(&self) {
+        if let Some((_, old)) = self.skybox.borrow_mut().take() {
+            self.renderer.borrow().set_environment(None, false);
+            self.free(&old);
+        }
     }
     pub fn show_only(&self, node: &Node) {
         self.set_visibility_only(node, true);
@@ -180,7 +269,7 @@ impl Scene {
     }
     pub fn add(&self, node: RcRcell<Node>) {
         self.show(&node.borrow());
-        self.root.borrow_mut().add(node);
+        self.root.borrow().add(node);
     }
     pub fn add_light(&self, light: &Light) {
         self.add(light.node());
@@ -200,24 +289,104 @@ impl Scene {
     ) -> Node {
         let sto = storage.clone();
         let mut storage = storage.borrow_mut();
-        let index = if let Some(mut mesh) = mesh {
+        let handle = if let Some(mut mesh) = mesh {
             if setup_unique_vertices {
                 mesh.setup_unique_vertices();
             }
-            let vao = renderer.create_vao(&mesh);
-            let urls = &mesh.material.texture_urls[..];
-            if urls.len() > 0 {
-                let tex_i = storage.add_texture(
-                    bind_texture(renderer.context(), urls, mesh.material.tex_type, is_img_obj)
-                        .expect("Couldn't bind albedo texture"),
-                );
-                mesh.material.texture_indices.push(tex_i);
-            }
+            let (mesh, vao) = Self::upload(&mut storage, renderer, mesh, is_img_obj);
             storage.add(Some(mesh), Some(vao), transform, info)
         } else {
             storage.add(None, None, transform, info)
         };
-        Node::new(index, sto)
+        Node::new(handle, sto)
+    }
+    /// Uploads the vertices of the mesh to a new vao, and its images to a new texture.
+    fn upload(
+        storage: &mut Storage,
+        renderer: &Renderer,
+        mut mesh: Mesh,
+        is_img_obj: bool,
+    ) -> (Rc<Mesh>, Rc<VertexArray>) {
+        let vao = renderer.create_vao(&mesh);
+        let urls = &mesh.material.texture_urls[..];
+        if !urls.is_empty() {
+            let texture = renderer.create_texture(urls, mesh.material.tex_type, is_img_obj);
+            let tex_i = storage.add_texture(texture);
+            mesh.material.texture_indices.push(tex_i);
+        }
+        for map in mesh.material.texture_maps_mut() {
+            let urls = [map.url.clone()];
+            let texture = renderer.create_texture(&urls, TextureType::Tex2d, is_img_obj);
+            map.texture_index = Some(storage.add_texture(texture));
+        }
+        (Rc::new(mesh), Rc::new(vao))
+    }
+    /// Deletes the vao and textures of a mesh that was taken out of its slot, unless another
+    /// instance still draws them.
+    fn release(
+        storage: &mut Storage,
+        renderer: &Renderer,
+        mesh: Option<Rc<Mesh>>,
+        vao: Option<Rc<VertexArray>>,
+    ) {
+        if let Some(Ok(vao)) = vao.map(Rc::try_unwrap) {
+            renderer.delete_vao(&vao);
+        }
+        if let Some(mesh) = mesh {
+            for tex_i in mesh.material.textures() {
+                if let Some(texture) = storage.remove_texture(tex_i) {
+                    renderer.delete_texture(texture);
+                }
+            }
+        }
+    }
+    /// Replaces the mesh of the node, uploading the new one and releasing the GPU objects of the
+    /// old one. An instanced node goes back to being drawn once.
+    pub fn set_mesh(&self, node: &Node, mesh: Option<Mesh>) {
+        let renderer = self.renderer.borrow();
+        let s = self.storage();
+        let mut storage = s.borrow_mut();
+        let i = storage.index_of(node.handle());
+        let (mesh, vao) = match mesh {
+            Some(mesh) => {
+                let (mesh, vao) = Self::upload(&mut storage, &renderer, mesh, false);
+                (Some(mesh), Some(vao))
+            }
+            None => (None, None),
+        };
+        let (old_mesh, old_vao) = storage.replace_mesh(i, mesh, vao);
+        storage.set_instances(i, None);
+        Self::release(&mut storage, &renderer, old_mesh, old_vao);
+    }
+    /// Deletes the node and its whole subtree from the scene, releasing their VAOs, buffers and
+    /// textures. The storage slots are recycled, and any Node or light still holding a handle to
+    /// them is treated as stale.
+    pub fn delete(&self, node: &Node) {
+        assert!(
+            node.handle() != self.root.borrow().handle(),
+            "Can't delete the scene root!"
+        );
+        self.free(node);
+    }
+    fn free(&self, node: &Node) {
+        for child in node.children() {
+            self.free(&child.borrow());
+        }
+        for child in node.owned_children() {
+            self.free(&child);
+        }
+        let renderer = self.renderer.borrow();
+        let s = self.storage();
+        let mut storage = s.borrow_mut();
+        if let Some(removed) = storage.remove(node.handle()) {
+            Self::release(&mut storage, &renderer, removed.mesh, removed.vao);
+        }
+        let light_ids: Vec<usize> = storage.light_ids().collect();
+        for i in light_ids {
+            if storage.light(i).node_id == node.handle() {
+                storage.remove_light(i);
+            }
+        }
     }
     pub fn from_mesh(&self, mesh: Option<Mesh>, setup_unique_vertices: bool) -> Node {
         Self::object(
@@ -250,252 +419,12 @@ impl Scene {
             light_type,
             intensity,
             color,
-            node_id: node.borrow().index(),
+            node_id: node.borrow().handle(),
             light: false,
+            shadows: None,
         });
         Light { light_id, node }
     }
-    pub fn load_object_from_obj_wired(
-        &self,
-        dir: &str,
-        object: &obj::Object,
-        mat_set: &Option<mtl::MtlSet>,
-        img_obj_url: Option<&HashMap<String, String>>,
-    ) -> Node {
-        let mut buf_vertices = Vec::new();
-        for vertex in &object.vertices {
-            buf_vertices.push(vertex.x as f32);
-            buf_vertices.push(vertex.y as f32);
-            buf_vertices.push(vertex.z as f32);
-        }
-        let mut buf_normals = Vec::new();
-        for normal in &object.normals {
-            buf_normals.push(normal.x as f32);
-            buf_normals.push(normal.y as f32);
-            buf_normals.push(normal.z as f32);
-        }
-        let mut buf_tex_coords = Vec::new();
-        for normal in &object.normals {
-            buf_tex_coords.push(normal.x as f32);
-            buf_tex_coords.push(normal.y as f32);
-            buf_tex_coords.push(normal.z as f32);
-        }
-        let mut vertices = Vec::new();
-        let mut normals = Vec::new();
-        let mut indices = Vec::new();
-        let mut tex_coords = Vec::new();
-        for shape in &object.geometry[0].shapes {
-            if let obj::Primitive::Triangle(a, b, c) = shape.primitive {
-                if a.1 != None && a.2 != None {
-                    for ((a, ua), na) in [a.0, b.0, c.0]
-                        .iter()
-                        .zip([a.1.unwrap(), b.1.unwrap(), c.1.unwrap()].iter())
-                        .zip([a.2.unwrap(), b.2.unwrap(), c.2.unwrap()].iter())
-                    {
-                        let (e, ue, ne) = (*a * 3, *ua, *na);
-                        indices.push(e as u16);
-                        vertices.push(buf_vertices[e]);
-                        vertices.push(buf_vertices[e + 1]);
-                        vertices.push(buf_vertices[e + 2]);
-                        normals.push(buf_vertices[e]);
-                        normals.push(buf_vertices[e + 1]);
-                        normals.push(buf_vertices[e + 2]);
-                        let u = object.tex_vertices[ue].u as f32;
-                        let v = -object.tex_vertices[ue].v as f32;
-                        tex_coords.push(u);
-                        tex_coords.push(v);
-                    }
-                } else {
-                    log!("obj file doesn't have normal or uv indices. Only vertices are loaded");
-                }
-            }
-        }
-        let geometry = Geometry {
-            vertices,
-            indices,
-            normals,
-        };
-        let material = Self::load_material(dir, object, mat_set, tex_coords, img_obj_url);
-        let node = self.from_mesh(Some(Mesh { geometry, material }), false);
-        let mut info = node.info();
-        info.draw_mode = DrawMode::Arrays;
-        info.name = object.name.clone();
-        node.set_info(info);
-        node
-    }
-    pub fn load_material(
-        dir: &str,
-        object: &obj::Object,
-        mat_set: &Option<mtl::MtlSet>,
-        tex_coords: Vec<f32>,
-        img_obj_url: Option<&HashMap<String, String>>,
-    ) -> Material {
-        let mut material = Material::new_color(1., 1., 1., 1.);
-        if let Some(material_name) = &object.geometry[0].material_name {
-            if let Some(mat_set) = mat_set {
-                for each in &mat_set.materials {
-                    if &each.name == material_name {
-                        let c = each.color_diffuse;
-                        material = Material::new_color(
-                            c.r as f32,
-                            c.g as f32,
-                            c.b as f32,
-                            each.alpha as f32,
-                        );
-                        if let Some(name) = &each.uv_map {
-                            let url = if let Some(urls) = img_obj_url {
-                                if let Some(url) = urls.get(name) {
-                                    Some(url.to_string())
-                                } else {
-                                    log!("The file with name " name.to_string() " was not uploaded. Resorting to color");
-                                    None
-                                }
-                            } else {
-                                if dir != "" {
-                                    Some(format!("{}/{}", dir, name))
-                                } else {
-                                    log!("Invalid texture path! Won't load any texture for " name.to_string() ".");
-                                    None
-                                }
-                            };
-                            if let Some(url) = url {
-                                material = material.tex_type(TextureType::Tex2d).tex_coords(tex_coords).texture(&url);
-                            }
-                        }
-                        if object.geometry[0].shapes[0].smoothing_groups.is_empty() {
-                            material = material.flat();
-                        }
-                        return material;
-                    }
-                }
-            }
-        }
-        material
-    }
-    pub fn load_object_from_obj(
-        &self,
-        dir: &str,
-        object: &obj::Object,
-        mat_set: &Option<mtl::MtlSet>,
-        img_obj_url: Option<&HashMap<String, String>>,
-    ) -> Node {
-        let mut vertices = Vec::new();
-        for vertex in &object.vertices {
-            vertices.push(vertex.x as f32);
-            vertices.push(vertex.y as f32);
-            vertices.push(vertex.z as f32);
-        }
-        let mut indices = Vec::new();
-        let mut normals: Vec<f32> = vec![0.; vertices.len()];
-        let mut tex_coords: Vec<f32> = vec![-1.; vertices.len() / 3 * 2];
-        let push_normals = |normals: &mut Vec<f32>, e: usize, ne: usize| {
-            let current_normal = Vector3::new(normals[e], normals[e + 1], normals[e + 2]);
-            let new_normal = Vector3::new(
-                object.normals[ne].x as f32,
-                object.normals[ne].y as f32,
-                object.normals[ne].z as f32,
-            )
-            .normalize();
-            let avg_normal = if current_normal.magnitude() == 1. {
-                ((new_normal + current_normal) * 0.5).normalize()
-            } else {
-                new_normal
-            };
-            normals[e] = avg_normal.x;
-            normals[e + 1] = avg_normal.y;
-            normals[e + 2] = avg_normal.z;
-        };
-        for shape in &object.geometry[0].shapes {
-            if let obj::Primitive::Triangle(a, b, c) = shape.primitive {
-                if a.1 != None && a.2 != None {
-                    for ((a, ua), na) in [a.0, b.0, c.0]
-                        .iter()
-                        .zip([a.1.unwrap(), b.1.unwrap(), c.1.unwrap()].iter())
-                        .zip([a.2.unwrap(), b.2.unwrap(), c.2.unwrap()].iter())
-                    {
-                        let (e, ue, ne) = (*a * 3, *ua, *na);
-                        push_normals(&mut normals, e, ne);
-                        let u = object.tex_vertices[ue].u as f32;
-                        let v = -object.tex_vertices[ue].v as f32;
-
-                        let current_tex = tex_coords[*a * 2];
-                        let duplicate_v = current_tex != -1. && current_tex != u;
-                        let current_tex = tex_coords[*a * 2 + 1];
-                        let duplicate_u = current_tex != -1. && current_tex != v;
-                        if duplicate_u || duplicate_v {
-                            indices.push((vertices.len() / 3) as u16);
-                            vertices.push(vertices[e]);
-                            vertices.push(vertices[e + 1]);
-                            vertices.push(vertices[e + 2]);
-                            normals.push(normals[e]);
-                            normals.push(normals[e + 1]);
-                            normals.push(normals[e + 2]);
-                            tex_coords.push(u);
-                            tex_coords.push(v);
-                        } else {
-                            indices.push(*a as u16);
-                            tex_coords[*a * 2] = u;
-                            tex_coords[*a * 2 + 1] = v;
-                        }
-                    }
-                } else {
-                    log!("obj file doesn't have normal or uv indices. Only vertices are loaded");
-                }
-            }
-        }
-        let geometry = Geometry {
-            vertices,
-            indices,
-            normals,
-        };
-        let material = Self::load_material(dir, object, mat_set, tex_coords, img_obj_url);
-        node!(
-            &self,
-            Some(Mesh { geometry, material }),
-            object.name.clone()
-        )
-    }
-    pub fn object_from_obj(
-        &self,
-        dir: &str,
-        obj_src: &str,
-        mtl_src: Option<&str>,
-        img_obj_url: Option<&HashMap<String, String>>,
-        wire_overlay: bool,
-    ) -> Node {
-        let obj_set = obj::parse(obj_src).unwrap();
-        assert!(!obj_set.objects.is_empty(), "No objects in the obj file");
-        let mat_set = if let Some(src) = mtl_src {
-            Some(mtl::parse(src).unwrap())
-        } else {
-            None
-        };
-        if wire_overlay {
-            let mut root =
-                self.load_object_from_obj_wired(dir, &obj_set.objects[0], &mat_set, img_obj_url);
-            for object in obj_set.objects.iter().skip(1) {
-                root.add(rc_rcell(self.load_object_from_obj_wired(
-                    dir,
-                    &object,
-                    &mat_set,
-                    img_obj_url,
-                )));
-            }
-            root
-        } else {
-            let mut root =
-                self.load_object_from_obj(dir, &obj_set.objects[0], &mat_set, img_obj_url);
-            for object in obj_set.objects.iter().skip(1) {
-                root.add(rc_rcell(self.load_object_from_obj(
-                    dir,
-                    &object,
-                    &mat_set,
-                    img_obj_url,
-                )));
-            }
-            root
-        }
-    }
     pub fn storage(&self) -> RcRcell<Storage> {
         self.root.borrow().storage()
     }
@@ -514,81 +443,67 @@ impl Scene {
     pub fn find_node_w_name(&self, name: &str) -> Option<RcRcell<Node>> {
         Self::find_node_recursive(self.root(), name)
     }
-    pub fn duplicate_node(&self, node: &Node) -> Node {
-        let transform = node.transform();
-        let info = node.info();
-        let mesh = node.mesh();
-        Self::object(
-            self.storage(),
-            &self.renderer.borrow(),
-            mesh,
-            transform,
+    /// Creates a node that draws the same mesh as the given one, sharing its GPU buffers and
+    /// textures instead of uploading them again. Owned children are instanced along with it.
+    pub fn instance(&self, node: &Node) -> Node {
+        let s = self.storage();
+        let (transform, info) = (node.transform(), node.info());
+        let handle = {
+            let mut storage = s.borrow_mut();
+            let (mesh, vao) = match storage.shared_mesh(node.index()) {
+                Some((mesh, vao)) => (Some(mesh), Some(vao)),
+                None => (None, None),
+            };
+            let handle = storage.add(mesh, vao, transform, info);
+            let instances = storage.instances(node.index()).cloned();
+            storage.set_instances(handle.index(), instances);
+            handle
+        };
+        let instance = Node::new(handle, s);
+        for child in node.owned_children() {
+            instance.own(self.instance(&child));
+        }
+        instance
+    }
+    /// Creates a node that draws the mesh once for each of the instances with a single draw
+    /// call. The mesh has to use the Simple or Color shader.
+    pub fn instanced(&self, mesh: Mesh, info: ObjectInfo, instances: Instances) -> Node {
+        let renderer = self.renderer.borrow();
+        let s = self.storage();
+        let mut storage = s.borrow_mut();
+        let vao = renderer.create_instanced_vao(&mesh, &instances);
+        let handle = storage.add(
+            Some(Rc::new(mesh)),
+            Some(Rc::new(vao)),
+            Default::default(),
             info,
-            false,
-            false,
-        )
+        );
+        storage.set_instances(handle.index(), Some(instances));
+        Node::new(handle, s.clone())
     }
-    fn add_viewport_events(&self) {
-        let window = window();
-        let perf = window.performance().unwrap();
-
+    /// Replaces the instances of an instanced node, uploading them right away. This is cheap
+    /// enough to be called on every frame.
+    pub fn set_instances(&self, node: &Node, instances: Instances) {
         let renderer = self.renderer.borrow();
-        let canvas = renderer.canvas();
-
-        let a_view = self.viewport.clone();
-        add_event(&canvas, "mousemove", move |e| {
-            let me = e.dyn_into::<MouseEvent>().unwrap();
-            let dt = perf.now();
-            a_view
-                .borrow_mut()
-                .update_rot(me.movement_x(), me.movement_y(), dt as f32);
-        });
-
-        let b_view = self.viewport.clone();
-        add_event(&canvas, "wheel", move |e| {
-            let mut view = b_view.borrow_mut();
-            let we = e.dyn_into::<WheelEvent>().unwrap();
-            view.enable_zoom();
-            view.update_zoom(we.delta_y() as i32);
-            view.disable_zoom();
-        });
-
-        if let Some(button) = self.viewport.borrow().button() {
-            let a_view = self.viewport.clone();
-            let a_rndr = self.renderer.clone();
-            add_event(canvas, "mousedown", move |e| {
-                let mut view = a_view.borrow_mut();
-                let renderer = a_rndr.borrow_mut();
-                let me = e.dyn_into::<MouseEvent>().unwrap();
-                if me.button() == button as i16 {
-                    renderer.change_cursor(CursorType::Grab);
-                    view.enable_rotation();
-                }
-                if me.button() == MouseButton::MIDDLE as i16 {
-                    view.enable_zoom();
-                }
-            });
-            let a_view = self.viewport.clone();
-            let a_rndr = self.renderer.clone();
-            add_event(&window, "mouseup", move |e| {
-                let mut view = a_view.borrow_mut();
-                let renderer = a_rndr.borrow_mut();
-                let me = e.dyn_into::<MouseEvent>().unwrap();
-                let pressed_btn = me.button();
-                if (pressed_btn == button as i16) || (pressed_btn == MouseButton::MIDDLE as i16) {
-                    renderer.change_cursor(CursorType::Pointer);
-                    view.disable_rotation();
-                    view.disable_zoom()
-                }
-            });
+        let s = self.storage();
+        let mut storage = s.borrow_mut();
+        let i = storage.index_of(node.handle());
+        assert!(
+            storage.instances(i).is_some(),
+            "Can't set the instances of a node that isn't instanced!"
+        );
+        let shared = storage.is_shared(i);
+        let (mesh, vao) = storage.shared_mesh(i).expect("Instanced node has no mesh!");
+        if shared {
+            // copies of the node keep drawing their own instances
+            let vao = renderer.create_instanced_vao(&mesh, &instances);
+            storage.set_vao(i, Some(Rc::new(vao)));
+        } else {
+            renderer.update_instances(&vao, &mesh, &instances);
         }
-
-        let a_rndr = self.renderer.clone();
-        let a_view = self.viewport.clone();
-        add_event(&window, "resize", move |_| {
-            let mut renderer = a_rndr.borrow_mut();
-            renderer.resize();
-            a_view.borrow_mut().resize(renderer.aspect_ratio());
-        });
+        storage.set_instances(i, Some(instances));
+    }
+    pub fn duplicate_node(&self, node: &Node) -> Node {
+        self.instance(node)
     }
 }
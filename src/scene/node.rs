@@ -1,27 +1,22 @@
 use crate::{
-    mesh::multiply, renderer::ShaderType, Color, Mesh, ObjectInfo, RcRcell, Storage, Transform,
+    mesh::multiply, physics::RigidBody, rc_rcell, scene::Handle, Material, Mesh, ObjectInfo,
+    RcRcell, Storage, Transform,
 };
 use nalgebra::{Isometry3, Point3, UnitQuaternion, Vector3};
+use std::rc::Rc;
 use ncollide3d::{query::Ray, query::RayCast, shape::ConvexHull};
 
-/// An entity in the scene that holds reference to its props in Storage, keeps tracks of
-/// other nodes that are its children either borrowed or owned.
+/// An entity in the scene that holds a handle to its props in Storage. The parent/child
+/// relations, either borrowed or owned, also live in Storage.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Node {
-    index: usize,
+    handle: Handle,
     storage: RcRcell<Storage>,
-    children: Vec<RcRcell<Node>>,
-    owned_children: Vec<Node>,
 }
 
 impl Node {
-    pub fn new(index: usize, storage: RcRcell<Storage>) -> Self {
-        Node {
-            index,
-            storage,
-            children: Vec::new(),
-            owned_children: Vec::new(),
-        }
+    pub fn new(handle: Handle, storage: RcRcell<Storage>) -> Self {
+        Node { handle, storage }
     }
     pub fn position(&self) -> Point3<f32> {
         let transform = self.transform();
@@ -43,136 +38,163 @@ impl Node {
         transform.scale
     }
     pub fn set_position(&self, x: f32, y: f32, z: f32) {
-        let p_transform = {
-            let mut storage = self.storage.borrow_mut();
-            let transform = storage.mut_transform(self.index);
-            transform.isometry.translation.vector = Vector3::new(x, y, z);
-            *transform
-        };
-        self.apply_parent_transform(self.parent_transform() * p_transform);
+        let mut storage = self.storage.borrow_mut();
+        let i = storage.index_of(self.handle);
+        let transform = storage.mut_transform(i);
+        transform.isometry.translation.vector = Vector3::new(x, y, z);
     }
     pub fn copy_location(&self, node: &Node) {
         let v = node.global_position();
         self.set_position(v[0], v[1], v[2]);
     }
     pub fn set_rotation(&self, rot: UnitQuaternion<f32>) {
-        let p_transform = {
-            let mut storage = self.storage.borrow_mut();
-            let mut transform = storage.mut_transform(self.index);
-            transform.isometry.rotation = rot;
-            *transform
-        };
-        self.apply_parent_transform(self.parent_transform() * p_transform);
+        let mut storage = self.storage.borrow_mut();
+        let i = storage.index_of(self.handle);
+        let transform = storage.mut_transform(i);
+        transform.isometry.rotation = rot;
     }
     pub fn rotate_by(&self, rot: UnitQuaternion<f32>) {
-        let p_transform = {
-            let mut storage = self.storage.borrow_mut();
-            let transform = storage.mut_transform(self.index);
-            transform.isometry.append_rotation_wrt_center_mut(&rot);
-            *transform
-        };
-        self.apply_parent_transform(self.parent_transform() * p_transform);
+        let mut storage = self.storage.borrow_mut();
+        let i = storage.index_of(self.handle);
+        let transform = storage.mut_transform(i);
+        transform.isometry.append_rotation_wrt_center_mut(&rot);
     }
     pub fn set_scale(&self, scale: f32) {
         self.set_scale_vec(scale, scale, scale);
     }
     pub fn set_scale_vec(&self, x: f32, y: f32, z: f32) {
-        let p_transform = {
-            let mut storage = self.storage.borrow_mut();
-            let transform = storage.mut_transform(self.index);
-            transform.scale = Vector3::new(x, y, z);
-            *transform
-        };
-        self.apply_parent_transform(self.parent_transform() * p_transform);
-    }
-    pub fn apply_parent_transform(&self, transform: Transform) {
-        let apply_transform = |child: &Node, t: Transform| {
-            child.set_parent_transform(t);
-            let p_t = t * child.transform();
-            child.apply_parent_transform(p_t);
-        };
-        for child in self.children.iter() {
-            apply_transform(&child.borrow(), transform);
-        }
-        for child in self.owned_children.iter() {
-            apply_transform(&child, transform);
-        }
+        let mut storage = self.storage.borrow_mut();
+        let i = storage.index_of(self.handle);
+        let transform = storage.mut_transform(i);
+        transform.scale = Vector3::new(x, y, z);
     }
     pub fn transform(&self) -> Transform {
         let storage = self.storage.borrow();
-        storage.transform(self.index)
+        storage.transform(storage.index_of(self.handle))
     }
     pub fn set_transform(&self, transform: Transform) {
-        let p_transform = {
-            let mut storage = self.storage.borrow_mut();
-            let t = storage.mut_transform(self.index);
-            *t = transform;
-            *t
-        };
-        self.apply_parent_transform(p_transform);
+        let mut storage = self.storage.borrow_mut();
+        let i = storage.index_of(self.handle);
+        *storage.mut_transform(i) = transform;
     }
     pub fn parent_transform(&self) -> Transform {
         let storage = self.storage.borrow();
-        storage.parent_tranform(self.index)
+        storage.parent_tranform(storage.index_of(self.handle))
     }
+    /// Places a node that has no parent relative to an arbitrary transform. This is ignored
+    /// while the node is attached to a parent.
     pub fn set_parent_transform(&self, transform: Transform) {
         let mut storage = self.storage.borrow_mut();
-        let t = storage.mut_parent_transform(self.index);
-        *t = transform;
+        let i = storage.index_of(self.handle);
+        *storage.mut_parent_transform(i) = transform;
     }
     pub fn info(&self) -> ObjectInfo {
         let storage = self.storage.borrow();
-        storage.info(self.index)
+        storage.info(storage.index_of(self.handle))
     }
     pub fn set_info(&self, info: ObjectInfo) {
         let mut storage = self.storage.borrow_mut();
-        *storage.mut_info(self.index) = info;
+        let i = storage.index_of(self.handle);
+        *storage.mut_info(i) = info;
+    }
+    /// The rigid body this node is moved by, with its current velocities.
+    pub fn body(&self) -> Option<RigidBody> {
+        let storage = self.storage.borrow();
+        storage.body(storage.index_of(self.handle)).cloned()
+    }
+    /// Gives the node a rigid body for the PhysicsWorld to move it by, or takes it away.
+    pub fn set_body(&self, body: Option<RigidBody>) {
+        let mut storage = self.storage.borrow_mut();
+        let i = storage.index_of(self.handle);
+        storage.set_body(i, body);
     }
-    pub fn mesh(&self) -> Option<Mesh> {
+    /// The mesh drawn by this node, which may be shared with other instances.
+    pub fn mesh(&self) -> Option<Rc<Mesh>> {
         let storage = self.storage.borrow();
-        storage.mesh(self.index)
+        storage.meshes()[storage.index_of(self.handle)].clone()
     }
-    pub fn set_mesh(&self, mesh: Option<Mesh>) {
+    /// Edits the material of this node's mesh without affecting other instances of it.
+    pub fn update_material(&self, f: impl FnOnce(&mut Material)) {
         let mut storage = self.storage.borrow_mut();
-        let m = storage.mut_mesh(self.index);
-        *m = mesh;
+        let i = storage.index_of(self.handle);
+        if let Some(material) = storage.mut_material(i) {
+            f(material);
+        }
     }
     pub fn index(&self) -> usize {
-        self.index
-    }
-    pub fn add(&mut self, node: RcRcell<Node>) {
-        self.children.push(node);
-        self.children.sort_by_cached_key(|e| e.borrow().info().name);
-        self.apply_parent_transform(self.parent_transform() * self.transform());
-    }
-    pub fn find_child(&self, name: &str) -> Option<usize> {
-        if let Ok(i) = self
-            .children
-            .binary_search_by_key(&String::from(name), |a| a.borrow().info().name)
-        {
-            Some(i)
-        } else {
-            None
+        self.handle.index()
+    }
+    pub fn handle(&self) -> Handle {
+        self.handle
+    }
+    /// Whether this node still exists in Storage, i.e. it hasn't been deleted from the scene.
+    pub fn is_alive(&self) -> bool {
+        self.storage.borrow().is_valid(self.handle)
+    }
+    fn node_at(&self, index: usize) -> Node {
+        let handle = self.storage.borrow().handle_at(index);
+        Node::new(handle, self.storage.clone())
+    }
+    pub fn parent(&self) -> Option<Node> {
+        let parent = {
+            let storage = self.storage.borrow();
+            storage.parent(storage.index_of(self.handle))
+        };
+        parent.map(|p| self.node_at(p))
+    }
+    pub fn add(&self, node: RcRcell<Node>) {
+        let mut storage = self.storage.borrow_mut();
+        let parent = storage.index_of(self.handle);
+        let child = storage.index_of(node.borrow().handle());
+        storage.attach(child, parent, false);
+        storage.sort_children_by_name(parent);
+    }
+    pub fn find_child(&self, name: &str) -> Option<RcRcell<Node>> {
+        self.children()
+            .into_iter()
+            .find(|c| c.borrow().info().name.as_str() == name)
+    }
+    pub fn remove(&self, name: &str) {
+        if let Some(child) = self.find_child(name) {
+            self.remove_child(child.borrow().handle());
         }
     }
-    pub fn remove(&mut self, name: &str) {
-        if let Some(i) = self.find_child(name) {
-            self.children.remove(i);
+    pub fn remove_child(&self, handle: Handle) -> Option<RcRcell<Node>> {
+        let mut storage = self.storage.borrow_mut();
+        let parent = storage.index_of(self.handle);
+        let child = storage.index_of(handle);
+        if storage.parent(child) == Some(parent) {
+            storage.detach(child);
+            Some(rc_rcell(Node::new(handle, self.storage.clone())))
+        } else {
+            None
         }
-        self.apply_parent_transform(Transform::identity());
     }
-    pub fn own(&mut self, node: Node) {
-        self.owned_children.push(node);
-        self.apply_parent_transform(self.parent_transform() * self.transform());
+    pub fn own(&self, node: Node) {
+        let mut storage = self.storage.borrow_mut();
+        let parent = storage.index_of(self.handle);
+        let child = storage.index_of(node.handle());
+        storage.attach(child, parent, true);
     }
     pub fn storage(&self) -> RcRcell<Storage> {
         self.storage.clone()
     }
-    pub fn children(&self) -> &Vec<RcRcell<Node>> {
-        &self.children
-    }
-    pub fn owned_children(&self) -> &Vec<Node> {
-        &self.owned_children
+    pub fn children(&self) -> Vec<RcRcell<Node>> {
+        let children = {
+            let storage = self.storage.borrow();
+            storage.children(storage.index_of(self.handle)).to_vec()
+        };
+        children
+            .into_iter()
+            .map(|c| rc_rcell(self.node_at(c)))
+            .collect()
+    }
+    pub fn owned_children(&self) -> Vec<Node> {
+        let children = {
+            let storage = self.storage.borrow();
+            storage.owned_children(storage.index_of(self.handle)).to_vec()
+        };
+        children.into_iter().map(|c| self.node_at(c)).collect()
     }
     pub fn owned_children_collide_w_ray(&self, ray: &Ray<f32>) -> Option<Isometry3<f32>> {
         for child in self.owned_children() {
@@ -190,7 +212,7 @@ impl Node {
             return Some((node.clone(), t));
         }
         for child in node.borrow().children() {
-            if let Some(result) = Self::collides_w_children_recursive(ray, child.clone()) {
+            if let Some(result) = Self::collides_w_children_recursive(ray, child) {
                 return Some(result);
             }
         }
@@ -201,7 +223,7 @@ impl Node {
     }
     pub fn collides_w_children(&self, ray: &Ray<f32>) -> Option<(RcRcell<Node>, Isometry3<f32>)> {
         for each in self.children() {
-            if let Some(result) = Self::collides_w_children_recursive(&ray, each.clone()) {
+            if let Some(result) = Self::collides_w_children_recursive(ray, each) {
                 return Some(result);
             }
         }
@@ -228,14 +250,15 @@ impl Node {
         None
     }
     pub fn change_color(&self, color: [f32; 3]) {
-        let mut mesh = self.mesh().unwrap();
-        mesh.material = mesh.material.color(color[0], color[1], color[2], 1.);
-        self.set_mesh(Some(mesh));
+        assert!(self.mesh().is_some(), "Can't change the color of a node without a mesh!");
+        self.update_material(|material| {
+            material.color = Some([color[0], color[1], color[2], 1.]);
+        });
     }
     pub fn set_outline(&self, outline_scale: Option<f32>) {
-        if let Some(mut mesh) = self.mesh() {
-            mesh.material.outline = outline_scale;
-            self.set_mesh(Some(mesh));
+        let outlined = self.mesh().map(|m| m.material.outline);
+        if outlined.is_some() && outlined != Some(outline_scale) {
+            self.update_material(|material| material.outline = outline_scale);
         }
         for each in self.owned_children() {
             each.set_outline(outline_scale);
@@ -0,0 +1,285 @@
+use crate::{
+    log, rc_rcell,
+    renderer::DrawMode,
+    scene::{Node, Scene},
+    Geometry, Material, Mesh, ObjectInfo, TextureType,
+};
+use std::collections::HashMap;
+use std::fmt;
+use wavefront_obj::{mtl, obj, ParseError};
+
+/// Errors that can occur while loading a Wavefront OBJ file and its material library.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjError {
+    /// The OBJ file couldn't be parsed.
+    Obj { line: usize, message: String },
+    /// The MTL file couldn't be parsed.
+    Mtl { line: usize, message: String },
+    /// The file doesn't have a single face, line or point to load.
+    Empty,
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjError::Obj { line, message } => write!(f, "obj file, line {}: {}", line, message),
+            ObjError::Mtl { line, message } => write!(f, "mtl file, line {}: {}", line, message),
+            ObjError::Empty => write!(f, "obj file has nothing to load"),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+/// The faces of one geometry group that share a primitive type, turned into an indexed mesh.
+struct Submesh {
+    material_name: Option<String>,
+    draw_mode: DrawMode,
+    geometry: Geometry,
+    tex_coords: Option<Vec<f32>>,
+    flat: bool,
+}
+
+fn corners(primitive: &obj::Primitive) -> Vec<obj::VTNIndex> {
+    match *primitive {
+        obj::Primitive::Point(a) => vec![a],
+        obj::Primitive::Line(a, b) => vec![a, b],
+        obj::Primitive::Triangle(a, b, c) => vec![a, b, c],
+    }
+}
+
+/// Builds a mesh out of the shapes, creating one vertex per distinct position/uv/normal triple.
+/// Normals are computed for the vertices of triangles that don't have one.
+fn submesh(object: &obj::Object, group: &obj::Geometry, shapes: &[&obj::Shape]) -> Submesh {
+    let mut lookup: HashMap<obj::VTNIndex, u32> = HashMap::new();
+    let mut vertices = Vec::new();
+    let mut normals = Vec::new();
+    let mut tex_coords = Vec::new();
+    let mut indices = Vec::new();
+    // whether each vertex came with a normal
+    let mut provided = Vec::new();
+    let mut has_uvs = false;
+    for shape in shapes {
+        for corner in corners(&shape.primitive) {
+            let next = lookup.len() as u32;
+            let index = *lookup.entry(corner).or_insert_with(|| {
+                let (v, t, n) = corner;
+                let v = object.vertices[v];
+                vertices.extend(&[v.x as f32, v.y as f32, v.z as f32]);
+                match n {
+                    Some(n) => {
+                        let n = object.normals[n];
+                        normals.extend(&[n.x as f32, n.y as f32, n.z as f32]);
+                        provided.push(true);
+                    }
+                    None => {
+                        normals.extend(&[0., 0., 0.]);
+                        provided.push(false);
+                    }
+                }
+                match t {
+                    Some(t) => {
+                        has_uvs = true;
+                        let t = object.tex_vertices[t];
+                        tex_coords.extend(&[t.u as f32, 1. - t.v as f32]);
+                    }
+                    None => tex_coords.extend(&[0., 0.]),
+                }
+                next
+            });
+            indices.push(index);
+        }
+    }
+    let draw_mode = match shapes[0].primitive {
+        obj::Primitive::Point(_) => DrawMode::Points,
+        obj::Primitive::Line(..) => DrawMode::Lines,
+        obj::Primitive::Triangle(..) => DrawMode::Triangle,
+    };
+    let mut geometry = Geometry {
+        vertices,
+        indices,
+        normals,
+    };
+    let no_normals = !provided.contains(&true);
+    // lines and points have no faces to take a normal from
+    if draw_mode == DrawMode::Triangle && provided.contains(&false) {
+        let normals = std::mem::take(&mut geometry.normals);
+        geometry.compute_normals();
+        for (i, normal) in normals.chunks(3).enumerate() {
+            if provided[i] {
+                geometry.normals[i * 3..i * 3 + 3].copy_from_slice(normal);
+            }
+        }
+    }
+    Submesh {
+        material_name: group.material_name.clone(),
+        draw_mode,
+        geometry,
+        tex_coords: if has_uvs { Some(tex_coords) } else { None },
+        flat: no_normals && shapes[0].smoothing_groups.is_empty(),
+    }
+}
+
+/// Splits the object into one submesh per geometry group (i.e. per `usemtl`) and primitive type.
+fn submeshes(object: &obj::Object) -> Vec<Submesh> {
+    let mut result = Vec::new();
+    for group in object.geometry.iter() {
+        for kind in 0..3 {
+            let shapes: Vec<&obj::Shape> = group
+                .shapes
+                .iter()
+                .filter(|s| corners(&s.primitive).len() == 3 - kind)
+                .collect();
+            if !shapes.is_empty() {
+                result.push(submesh(object, group, &shapes));
+            }
+        }
+    }
+    result
+}
+
+fn material(
+    dir: &str,
+    material_name: Option<&str>,
+    mat_set: Option<&mtl::MtlSet>,
+    tex_coords: Option<Vec<f32>>,
+    img_obj_url: Option<&HashMap<String, String>>,
+) -> Material {
+    let each = match (material_name, mat_set) {
+        (Some(name), Some(mat_set)) => {
+            match mat_set.materials.iter().find(|m| m.name == name) {
+                Some(each) => each,
+                None => {
+                    log!("Material " name.to_string() " was not found. Resorting to color");
+                    return Material::new_color(1., 1., 1., 1.);
+                }
+            }
+        }
+        _ => return Material::new_color(1., 1., 1., 1.),
+    };
+    let c = each.color_diffuse;
+    let material = Material::new_color(c.r as f32, c.g as f32, c.b as f32, each.alpha as f32);
+    let name = match &each.uv_map {
+        Some(name) => name,
+        None => return material,
+    };
+    let url = if let Some(urls) = img_obj_url {
+        if let Some(url) = urls.get(name) {
+            Some(url.to_string())
+        } else {
+            log!("The file with name " name.to_string() " was not uploaded. Resorting to color");
+            None
+        }
+    } else if !dir.is_empty() {
+        Some(format!("{}/{}", dir, name))
+    } else {
+        log!("Invalid texture path! Won't load any texture for " name.to_string() ".");
+        None
+    };
+    match (url, tex_coords) {
+        (Some(url), Some(tex_coords)) => material
+            .tex_type(TextureType::Tex2d)
+            .tex_coords(tex_coords)
+            .texture(&url),
+        _ => material,
+    }
+}
+
+impl Scene {
+    /// Loads an object from a Wavefront OBJ file, along with its material library if provided.
+    ///
+    /// Every object in the file becomes a node, the first of which holds the others as children.
+    /// Objects that use several materials own one node per material. Textures are looked up in
+    /// `img_obj_url` by their name in the MTL file, or loaded relative to `dir` otherwise.
+    ///
+    /// With `wire_overlay`, triangles are split into unique vertices so that they can be drawn
+    /// with a wireframe overlay.
+    pub fn object_from_obj(
+        &self,
+        dir: &str,
+        obj_src: &str,
+        mtl_src: Option<&str>,
+        img_obj_url: Option<&HashMap<String, String>>,
+        wire_overlay: bool,
+    ) -> Result<Node, ObjError> {
+        let obj_set = obj::parse(obj_src).map_err(|e: ParseError| ObjError::Obj {
+            line: e.line_number,
+            message: e.message,
+        })?;
+        let mat_set = match mtl_src {
+            Some(src) => Some(mtl::parse(src).map_err(|e: ParseError| ObjError::Mtl {
+                line: e.line_number,
+                message: e.message,
+            })?),
+            None => None,
+        };
+        let mut nodes = obj_set.objects.iter().filter_map(|object| {
+            self.load_obj_object(dir, object, mat_set.as_ref(), img_obj_url, wire_overlay)
+        });
+        let root = nodes.next().ok_or(ObjError::Empty)?;
+        for node in nodes {
+            root.add(rc_rcell(node));
+        }
+        Ok(root)
+    }
+    fn load_obj_object(
+        &self,
+        dir: &str,
+        object: &obj::Object,
+        mat_set: Option<&mtl::MtlSet>,
+        img_obj_url: Option<&HashMap<String, String>>,
+        wire_overlay: bool,
+    ) -> Option<Node> {
+        let submeshes = submeshes(object);
+        let single = submeshes.len() == 1;
+        let mut nodes: Vec<Node> = submeshes
+            .into_iter()
+            .enumerate()
+            .map(|(i, submesh)| {
+                let name = if single {
+                    object.name.clone()
+                } else {
+                    let suffix = submesh.material_name.clone();
+                    format!("{} {}", object.name, suffix.unwrap_or_else(|| i.to_string()))
+                };
+                let material_name = submesh.material_name.as_deref();
+                let mut material =
+                    material(dir, material_name, mat_set, submesh.tex_coords, img_obj_url);
+                if submesh.flat {
+                    material = material.flat();
+                }
+                let unroll = wire_overlay && submesh.draw_mode == DrawMode::Triangle;
+                let info = ObjectInfo {
+                    name,
+                    draw_mode: if unroll {
+                        DrawMode::Arrays
+                    } else {
+                        submesh.draw_mode
+                    },
+                    ..Default::default()
+                };
+                let mesh = Mesh::new(submesh.geometry, material);
+                Self::object(
+                    self.storage(),
+                    &self.renderer.borrow(),
+                    Some(mesh),
+                    Default::default(),
+                    info,
+                    false,
+                    unroll,
+                )
+            })
+            .collect();
+        if single {
+            nodes.pop()
+        } else if nodes.is_empty() {
+            None
+        } else {
+            let node = self.empty(&object.name);
+            for each in nodes {
+                node.own(each);
+            }
+            Some(node)
+        }
+    }
+}
@@ -53,7 +53,7 @@ pub fn create_arrow(
     has_stem: bool,
     depthless: bool,
 ) -> Node {
-    let mut node = node!(scene, None, String::from(name));
+    let node = node!(scene, None, String::from(name));
     if has_stem {
         let stem = node!(
             scene,
@@ -134,7 +134,7 @@ pub fn create_light_node(scene: &Scene, light_type: LightType, color: [f32; 3])
             DrawMode::Arrays
         ),
         LightType::Directional => {
-            let mut n = node!(scene, None, light_type.to_string());
+            let n = node!(scene, None, light_type.to_string());
             let cube = node!(
                 scene,
                 Some(Mesh::new(
@@ -183,7 +183,7 @@ pub fn create_transform_gizmo(scene: &Scene, arrow_type: ArrowTip) -> Node {
     let x = create_arrow(scene, [0.8, 0., 0., 1.], arrow_type, "XAxis", true, true);
     let y = create_arrow(scene, [0., 0.8, 0., 1.], arrow_type, "YAxis", true, true);
     let z = create_arrow(scene, [0., 0., 0.8, 1.], arrow_type, "ZAxis", true, true);
-    let mut node = node!(
+    let node = node!(
         scene,
         Some(Mesh::new(
             Geometry::from_genmesh(&IcoSphere::subdivide(2)),
@@ -282,7 +282,7 @@ pub fn create_origin(scene: &Scene) -> Node {
     x.set_scale(0.5);
     y.set_scale(0.5);
     z.set_scale(0.5);
-    let mut center = node!(
+    let center = node!(
         scene,
         Some(Mesh::new(
             Geometry::from_genmesh_no_normals(&IcoSphere::subdivide(2)),
@@ -0,0 +1,215 @@
+//! Raycasting against the triangles of meshes. Each mesh's triangles are put in a TriMesh, in
+//! the space of the mesh, the first time a ray is cast at it, and rays are brought into that
+//! space instead of the triangles into the world. Only the meshes whose bounds the ray meets in
+//! the spatial index are tested.
+
+use crate::{
+    renderer::{DrawMode, ShaderType},
+    Handle, Mesh, Node, Scene, Storage,
+};
+use nalgebra::{Isometry3, Point3, Vector3};
+use ncollide3d::{
+    query::{Ray, RayCast},
+    shape::{FeatureId, TriMesh},
+};
+use std::{collections::HashMap, fmt, rc::Rc};
+
+/// Where a ray meets the mesh of a node.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RaycastHit {
+    pub node: Handle,
+    /// The instance that was hit, 0 for nodes that aren't instanced.
+    pub instance: usize,
+    /// How far along the ray the hit is, in world units.
+    pub distance: f32,
+    /// The hit point, in world space.
+    pub point: Point3<f32>,
+    /// The normal of the triangle that was hit, in world space.
+    pub normal: Vector3<f32>,
+    /// The index of the triangle that was hit, among the triangles of the mesh.
+    pub triangle: usize,
+}
+
+/// The triangles of a mesh that rays are tested against, in the space of the mesh. Slots that
+/// share a mesh share its collider.
+#[derive(Clone)]
+pub struct MeshCollider {
+    trimesh: Rc<TriMesh<f32>>,
+    draw_mode: DrawMode,
+}
+
+impl fmt::Debug for MeshCollider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MeshCollider")
+            .field("triangles", &self.trimesh.faces().len())
+            .field("draw_mode", &self.draw_mode)
+            .finish()
+    }
+}
+
+impl PartialEq for MeshCollider {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.trimesh, &other.trimesh)
+    }
+}
+
+impl MeshCollider {
+    /// The collider of a mesh drawn as triangles. Meshes drawn as lines or points, and meshes
+    /// without triangles, have none.
+    pub fn new(mesh: &Mesh, draw_mode: DrawMode) -> Option<Self> {
+        let geometry = &mesh.geometry;
+        let vertices = geometry.vertices.len() / 3;
+        let triangles: Vec<Point3<usize>> = match draw_mode {
+            // meshes with unique vertices keep their indices, but draw the vertices in order
+            DrawMode::Arrays => (0..vertices / 3)
+                .map(|t| Point3::new(3 * t, 3 * t + 1, 3 * t + 2))
+                .collect(),
+            DrawMode::Triangle => geometry
+                .indices
+                .chunks_exact(3)
+                .map(|t| Point3::new(t[0] as usize, t[1] as usize, t[2] as usize))
+                .collect(),
+            DrawMode::Lines | DrawMode::Points => return None,
+        };
+        if triangles.is_empty() || triangles.iter().any(|t| t.iter().any(|v| *v >= vertices)) {
+            return None;
+        }
+        let points = geometry
+            .vertices
+            .chunks_exact(3)
+            .map(|v| Point3::new(v[0], v[1], v[2]))
+            .collect();
+        Some(Self {
+            trimesh: Rc::new(TriMesh::new(points, triangles, None)),
+            draw_mode,
+        })
+    }
+    /// Where a ray given in the space of the mesh meets it first: how far along the ray, the
+    /// normal and the triangle.
+    fn cast(&self, ray: &Ray<f32>) -> Option<(f32, Vector3<f32>, usize)> {
+        let hit = self
+            .trimesh
+            .toi_and_normal_with_ray(&Isometry3::identity(), ray, false)?;
+        let count = self.trimesh.faces().len();
+        match hit.feature {
+            // triangles hit from behind are counted after the ones hit from the front
+            FeatureId::Face(face) => Some((hit.toi, hit.normal, face % count)),
+            _ => None,
+        }
+    }
+}
+
+impl Scene {
+    /// Casts a ray at the meshes of the nodes that `filter` accepts, and returns where it meets
+    /// each of them, nearest first. A node is hit once at most, at its nearest triangle, and an
+    /// instanced node once per instance. Skyboxes and meshes drawn as lines or points are never
+    /// hit; hidden nodes are, unless the filter leaves them out.
+    pub fn raycast(
+        &self,
+        ray: &Ray<f32>,
+        mut filter: impl FnMut(&Node) -> bool,
+    ) -> Vec<RaycastHit> {
+        let storage = self.storage();
+        {
+            let mut storage = storage.borrow_mut();
+            storage.update_world_transforms();
+            storage.update_spatial_index();
+        }
+        let handles: Vec<Handle> = {
+            let storage = storage.borrow();
+            let mut nearby = storage.spatial_index().query_ray(ray);
+            nearby.sort_unstable();
+            nearby
+                .into_iter()
+                .filter(|i| {
+                    storage.is_alive(*i)
+                        && storage
+                            .mesh(*i)
+                            .is_some_and(|m| m.material.shader_type != ShaderType::CubeMap)
+                })
+                .map(|i| storage.handle_at(i))
+                .collect()
+        };
+        let candidates: Vec<usize> = handles
+            .into_iter()
+            .filter(|handle| filter(&Node::new(*handle, storage.clone())))
+            .map(|handle| handle.index())
+            .collect();
+        build_colliders(&mut storage.borrow_mut(), &candidates);
+        let storage = storage.borrow();
+        let mut hits = Vec::new();
+        for i in candidates {
+            let collider = match storage.collider(i) {
+                Some(collider) => collider,
+                None => continue,
+            };
+            let world = storage.world_transform(i).to_homogeneous();
+            let instances = match storage.instances(i) {
+                Some(instances) => instances
+                    .transforms
+                    .iter()
+                    .map(|t| world * t.to_homogeneous())
+                    .collect(),
+                None => vec![world],
+            };
+            for (instance, transform) in instances.iter().enumerate() {
+                let inverse = match transform.try_inverse() {
+                    Some(inverse) => inverse,
+                    None => continue,
+                };
+                // the ray keeps its parametrization in the space of the mesh, so how far
+                // along it a hit is holds in both spaces
+                let local = Ray::new(
+                    inverse.transform_point(&ray.origin),
+                    inverse.transform_vector(&ray.dir),
+                );
+                if let Some((toi, normal, triangle)) = collider.cast(&local) {
+                    let normal = inverse.transpose().transform_vector(&normal);
+                    hits.push(RaycastHit {
+                        node: storage.handle_at(i),
+                        instance,
+                        distance: toi * ray.dir.norm(),
+                        point: ray.point_at(toi),
+                        normal: normal.normalize(),
+                        triangle,
+                    });
+                }
+            }
+        }
+        hits.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap());
+        hits
+    }
+}
+
+/// Puts the colliders of the slots' meshes in Storage, unless they're there already. Slots that
+/// share a mesh and draw mode share its collider, which is only built once.
+fn build_colliders(storage: &mut Storage, slots: &[usize]) {
+    if slots.iter().all(|i| storage.collider(*i).is_some()) {
+        return;
+    }
+    let mut built: HashMap<(*const Mesh, DrawMode), Option<MeshCollider>> = storage
+        .meshes()
+        .iter()
+        .enumerate()
+        .filter_map(|(j, mesh)| {
+            let collider = storage.collider(j)?;
+            let key = (Rc::as_ptr(mesh.as_ref()?), collider.draw_mode);
+            Some((key, Some(collider.clone())))
+        })
+        .collect();
+    for i in slots.iter().copied() {
+        if storage.collider(i).is_some() {
+            continue;
+        }
+        let mesh = match storage.meshes()[i].clone() {
+            Some(mesh) => mesh,
+            None => continue,
+        };
+        let draw_mode = storage.info(i).draw_mode;
+        let collider = built
+            .entry((Rc::as_ptr(&mesh), draw_mode))
+            .or_insert_with(|| MeshCollider::new(&mesh, draw_mode))
+            .clone();
+        storage.set_collider(i, collider);
+    }
+}
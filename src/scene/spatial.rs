@@ -0,0 +1,207 @@
+//! A spatial index over the world bounds of the meshes in Storage, so that rays, views and
+//! volumes only look at the meshes near them. It's a dynamic bounding volume tree whose leaves
+//! hold boxes a little larger than the meshes, so a mesh that moves a bit stays in its leaf and
+//! the tree is only touched when it leaves it.
+
+use crate::{mesh::Bounds, renderer::Frustum};
+use nalgebra::Isometry3;
+use ncollide3d::{
+    bounding_volume::{BoundingSphere, BoundingVolume, AABB},
+    partitioning::{DBVTLeaf, DBVTLeafId, VisitStatus, Visitor, BVH, DBVT},
+    query::{Ray, RayCast},
+};
+use std::{collections::BTreeSet, fmt};
+
+/// Where a slot is in the tree.
+#[derive(Clone)]
+struct Entry {
+    leaf: DBVTLeafId,
+    /// The box the leaf holds, loosened around the bounds.
+    fat: AABB<f32>,
+    /// The bounds of the slot's mesh in world space.
+    bounds: Bounds,
+}
+
+/// The world bounds of every mesh, kept in sync with Storage by `update_spatial_index`.
+/// Queries return slot indices, in no particular order.
+#[derive(Clone)]
+pub struct SpatialIndex {
+    tree: DBVT<f32, usize, AABB<f32>>,
+    entries: Vec<Option<Entry>>,
+    /// The slots whose world transform or mesh changed since the last update.
+    dirty: BTreeSet<usize>,
+}
+
+impl Default for SpatialIndex {
+    fn default() -> Self {
+        Self {
+            tree: DBVT::new(),
+            entries: Vec::new(),
+            dirty: BTreeSet::new(),
+        }
+    }
+}
+
+impl fmt::Debug for SpatialIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SpatialIndex")
+            .field("len", &self.len())
+            .field("dirty", &self.dirty)
+            .finish()
+    }
+}
+
+impl PartialEq for SpatialIndex {
+    /// Indices that hold the same bounds are equal, however their trees are balanced.
+    fn eq(&self, other: &Self) -> bool {
+        let bounds = |index: &Self| -> Vec<Option<Bounds>> {
+            index
+                .entries
+                .iter()
+                .map(|e| e.as_ref().map(|e| e.bounds.clone()))
+                .collect()
+        };
+        bounds(self) == bounds(other) && self.dirty == other.dirty
+    }
+}
+
+impl SpatialIndex {
+    /// How many meshes are in the index.
+    pub fn len(&self) -> usize {
+        self.entries.iter().filter(|e| e.is_some()).count()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+    /// Whether the slot's mesh is in the index. Meshes without vertices never are.
+    pub fn contains(&self, indx: usize) -> bool {
+        self.entry(indx).is_some()
+    }
+    /// The world bounds of the slot's mesh, as of the last update.
+    pub fn bounds(&self, indx: usize) -> Option<&Bounds> {
+        self.entry(indx).map(|e| &e.bounds)
+    }
+    /// The slots whose bounds the ray meets.
+    pub fn query_ray(&self, ray: &Ray<f32>) -> Vec<usize> {
+        let identity = Isometry3::identity();
+        self.query(
+            |aabb| aabb.intersects_ray(&identity, ray),
+            |bounds| bounds.aabb.intersects_ray(&identity, ray),
+        )
+    }
+    /// The slots whose bounds may be in view of the frustum.
+    pub fn query_frustum(&self, frustum: &Frustum) -> Vec<usize> {
+        self.query(
+            |aabb| frustum.intersects_aabb(aabb),
+            |bounds| frustum.intersects(bounds),
+        )
+    }
+    /// The slots whose bounds overlap the sphere.
+    pub fn query_sphere(&self, sphere: &BoundingSphere<f32>) -> Vec<usize> {
+        let overlaps = |aabb: &AABB<f32>| {
+            let center = sphere.center();
+            let closest = center.coords.zip_zip_map(
+                &aabb.mins().coords,
+                &aabb.maxs().coords,
+                |c, min, max| c.max(min).min(max),
+            );
+            (closest - center.coords).norm() <= sphere.radius()
+        };
+        self.query(overlaps, |bounds| {
+            overlaps(&bounds.aabb) && bounds.sphere.intersects(sphere)
+        })
+    }
+    /// The slots whose bounds overlap the box.
+    pub fn query_aabb(&self, aabb: &AABB<f32>) -> Vec<usize> {
+        self.query(
+            |other| other.intersects(aabb),
+            |bounds| bounds.aabb.intersects(aabb),
+        )
+    }
+    /// Walks down the branches of the tree whose boxes `overlaps` accepts, and collects the
+    /// slots whose own bounds `hits` accepts.
+    fn query(
+        &self,
+        overlaps: impl Fn(&AABB<f32>) -> bool,
+        hits: impl Fn(&Bounds) -> bool,
+    ) -> Vec<usize> {
+        let mut visitor = Collector {
+            overlaps,
+            hits,
+            entries: &self.entries,
+            found: Vec::new(),
+        };
+        self.tree.visit(&mut visitor);
+        visitor.found
+    }
+    fn entry(&self, indx: usize) -> Option<&Entry> {
+        self.entries.get(indx).and_then(|e| e.as_ref())
+    }
+    /// Queues the slot for the next update.
+    pub(super) fn mark(&mut self, indx: usize) {
+        self.dirty.insert(indx);
+    }
+    /// The slots queued since the last update.
+    pub(super) fn take_dirty(&mut self) -> BTreeSet<usize> {
+        std::mem::take(&mut self.dirty)
+    }
+    /// Puts the slot's new world bounds in the index, or takes it out for None. The tree is only
+    /// changed when the bounds outgrow the leaf.
+    pub(super) fn update(&mut self, indx: usize, bounds: Option<Bounds>) {
+        if self.entries.len() <= indx {
+            self.entries.resize(indx + 1, None);
+        }
+        let bounds = match bounds {
+            Some(bounds) => bounds,
+            None => return self.remove(indx),
+        };
+        if let Some(entry) = self.entries[indx].as_mut() {
+            if entry.fat.contains(&bounds.aabb) {
+                entry.bounds = bounds;
+                return;
+            }
+            self.tree.remove(entry.leaf);
+        }
+        // a tenth of the size of the box on every side
+        let fat = bounds
+            .aabb
+            .loosened(bounds.aabb.half_extents().amax() * 0.2);
+        let leaf = self.tree.insert(DBVTLeaf::new(fat.clone(), indx));
+        self.entries[indx] = Some(Entry { leaf, fat, bounds });
+    }
+    /// Takes the slot out of the index.
+    pub(super) fn remove(&mut self, indx: usize) {
+        if let Some(entry) = self.entries.get_mut(indx).and_then(Option::take) {
+            self.tree.remove(entry.leaf);
+        }
+        self.dirty.remove(&indx);
+    }
+}
+
+struct Collector<'a, O, H> {
+    overlaps: O,
+    hits: H,
+    entries: &'a [Option<Entry>],
+    found: Vec<usize>,
+}
+
+impl<'a, O, H> Visitor<usize, AABB<f32>> for Collector<'a, O, H>
+where
+    O: Fn(&AABB<f32>) -> bool,
+    H: Fn(&Bounds) -> bool,
+{
+    fn visit(&mut self, aabb: &AABB<f32>, indx: Option<&usize>) -> VisitStatus {
+        if !(self.overlaps)(aabb) {
+            return VisitStatus::Stop;
+        }
+        if let Some(indx) = indx {
+            let entry = self.entries[*indx]
+                .as_ref()
+                .expect("Leaf without an entry!");
+            if (self.hits)(&entry.bounds) {
+                self.found.push(*indx);
+            }
+        }
+        VisitStatus::Continue
+    }
+}
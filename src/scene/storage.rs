@@ -1,18 +1,79 @@
-use crate::{scene::LightInfo, Mesh, ObjectInfo, Transform};
-use std::rc::Rc;
-use web_sys::{WebGlTexture, WebGlVertexArrayObject};
+use crate::{
+    renderer::{TextureId, VaoId, VertexArray},
+    scene::{Instances, LightInfo, MeshCollider, SpatialIndex},
+    mesh::Bounds,
+    physics::RigidBody,
+    Material, Mesh, ObjectInfo, Transform,
+};
+use std::{
+    collections::{BTreeSet, HashMap},
+    rc::Rc,
+};
+
+/// A generational reference to a slot in Storage.
+///
+/// Slots are recycled once their node is deleted, and each reuse bumps the slot's generation so
+/// that a handle to the deleted node no longer matches.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Handle {
+    index: usize,
+    generation: u32,
+}
+
+impl Handle {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+/// Everything that a deleted slot held and that may still need to be released on the GPU. The
+/// mesh and vao are only this slot's share of them, other instances may still be drawing them.
+#[derive(Debug)]
+pub struct RemovedSlot {
+    pub mesh: Option<Rc<Mesh>>,
+    pub vao: Option<Rc<VertexArray>>,
+    pub info: ObjectInfo,
+}
 
 /// The main data structure that holds almost everything: object info, meshes, transforms, vaos,
-/// etc.
+/// the parent/child hierarchy, etc.
+///
+/// Meshes and their vaos are reference counted so that any number of slots can draw the same
+/// GPU buffers. A shared mesh is copied the first time one of its slots edits it.
+///
+/// Local transforms are written directly and only flag their slot as dirty. World transforms are
+/// cached and refreshed for the dirty subtrees alone in a single pass by
+/// `update_world_transforms`. The world bounds of the meshes are kept in a spatial index that
+/// `update_spatial_index` refreshes the same way.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Storage {
     info: Vec<ObjectInfo>,
-    meshes: Vec<Option<Mesh>>,
+    meshes: Vec<Option<Rc<Mesh>>>,
     transforms: Vec<Transform>,
     parent_transforms: Vec<Transform>,
-    vaos: Vec<Option<WebGlVertexArrayObject>>,
-    textures: Vec<Rc<WebGlTexture>>,
-    lights: Vec<LightInfo>,
+    world_transforms: Vec<Transform>,
+    /// The slots whose local transform or parent changed since the last pass.
+    dirty: BTreeSet<usize>,
+    parents: Vec<Option<usize>>,
+    children: Vec<Vec<usize>>,
+    owned_children: Vec<Vec<usize>>,
+    vaos: Vec<Option<Rc<VertexArray>>>,
+    instances: Vec<Option<Instances>>,
+    generations: Vec<u32>,
+    alive: Vec<bool>,
+    free_slots: Vec<usize>,
+    textures: Vec<Option<TextureId>>,
+    free_textures: Vec<usize>,
+    lights: Vec<Option<LightInfo>>,
+    free_lights: Vec<usize>,
+    colliders: Vec<Option<MeshCollider>>,
+    /// The bounds of each slot's mesh, in the space of the mesh.
+    bounds: Vec<Option<Bounds>>,
+    spatial: SpatialIndex,
+    bodies: Vec<Option<RigidBody>>,
 }
 
 impl Default for Storage {
@@ -22,40 +83,264 @@ impl Default for Storage {
             meshes: Vec::new(),
             transforms: Vec::new(),
             parent_transforms: Vec::new(),
+            world_transforms: Vec::new(),
+            dirty: BTreeSet::new(),
+            parents: Vec::new(),
+            children: Vec::new(),
+            owned_children: Vec::new(),
             vaos: Vec::new(),
+            instances: Vec::new(),
+            generations: Vec::new(),
+            alive: Vec::new(),
+            free_slots: Vec::new(),
             textures: Vec::new(),
+            free_textures: Vec::new(),
             lights: Vec::new(),
+            free_lights: Vec::new(),
+            colliders: Vec::new(),
+            bounds: Vec::new(),
+            spatial: Default::default(),
+            bodies: Vec::new(),
         }
     }
 }
 
 impl Storage {
     pub fn add_light(&mut self, light: LightInfo) -> usize {
-        let index = self.lights.len();
-        self.lights.push(light);
-        index
+        if let Some(index) = self.free_lights.pop() {
+            self.lights[index] = Some(light);
+            index
+        } else {
+            let index = self.lights.len();
+            self.lights.push(Some(light));
+            index
+        }
+    }
+    pub fn remove_light(&mut self, indx: usize) -> Option<LightInfo> {
+        let light = self.lights.get_mut(indx).and_then(|light| light.take());
+        if light.is_some() {
+            self.free_lights.push(indx);
+        }
+        light
+    }
+    pub fn add_texture(&mut self, texture: TextureId) -> usize {
+        if let Some(index) = self.free_textures.pop() {
+            self.textures[index] = Some(texture);
+            index
+        } else {
+            let index = self.textures.len();
+            self.textures.push(Some(texture));
+            index
+        }
     }
-    pub fn add_texture(&mut self, texture: Rc<WebGlTexture>) -> usize {
-        let index = self.textures.len();
-        self.textures.push(texture);
-        index
+    /// Frees the texture slot unless a mesh that is still alive refers to it.
+    pub fn remove_texture(&mut self, indx: usize) -> Option<TextureId> {
+        let in_use = self
+            .meshes
+            .iter()
+            .flatten()
+            .any(|mesh| mesh.material.textures().any(|i| i == indx));
+        if in_use {
+            return None;
+        }
+        let texture = self.textures.get_mut(indx).and_then(|t| t.take());
+        if texture.is_some() {
+            self.free_textures.push(indx);
+        }
+        texture
     }
     pub fn add(
         &mut self,
-        mesh: Option<Mesh>,
-        vao: Option<WebGlVertexArrayObject>,
+        mesh: Option<Rc<Mesh>>,
+        vao: Option<Rc<VertexArray>>,
         transform: Transform,
         info: ObjectInfo,
-    ) -> usize {
-        let index = self.meshes.len();
-        self.meshes.push(mesh);
-        self.transforms.push(transform);
-        self.parent_transforms.push(Default::default());
-        self.vaos.push(vao);
-        self.info.push(info);
-        index
+    ) -> Handle {
+        if let Some(index) = self.free_slots.pop() {
+            self.meshes[index] = mesh;
+            self.transforms[index] = transform;
+            self.parent_transforms[index] = Default::default();
+            self.world_transforms[index] = transform;
+            self.dirty.insert(index);
+            self.parents[index] = None;
+            self.children[index].clear();
+            self.owned_children[index].clear();
+            self.vaos[index] = vao;
+            self.colliders[index] = None;
+            self.bounds[index] = None;
+            self.bodies[index] = None;
+            self.instances[index] = None;
+            self.info[index] = info;
+            self.alive[index] = true;
+            Handle {
+                index,
+                generation: self.generations[index],
+            }
+        } else {
+            let index = self.meshes.len();
+            self.meshes.push(mesh);
+            self.transforms.push(transform);
+            self.parent_transforms.push(Default::default());
+            self.world_transforms.push(transform);
+            self.dirty.insert(index);
+            self.parents.push(None);
+            self.children.push(Vec::new());
+            self.owned_children.push(Vec::new());
+            self.vaos.push(vao);
+            self.colliders.push(None);
+            self.bounds.push(None);
+            self.bodies.push(None);
+            self.instances.push(None);
+            self.info.push(info);
+            self.generations.push(0);
+            self.alive.push(true);
+            Handle {
+                index,
+                generation: 0,
+            }
+        }
+    }
+    /// Empties the slot pointed to by the handle and queues it for reuse. The slot is unlinked
+    /// from its parent, and any children left in it become roots.
+    ///
+    /// Returns None if the handle is stale.
+    pub fn remove(&mut self, handle: Handle) -> Option<RemovedSlot> {
+        if !self.is_valid(handle) {
+            return None;
+        }
+        let index = handle.index;
+        self.detach(index);
+        let children: Vec<usize> = self.children[index]
+            .drain(..)
+            .chain(self.owned_children[index].drain(..))
+            .collect();
+        for child in children {
+            self.parents[child] = None;
+            self.dirty.insert(child);
+        }
+        self.alive[index] = false;
+        self.generations[index] = self.generations[index].wrapping_add(1);
+        self.free_slots.push(index);
+        self.transforms[index] = Default::default();
+        self.parent_transforms[index] = Default::default();
+        self.instances[index] = None;
+        self.colliders[index] = None;
+        self.bounds[index] = None;
+        self.spatial.remove(index);
+        self.bodies[index] = None;
+        Some(RemovedSlot {
+            mesh: self.meshes[index].take(),
+            vao: self.vaos[index].take(),
+            info: std::mem::take(&mut self.info[index]),
+        })
+    }
+    /// Whether the handle still points to the node it was created for.
+    pub fn is_valid(&self, handle: Handle) -> bool {
+        self.alive.get(handle.index) == Some(&true)
+            && self.generations[handle.index] == handle.generation
+    }
+    pub fn is_alive(&self, indx: usize) -> bool {
+        self.alive.get(indx) == Some(&true)
+    }
+    /// The handle of the node that currently occupies the slot.
+    pub fn handle_at(&self, indx: usize) -> Handle {
+        assert!(self.is_alive(indx), "No node at slot {}", indx);
+        Handle {
+            index: indx,
+            generation: self.generations[indx],
+        }
+    }
+    /// Resolves a handle to its slot index, panicking if the node was deleted.
+    pub fn index_of(&self, handle: Handle) -> usize {
+        assert!(self.is_valid(handle), "Stale node handle: {:?}", handle);
+        handle.index
+    }
+    /// Makes child a child of parent, detaching it from its previous parent first. Owned children
+    /// are parts of their parent (e.g. an arrow's head and stem) rather than separate objects.
+    ///
+    /// Panics if parent is the child itself or one of its descendants, which would make a cycle.
+    pub fn attach(&mut self, child: usize, parent: usize, owned: bool) {
+        assert!(child != parent, "Can't make a node its own child!");
+        let mut ancestor = self.parents[parent];
+        while let Some(a) = ancestor {
+            assert!(a != child, "Can't make a node the child of its own descendant!");
+            ancestor = self.parents[a];
+        }
+        self.detach(child);
+        if owned {
+            self.owned_children[parent].push(child);
+        } else {
+            self.children[parent].push(child);
+        }
+        self.parents[child] = Some(parent);
+        self.dirty.insert(child);
+    }
+    /// Unlinks the node from its parent, leaving it as a root.
+    pub fn detach(&mut self, child: usize) {
+        if let Some(parent) = self.parents[child].take() {
+            self.children[parent].retain(|c| *c != child);
+            self.owned_children[parent].retain(|c| *c != child);
+            self.dirty.insert(child);
+        }
+    }
+    pub fn sort_children_by_name(&mut self, indx: usize) {
+        let info = &self.info;
+        self.children[indx].sort_by_cached_key(|c| info[*c].name.clone());
+    }
+    pub fn parent(&self, indx: usize) -> Option<usize> {
+        *self.parents.get(indx).expect("No such node found!")
+    }
+    pub fn children(&self, indx: usize) -> &[usize] {
+        self.children.get(indx).expect("No such node found!")
+    }
+    pub fn owned_children(&self, indx: usize) -> &[usize] {
+        self.owned_children.get(indx).expect("No such node found!")
+    }
+    /// Recomputes the cached world transform of every node whose local transform, or that of one
+    /// of its ancestors, changed since the last pass. The walk starts from the topmost dirty
+    /// nodes, so clean subtrees are left untouched.
+    pub fn update_world_transforms(&mut self) {
+        let dirty = std::mem::take(&mut self.dirty);
+        let is_topmost = |i: usize| {
+            let mut ancestor = self.parents[i];
+            while let Some(a) = ancestor {
+                if dirty.contains(&a) {
+                    return false;
+                }
+                ancestor = self.parents[a];
+            }
+            true
+        };
+        let mut stack: Vec<usize> = dirty
+            .iter()
+            .copied()
+            .filter(|i| self.alive[*i] && is_topmost(*i))
+            .collect();
+        while let Some(i) = stack.pop() {
+            let parent = match self.parents[i] {
+                Some(p) => self.world_transforms[p],
+                None => self.parent_transforms[i],
+            };
+            self.world_transforms[i] = parent * self.transforms[i];
+            self.spatial.mark(i);
+            stack.extend(self.children[i].iter().chain(self.owned_children[i].iter()));
+        }
+    }
+    /// The world transform cached by the last
+    /// `update_world_transforms` pass.
+    pub fn world_transform(&self, indx: usize) -> Transform {
+        *self
+            .world_transforms
+            .get(indx)
+            .expect("No such transform found!")
+    }
+    /// The up-to-date world transform, computed by walking up the hierarchy without touching the
+    /// cache.
+    pub fn global_transform(&self, indx: usize) -> Transform {
+        self.parent_tranform(indx) * self.transform(indx)
     }
     pub fn mut_transform(&mut self, indx: usize) -> &mut Transform {
+        self.dirty.insert(indx);
         self.transforms
             .get_mut(indx)
             .expect("No such transform found!")
@@ -63,45 +348,222 @@ impl Storage {
     pub fn transform(&self, indx: usize) -> Transform {
         *self.transforms.get(indx).expect("No such transform found!")
     }
+    /// The world transform of the node's parent. Nodes without a parent use the transform set via
+    /// `mut_parent_transform`, which defaults to identity.
     pub fn parent_tranform(&self, indx: usize) -> Transform {
-        *self
-            .parent_transforms
-            .get(indx)
-            .expect("No such transform found!")
+        match self.parent(indx) {
+            Some(parent) => self.global_transform(parent),
+            None => *self
+                .parent_transforms
+                .get(indx)
+                .expect("No such transform found!"),
+        }
     }
     pub fn mut_parent_transform(&mut self, indx: usize) -> &mut Transform {
+        self.dirty.insert(indx);
         self.parent_transforms
             .get_mut(indx)
             .expect("No such transform found!")
     }
-    pub fn mesh(&self, indx: usize) -> Option<Mesh> {
-        self.meshes.get(indx).expect("No such mesh found!").clone()
+    pub fn mesh(&self, indx: usize) -> Option<&Mesh> {
+        self.meshes
+            .get(indx)
+            .expect("No such mesh found!")
+            .as_deref()
+    }
+    /// The mesh of the slot along with its vao, to be shared with another slot.
+    pub fn shared_mesh(&self, indx: usize) -> Option<(Rc<Mesh>, Rc<VertexArray>)> {
+        let mesh = self.meshes.get(indx).expect("No such mesh found!").clone();
+        let vao = self.vaos.get(indx).expect("No vao info found!").clone();
+        mesh.zip(vao)
+    }
+    /// Whether another slot draws the same vao as this one.
+    pub fn is_shared(&self, indx: usize) -> bool {
+        self.vaos
+            .get(indx)
+            .expect("No vao info found!")
+            .as_ref()
+            .is_some_and(|m| Rc::strong_count(m) > 1)
+    }
+    pub fn texture(&self, indx: usize) -> TextureId {
+        self.textures
+            .get(indx)
+            .and_then(|t| *t)
+            .expect("No such texture found!")
+    }
+    /// The material of the slot's own copy of its mesh. A mesh shared with other slots is cloned
+    /// first, while the vao, bounds and collider stay shared since the geometry can't change.
+    /// Geometry is changed by putting another mesh in the slot instead.
+    pub fn mut_material(&mut self, indx: usize) -> Option<&mut Material> {
+        self.meshes
+            .get_mut(indx)
+            .expect("No such mesh found!")
+            .as_mut()
+            .map(|mesh| &mut Rc::make_mut(mesh).material)
+    }
+    /// Puts another mesh and vao in the slot, handing back the previous ones so that they can be
+    /// released.
+    pub fn replace_mesh(
+        &mut self,
+        indx: usize,
+        mesh: Option<Rc<Mesh>>,
+        vao: Option<Rc<VertexArray>>,
+    ) -> (Option<Rc<Mesh>>, Option<Rc<VertexArray>>) {
+        self.colliders[indx] = None;
+        self.bounds[indx] = None;
+        self.spatial.mark(indx);
+        let old_mesh = std::mem::replace(
+            self.meshes.get_mut(indx).expect("No such mesh found!"),
+            mesh,
+        );
+        let old_vao = std::mem::replace(self.vaos.get_mut(indx).expect("No vao info found!"), vao);
+        (old_mesh, old_vao)
+    }
+    /// The triangles that rays are tested against, built from the slot's mesh the first time
+    /// a ray is cast at it, and dropped whenever the mesh changes.
+    pub fn collider(&self, indx: usize) -> Option<&MeshCollider> {
+        self.colliders
+            .get(indx)
+            .expect("No collider info found!")
+            .as_ref()
+    }
+    pub fn set_collider(&mut self, indx: usize, collider: Option<MeshCollider>) {
+        *self.colliders.get_mut(indx).expect("No collider info found!") = collider;
+    }
+    /// Computes the bounds of the meshes that changed since the last pass. Slots that share a
+    /// mesh share its bounds, which are only computed once.
+    pub fn update_bounds(&mut self) {
+        let missing = (0..self.meshes.len())
+            .any(|i| self.alive[i] && self.bounds[i].is_none() && self.meshes[i].is_some());
+        if !missing {
+            return;
+        }
+        let mut computed: HashMap<*const Mesh, Option<Bounds>> = self
+            .meshes
+            .iter()
+            .zip(&self.bounds)
+            .filter_map(|(mesh, bounds)| Some((Rc::as_ptr(mesh.as_ref()?), Some(bounds.clone()?))))
+            .collect();
+        for i in 0..self.meshes.len() {
+            if !self.alive[i] || self.bounds[i].is_some() {
+                continue;
+            }
+            let mesh = match self.meshes[i].as_ref() {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            self.bounds[i] = computed
+                .entry(Rc::as_ptr(mesh))
+                .or_insert_with(|| mesh.geometry.bounds())
+                .clone();
+        }
+    }
+    /// Brings the spatial index up to date with the meshes and world transforms that changed
+    /// since the last pass, computing their bounds first. Relies on the world transforms being
+    /// up to date.
+    pub fn update_spatial_index(&mut self) {
+        self.update_bounds();
+        for i in self.spatial.take_dirty() {
+            let bounds = if self.alive[i] {
+                self.world_bounds(i)
+            } else {
+                None
+            };
+            self.spatial.update(i, bounds);
+        }
+    }
+    /// The index of the world bounds of the meshes, as of the last `update_spatial_index` pass.
+    pub fn spatial_index(&self) -> &SpatialIndex {
+        &self.spatial
     }
-    pub fn texture(&self, indx: usize) -> &WebGlTexture {
-        &self.textures.get(indx).expect("No such texture found!")
+    /// The bounds of the slot's mesh in its own space, as of the last `update_bounds` pass.
+    pub fn bounds(&self, indx: usize) -> Option<&Bounds> {
+        self.bounds
+            .get(indx)
+            .expect("No bounds info found!")
+            .as_ref()
     }
-    pub fn mut_mesh(&mut self, indx: usize) -> &mut Option<Mesh> {
-        self.meshes.get_mut(indx).expect("No such mesh found!")
+    /// The bounds of the slot's mesh in world space, around all of its instances for an
+    /// instanced mesh. Relies on the world transforms and bounds being up to date.
+    pub fn world_bounds(&self, indx: usize) -> Option<Bounds> {
+        let bounds = self.bounds(indx)?;
+        let world = self.world_transform(indx).to_homogeneous();
+        match self.instances(indx) {
+            Some(instances) => instances
+                .transforms
+                .iter()
+                .map(|t| bounds.transformed(&(world * t.to_homogeneous())))
+                .fold(None, |all: Option<Bounds>, b| {
+                    Some(match all {
+                        Some(all) => all.merged(&b),
+                        None => b,
+                    })
+                }),
+            None => Some(bounds.transformed(&world)),
+        }
     }
-    pub fn meshes(&self) -> &Vec<Option<Mesh>> {
+    pub fn meshes(&self) -> &[Option<Rc<Mesh>>] {
         &self.meshes
     }
     pub fn info(&self, indx: usize) -> ObjectInfo {
         self.info.get(indx).expect("No node info found!").clone()
     }
-    pub fn vao(&self, indx: usize) -> Option<&WebGlVertexArrayObject> {
-        self.vaos.get(indx).expect("No vao info found!").as_ref()
+    pub fn vao(&self, indx: usize) -> Option<VaoId> {
+        self.vaos
+            .get(indx)
+            .expect("No vao info found!")
+            .as_ref()
+            .map(|v| v.vao)
+    }
+    /// The instances of an instanced mesh, or None for a mesh that is drawn once.
+    pub fn instances(&self, indx: usize) -> Option<&Instances> {
+        self.instances
+            .get(indx)
+            .expect("No instance info found!")
+            .as_ref()
+    }
+    pub fn set_instances(&mut self, indx: usize, instances: Option<Instances>) {
+        *self.instances.get_mut(indx).expect("No instance info found!") = instances;
+        self.spatial.mark(indx);
+    }
+    /// The rigid body that the PhysicsWorld moves the slot by, if it has one.
+    pub fn body(&self, indx: usize) -> Option<&RigidBody> {
+        self.bodies.get(indx).expect("No body info found!").as_ref()
+    }
+    pub fn mut_body(&mut self, indx: usize) -> Option<&mut RigidBody> {
+        self.bodies
+            .get_mut(indx)
+            .expect("No body info found!")
+            .as_mut()
+    }
+    pub fn set_body(&mut self, indx: usize, body: Option<RigidBody>) {
+        *self.bodies.get_mut(indx).expect("No body info found!") = body;
+    }
+    pub fn set_vao(&mut self, indx: usize, vao: Option<Rc<VertexArray>>) {
+        *self.vaos.get_mut(indx).expect("No vao info found!") = vao;
     }
     pub fn mut_info(&mut self, indx: usize) -> &mut ObjectInfo {
         self.info.get_mut(indx).expect("No node info found!")
     }
     pub fn light(&self, indx: usize) -> LightInfo {
-        *self.lights.get(indx).expect("No light info found!")
+        self.lights
+            .get(indx)
+            .and_then(|l| *l)
+            .expect("No light info found!")
     }
-    pub fn lights(&self) -> &Vec<LightInfo> {
-        &self.lights
+    pub fn lights(&self) -> impl Iterator<Item = &LightInfo> {
+        self.lights.iter().flatten()
+    }
+    pub fn light_ids(&self) -> impl Iterator<Item = usize> + '_ {
+        self.lights
+            .iter()
+            .enumerate()
+            .filter_map(|(i, l)| l.map(|_| i))
     }
     pub fn mut_light_info(&mut self, indx: usize) -> &mut LightInfo {
-        self.lights.get_mut(indx).expect("No node info found!")
+        self.lights
+            .get_mut(indx)
+            .and_then(|l| l.as_mut())
+            .expect("No node info found!")
     }
 }
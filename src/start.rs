@@ -3,7 +3,7 @@ use crate::{
     dom_factory::{document, loop_animation_frame},
     editor::console::{self, ConsoleConfig},
     node, node_from_obj, node_from_obj_wired, rc_rcell,
-    renderer::{Renderer, RendererConfig},
+    renderer::{Pipeline, Renderer, RendererConfig, ToneMapping},
     scene::LightType,
     Color,
     Editor, Geometry, Material, Mesh, Node, Scene, Viewport,
@@ -61,6 +61,10 @@ pub fn start() -> Result<(), JsValue> {
     let renderer = Renderer::new(RendererConfig {
         id: "gl-canvas",
         pixel_ratio: 1.0,
+        pipeline: Pipeline::Forward,
+        hdr: false,
+        tone_mapping: ToneMapping::Aces,
+        exposure: 1.0,
     });
     let viewport = Viewport::new(
         ProjectionConfig {
@@ -0,0 +1,47 @@
+//! Helpers shared by the tests that run scenes without a browser.
+
+// each test file only uses some of them
+#![allow(dead_code)]
+
+use genmesh::generators::Cube;
+use moksha::{
+    controller::ProjectionConfig,
+    rc_rcell,
+    renderer::{Backend, RecordingBackend},
+    Geometry, Material, Mesh, Renderer, Scene, Viewport,
+};
+use std::rc::Rc;
+
+/// A scene drawn through the backend onto a canvas of the given size, seen with the given field
+/// of view.
+pub fn scene_with(backend: Rc<dyn Backend>, width: u32, height: u32, fov: f32) -> Scene {
+    let renderer = Renderer::with_backend(backend, width, height);
+    let viewport = Viewport::new(
+        ProjectionConfig {
+            fov,
+            near: 0.1,
+            far: 100.,
+        },
+        renderer.aspect_ratio(),
+    );
+    Scene::headless(rc_rcell(renderer), rc_rcell(viewport))
+}
+
+/// A scene that records what it draws onto an 800x600 canvas.
+pub fn setup() -> (Rc<RecordingBackend>, Scene) {
+    let backend = Rc::new(RecordingBackend::new());
+    let scene = scene_with(backend.clone(), 800, 600, std::f32::consts::PI / 2.);
+    (backend, scene)
+}
+
+/// The scene of `setup`, for tests that don't look at what's drawn.
+pub fn headless() -> Scene {
+    setup().1
+}
+
+pub fn cube(r: f32, g: f32, b: f32) -> Mesh {
+    Mesh::new(
+        Geometry::from_genmesh(&Cube::new()),
+        Material::new_color(r, g, b, 1.0),
+    )
+}
@@ -0,0 +1,253 @@
+//! Exports headless scenes to glTF and imports them back.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use common::headless;
+use genmesh::generators::Cube;
+use moksha::{
+    mesh::IndexType, rc_rcell, renderer::ShaderType, scene::GltfError, Geometry, LightType,
+    Material, Mesh, Node, RcRcell, Scene,
+};
+use nalgebra::{UnitQuaternion, Vector3};
+use serde_json::Value;
+use std::collections::HashMap;
+
+fn named(scene: &Scene, mesh: Option<Mesh>, name: &str) -> RcRcell<Node> {
+    let node = scene.from_mesh(mesh, false);
+    let mut info = node.info();
+    info.name = name.into();
+    node.set_info(info);
+    rc_rcell(node)
+}
+
+/// A mesh with more vertices than 16-bit indices can reach.
+fn large_mesh() -> Mesh {
+    let count = 70_000;
+    let vertices = (0..count * 3).map(|i| (i % 7) as f32).collect();
+    let geometry = Geometry {
+        vertices,
+        indices: vec![0, 1, count as u32 - 1],
+        normals: vec![0.; count * 3],
+    };
+    Mesh::new(geometry, Material::new_color(0., 0., 1., 1.))
+}
+
+/// The JSON chunk of a binary glTF file.
+fn glb_json(glb: &[u8]) -> Value {
+    let length = u32::from_le_bytes([glb[12], glb[13], glb[14], glb[15]]) as usize;
+    serde_json::from_slice(&glb[20..20 + length]).unwrap()
+}
+
+fn child(node: &Node, name: &str) -> RcRcell<Node> {
+    node.find_child(name)
+        .unwrap_or_else(|| panic!("No child named {}", name))
+}
+
+fn assert_close(a: &[f32], b: &[f32]) {
+    assert_eq!(a.len(), b.len());
+    for (a, b) in a.iter().zip(b) {
+        assert!((a - b).abs() < 1e-5, "{:?} != {:?}", a, b);
+    }
+}
+
+#[test]
+fn exported_scenes_import_back() {
+    let scene = headless();
+    let material = Material::new_pbr(1., 0., 0., 1.)
+        .metallic(0.25)
+        .roughness(0.75);
+    let cube = Mesh::new(Geometry::from_genmesh(&Cube::new()), material);
+    let parent = named(&scene, Some(cube.clone()), "red cube");
+    parent.borrow().set_position(1., 2., 3.);
+    parent
+        .borrow()
+        .set_rotation(UnitQuaternion::from_euler_angles(0.1, 0.2, 0.3));
+    parent.borrow().set_scale_vec(1., 2., 3.);
+    scene.add(parent.clone());
+    let nested = named(&scene, Some(cube), "nested cube");
+    nested.borrow().set_position(0., 5., 0.);
+    parent.borrow().add(nested);
+    scene.add(named(&scene, Some(large_mesh()), "large mesh"));
+    let light = scene.light(LightType::Point, [1., 0.5, 0.25], 3.);
+    scene.add_light(&light);
+
+    let glb = scene.to_glb();
+    let json = glb_json(&glb);
+    assert_eq!(
+        json["extensionsUsed"],
+        serde_json::json!(["KHR_lights_punctual"])
+    );
+    let lights = &json["extensions"]["KHR_lights_punctual"]["lights"];
+    assert_eq!(lights.as_array().unwrap().len(), 1);
+    assert_eq!(lights[0]["type"], "point");
+    assert_eq!(lights[0]["intensity"], 3.);
+    assert_eq!(lights[0]["color"], serde_json::json!([1., 0.5, 0.25]));
+
+    let imported = scene
+        .object_from_glb("", &glb, &HashMap::new(), None)
+        .unwrap();
+    assert_eq!(imported.info().name, "Scene");
+
+    let red = child(&imported, "red cube");
+    let red = red.borrow();
+    let transform = red.transform();
+    assert_close(
+        transform.isometry.translation.vector.as_slice(),
+        &[1., 2., 3.],
+    );
+    let (roll, pitch, yaw) = transform.isometry.rotation.euler_angles();
+    assert_close(&[roll, pitch, yaw], &[0.1, 0.2, 0.3]);
+    assert_close(transform.scale.as_slice(), &[1., 2., 3.]);
+    let mesh = red.mesh().unwrap();
+    assert_eq!(mesh.material.shader_type, ShaderType::Pbr);
+    assert_eq!(mesh.material.color, Some([1., 0., 0., 1.]));
+    assert_eq!(mesh.material.metallic, 0.25);
+    assert_eq!(mesh.material.roughness, 0.75);
+    assert_eq!(mesh.geometry.index_type(), IndexType::U16);
+    let nested = child(&red, "nested cube");
+    assert_eq!(
+        nested.borrow().transform().isometry.translation.vector,
+        Vector3::new(0., 5., 0.)
+    );
+
+    let large = child(&imported, "large mesh");
+    let large = large.borrow().mesh().unwrap();
+    assert_eq!(large.geometry.index_type(), IndexType::U32);
+    assert_eq!(large.geometry.indices, vec![0, 1, 69_999]);
+    // rough dielectrics stand in for materials without metalness
+    assert_eq!(large.material.metallic, 0.);
+    assert_eq!(large.material.roughness, 1.);
+
+    let light_node = child(&imported, "Point");
+    assert!(light_node.borrow().mesh().is_none());
+    assert!(light_node.borrow().find_child("Point light").is_some());
+}
+
+#[test]
+fn index_widths_follow_the_vertex_count() {
+    let scene = headless();
+    let cube = Mesh::new(
+        Geometry::from_genmesh(&Cube::new()),
+        Material::new_color(1., 1., 1., 1.),
+    );
+    scene.add(named(&scene, Some(cube), "small"));
+    scene.add(named(&scene, Some(large_mesh()), "large"));
+    let json = glb_json(&scene.to_glb());
+    let index_types: HashMap<&str, u64> = json["meshes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|mesh| {
+            let accessor = mesh["primitives"][0]["indices"].as_u64().unwrap() as usize;
+            let component_type = json["accessors"][accessor]["componentType"].as_u64();
+            (mesh["name"].as_str().unwrap(), component_type.unwrap())
+        })
+        .collect();
+    // UNSIGNED_SHORT and UNSIGNED_INT
+    assert_eq!(index_types["small"], 5123);
+    assert_eq!(index_types["large"], 5125);
+}
+
+#[test]
+fn truncated_glb_files_are_rejected() {
+    let scene = headless();
+    scene.add(named(&scene, Some(large_mesh()), "large"));
+    let glb = scene.to_glb();
+    let error = scene
+        .object_from_glb("", &glb[..glb.len() / 2], &HashMap::new(), None)
+        .unwrap_err();
+    assert!(matches!(error, GltfError::Glb(_)), "{}", error);
+    let error = scene
+        .object_from_glb("", &glb[..10], &HashMap::new(), None)
+        .unwrap_err();
+    assert!(matches!(error, GltfError::Glb(_)), "{}", error);
+    // a JSON chunk that claims to run to the end of the address space
+    let mut forged = glb.clone();
+    forged[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+    let error = scene
+        .object_from_glb("", &forged, &HashMap::new(), None)
+        .unwrap_err();
+    assert!(matches!(error, GltfError::Glb(_)), "{}", error);
+}
+
+/// A document with a single triangle whose positions are read by the given accessor, from a
+/// buffer of the given uri.
+fn triangle_gltf(accessor: Value, uri: &str) -> String {
+    serde_json::json!({
+        "asset": { "version": "2.0" },
+        "nodes": [{ "name": "triangle", "mesh": 0 }],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 } }] }],
+        "accessors": [accessor],
+        "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+        "buffers": [{ "uri": uri, "byteLength": 36 }],
+    })
+    .to_string()
+}
+
+/// Three vertices at the origin.
+const ZEROS: &str = "data:application/octet-stream;base64,\
+                     AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+
+#[test]
+fn documents_that_read_out_of_range_are_rejected() {
+    let scene = headless();
+    let load = |src: &str| scene.object_from_gltf("", src, &HashMap::new(), None);
+    let accessor = serde_json::json!({
+        "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+    });
+    assert!(load(&triangle_gltf(accessor.clone(), ZEROS)).is_ok());
+
+    let mut past_the_end = accessor.clone();
+    past_the_end["count"] = 4.into();
+    let error = load(&triangle_gltf(past_the_end, ZEROS)).unwrap_err();
+    assert!(matches!(error, GltfError::InvalidData(_)), "{}", error);
+
+    // counts and offsets too large to add up are rejected before anything is allocated
+    let mut huge = accessor.clone();
+    huge["count"] = (1u64 << 62).into();
+    let error = load(&triangle_gltf(huge.clone(), ZEROS)).unwrap_err();
+    assert!(matches!(error, GltfError::InvalidData(_)), "{}", error);
+    huge.as_object_mut().unwrap().remove("bufferView");
+    let error = load(&triangle_gltf(huge, ZEROS)).unwrap_err();
+    assert!(matches!(error, GltfError::InvalidData(_)), "{}", error);
+    let mut document: Value = serde_json::from_str(&triangle_gltf(accessor, ZEROS)).unwrap();
+    document["bufferViews"][0]["byteStride"] = 4.into();
+    let error = load(&document.to_string()).unwrap_err();
+    assert!(matches!(error, GltfError::InvalidData(_)), "{}", error);
+    document["bufferViews"][0]["byteStride"] = Value::Null;
+    document["bufferViews"][0]["byteOffset"] = u64::MAX.into();
+    let error = load(&document.to_string()).unwrap_err();
+    assert!(matches!(error, GltfError::InvalidData(_)), "{}", error);
+
+    let missing = serde_json::json!({
+        "asset": { "version": "2.0" },
+        "nodes": [{ "mesh": 0 }],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 3 } }] }],
+    });
+    let error = load(&missing.to_string()).unwrap_err();
+    assert_eq!(error, GltfError::InvalidReference("accessor", 3));
+    // nothing is left behind by a failed import
+    assert!(scene.root().borrow().children().is_empty());
+}
+
+#[test]
+fn bad_data_uris_are_rejected() {
+    let scene = headless();
+    let load = |uri: &str| {
+        let accessor = serde_json::json!({
+            "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+        });
+        scene.object_from_gltf("", &triangle_gltf(accessor, uri), &HashMap::new(), None)
+    };
+    let error = load("data:application/octet-stream;base64,AAAA*AAA").unwrap_err();
+    assert_eq!(error, GltfError::InvalidData("Invalid base64 data".into()));
+    let error = load("data:application/octet-stream,AAAA").unwrap_err();
+    assert!(matches!(error, GltfError::Unsupported(_)), "{}", error);
+    let error = load("data:application/octet-stream;base64,AAAA").unwrap_err();
+    assert_eq!(
+        error,
+        GltfError::InvalidData("Buffer 0 is too short".into())
+    );
+}
@@ -0,0 +1,118 @@
+//! Loads Wavefront OBJ files into headless scenes.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use common::headless;
+use moksha::{renderer::DrawMode, scene::ObjError};
+
+const TWO_MATERIALS: &str = "
+newmtl red
+Ns 10
+Ka 0 0 0
+Kd 1 0 0
+Ks 0 0 0
+Ni 1
+d 1
+illum 2
+
+newmtl blue
+Ns 10
+Ka 0 0 0
+Kd 0 0 1
+Ks 0 0 0
+Ni 1
+d 1
+illum 2
+";
+
+#[test]
+fn parse_errors_report_their_line() {
+    let scene = headless();
+    let src = "o broken\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 x\n";
+    match scene.object_from_obj("", src, None, None, false) {
+        Err(ObjError::Obj { line, .. }) => assert_eq!(line, 5),
+        other => panic!("Expected a parse error, got {:?}", other.map(|n| n.info())),
+    }
+    match scene.object_from_obj("", "o tri\n", Some("Kd 1 0 0\n"), None, false) {
+        Err(ObjError::Mtl { line, .. }) => assert_eq!(line, 1),
+        other => panic!("Expected a parse error, got {:?}", other.map(|n| n.info())),
+    }
+}
+
+#[test]
+fn files_without_faces_are_empty() {
+    let scene = headless();
+    let src = "o points\nv 0 0 0\nv 1 0 0\n";
+    let error = scene.object_from_obj("", src, None, None, false);
+    assert_eq!(error.unwrap_err(), ObjError::Empty);
+    let error = scene.object_from_obj("", "", None, None, false);
+    assert_eq!(error.unwrap_err(), ObjError::Empty);
+}
+
+#[test]
+fn faces_without_normals_are_shaded_flat() {
+    let scene = headless();
+    let src = "o tri\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
+    let node = scene.object_from_obj("", src, None, None, false).unwrap();
+    assert_eq!(node.info().name, "tri");
+    let mesh = node.mesh().unwrap();
+    assert_eq!(mesh.geometry.indices, vec![0, 1, 2]);
+    assert_eq!(
+        mesh.geometry.normals,
+        vec![0., 0., 1., 0., 0., 1., 0., 0., 1.]
+    );
+    assert!(mesh.material.flat_shade);
+    assert_eq!(mesh.material.tex_coords, None);
+
+    // normals that are given are kept, and only the missing ones are computed
+    let src = "o quad\nv 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\nvn 0 1 0\n\
+               f 1//1 2//1 3//1\nf 2 4 3\n";
+    let node = scene.object_from_obj("", src, None, None, false).unwrap();
+    let mesh = node.mesh().unwrap();
+    assert_eq!(mesh.geometry.vertices.len(), 6 * 3);
+    assert_eq!(
+        &mesh.geometry.normals[..9],
+        &[0., 1., 0., 0., 1., 0., 0., 1., 0.]
+    );
+    assert_eq!(
+        &mesh.geometry.normals[9..],
+        &[0., 0., 1., 0., 0., 1., 0., 0., 1.]
+    );
+    assert!(!mesh.material.flat_shade);
+}
+
+#[test]
+fn lines_without_normals_keep_them_empty() {
+    let scene = headless();
+    let src = "o wire\nv 0 0 0\nv 1 0 0\nv 0 1 0\nl 1 2\nl 2 3\n";
+    let node = scene.object_from_obj("", src, None, None, false).unwrap();
+    assert_eq!(node.info().draw_mode, DrawMode::Lines);
+    let mesh = node.mesh().unwrap();
+    assert_eq!(mesh.geometry.indices, vec![0, 1, 1, 2]);
+    assert_eq!(mesh.geometry.normals, vec![0.; 9]);
+}
+
+#[test]
+fn material_groups_become_parts_of_the_object() {
+    let scene = headless();
+    let src = "o quad\nv 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\n\
+               usemtl red\nf 1 2 3\nusemtl blue\nf 2 4 3\n";
+    let node = scene
+        .object_from_obj("", src, Some(TWO_MATERIALS), None, false)
+        .unwrap();
+    assert_eq!(node.info().name, "quad");
+    assert!(node.mesh().is_none());
+    let parts = node.owned_children();
+    let names: Vec<String> = parts.iter().map(|p| p.info().name).collect();
+    assert_eq!(names, vec!["quad red", "quad blue"]);
+    let colors: Vec<Option<[f32; 4]>> = parts
+        .iter()
+        .map(|p| p.mesh().unwrap().material.color)
+        .collect();
+    assert_eq!(colors, vec![Some([1., 0., 0., 1.]), Some([0., 0., 1., 1.])]);
+    for part in parts.iter() {
+        assert_eq!(part.mesh().unwrap().geometry.indices.len(), 3);
+    }
+}
@@ -0,0 +1,230 @@
+//! Steps headless scenes through the PhysicsWorld.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use common::headless;
+use genmesh::generators::{Cube, Plane};
+use moksha::{
+    physics::{BodyType, Collider, ContactEvent, PhysicsConfig, PhysicsWorld, RigidBody},
+    rc_rcell, Geometry, Node, Scene,
+};
+use nalgebra::Vector3;
+
+/// A timestep that adds up without rounding.
+const TIMESTEP: f32 = 1. / 64.;
+
+fn setup() -> (Scene, PhysicsWorld) {
+    let scene = headless();
+    let world = PhysicsWorld::new(PhysicsConfig {
+        timestep: TIMESTEP,
+        ..Default::default()
+    });
+    (scene, world)
+}
+
+/// A node without a mesh that's moved by the body.
+fn body_node(scene: &Scene, body: RigidBody, position: [f32; 3]) -> Node {
+    let node = scene.from_mesh(None, false);
+    node.set_position(position[0], position[1], position[2]);
+    node.set_body(Some(body));
+    scene.add(rc_rcell(node.clone()));
+    node
+}
+
+/// A static ground whose top is at y = 0.
+fn ground(scene: &Scene) -> Node {
+    let collider = Collider::cuboid(Vector3::new(20., 0.5, 20.));
+    body_node(
+        scene,
+        RigidBody::new(BodyType::Static, collider),
+        [0., -0.5, 0.],
+    )
+}
+
+/// Steps the world for the given number of seconds, a step at a time, and collects the events.
+fn run(world: &mut PhysicsWorld, scene: &Scene, seconds: f32) -> Vec<ContactEvent> {
+    let mut events = Vec::new();
+    for _ in 0..(seconds / TIMESTEP) as usize {
+        assert_eq!(world.step(scene, TIMESTEP), 1);
+        events.extend_from_slice(world.events());
+    }
+    events
+}
+
+#[test]
+fn balls_fall_onto_the_ground_and_rest_there() {
+    let (scene, mut world) = setup();
+    let ground = ground(&scene);
+    let ball = RigidBody::new(BodyType::Dynamic, Collider::ball(0.5));
+    let ball = body_node(&scene, ball, [0., 3., 0.]);
+
+    // a quarter of a second in, the ball has fallen freely
+    run(&mut world, &scene, 0.25);
+    let fallen = 9.81 * 0.25 * 0.25 / 2.;
+    assert!((ball.position().y - (3. - fallen)).abs() < 0.05);
+    let events = run(&mut world, &scene, 2.75);
+    assert!((ball.position().y - 0.5).abs() < 0.02);
+    assert!(ball.body().unwrap().linear_velocity.norm() < 0.05);
+    assert_eq!(
+        events,
+        vec![ContactEvent::Started(ground.handle(), ball.handle())]
+    );
+    assert_eq!(ground.position().y, -0.5);
+}
+
+#[test]
+fn bodies_bounce_by_their_restitution() {
+    let (scene, mut world) = setup();
+    let ground = ground(&scene);
+    let mut ball = RigidBody::new(BodyType::Dynamic, Collider::ball(0.5));
+    ball.restitution = 0.8;
+    let ball = body_node(&scene, ball, [0., 5.5, 0.]);
+
+    // a fall of 5 meters takes about a second, and the ball is on its way back up soon after
+    let events = run(&mut world, &scene, 1.1);
+    assert_eq!(
+        events,
+        vec![
+            ContactEvent::Started(ground.handle(), ball.handle()),
+            ContactEvent::Stopped(ground.handle(), ball.handle())
+        ]
+    );
+    assert!(ball.body().unwrap().linear_velocity.y > 0.);
+    let mut highest: f32 = 0.;
+    let mut events = Vec::new();
+    for _ in 0..64 {
+        events.extend(run(&mut world, &scene, TIMESTEP));
+        highest = highest.max(ball.position().y);
+    }
+    // it keeps 0.8 of its speed, and so 0.64 of its height
+    assert!(
+        (highest - 0.5 - 5. * 0.64).abs() < 0.3,
+        "bounced up to {}",
+        highest
+    );
+    // and it's still in the air
+    assert!(events.is_empty());
+}
+
+#[test]
+fn friction_stops_sliding_boxes() {
+    let (scene, mut world) = setup();
+    ground(&scene);
+    let collider = Collider::cuboid(Vector3::new(0.5, 0.5, 0.5));
+    let mut rough = RigidBody::new(BodyType::Dynamic, collider.clone());
+    rough.linear_velocity = Vector3::new(3., 0., 0.);
+    let rough = body_node(&scene, rough, [0., 0.5, 0.]);
+    let mut smooth = RigidBody::new(BodyType::Dynamic, collider);
+    smooth.linear_velocity = Vector3::new(3., 0., 0.);
+    smooth.friction = 0.;
+    let smooth = body_node(&scene, smooth, [0., 0.5, 5.]);
+
+    run(&mut world, &scene, 2.);
+    assert!(rough.body().unwrap().linear_velocity.norm() < 0.05);
+    // it slides 3 * 3 / (2 * 0.5 * 9.81) meters before it stops
+    assert!((rough.position().x - 0.92).abs() < 0.1);
+    assert!((smooth.body().unwrap().linear_velocity.x - 3.).abs() < 0.05);
+    for node in [rough, smooth].iter() {
+        // both slid flat on their faces, without tipping over
+        assert!((node.position().y - 0.5).abs() < 0.02);
+        assert!(node.rotation().angle() < 0.01);
+    }
+}
+
+#[test]
+fn steps_are_fixed_and_only_dynamic_bodies_fall() {
+    let (scene, mut world) = setup();
+    let fixed = RigidBody::new(BodyType::Static, Collider::ball(0.5));
+    let fixed = body_node(&scene, fixed, [0., 10., 0.]);
+    let mut platform = RigidBody::new(BodyType::Kinematic, Collider::capsule(1., 0.5));
+    platform.linear_velocity = Vector3::new(1., 0., 0.);
+    let platform = body_node(&scene, platform, [10., 10., 0.]);
+    // bodies move their nodes within their parent
+    let parent = scene.from_mesh(None, false);
+    parent.set_position(-10., 0., 0.);
+    let parent = rc_rcell(parent);
+    scene.add(parent.clone());
+    let child = scene.from_mesh(None, false);
+    child.set_body(Some(RigidBody::new(BodyType::Dynamic, Collider::ball(0.5))));
+    child.set_position(0., 10., 0.);
+    let child = rc_rcell(child);
+    parent.borrow().add(child.clone());
+
+    assert_eq!(world.step(&scene, 0.05), 3);
+    assert_eq!(world.step(&scene, 0.01), 0);
+    assert_eq!(world.step(&scene, 0.01), 1);
+    assert_eq!(fixed.position(), [0., 10., 0.].into());
+    assert_eq!(platform.position(), [10. + 4. * TIMESTEP, 10., 0.].into());
+    let child = child.borrow();
+    assert_eq!(child.position().x, 0.);
+    assert!(child.position().y < 10.);
+    assert!(child.global_position()[0] == -10.);
+    // a long frame is cut short
+    assert_eq!(world.step(&scene, 1.), world.config().max_steps);
+}
+
+#[test]
+fn bodies_without_mass_pass_through_static_ones() {
+    let (scene, mut world) = setup();
+    ground(&scene);
+    let mut weightless = RigidBody::new(BodyType::Dynamic, Collider::ball(0.5));
+    weightless.mass = 0.;
+    let weightless = body_node(&scene, weightless, [0., 0.49, 0.]);
+
+    // nothing can push a body that has no inverse mass, so it keeps falling
+    run(&mut world, &scene, 0.5);
+    let body = weightless.body().unwrap();
+    assert!(body.linear_velocity.iter().all(|v| v.is_finite()));
+    assert!((body.linear_velocity.y + 9.81 * 0.5).abs() < 0.05);
+    assert!(weightless.position().y < 0.);
+}
+
+#[test]
+fn contacts_between_immovable_bodies_are_skipped() {
+    let (scene, mut world) = setup();
+    let collider = Collider::cuboid(Vector3::new(0.5, 0.5, 0.5));
+    let platform = RigidBody::new(BodyType::Kinematic, collider.clone());
+    let platform = body_node(&scene, platform, [0., 0., 0.]);
+    let mut weightless = RigidBody::new(BodyType::Dynamic, collider);
+    weightless.mass = 0.;
+    let weightless = body_node(&scene, weightless, [0.5, 0., 0.]);
+    let config = PhysicsConfig {
+        gravity: Vector3::zeros(),
+        ..world.config()
+    };
+    world.set_config(config);
+
+    let events = run(&mut world, &scene, 0.5);
+    assert_eq!(
+        events,
+        vec![ContactEvent::Started(
+            platform.handle(),
+            weightless.handle()
+        )]
+    );
+    assert_eq!(platform.position(), [0., 0., 0.].into());
+    assert_eq!(weightless.position(), [0.5, 0., 0.].into());
+    assert_eq!(weightless.body().unwrap().linear_velocity, Vector3::zeros());
+}
+
+#[test]
+fn colliders_are_built_from_geometry() {
+    let cube = Geometry::from_genmesh(&Cube::new());
+    assert!(Collider::convex_hull(&cube).is_some());
+    assert!(Collider::trimesh(&cube).is_some());
+    let plane = Geometry::from_genmesh(&Plane::new());
+    assert!(Collider::convex_hull(&plane).is_none());
+    assert!(Collider::trimesh(&Geometry::default()).is_none());
+
+    let (scene, mut world) = setup();
+    let mut slab = cube.clone();
+    slab.vertices.iter_mut().for_each(|v| *v *= 4.);
+    let floor = RigidBody::new(BodyType::Static, Collider::trimesh(&slab).unwrap());
+    body_node(&scene, floor, [0., -4., 0.]);
+    let hull = RigidBody::new(BodyType::Dynamic, Collider::convex_hull(&cube).unwrap());
+    let hull = body_node(&scene, hull, [0., 3., 0.]);
+    run(&mut world, &scene, 2.);
+    assert!((hull.position().y - 1.).abs() < 0.05);
+}
@@ -0,0 +1,1196 @@
+//! Renders scenes through the RecordingBackend, so these run natively without a browser.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use common::{cube, setup};
+use genmesh::generators::Cube;
+use moksha::{
+    controller::ProjectionConfig,
+    rc_rcell,
+    renderer::{
+        gl, Backend, BufferContents, Command, DrawCall, Frustum, HdrError, HdrImage, Pipeline,
+        PostEffect, RecordingBackend, RenderTargetConfig, ShadowConfig, TargetSize, ToneMapping,
+        Uniform, PREFILTERED_LEVELS,
+    },
+    scene::{Instances, SpatialIndex},
+    Geometry, LightType, Material, Mesh, Scene, TextureType, Transform, Viewport,
+};
+use nalgebra::{Point3, Vector3};
+use ncollide3d::{
+    bounding_volume::{BoundingSphere, AABB},
+    query::Ray,
+};
+
+fn render(scene: &Scene) {
+    scene
+        .renderer()
+        .borrow()
+        .render(scene, &scene.view().borrow());
+}
+
+#[test]
+fn draws_a_mesh() {
+    let (backend, scene) = setup();
+    let node = scene.from_mesh(Some(cube(1., 0., 0.)), false);
+    node.set_position(1., 2., 3.);
+    scene.add(rc_rcell(node));
+    backend.clear();
+    render(&scene);
+
+    let draws = backend.draw_calls();
+    assert_eq!(draws.len(), 1);
+    let draw = &draws[0];
+    assert_eq!(draw.mode, gl::TRIANGLES);
+    assert_eq!(draw.count, 36);
+    assert_eq!(draw.index_type, Some(gl::UNSIGNED_SHORT));
+    assert_eq!(draw.instances, None);
+    assert!(draw.is_enabled(gl::DEPTH_TEST));
+    assert_eq!(draw.uniform("color"), Some(Uniform::Vec4([1., 0., 0., 1.])));
+    match draw.uniform("model") {
+        Some(Uniform::Mat4(model)) => assert_eq!(&model[12..15], &[1., 2., 3.]),
+        other => panic!("Unexpected model uniform: {:?}", other),
+    }
+
+    let vao = backend.vertex_array(draw.vao.unwrap()).unwrap();
+    assert!(vao.elements.is_some());
+}
+
+#[test]
+fn only_declared_uniforms_can_be_written() {
+    let backend = RecordingBackend::new();
+    let vertex = "uniform mat4 model, view, proj;\nuniform highp mat4 matrices[4];";
+    let fragment = "uniform Light lights[MAX_NUM_LIGHTS];\nuniform vec4 color; // rgba";
+    let program = backend.create_program(vertex, fragment).unwrap();
+    backend.use_program(Some(program));
+    let matrix = Uniform::Mat4([0.; 16]);
+    assert!(backend.set_uniform(program, "proj", matrix));
+    assert!(backend.set_uniform(program, "matrices[3]", matrix));
+    assert!(backend.set_uniform(program, "lights[1].intensity", Uniform::F32(1.)));
+    assert!(backend.set_uniform(program, "color", Uniform::Vec4([1.; 4])));
+    assert!(!backend.set_uniform(program, "colour", Uniform::Vec4([1.; 4])));
+    assert!(!backend.set_uniform(program, "rgba", Uniform::Vec4([1.; 4])));
+    let uniforms = backend.program(program).unwrap().uniforms;
+    assert_eq!(uniforms.len(), 4);
+    assert!(!uniforms.contains_key("colour"));
+}
+
+#[test]
+fn buffers_are_uploaded_once() {
+    let (backend, scene) = setup();
+    scene.add(rc_rcell(scene.from_mesh(Some(cube(1., 1., 1.)), false)));
+    render(&scene);
+    backend.clear();
+    render(&scene);
+
+    let commands = backend.commands();
+    assert!(!commands
+        .iter()
+        .any(|c| matches!(c, Command::CreateBuffer(_) | Command::CreateVertexArray(_))));
+    assert_eq!(backend.draw_calls().len(), 1);
+}
+
+#[test]
+fn recolored_instances_keep_drawing_the_shared_vao() {
+    let (backend, scene) = setup();
+    let node = scene.from_mesh(Some(cube(1., 0., 0.)), false);
+    let copy = scene.instance(&node);
+    copy.change_color([0., 1., 0.]);
+    scene.add(rc_rcell(node));
+    scene.add(rc_rcell(copy));
+    backend.clear();
+    render(&scene);
+
+    assert!(!backend
+        .commands()
+        .iter()
+        .any(|c| matches!(c, Command::CreateBuffer(_) | Command::CreateVertexArray(_))));
+    let draws = backend.draw_calls();
+    assert_eq!(draws.len(), 2);
+    assert_eq!(draws[0].vao, draws[1].vao);
+    let colors: Vec<_> = draws.iter().map(|draw| draw.uniform("color")).collect();
+    assert!(colors.contains(&Some(Uniform::Vec4([1., 0., 0., 1.]))));
+    assert!(colors.contains(&Some(Uniform::Vec4([0., 1., 0., 1.]))));
+}
+
+#[test]
+fn instanced_nodes_are_drawn_once() {
+    let (backend, scene) = setup();
+    let transforms = (0..5)
+        .map(|i| {
+            let mut transform = Transform::identity();
+            transform.isometry.translation.vector.x = i as f32;
+            transform
+        })
+        .collect();
+    let node = scene.instanced(
+        cube(0., 1., 0.),
+        Default::default(),
+        Instances {
+            transforms,
+            colors: None,
+        },
+    );
+    scene.add(rc_rcell(node));
+    backend.clear();
+    render(&scene);
+
+    let draws = backend.draw_calls();
+    assert_eq!(draws.len(), 1);
+    assert_eq!(draws[0].instances, Some(5));
+    assert_eq!(draws[0].uniform("instanced"), Some(Uniform::Bool(true)));
+}
+
+#[test]
+fn deleting_nodes_frees_their_resources() {
+    let (_, scene) = setup();
+    let renderer = scene.renderer();
+    let baseline = renderer.borrow().resource_counts();
+
+    let node = scene.from_mesh(Some(cube(1., 1., 1.)), false);
+    let copy = scene.instance(&node);
+    let node = rc_rcell(node);
+    let copy = rc_rcell(copy);
+    scene.add(node.clone());
+    scene.add(copy.clone());
+    render(&scene);
+    let live = renderer.borrow().resource_counts();
+    assert_eq!(live.vaos, baseline.vaos + 1);
+
+    scene.delete(&copy.borrow());
+    assert_eq!(renderer.borrow().resource_counts(), live);
+    scene.delete(&node.borrow());
+    assert_eq!(renderer.borrow().resource_counts(), baseline);
+}
+
+#[test]
+fn deleted_lights_free_their_slots() {
+    let (_, scene) = setup();
+    let first = scene.light(LightType::Point, [1., 1., 1.], 1.);
+    scene.add_light(&first);
+    let second = scene.light(LightType::Point, [1., 1., 1.], 1.);
+    scene.add_light(&second);
+    scene.delete(&first.node().borrow());
+    let third = scene.light(LightType::Point, [1., 0., 0.], 1.);
+    assert_eq!(third.index(), first.index());
+    let storage = scene.storage();
+    assert_eq!(storage.borrow().light_ids().count(), 2);
+}
+
+#[test]
+fn pbr_maps_are_bound_after_the_albedo() {
+    let (backend, scene) = setup();
+    let renderer = scene.renderer();
+    let baseline = renderer.borrow().resource_counts();
+    let material = Material::new_pbr(1., 1., 1., 1.)
+        .metallic(0.2)
+        .roughness(0.7)
+        .tex_type(TextureType::Tex2d)
+        .tex_coords(vec![0.; 48])
+        .texture("albedo.png")
+        .normal_map("normal.png", 0.5)
+        .metallic_roughness_map("metallic_roughness.png");
+    let node = scene.from_mesh(
+        Some(Mesh::new(Geometry::from_genmesh(&Cube::new()), material)),
+        false,
+    );
+    let node = rc_rcell(node);
+    scene.add(node.clone());
+    backend.clear();
+    render(&scene);
+
+    let draws = backend.draw_calls();
+    assert_eq!(draws.len(), 1);
+    let draw = &draws[0];
+    let url = |unit: u32| backend.texture(draw.textures[&unit]).unwrap().0[0].clone();
+    assert_eq!(url(0), "albedo.png");
+    assert_eq!(url(1), "normal.png");
+    assert_eq!(url(3), "metallic_roughness.png");
+    assert!(!draw.textures.contains_key(&2));
+    assert_eq!(draw.uniform("normal_map"), Some(Uniform::I32(1)));
+    assert_eq!(draw.uniform("normal_scale"), Some(Uniform::F32(0.5)));
+    assert_eq!(draw.uniform("has_occlusion_map"), Some(Uniform::Bool(false)));
+    assert_eq!(draw.uniform("metallic"), Some(Uniform::F32(0.2)));
+    assert_eq!(draw.uniform("roughness"), Some(Uniform::F32(0.7)));
+    assert_eq!(
+        renderer.borrow().resource_counts().textures,
+        baseline.textures + 3
+    );
+
+    scene.delete(&node.borrow());
+    assert_eq!(renderer.borrow().resource_counts(), baseline);
+}
+
+/// Draws into framebuffers, where the environment maps are generated.
+fn offscreen_draws(backend: &RecordingBackend) -> usize {
+    backend
+        .draw_calls()
+        .iter()
+        .filter(|draw| draw.framebuffer.is_some())
+        .count()
+}
+
+#[test]
+fn environment_maps_are_generated_once_the_skybox_loads() {
+    let (backend, scene) = setup();
+    let renderer = scene.renderer();
+    scene.set_skybox("sky", "png");
+    render(&scene);
+    assert_eq!(offscreen_draws(&backend), 0);
+    assert_eq!(renderer.borrow().environment().unwrap().maps, None);
+
+    backend.load_textures();
+    backend.clear();
+    render(&scene);
+    // six faces of the irradiance map, six for each level of the prefiltered map, and the lut
+    assert_eq!(offscreen_draws(&backend), 6 + 6 * PREFILTERED_LEVELS as usize + 1);
+    assert_eq!(backend.bound_framebuffer(), None);
+    let maps = renderer.borrow().environment().unwrap().maps.unwrap();
+    let (_, tex_type) = backend.texture(maps.prefiltered).unwrap();
+    assert_eq!(tex_type, TextureType::CubeMap);
+    assert_eq!(renderer.borrow().resource_counts().framebuffers, 0);
+    let last_viewport = backend
+        .commands()
+        .into_iter()
+        .rfind(|c| matches!(c, Command::Viewport(..)));
+    assert_eq!(last_viewport, Some(Command::Viewport(0, 0, 800, 600)));
+
+    backend.clear();
+    render(&scene);
+    assert_eq!(offscreen_draws(&backend), 0);
+}
+
+#[test]
+fn pbr_materials_reflect_the_skybox() {
+    let (backend, scene) = setup();
+    let renderer = scene.renderer();
+    let mesh = Mesh::new(
+        Geometry::from_genmesh(&Cube::new()),
+        Material::new_pbr(1., 1., 1., 1.),
+    );
+    scene.add(rc_rcell(scene.from_mesh(Some(mesh), false)));
+    let baseline = renderer.borrow().resource_counts();
+    scene.set_skybox("sky", "png");
+    backend.load_textures();
+    backend.clear();
+    render(&scene);
+
+    let maps = renderer.borrow().environment().unwrap().maps.unwrap();
+    let draws = backend.draw_calls();
+    let draw = draws
+        .iter()
+        .find(|draw| draw.uniform("has_environment").is_some())
+        .unwrap();
+    assert_eq!(draw.uniform("has_environment"), Some(Uniform::Bool(true)));
+    assert_eq!(draw.uniform("irradiance_map"), Some(Uniform::I32(4)));
+    assert_eq!(draw.textures[&4], maps.irradiance);
+    assert_eq!(draw.textures[&5], maps.prefiltered);
+
+    scene.remove_skybox();
+    assert_eq!(renderer.borrow().environment(), None);
+    // the brdf lut doesn't depend on the skybox, so it's kept for the next one
+    let mut live = renderer.borrow().resource_counts();
+    live.textures -= 1;
+    assert_eq!(live, baseline);
+    backend.clear();
+    render(&scene);
+    let draws = backend.draw_calls();
+    assert_eq!(draws.len(), 1);
+    assert_eq!(draws[0].uniform("has_environment"), Some(Uniform::Bool(false)));
+}
+
+/// A 8x2 .hdr image whose first scanline is run length encoded and second one is flat.
+fn hdr_file() -> Vec<u8> {
+    let mut bytes = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\nEXPOSURE=1.0\n\n-Y 2 +X 8\n".to_vec();
+    bytes.extend_from_slice(&[2, 2, 0, 8]);
+    for &value in [128, 64, 0, 129].iter() {
+        bytes.extend_from_slice(&[128 + 8, value]);
+    }
+    for _ in 0..8 {
+        bytes.extend_from_slice(&[128, 128, 128, 128]);
+    }
+    bytes
+}
+
+#[test]
+fn hdr_images_are_decoded() {
+    let image = HdrImage::decode(&hdr_file()).unwrap();
+    assert_eq!((image.width, image.height), (8, 2));
+    assert_eq!(image.pixels.len(), 8 * 2 * 3);
+    let expected = [128.5 / 128., 64.5 / 128., 0.5 / 128.];
+    assert_eq!(&image.pixels[..3], &expected);
+    assert_eq!(&image.pixels[21..24], &expected);
+    assert_eq!(&image.pixels[24..27], &[128.5 / 256.; 3]);
+
+    assert_eq!(HdrImage::decode(b"P6\n"), Err(HdrError::NotRadiance));
+    let truncated = hdr_file();
+    assert_eq!(
+        HdrImage::decode(&truncated[..truncated.len() - 1]),
+        Err(HdrError::Truncated)
+    );
+}
+
+#[test]
+fn hdr_skyboxes_are_converted_to_cubemaps() {
+    let (backend, scene) = setup();
+    let renderer = scene.renderer();
+    let baseline = renderer.borrow().resource_counts();
+    scene.set_hdr_skybox(&hdr_file()).unwrap();
+    assert_eq!(scene.skybox(), None);
+    let environment = renderer.borrow().environment().unwrap();
+    assert!(environment.hdr);
+    // the cubemap is rendered from the equirectangular image, which is deleted right after
+    assert!(backend
+        .commands()
+        .iter()
+        .any(|c| matches!(c, Command::TexSubImage { width: 8, height: 2, .. })));
+    assert_eq!(
+        renderer.borrow().resource_counts().textures,
+        baseline.textures + 1
+    );
+
+    backend.clear();
+    render(&scene);
+    assert!(environment.maps.is_none());
+    assert!(renderer.borrow().environment().unwrap().maps.is_some());
+    let draws = backend.draw_calls();
+    let skybox = draws.iter().find(|draw| draw.framebuffer.is_none()).unwrap();
+    assert_eq!(skybox.uniform("hdr"), Some(Uniform::Bool(true)));
+    assert_eq!(skybox.textures[&0], environment.source);
+}
+
+#[test]
+fn shadow_maps_are_rendered_into_the_atlas() {
+    let (backend, scene) = setup();
+    let caster = scene.from_mesh(Some(cube(1., 1., 1.)), false);
+    let ghost = scene.from_mesh(Some(cube(1., 0., 0.)), false);
+    let mut info = ghost.info();
+    info.render_flags.cast_shadows = false;
+    info.render_flags.receive_shadows = false;
+    ghost.set_info(info);
+    scene.add(rc_rcell(caster));
+    scene.add(rc_rcell(ghost));
+    let sun = scene.light(LightType::Directional, [1., 1., 1.], 1.);
+    scene.add_light(&sun);
+    scene.set_shadows(&sun, Some(Default::default()));
+    let spot = scene.light(LightType::Spot, [1., 1., 1.], 1.);
+    scene.add_light(&spot);
+    scene.set_shadows(
+        &spot,
+        Some(ShadowConfig {
+            resolution: 300,
+            ..Default::default()
+        }),
+    );
+    let point = scene.light(LightType::Point, [1., 1., 1.], 1.);
+    scene.add_light(&point);
+    backend.clear();
+    render(&scene);
+
+    // three cascades and a spot light tile, each with only the caster drawn into it
+    let draws = backend.draw_calls();
+    let offscreen: Vec<_> = draws.iter().filter(|d| d.framebuffer.is_some()).collect();
+    assert_eq!(offscreen.len(), 4);
+    assert!(offscreen.iter().all(|d| d.is_enabled(gl::DEPTH_TEST)));
+    let atlas = backend
+        .commands()
+        .into_iter()
+        .find_map(|c| match c {
+            Command::CreateTextureStorage {
+                texture,
+                internal_format: gl::DEPTH_COMPONENT24,
+                width,
+                ..
+            } => Some((texture, width)),
+            _ => None,
+        })
+        .unwrap();
+    // 3 * 1024² and 512² texels fit in 2048²
+    assert_eq!(atlas.1, 2048);
+    assert!(backend.commands().contains(&Command::FramebufferTexture2d {
+        attachment: gl::DEPTH_ATTACHMENT,
+        tex_target: gl::TEXTURE_2D,
+        texture: Some(atlas.0),
+        level: 0,
+    }));
+
+    let lit: Vec<_> = draws
+        .iter()
+        .filter(|d| d.framebuffer.is_none() && d.uniform("receive_shadows").is_some())
+        .collect();
+    assert_eq!(lit.len(), 2);
+    let draw = lit[0];
+    assert_eq!(draw.textures[&7], atlas.0);
+    assert_eq!(draw.uniform("shadow_atlas"), Some(Uniform::I32(7)));
+    assert_eq!(draw.uniform("dir_lights[0].shadow"), Some(Uniform::I32(0)));
+    assert_eq!(draw.uniform("dir_lights[0].cascades"), Some(Uniform::I32(3)));
+    assert_eq!(draw.uniform("spot_lights[0].shadow"), Some(Uniform::I32(3)));
+    assert_eq!(draw.uniform("spot_lights[0].shadow_bias"), Some(Uniform::F32(0.05)));
+    assert_eq!(draw.uniform("point_lights[0].shadow"), None);
+    // the cascades cover farther and farther slices of the view
+    let split = |i: usize| match draw.uniform(&format!("shadow_splits[{}]", i)) {
+        Some(Uniform::F32(split)) => split,
+        other => panic!("Unexpected split: {:?}", other),
+    };
+    assert!(split(0) < split(1) && split(1) < split(2));
+    assert!((split(2) - 50.).abs() < 1e-3);
+    assert_eq!(draw.uniform("receive_shadows"), Some(Uniform::Bool(true)));
+    assert_eq!(lit[1].uniform("receive_shadows"), Some(Uniform::Bool(false)));
+}
+
+#[test]
+fn lights_without_shadows_skip_the_shadow_pass() {
+    let (backend, scene) = setup();
+    scene.add(rc_rcell(scene.from_mesh(Some(cube(1., 1., 1.)), false)));
+    let sun = scene.light(LightType::Directional, [1., 1., 1.], 1.);
+    scene.add_light(&sun);
+    backend.clear();
+    render(&scene);
+
+    let draws = backend.draw_calls();
+    assert!(draws.iter().all(|d| d.framebuffer.is_none()));
+    let draw = draws
+        .iter()
+        .find(|d| d.uniform("receive_shadows").is_some())
+        .unwrap();
+    assert_eq!(draw.uniform("dir_lights[0].shadow"), Some(Uniform::I32(-1)));
+    assert_eq!(scene.renderer().borrow().resource_counts().framebuffers, 0);
+}
+
+#[test]
+fn deferred_shading_lights_the_gbuffer() {
+    let (backend, scene) = setup();
+    scene
+        .renderer()
+        .borrow_mut()
+        .set_pipeline(Pipeline::Deferred);
+    scene.add(rc_rcell(scene.from_mesh(Some(cube(1., 0., 0.)), false)));
+    let glass = scene.from_mesh(Some(cube(0., 0., 1.)), false);
+    let mut info = glass.info();
+    info.render_flags.blend = true;
+    glass.set_info(info);
+    scene.add(rc_rcell(glass));
+    let sun = scene.light(LightType::Directional, [1., 1., 1.], 1.);
+    scene.add_light(&sun);
+    for _ in 0..3 {
+        let point = scene.light(LightType::Point, [1., 1., 1.], 1.);
+        scene.add_light(&point);
+    }
+    let dark = scene.light(LightType::Point, [0., 0., 0.], 1.);
+    scene.add_light(&dark);
+    backend.clear();
+    render(&scene);
+
+    let commands = backend.commands();
+    let attachments: Vec<u32> = (0..4).map(|i| gl::COLOR_ATTACHMENT0 + i).collect();
+    assert!(commands.contains(&Command::DrawBuffers(attachments)));
+    let draws = backend.draw_calls();
+    let red = Some(Uniform::Vec4([1., 0., 0., 1.]));
+    let blue = Some(Uniform::Vec4([0., 0., 1., 1.]));
+
+    // only the opaque cube goes into the G-buffer
+    let gbuffer: Vec<_> = draws
+        .iter()
+        .filter(|d| d.uniform("pbr").is_some())
+        .collect();
+    assert_eq!(gbuffer.len(), 1);
+    assert_eq!(gbuffer[0].uniform("color"), red);
+    assert!(gbuffer[0].framebuffer.is_some());
+    let lighting = draws
+        .iter()
+        .find(|d| d.uniform("num_l_dir").is_some() && d.framebuffer.is_some())
+        .unwrap();
+    assert_eq!(lighting.uniform("num_l_dir"), Some(Uniform::I32(1)));
+    assert_eq!(lighting.uniform("num_l_point"), None);
+    assert_eq!(lighting.uniform("depth_buffer"), Some(Uniform::I32(8)));
+    // the lights that are too dark to see get no volume
+    let volumes = draws.iter().find(|d| d.instances.is_some()).unwrap();
+    assert_eq!(volumes.instances, Some(3));
+    assert!(volumes.is_enabled(gl::BLEND));
+    // a position, radius, color and intensity for each light
+    let vao = backend.vertex_array(volumes.vao.unwrap()).unwrap();
+    match backend.buffer(vao.attributes[&1].buffer) {
+        Some(BufferContents::F32(data)) => assert_eq!(data.len(), 3 * 8),
+        other => panic!("Unexpected light volumes: {:?}", other),
+    }
+
+    // the lit G-buffer reaches the canvas before the transparent cube is drawn over it
+    let canvas: Vec<_> = draws.iter().filter(|d| d.framebuffer.is_none()).collect();
+    let resolve = canvas
+        .iter()
+        .position(|d| d.uniform("light_buffer").is_some())
+        .unwrap();
+    assert!(canvas[resolve].is_enabled(gl::DEPTH_TEST));
+    let glass = canvas.iter().position(|d| d.uniform("color") == blue).unwrap();
+    assert!(resolve < glass);
+    assert!(canvas.iter().all(|d| d.uniform("color") != red));
+}
+
+#[test]
+fn switching_back_to_forward_shading_frees_the_gbuffer() {
+    let (backend, scene) = setup();
+    scene.add(rc_rcell(scene.from_mesh(Some(cube(1., 1., 1.)), false)));
+    let point = scene.light(LightType::Point, [1., 1., 1.], 1.);
+    scene.add_light(&point);
+    render(&scene);
+    let renderer = scene.renderer();
+    let baseline = renderer.borrow().resource_counts();
+    renderer.borrow_mut().set_pipeline(Pipeline::Deferred);
+    render(&scene);
+    let deferred = renderer.borrow().resource_counts();
+    assert_eq!(deferred.framebuffers, baseline.framebuffers + 2);
+    assert_eq!(deferred.textures, baseline.textures + 6);
+    render(&scene);
+    assert_eq!(renderer.borrow().resource_counts(), deferred);
+
+    renderer.borrow_mut().set_pipeline(Pipeline::Forward);
+    assert_eq!(renderer.borrow().resource_counts(), baseline);
+    backend.clear();
+    render(&scene);
+    assert!(backend.draw_calls().iter().all(|d| d.framebuffer.is_none()));
+}
+
+#[test]
+fn hdr_rendering_tone_maps_a_float_target() {
+    let (backend, scene) = setup();
+    {
+        let renderer = scene.renderer();
+        let mut renderer = renderer.borrow_mut();
+        renderer.set_hdr(true);
+        renderer.set_tone_mapping(ToneMapping::Reinhard);
+        renderer.set_exposure(2.);
+    }
+    scene.add(rc_rcell(scene.from_mesh(Some(cube(1., 0., 0.)), false)));
+    let overlay = scene.from_mesh(Some(cube(0., 1., 0.)), false);
+    let mut info = overlay.info();
+    info.render_flags.depth = false;
+    overlay.set_info(info);
+    scene.add(rc_rcell(overlay));
+    backend.clear();
+    render(&scene);
+
+    let commands = backend.commands();
+    let storage = |format: u32| {
+        commands.iter().any(|c| match c {
+            Command::CreateTextureStorage {
+                internal_format, ..
+            } => *internal_format == format,
+            _ => false,
+        })
+    };
+    assert!(storage(gl::RGBA16F));
+    assert!(storage(gl::DEPTH24_STENCIL8));
+    assert!(commands.iter().any(|c| match c {
+        Command::FramebufferTexture2d { attachment, .. } => {
+            *attachment == gl::DEPTH_STENCIL_ATTACHMENT
+        }
+        _ => false,
+    }));
+
+    let draws = backend.draw_calls();
+    let position = |color: [f32; 4]| {
+        draws
+            .iter()
+            .position(|d| d.uniform("color") == Some(Uniform::Vec4(color)))
+            .unwrap()
+    };
+    let (lit, overlay) = (position([1., 0., 0., 1.]), position([0., 1., 0., 1.]));
+    let tone_map = draws
+        .iter()
+        .position(|d| d.uniform("tone_mapping").is_some())
+        .unwrap();
+    // the scene is drawn in linear space into the target, then tone mapped to the canvas
+    assert!(draws[lit].framebuffer.is_some());
+    assert_eq!(draws[lit].uniform("linear_output"), Some(Uniform::Bool(true)));
+    assert!(lit < tone_map);
+    assert!(draws[tone_map].framebuffer.is_none());
+    assert_eq!(draws[tone_map].uniform("tone_mapping"), Some(Uniform::I32(1)));
+    assert_eq!(draws[tone_map].uniform("exposure"), Some(Uniform::F32(2.)));
+    // overlays keep their colors
+    assert!(tone_map < overlay);
+    assert!(draws[overlay].framebuffer.is_none());
+    assert_eq!(
+        draws[overlay].uniform("linear_output"),
+        Some(Uniform::Bool(false))
+    );
+}
+
+#[test]
+fn turning_hdr_off_frees_the_target() {
+    let (backend, scene) = setup();
+    scene.add(rc_rcell(scene.from_mesh(Some(cube(1., 1., 1.)), false)));
+    render(&scene);
+    let renderer = scene.renderer();
+    let baseline = renderer.borrow().resource_counts();
+    renderer.borrow_mut().set_hdr(true);
+    render(&scene);
+    let hdr = renderer.borrow().resource_counts();
+    assert_eq!(hdr.framebuffers, baseline.framebuffers + 1);
+    assert_eq!(hdr.textures, baseline.textures + 2);
+    render(&scene);
+    assert_eq!(renderer.borrow().resource_counts(), hdr);
+
+    renderer.borrow_mut().set_hdr(false);
+    assert_eq!(renderer.borrow().resource_counts(), baseline);
+    backend.clear();
+    render(&scene);
+    let draws = backend.draw_calls();
+    assert!(draws.iter().all(|d| d.framebuffer.is_none()));
+    assert_eq!(draws[0].uniform("linear_output"), Some(Uniform::Bool(false)));
+}
+
+#[test]
+fn post_effects_run_in_order_before_the_overlays() {
+    let (backend, scene) = setup();
+    scene.renderer().borrow_mut().set_post_effects(vec![
+        PostEffect::Ssao {
+            radius: 0.5,
+            intensity: 1.,
+        },
+        PostEffect::Bloom {
+            threshold: 1.,
+            intensity: 0.5,
+        },
+        PostEffect::Fxaa,
+    ]);
+    scene.add(rc_rcell(scene.from_mesh(Some(cube(1., 0., 0.)), false)));
+    let overlay = scene.from_mesh(Some(cube(0., 1., 0.)), false);
+    let mut info = overlay.info();
+    info.render_flags.depth = false;
+    overlay.set_info(info);
+    scene.add(rc_rcell(overlay));
+    backend.clear();
+    render(&scene);
+
+    let draws = backend.draw_calls();
+    let position = |f: &dyn Fn(&DrawCall) -> bool| draws.iter().position(f).unwrap();
+    let lit = position(&|d| d.uniform("color") == Some(Uniform::Vec4([1., 0., 0., 1.])));
+    let overlay = position(&|d| d.uniform("color") == Some(Uniform::Vec4([0., 1., 0., 1.])));
+    let ssao = position(&|d| d.uniform("radius").is_some());
+    let bloom = position(&|d| d.uniform("threshold").is_some());
+    let fxaa = position(&|d| d.uniform("color_buffer").is_some() && d.uniforms.len() == 1);
+    assert!(lit < ssao && ssao < bloom && bloom < fxaa && fxaa < overlay);
+    // without HDR the scene keeps its sRGB colors, and there's no tone mapping
+    assert_eq!(draws[lit].uniform("linear_output"), Some(Uniform::Bool(false)));
+    assert!(draws.iter().all(|d| d.uniform("tone_mapping").is_none()));
+
+    // each pass reads the one before, and only the last one draws to the canvas
+    let target = draws[lit].framebuffer;
+    assert!(target.is_some());
+    assert_eq!(draws[ssao].uniform("depth_buffer"), Some(Uniform::I32(1)));
+    assert!(draws[ssao].textures.contains_key(&1));
+    for pass in [ssao, bloom].iter() {
+        let output = draws[*pass].framebuffer;
+        assert!(output.is_some() && output != target);
+    }
+    assert_ne!(draws[ssao].framebuffer, draws[bloom].framebuffer);
+    assert_eq!(draws[bloom].uniform("depth_buffer"), None);
+    assert!(draws[fxaa].framebuffer.is_none());
+    assert!(draws[overlay].framebuffer.is_none());
+    // bloom blurs with the mip levels of its input
+    assert!(backend
+        .commands()
+        .contains(&Command::GenerateMipmap(gl::TEXTURE_2D)));
+}
+
+#[test]
+fn color_grading_waits_for_its_lut() {
+    let (backend, scene) = setup();
+    scene.add(rc_rcell(scene.from_mesh(Some(cube(1., 1., 1.)), false)));
+    render(&scene);
+    let renderer = scene.renderer();
+    let baseline = renderer.borrow().resource_counts();
+    renderer
+        .borrow_mut()
+        .set_post_effects(vec![PostEffect::ColorGrading {
+            lut: "lut.png".to_owned(),
+            intensity: 0.8,
+        }]);
+    backend.clear();
+    render(&scene);
+    let grading = |backend: &RecordingBackend| {
+        backend
+            .draw_calls()
+            .into_iter()
+            .find(|d| d.uniform("lut").is_some())
+            .unwrap()
+    };
+    let draw = grading(&backend);
+    assert!(draw.framebuffer.is_none());
+    assert_eq!(draw.uniform("lut"), Some(Uniform::I32(2)));
+    assert_eq!(draw.uniform("intensity"), Some(Uniform::F32(0.)));
+    let lut = draw.textures[&2];
+    assert_eq!(backend.texture(lut).unwrap().0, vec!["lut.png".to_owned()]);
+
+    backend.load_textures();
+    backend.clear();
+    render(&scene);
+    assert_eq!(grading(&backend).uniform("intensity"), Some(Uniform::F32(0.8)));
+    // the scene target and the lut, without buffers between passes for a single one
+    let counts = renderer.borrow().resource_counts();
+    assert_eq!(counts.framebuffers, baseline.framebuffers + 1);
+    assert_eq!(counts.textures, baseline.textures + 3);
+
+    renderer.borrow_mut().set_post_effects(Vec::new());
+    assert_eq!(renderer.borrow().resource_counts(), baseline);
+}
+
+#[test]
+fn scenes_render_into_targets_that_materials_draw_with() {
+    let (backend, scene) = setup();
+    let renderer = scene.renderer();
+    let target = renderer
+        .borrow()
+        .create_render_target(RenderTargetConfig {
+            size: TargetSize::Fixed {
+                width: 256,
+                height: 128,
+            },
+            ..Default::default()
+        });
+    let texture = renderer.borrow().render_target_texture(target).unwrap();
+    scene.add(rc_rcell(scene.from_mesh(Some(cube(1., 0., 0.)), false)));
+    let mirror = Mesh::new(
+        Geometry::from_genmesh(&Cube::new()),
+        Material::new_color(0., 0., 1., 1.0).render_target(target),
+    );
+    scene.add(rc_rcell(scene.from_mesh(Some(mirror), false)));
+    let minimap = Viewport::new(
+        ProjectionConfig {
+            fov: std::f32::consts::PI / 2.,
+            near: 0.1,
+            far: 100.,
+        },
+        2.,
+    );
+    backend.clear();
+    renderer
+        .borrow()
+        .render_to_target(&scene, &minimap, target);
+
+    let commands = backend.commands();
+    assert!(commands.contains(&Command::Viewport(0, 0, 256, 128)));
+    assert_eq!(commands.last(), Some(&Command::Viewport(0, 0, 800, 600)));
+    let draws = backend.draw_calls();
+    let red = Some(Uniform::Vec4([1., 0., 0., 1.]));
+    let blue = Some(Uniform::Vec4([0., 0., 1., 1.]));
+    let cube = draws.iter().find(|d| d.uniform("color") == red).unwrap();
+    let framebuffer = backend.framebuffer(cube.framebuffer.unwrap()).unwrap();
+    assert_eq!(framebuffer[&gl::COLOR_ATTACHMENT0].texture, texture);
+    // the mirror can't show itself
+    assert!(draws.iter().all(|d| d.uniform("color") != blue));
+
+    backend.clear();
+    render(&scene);
+    let mirror = backend
+        .draw_calls()
+        .into_iter()
+        .find(|d| d.uniform("color") == blue)
+        .unwrap();
+    assert!(mirror.framebuffer.is_none());
+    assert_eq!(mirror.uniform("has_albedo"), Some(Uniform::Bool(true)));
+    assert_eq!(mirror.textures.get(&0), Some(&texture));
+}
+
+#[test]
+fn multisampled_targets_are_resolved_into_their_textures() {
+    let (backend, scene) = setup();
+    let renderer = scene.renderer();
+    backend.clear();
+    let target = renderer
+        .borrow()
+        .create_render_target(RenderTargetConfig {
+            samples: 4,
+            ..Default::default()
+        });
+    let samples: Vec<i32> = backend
+        .commands()
+        .iter()
+        .filter_map(|c| match c {
+            Command::CreateRenderbuffer { samples, .. } => Some(*samples),
+            _ => None,
+        })
+        .collect();
+    // color and depth
+    assert_eq!(samples, vec![4, 4]);
+    scene.add(rc_rcell(scene.from_mesh(Some(cube(1., 1., 1.)), false)));
+    backend.clear();
+    renderer
+        .borrow()
+        .render_to_target(&scene, &scene.view().borrow(), target);
+
+    let draw = backend.draw_calls()[0].clone();
+    let blit = backend
+        .commands()
+        .into_iter()
+        .find_map(|c| match c {
+            Command::BlitFramebuffer {
+                read, draw, mask, ..
+            } => Some((read, draw, mask)),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(blit.0, draw.framebuffer);
+    assert_eq!(blit.2, gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+    let resolved = backend.framebuffer(blit.1.unwrap()).unwrap();
+    assert_eq!(
+        Some(resolved[&gl::COLOR_ATTACHMENT0].texture),
+        renderer.borrow().render_target_texture(target)
+    );
+    assert_eq!(
+        Some(resolved[&gl::DEPTH_ATTACHMENT].texture),
+        renderer.borrow().render_target_depth(target)
+    );
+}
+
+#[test]
+fn canvas_sized_targets_follow_resizes() {
+    let (_, scene) = setup();
+    let renderer = scene.renderer();
+    let baseline = renderer.borrow().resource_counts();
+    let target = renderer
+        .borrow()
+        .create_render_target(RenderTargetConfig {
+            size: TargetSize::Canvas(0.5),
+            stencil: true,
+            samples: 2,
+            ..Default::default()
+        });
+    assert_eq!(
+        renderer.borrow().render_target_size(target),
+        Some((400, 300))
+    );
+    let counts = renderer.borrow().resource_counts();
+    assert_eq!(counts.framebuffers, baseline.framebuffers + 2);
+    assert_eq!(counts.renderbuffers, baseline.renderbuffers + 2);
+    assert_eq!(counts.textures, baseline.textures + 2);
+    let texture = renderer.borrow().render_target_texture(target);
+
+    renderer.borrow_mut().set_size(1000, 500);
+    assert_eq!(
+        renderer.borrow().render_target_size(target),
+        Some((500, 250))
+    );
+    assert_ne!(renderer.borrow().render_target_texture(target), texture);
+    assert_eq!(renderer.borrow().resource_counts(), counts);
+
+    renderer.borrow().delete_render_target(target);
+    assert_eq!(renderer.borrow().resource_counts(), baseline);
+    assert_eq!(renderer.borrow().render_target_texture(target), None);
+}
+
+#[test]
+fn picking_reads_back_the_id_under_the_pixel() {
+    let (backend, scene) = setup();
+    let cube_node = scene.from_mesh(Some(cube(1., 0., 0.)), false);
+    cube_node.set_position(0., 0., 1.);
+    let handle = cube_node.handle();
+    let index = cube_node.index() as u32;
+    scene.add(rc_rcell(cube_node));
+    let overlay = scene.from_mesh(Some(cube(0., 1., 0.)), false);
+    let mut info = overlay.info();
+    info.render_flags.depth = false;
+    overlay.set_info(info);
+    let overlay_index = overlay.index() as u32;
+    scene.add(rc_rcell(overlay));
+
+    // the ray through the center of the canvas meets the top of the cube at (0, 1, 1)
+    let view = scene.view();
+    let view_proj = view.borrow().proj() * view.borrow().view();
+    let depth = (view_proj.transform_point(&Point3::new(0., 1., 1.)).z + 1.) / 2.;
+    backend.clear();
+    backend.stage_pixels(vec![index + 1, 0, depth.to_bits(), 0]);
+    let pick = scene
+        .renderer()
+        .borrow()
+        .pick(&scene, &view.borrow(), 400., 300.)
+        .unwrap();
+
+    let commands = backend.commands();
+    assert!(commands.contains(&Command::Viewport(0, 0, 1, 1)));
+    assert!(commands.contains(&Command::ClearBuffer {
+        buffer: gl::COLOR,
+        draw_buffer: 0,
+        value: [0; 4],
+    }));
+    let draws = backend.draw_calls();
+    assert_eq!(draws.len(), 2);
+    assert!(draws.iter().all(|d| d.framebuffer.is_some()));
+    // meshes without depth are drawn last, over the others
+    assert_eq!(draws[0].uniform("node"), Some(Uniform::U32(index + 1)));
+    assert!(draws[0].is_enabled(gl::DEPTH_TEST));
+    assert_eq!(draws[1].uniform("node"), Some(Uniform::U32(overlay_index + 1)));
+    assert!(!draws[1].is_enabled(gl::DEPTH_TEST));
+    match commands.last() {
+        Some(Command::Viewport(0, 0, 800, 600)) => (),
+        other => panic!("The viewport wasn't restored: {:?}", other),
+    }
+
+    assert_eq!(pick.node, handle);
+    assert_eq!(pick.instance, 0);
+    // the center of the pixel is half a pixel off the center of the canvas
+    assert!((pick.position - Point3::new(0., 1., 1.)).norm() < 1e-2);
+    let node = scene.root().borrow().children()[0].clone();
+    let geometry = node.borrow().mesh().unwrap().geometry.clone();
+    let triangle = pick.triangle.unwrap();
+    for i in &geometry.indices[triangle * 3..triangle * 3 + 3] {
+        assert_eq!(geometry.vertices[*i as usize * 3 + 1], 1.);
+    }
+}
+
+#[test]
+fn picking_the_background_finds_nothing() {
+    let (backend, scene) = setup();
+    scene.add(rc_rcell(scene.from_mesh(Some(cube(1., 0., 0.)), false)));
+    let renderer = scene.renderer();
+    let view = scene.view();
+    backend.clear();
+    assert_eq!(renderer.borrow().pick(&scene, &view.borrow(), 10., 10.), None);
+    // the cube is nowhere near the corner, so it isn't drawn
+    assert!(backend.draw_calls().is_empty());
+    backend.clear();
+    assert_eq!(renderer.borrow().pick(&scene, &view.borrow(), 800., 10.), None);
+    assert!(backend.commands().is_empty());
+}
+
+#[test]
+fn raycasts_return_every_hit_nearest_first() {
+    let (_, scene) = setup();
+    let near = scene.from_mesh(Some(cube(1., 0., 0.)), false);
+    let near_handle = near.handle();
+    scene.add(rc_rcell(near));
+    let far = scene.from_mesh(Some(cube(0., 1., 0.)), false);
+    far.set_position(0., 0., -5.);
+    far.set_scale(2.);
+    let far_handle = far.handle();
+    scene.add(rc_rcell(far));
+    let transforms = [5., 0.]
+        .iter()
+        .map(|x| {
+            let mut transform = Transform::identity();
+            transform.isometry.translation.vector.x = *x;
+            transform
+        })
+        .collect();
+    let instanced = scene.instanced(
+        cube(0., 0., 1.),
+        Default::default(),
+        Instances {
+            transforms,
+            colors: None,
+        },
+    );
+    instanced.set_position(0., 0., -20.);
+    let instanced_handle = instanced.handle();
+    scene.add(rc_rcell(instanced));
+    let ignored = scene.from_mesh(Some(cube(1., 1., 1.)), false);
+    ignored.set_position(0., 0., 5.);
+    let mut info = ignored.info();
+    info.name = "ignored".into();
+    ignored.set_info(info);
+    scene.add(rc_rcell(ignored));
+
+    let ray = Ray::new(Point3::new(0., 0., 10.), Vector3::new(0., 0., -1.));
+    let hits = scene.raycast(&ray, |node| node.info().name != "ignored");
+    let found: Vec<_> = hits.iter().map(|h| (h.node, h.instance)).collect();
+    assert_eq!(
+        found,
+        vec![(near_handle, 0), (far_handle, 0), (instanced_handle, 1)]
+    );
+    let distances: Vec<f32> = hits.iter().map(|h| h.distance).collect();
+    assert_eq!(distances, vec![9., 13., 29.]);
+    assert_eq!(hits[1].point, Point3::new(0., 0., -3.));
+    for hit in hits.iter() {
+        assert!((hit.normal - Vector3::z()).norm() < 1e-5);
+    }
+    let node = scene.root().borrow().children()[0].clone();
+    let geometry = node.borrow().mesh().unwrap().geometry.clone();
+    let triangle = hits[0].triangle;
+    for i in &geometry.indices[triangle * 3..triangle * 3 + 3] {
+        assert_eq!(geometry.vertices[*i as usize * 3 + 2], 1.);
+    }
+}
+
+#[test]
+fn raycast_colliders_are_cached_until_the_mesh_changes() {
+    let (_, scene) = setup();
+    let node = scene.from_mesh(Some(cube(1., 0., 0.)), false);
+    let copy = scene.instance(&node);
+    copy.set_position(0., 0., -5.);
+    let (index, copy_index) = (node.index(), copy.index());
+    let node = rc_rcell(node);
+    scene.add(node.clone());
+    scene.add(rc_rcell(copy));
+    let storage = scene.storage();
+    assert!(storage.borrow().collider(index).is_none());
+
+    let ray = Ray::new(Point3::new(0., 0., 10.), Vector3::new(0., 0., -1.));
+    assert_eq!(scene.raycast(&ray, |_| true).len(), 2);
+    let collider = storage.borrow().collider(index).cloned().unwrap();
+    // the instance shares the mesh, and so its collider
+    assert_eq!(storage.borrow().collider(copy_index), Some(&collider));
+    scene.raycast(&ray, |_| true);
+    assert_eq!(storage.borrow().collider(index), Some(&collider));
+
+    // recoloring leaves the triangles as they are
+    node.borrow().change_color([0., 1., 0.]);
+    assert_eq!(storage.borrow().collider(index), Some(&collider));
+    scene.set_mesh(&node.borrow(), Some(cube(0., 1., 0.)));
+    assert!(storage.borrow().collider(index).is_none());
+    let hits = scene.raycast(&ray, |n| n.index() == index);
+    assert_eq!(hits.len(), 1);
+    assert_ne!(storage.borrow().collider(index), Some(&collider));
+}
+
+#[test]
+fn meshes_outside_the_view_are_culled() {
+    // the camera is at (0, 3, 3) looking at the origin
+    let (backend, scene) = setup();
+    scene.add(rc_rcell(scene.from_mesh(Some(cube(1., 0., 0.)), false)));
+    let behind = scene.from_mesh(Some(cube(0., 1., 0.)), false);
+    behind.set_position(0., 9., 9.);
+    scene.add(rc_rcell(behind));
+    let aside = scene.from_mesh(Some(cube(0., 1., 0.)), false);
+    aside.set_position(12., 0., 0.);
+    let aside = rc_rcell(aside);
+    scene.add(aside.clone());
+    // scaled up, the same cube reaches into the view
+    let scaled = scene.from_mesh(Some(cube(1., 0., 1.)), false);
+    scaled.set_position(12., 0., 0.);
+    scaled.set_scale(6.);
+    scene.add(rc_rcell(scaled));
+    // the node is out of the view but one of its instances isn't
+    let transforms = [-50., 0.]
+        .iter()
+        .map(|x| {
+            let mut transform = Transform::identity();
+            transform.isometry.translation.vector.x = *x;
+            transform
+        })
+        .collect();
+    let instanced = scene.instanced(
+        cube(0., 0., 1.),
+        Default::default(),
+        Instances {
+            transforms,
+            colors: None,
+        },
+    );
+    instanced.set_position(50., 0., 0.);
+    scene.add(rc_rcell(instanced));
+    backend.clear();
+    render(&scene);
+
+    assert_eq!(scene.renderer().borrow().culled(), 2);
+    assert_eq!(backend.draw_calls().len(), 3);
+    assert!(backend
+        .draw_calls()
+        .iter()
+        .all(|draw| draw.uniform("color") != Some(Uniform::Vec4([0., 1., 0., 1.]))));
+
+    aside.borrow().set_position(0., 0., -2.);
+    backend.clear();
+    render(&scene);
+    assert_eq!(scene.renderer().borrow().culled(), 1);
+    assert_eq!(backend.draw_calls().len(), 4);
+}
+
+#[test]
+fn bounds_are_cached_per_mesh_and_brought_into_world_space() {
+    let (_, scene) = setup();
+    let node = scene.from_mesh(Some(cube(1., 0., 0.)), false);
+    node.set_position(1., 2., 3.);
+    node.set_scale(2.);
+    let copy = scene.instance(&node);
+    let (index, copy_index) = (node.index(), copy.index());
+    let node = rc_rcell(node);
+    scene.add(node.clone());
+    scene.add(rc_rcell(copy));
+    let storage = scene.storage();
+    assert!(storage.borrow().bounds(index).is_none());
+    render(&scene);
+
+    let bounds = storage.borrow().bounds(index).cloned().unwrap();
+    assert_eq!(*bounds.aabb.mins(), Point3::new(-1., -1., -1.));
+    assert_eq!(*bounds.aabb.maxs(), Point3::new(1., 1., 1.));
+    assert_eq!(*bounds.sphere.center(), Point3::origin());
+    assert!((bounds.sphere.radius() - 3f32.sqrt()).abs() < 1e-5);
+    // the instance shares the mesh, and so its bounds
+    assert_eq!(storage.borrow().bounds(copy_index), Some(&bounds));
+
+    let world = storage.borrow().world_bounds(index).unwrap();
+    assert_eq!(*world.aabb.mins(), Point3::new(-1., 0., 1.));
+    assert_eq!(*world.aabb.maxs(), Point3::new(3., 4., 5.));
+    assert_eq!(*world.sphere.center(), Point3::new(1., 2., 3.));
+    assert!((world.sphere.radius() - 2. * 3f32.sqrt()).abs() < 1e-5);
+
+    node.borrow().change_color([0., 1., 0.]);
+    assert_eq!(storage.borrow().bounds(index), Some(&bounds));
+    scene.set_mesh(&node.borrow(), Some(cube(0., 1., 0.)));
+    assert!(storage.borrow().bounds(index).is_none());
+    render(&scene);
+    assert_eq!(storage.borrow().bounds(index), Some(&bounds));
+}
+
+#[test]
+fn the_spatial_index_follows_nodes_as_they_move() {
+    let (_, scene) = setup();
+    // a row of cubes along x, 10 apart
+    let nodes: Vec<_> = (0..10)
+        .map(|n| {
+            let node = scene.from_mesh(Some(cube(1., 0., 0.)), false);
+            node.set_position(10. * n as f32, 0., 0.);
+            let node = rc_rcell(node);
+            scene.add(node.clone());
+            node
+        })
+        .collect();
+    let slots: Vec<usize> = nodes.iter().map(|n| n.borrow().index()).collect();
+    let storage = scene.storage();
+    let update = || {
+        let mut storage = storage.borrow_mut();
+        storage.update_world_transforms();
+        storage.update_spatial_index();
+    };
+    let query = |query: &dyn Fn(&SpatialIndex) -> Vec<usize>| {
+        let mut found = query(storage.borrow().spatial_index());
+        found.sort_unstable();
+        found
+    };
+    update();
+    assert_eq!(storage.borrow().spatial_index().len(), 10);
+
+    let along_x = Ray::new(Point3::new(-10., 0., 0.), Vector3::x());
+    assert_eq!(query(&|index| index.query_ray(&along_x)), slots);
+    let across = Ray::new(Point3::new(30., -10., 0.), Vector3::y());
+    assert_eq!(query(&|index| index.query_ray(&across)), vec![slots[3]]);
+    let sphere = BoundingSphere::new(Point3::new(50., 0., 0.), 5.);
+    assert_eq!(query(&|index| index.query_sphere(&sphere)), vec![slots[5]]);
+    let sphere = BoundingSphere::new(Point3::new(50., 0., 0.), 10.);
+    assert_eq!(
+        query(&|index| index.query_sphere(&sphere)),
+        slots[4..7].to_vec()
+    );
+    let aabb = AABB::new(Point3::new(15., -1., -1.), Point3::new(35., 1., 1.));
+    assert_eq!(query(&|index| index.query_aabb(&aabb)), slots[2..4].to_vec());
+    let view = scene.view();
+    let frustum = Frustum::new(&(view.borrow().proj() * view.borrow().view()));
+    assert_eq!(query(&|index| index.query_frustum(&frustum)), vec![slots[0]]);
+
+    nodes[3].borrow().set_position(30., 20., 0.);
+    nodes[5].borrow().set_position(50.1, 0., 0.);
+    update();
+    let moved = BoundingSphere::new(Point3::new(30., 20., 0.), 2.);
+    assert_eq!(query(&|index| index.query_sphere(&moved)), vec![slots[3]]);
+    let bounds = storage.borrow().spatial_index().bounds(slots[5]).cloned();
+    assert_eq!(*bounds.unwrap().aabb.mins(), Point3::new(49.1, -1., -1.));
+    let mut rest = slots.clone();
+    rest.remove(3);
+    assert_eq!(query(&|index| index.query_ray(&along_x)), rest);
+
+    scene.delete(&nodes[3].borrow());
+    update();
+    assert_eq!(storage.borrow().spatial_index().len(), 9);
+    assert!(query(&|index| index.query_sphere(&moved)).is_empty());
+}
@@ -0,0 +1,188 @@
+//! The scene graph of headless scenes, and saving and loading it.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use common::{cube, headless};
+use moksha::{
+    rc_rcell,
+    renderer::ShadowConfig,
+    scene::{Instances, SkyboxSource, SCENE_FORMAT_VERSION},
+    LightType, Node, RcRcell, Scene, Transform,
+};
+
+/// An empty node under the parent, moved one meter along x.
+fn child_of(scene: &Scene, parent: &RcRcell<Node>, name: &str) -> RcRcell<Node> {
+    let node = rc_rcell(scene.empty(name));
+    node.borrow().set_position(1., 0., 0.);
+    parent.borrow().add(node.clone());
+    node
+}
+
+/// Where the last pass of `update_world_transforms` put the node.
+fn cached_x(scene: &Scene, node: &RcRcell<Node>) -> f32 {
+    let storage = scene.storage();
+    let storage = storage.borrow();
+    storage
+        .world_transform(node.borrow().index())
+        .isometry
+        .translation
+        .x
+}
+
+#[test]
+fn world_transforms_follow_the_dirty_subtrees() {
+    let scene = headless();
+    let a = rc_rcell(scene.empty("a"));
+    scene.add(a.clone());
+    let b = child_of(&scene, &a, "b");
+    let c = child_of(&scene, &b, "c");
+    let other = rc_rcell(scene.empty("other"));
+    scene.add(other.clone());
+    let d = child_of(&scene, &other, "d");
+    scene.storage().borrow_mut().update_world_transforms();
+    assert_eq!(cached_x(&scene, &c), 2.);
+    assert_eq!(cached_x(&scene, &d), 1.);
+
+    // moving an ancestor moves the whole subtree
+    a.borrow().set_position(10., 0., 0.);
+    c.borrow().set_position(2., 0., 0.);
+    scene.storage().borrow_mut().update_world_transforms();
+    assert_eq!(cached_x(&scene, &b), 11.);
+    assert_eq!(cached_x(&scene, &c), 13.);
+    assert_eq!(cached_x(&scene, &d), 1.);
+
+    // as does moving a node to another parent
+    other.borrow().add(b.clone());
+    scene.storage().borrow_mut().update_world_transforms();
+    assert_eq!(cached_x(&scene, &c), 3.);
+    assert_eq!(c.borrow().global_position()[0], 3.);
+}
+
+#[test]
+#[should_panic(expected = "Can't make a node the child of its own descendant!")]
+fn nodes_cant_be_attached_under_their_descendants() {
+    let scene = headless();
+    let a = rc_rcell(scene.empty("a"));
+    scene.add(a.clone());
+    let b = child_of(&scene, &a, "b");
+    let c = child_of(&scene, &b, "c");
+    c.borrow().add(a);
+}
+
+/// A scene with a hierarchy, lights, an instanced node and a skybox.
+fn furnished_scene() -> Scene {
+    let scene = headless();
+    let parent = rc_rcell(scene.from_mesh(Some(cube(1., 0., 0.)), false));
+    parent.borrow().set_position(1., 2., 3.);
+    scene.add(parent.clone());
+    let child = child_of(&scene, &parent, "child");
+    child.borrow().set_scale(2.);
+    let transforms = (0..3)
+        .map(|i| {
+            let mut transform = Transform::identity();
+            transform.isometry.translation.vector.z = i as f32;
+            transform
+        })
+        .collect();
+    let instanced = scene.instanced(
+        cube(0., 1., 0.),
+        Default::default(),
+        Instances {
+            transforms,
+            colors: Some(vec![[1., 1., 0., 1.]; 3]),
+        },
+    );
+    scene.add(rc_rcell(instanced));
+    let sun = scene.light(LightType::Directional, [1., 0.9, 0.8], 2.);
+    scene.add_light(&sun);
+    scene.set_shadows(&sun, Some(ShadowConfig::default()));
+    let point = scene.light(LightType::Point, [0., 0., 1.], 0.5);
+    scene.add_light(&point);
+    scene.set_skybox("sky", "png");
+    scene
+}
+
+#[test]
+fn scenes_are_saved_and_loaded() {
+    let scene = furnished_scene();
+    let document = scene.to_document();
+    assert_eq!(document.version, SCENE_FORMAT_VERSION);
+    assert_eq!(
+        document.skybox,
+        Some(SkyboxSource {
+            dir: "sky".into(),
+            ext: "png".into(),
+        })
+    );
+    assert_eq!(document.nodes.len(), 4);
+    assert_eq!(
+        document.nodes.iter().filter(|n| n.light.is_some()).count(),
+        2
+    );
+    let instanced = document.nodes.iter().find(|n| n.instances.is_some());
+    assert_eq!(
+        instanced
+            .unwrap()
+            .instances
+            .as_ref()
+            .unwrap()
+            .transforms
+            .len(),
+        3
+    );
+
+    let loaded = headless();
+    loaded.load(&scene.save()).unwrap();
+    assert_eq!(loaded.to_document(), document);
+    let child = loaded.find_node_w_name("child").unwrap();
+    assert_eq!(child.borrow().global_position(), [2., 2., 3.]);
+    assert_eq!(child.borrow().scale().x, 2.);
+    {
+        let storage = loaded.storage();
+        let storage = storage.borrow();
+        assert_eq!(storage.lights().count(), 2);
+        assert!(storage.lights().any(|l| l.shadows.is_some()));
+    }
+
+    // loading again replaces the nodes instead of adding to them
+    loaded.load(&scene.save()).unwrap();
+    assert_eq!(loaded.to_document(), document);
+}
+
+#[test]
+fn documents_from_newer_versions_are_rejected() {
+    let scene = furnished_scene();
+    let mut document = scene.to_document();
+    document.version = SCENE_FORMAT_VERSION + 1;
+    let src = serde_json::to_string(&document).unwrap();
+    let error = headless().load(&src).unwrap_err();
+    assert!(
+        error.contains("newer than the supported version"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn fields_missing_from_older_documents_take_their_defaults() {
+    // a node written before nodes could be left out of the document or be instanced
+    let src = serde_json::json!({
+        "version": SCENE_FORMAT_VERSION,
+        "skybox": null,
+        "nodes": [{
+            "info": { "name": "old", "draw_mode": "Triangle" },
+            "transform": Transform::identity(),
+            "mesh": null,
+            "light": null,
+            "children": [],
+            "owned_children": [],
+        }],
+    });
+    let scene = headless();
+    scene.load(&src.to_string()).unwrap();
+    let old = scene.find_node_w_name("old").unwrap();
+    assert!(old.borrow().info().persist);
+    assert_eq!(scene.to_document().nodes[0].instances, None);
+}
@@ -0,0 +1,233 @@
+//! Renders scenes with the software rasterizer and compares them with the images in
+//! `tests/golden`. Run with `UPDATE_GOLDEN=1 cargo test --features software` to write the current
+//! output as the new golden images after an intended change in shading.
+
+#![cfg(all(feature = "software", not(target_arch = "wasm32")))]
+
+mod common;
+
+use common::scene_with;
+use genmesh::generators::{Cube, IcoSphere, Plane};
+use moksha::{
+    rc_rcell,
+    renderer::{DrawMode, Image, RenderFlags, SoftwareBackend},
+    scene::Instances,
+    Color, Geometry, LightType, Material, Mesh, Node, ObjectInfo, Scene, Transform,
+};
+use nalgebra::{UnitQuaternion, Vector3};
+use std::{env, fs, path::PathBuf, rc::Rc};
+
+const SIZE: u32 = 64;
+/// How far a channel may drift from the golden image before the pixel counts as different.
+const TOLERANCE: u8 = 2;
+/// How many pixels may differ, to allow for floating point differences between platforms.
+const MAX_DIFFERING: usize = 8;
+
+fn setup() -> (Rc<SoftwareBackend>, Scene) {
+    let backend = Rc::new(SoftwareBackend::new(SIZE, SIZE));
+    let scene = scene_with(backend.clone(), SIZE, SIZE, std::f32::consts::PI / 3.);
+    (backend, scene)
+}
+
+fn add_lights(scene: &Scene) {
+    let ambient = scene.light(LightType::Ambient, [1., 1., 1.], 0.3);
+    let directional = scene.light(LightType::Directional, [1., 1., 1.], 1.);
+    // the renderer points directional lights along their x axis turned a quarter around y
+    let towards = Vector3::new(-0.6, 0.7, 0.4);
+    directional
+        .node()
+        .borrow()
+        .set_rotation(UnitQuaternion::rotation_between(&Vector3::x(), &towards).unwrap());
+    for light in [ambient, directional].iter() {
+        scene.add_light(light);
+        scene.set_visibility_only(&light.node().borrow(), false);
+    }
+}
+
+fn with_info(node: Node, draw_mode: DrawMode, render_flags: RenderFlags) -> Node {
+    let mut info = node.info();
+    info.draw_mode = draw_mode;
+    info.render_flags = RenderFlags {
+        render: info.render_flags.render,
+        ..render_flags
+    };
+    node.set_info(info);
+    node
+}
+
+fn render(backend: &SoftwareBackend, scene: &Scene) -> Image {
+    scene
+        .renderer()
+        .borrow()
+        .render(scene, &scene.view().borrow());
+    backend.image()
+}
+
+fn assert_golden(name: &str, image: &Image) {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let path = dir.join(format!("{}.png", name));
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&path, image.to_png()).unwrap();
+        return;
+    }
+    if !path.exists() {
+        panic!(
+            "There is no golden image at {}, run with UPDATE_GOLDEN=1 to write it",
+            path.display()
+        );
+    }
+    let golden = Image::from_png(&fs::read(&path).unwrap()).unwrap();
+    let differing = image
+        .diff(&golden, TOLERANCE)
+        .expect("The image isn't the size of the golden image!");
+    if differing > MAX_DIFFERING {
+        let actual = dir.join(format!("{}.actual.png", name));
+        fs::write(&actual, image.to_png()).unwrap();
+        panic!(
+            "{} pixels differ from {}, the output was written to {}",
+            differing,
+            path.display(),
+            actual.display()
+        );
+    }
+}
+
+#[test]
+fn png_round_trip() {
+    let mut image = Image::new(5, 3);
+    for y in 0..3 {
+        for x in 0..5 {
+            image.set_pixel(x, y, [x as u8 * 50, y as u8 * 100, 7, 255 - x as u8]);
+        }
+    }
+    let decoded = Image::from_png(&image.to_png()).unwrap();
+    assert_eq!(decoded, image);
+    assert_eq!(image.diff(&decoded, 0), Some(0));
+    assert_eq!(image.diff(&Image::new(5, 4), 0), None);
+}
+
+#[test]
+fn reads_compressed_png() {
+    // an RGB gradient written by another encoder, with dynamic Huffman codes and every filter
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden/gradient.png");
+    let image = Image::from_png(&fs::read(path).unwrap()).unwrap();
+    assert_eq!((image.width(), image.height()), (16, 10));
+    for y in 0..10 {
+        for x in 0..16 {
+            assert_eq!(image.pixel(x, y), [x as u8 * 16, y as u8 * 25, 128, 255]);
+        }
+    }
+}
+
+#[test]
+fn lit_cube() {
+    let (backend, scene) = setup();
+    add_lights(&scene);
+    let cube = scene.from_mesh(
+        Some(Mesh::new(
+            Geometry::from_genmesh(&Cube::new()),
+            Material::new_color(0.8, 0.3, 0.2, 1.),
+        )),
+        false,
+    );
+    cube.set_rotation(UnitQuaternion::from_euler_angles(0.3, 0.6, 0.));
+    scene.add(rc_rcell(cube));
+    assert_golden("lit_cube", &render(&backend, &scene));
+}
+
+#[test]
+fn flat_sphere_with_wire_overlay() {
+    let (backend, scene) = setup();
+    add_lights(&scene);
+    let sphere = scene.from_mesh(
+        Some(Mesh::new(
+            Geometry::from_genmesh(&IcoSphere::new()),
+            Material::new_color(0.2, 0.5, 0.9, 1.)
+                .flat()
+                .wire_overlay_colored(Color::rgb(1., 1., 1.)),
+        )),
+        true,
+    );
+    let sphere = with_info(sphere, DrawMode::Arrays, Default::default());
+    scene.add(rc_rcell(sphere));
+    assert_golden("flat_sphere_with_wire_overlay", &render(&backend, &scene));
+}
+
+#[test]
+fn wireframe() {
+    let (backend, scene) = setup();
+    let cube = scene.from_mesh(
+        Some(Mesh::new(
+            Geometry::from_genmesh_no_normals(&Cube::new()),
+            Material::new_wire(0.1, 0.9, 0.4, 1.),
+        )),
+        true,
+    );
+    let cube = with_info(cube, DrawMode::Arrays, RenderFlags::blend_cull());
+    cube.set_rotation(UnitQuaternion::from_euler_angles(0.3, 0.6, 0.));
+    scene.add(rc_rcell(cube));
+    assert_golden("wireframe", &render(&backend, &scene));
+}
+
+#[test]
+fn pbr_spheres() {
+    let (backend, scene) = setup();
+    add_lights(&scene);
+    let materials = [
+        Material::new_pbr(0.8, 0.1, 0.1, 1.).metallic(0.).roughness(0.9),
+        Material::new_pbr(0.8, 0.1, 0.1, 1.).metallic(0.).roughness(0.2),
+        Material::new_pbr(1., 0.8, 0.4, 1.).metallic(1.).roughness(0.3),
+    ];
+    for (i, material) in materials.iter().enumerate() {
+        let sphere = scene.from_mesh(
+            Some(Mesh::new(
+                Geometry::from_genmesh(&IcoSphere::subdivide(2)),
+                material.clone(),
+            )),
+            false,
+        );
+        sphere.set_position(i as f32 * 1.5 - 1.5, 0., 0.);
+        sphere.set_scale(0.65);
+        scene.add(rc_rcell(sphere));
+    }
+    assert_golden("pbr_spheres", &render(&backend, &scene));
+}
+
+#[test]
+fn instanced_planes_with_outline() {
+    let (backend, scene) = setup();
+    let transforms = (0..3)
+        .map(|i| {
+            let mut transform = Transform::from_scale(0.4);
+            transform.isometry.translation.vector.x = i as f32 - 1.;
+            transform
+        })
+        .collect();
+    let planes = scene.instanced(
+        Mesh::new(
+            Geometry::from_genmesh(&Plane::new()),
+            Material::new_color_no_shade(1., 1., 1., 1.),
+        ),
+        ObjectInfo {
+            render_flags: RenderFlags::no_cull(),
+            ..Default::default()
+        },
+        Instances {
+            transforms,
+            colors: Some(vec![[1., 0., 0., 1.], [0., 1., 0., 1.], [0., 0., 1., 1.]]),
+        },
+    );
+    scene.add(rc_rcell(planes));
+    let cube = scene.from_mesh(
+        Some(Mesh::new(
+            Geometry::from_genmesh(&Cube::new()),
+            Material::new_color_no_shade(0.5, 0.5, 0.5, 1.).outline(),
+        )),
+        false,
+    );
+    cube.set_position(0., 0.5, -1.5);
+    cube.set_scale(0.3);
+    scene.add(rc_rcell(cube));
+    assert_golden("instanced_planes_with_outline", &render(&backend, &scene));
+}